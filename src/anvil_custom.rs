@@ -14,10 +14,11 @@
 use std::path::PathBuf;
 use std::time::Instant;
 use std::fs;
-use toml::Table;
 use regex::Regex;
+use crate::error::AnvilError;
+use crate::stego::{deserialize_stego, STEGO_CUSTOMBUILDER_KEYNAME};
 
-pub const ANVILCUST_CUSTOM_BUILDER_KEYNAME: &str = "custombuilder";
+pub const ANVILCUST_CUSTOM_BUILDER_KEYNAME: &str = STEGO_CUSTOMBUILDER_KEYNAME;
 
 #[derive(Debug)]
 pub struct AnvilCustomEnv {
@@ -25,14 +26,14 @@ pub struct AnvilCustomEnv {
     pub custom_builder: String,
 }
 
-pub fn parse_anvilcustom_toml(stego_path: &PathBuf) -> Result<AnvilCustomEnv,String> {
+pub fn parse_anvilcustom_toml(stego_path: &PathBuf) -> Result<AnvilCustomEnv,AnvilError> {
     let start_time = Instant::now();
-    let stego = fs::read_to_string(stego_path).expect("Could not read {stego_path} contents");
+    let stego = fs::read_to_string(stego_path).map_err(|e| AnvilError::io(stego_path, e))?;
     //trace!("Pyproject contents: {{{}}}", pyproj);
     let mut stego_dir = stego_path.clone();
     if ! stego_dir.pop() {
         error!("Failed pop for {{{}}}", stego_dir.display());
-        return Err(format!("Unexpected stego_dir value: {{{}}}", stego_dir.display()));
+        return Err(AnvilError::Config(format!("Unexpected stego_dir value: {{{}}}", stego_dir.display())));
     }
     return parse_anvilcustom_tomlvalue(&stego, stego_path, start_time);
 }
@@ -49,31 +50,30 @@ fn has_reserved_char(input: &str) -> bool {
     false
 }
 
-fn parse_anvilcustom_tomlvalue(stego_str: &str, stego_path: &PathBuf, start_time: Instant) -> Result<AnvilCustomEnv,String> {
-    let toml_value = stego_str.parse::<Table>();
-    match toml_value {
+fn parse_anvilcustom_tomlvalue(stego_str: &str, stego_path: &PathBuf, start_time: Instant) -> Result<AnvilCustomEnv,AnvilError> {
+    let stego = deserialize_stego(stego_path, stego_str);
+    match stego {
         Ok(y) => {
             let mut anvilcustom_env: AnvilCustomEnv = AnvilCustomEnv {
                 custom_builder : "".to_string(),
             };
-            trace!("Toml value: {{{}}}", y);
-            if let Some(anvil_table) = y.get("anvil").and_then(|v| v.as_table()) {
-                if let Some(custom_builder) = anvil_table.get(ANVILCUST_CUSTOM_BUILDER_KEYNAME) {
-                    let anvilcust_builder_str = custom_builder.as_str().expect("toml conversion failed");
+            if let Some(ref anvil_table) = y.anvil {
+                if let Some(ref custom_builder) = anvil_table.custombuilder {
+                    let anvilcust_builder_str = custom_builder.as_str();
                     if has_reserved_char(anvilcust_builder_str) {
                         //TODO: warning about the reserved chars?
                         error!("anvil_custombuilder: --> {{{anvilcust_builder_str}}}");
-                        return Err("Invalid custombuilder arg".to_string());
+                        return Err(AnvilError::Config("Invalid custombuilder arg".to_string()));
                     }
                     debug!("anvil_custombuilder: {{{anvilcust_builder_str}}}");
                     anvilcustom_env.custom_builder = anvilcust_builder_str.to_string();
                 } else {
                     error!("Missing ANVILCUST_CUSTOM_BUILDER definition.");
-                    return Err(format!("Missing anvil_custombuilder in {{{}}}", stego_path.display()));
+                    return Err(AnvilError::Config(format!("Missing anvil_custombuilder in {{{}}}", stego_path.display())));
                 }
             } else {
                 error!("Missing anvil section.");
-                return Err(format!("Missing anvil section in {{{}}}", stego_path.display()));
+                return Err(AnvilError::Config(format!("Missing anvil section in {{{}}}", stego_path.display())));
             }
 
             let elapsed = start_time.elapsed();
@@ -83,8 +83,7 @@ fn parse_anvilcustom_tomlvalue(stego_str: &str, stego_path: &PathBuf, start_time
         Err(e) => {
             let elapsed = start_time.elapsed();
             debug!("Done parsing stego.toml. Elapsed: {:.2?}", elapsed);
-            error!("Failed parsing {{{}}}  as TOML. Err: [{}]", stego_str, e);
-            return Err("Failed parsing TOML".to_string());
+            return Err(e);
         }
     }
 }
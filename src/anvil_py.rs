@@ -24,6 +24,7 @@ use std::os::unix::fs::PermissionsExt;
 use std::fs::OpenOptions;
 
 use crate::core::{INVIL_VERSION, AmbosoEnv};
+use crate::error::AnvilError;
 
 pub const ANVILPY_PROJECT_VERSION_KEYNAME: &str = "version";
 pub const ANVILPY_PROJECT_NAME_KEYNAME: &str = "name";
@@ -104,19 +105,19 @@ pub struct AnvilPyEnv {
     pub build_sys: BuildSystem,
 }
 
-pub fn parse_pyproject_toml(pyproj_path: &PathBuf) -> Result<AnvilPyEnv,String> {
+pub fn parse_pyproject_toml(pyproj_path: &PathBuf) -> Result<AnvilPyEnv,AnvilError> {
     let start_time = Instant::now();
-    let pyproj = fs::read_to_string(pyproj_path).expect("Could not read {pyproj_path} contents");
+    let pyproj = fs::read_to_string(pyproj_path).map_err(|e| AnvilError::io(pyproj_path, e))?;
     //trace!("Pyproject contents: {{{}}}", pyproj);
     let mut pyproj_dir = pyproj_path.clone();
     if ! pyproj_dir.pop() {
         error!("Failed pop for {{{}}}", pyproj_dir.display());
-        return Err(format!("Unexpected pyproj_dir value: {{{}}}", pyproj_dir.display()));
+        return Err(AnvilError::Config(format!("Unexpected pyproj_dir value: {{{}}}", pyproj_dir.display())));
     }
     return parse_pyproject_tomlvalue(&pyproj, pyproj_path, start_time);
 }
 
-fn parse_pyproject_tomlvalue(pyproj_str: &str, pyproj_path: &PathBuf, start_time: Instant) -> Result<AnvilPyEnv,String> {
+fn parse_pyproject_tomlvalue(pyproj_str: &str, pyproj_path: &PathBuf, start_time: Instant) -> Result<AnvilPyEnv,AnvilError> {
     let toml_value = pyproj_str.parse::<Table>();
     match toml_value {
         Ok(y) => {
@@ -143,7 +144,7 @@ fn parse_pyproject_tomlvalue(pyproj_str: &str, pyproj_path: &PathBuf, start_time
                         debug!("anvilpy_version: {{{anvilpy_proj_v_str}}}");
                     } else {
                         error!("anvilpy_version is not a valid semver: {{{}}}", anvilpy_proj_v_str);
-                        return Err("Invalid anvilpy_version: {{{anvilpy_proj_v_str}}}, not a semver.".to_string());
+                        return Err("Invalid anvilpy_version: {{{anvilpy_proj_v_str}}}, not a semver.".into());
                     }
                     */
                 } else {
@@ -190,7 +191,7 @@ fn parse_pyproject_tomlvalue(pyproj_str: &str, pyproj_path: &PathBuf, start_time
                         }
                     } else {
                         error!("ANVILPY_PROJECT_AUTHORS is not an array");
-                        return Err("Authors is not an array".to_string());
+                        return Err(AnvilError::Config("Authors is not an array".to_string()));
                     }
                 } else {
                     warn!("Missing ANVILPY_AUTHORS definition.");
@@ -204,7 +205,7 @@ fn parse_pyproject_tomlvalue(pyproj_str: &str, pyproj_path: &PathBuf, start_time
                         }
                     } else {
                         error!("ANVILPY_PROJECT_CLASSIFIERS is not an array");
-                        return Err("Classifiers is not an array".to_string());
+                        return Err(AnvilError::Config("Classifiers is not an array".to_string()));
                     }
                 } else {
                     warn!("Missing ANVILPY_CLASSIFIERS definition.");
@@ -229,7 +230,7 @@ fn parse_pyproject_tomlvalue(pyproj_str: &str, pyproj_path: &PathBuf, start_time
                                     }
                                     Err(e) => {
                                         error!("Failed parsing url: {{{}}}. Err was: {e}", url);
-                                        return Err("Failed parsing url".to_string());
+                                        return Err(AnvilError::Config("Failed parsing url".to_string()));
                                     }
                                 }
                                 let url_entry = UrlEntry { name: k.to_string(), link: url_parsed };
@@ -238,7 +239,7 @@ fn parse_pyproject_tomlvalue(pyproj_str: &str, pyproj_path: &PathBuf, start_time
                         }
                     } else {
                         error!("ANVILPY_URLS is not a table");
-                        return Err("Urls is not an table".to_string());
+                        return Err(AnvilError::Config("Urls is not an table".to_string()));
                     }
                 } else {
                     warn!("Missing ANVILPY_URLS definition.");
@@ -255,14 +256,14 @@ fn parse_pyproject_tomlvalue(pyproj_str: &str, pyproj_path: &PathBuf, start_time
                         }
                     } else {
                         error!("ANVILPY_ENTRYPOINTS is not a table");
-                        return Err("Scripts is not an table".to_string());
+                        return Err(AnvilError::Config("Scripts is not an table".to_string()));
                     }
                 } else {
                     warn!("Missing ANVILPY_ENTRYPOINTS definition.");
                 }
             } else {
                 error!("Missing project section.");
-                return Err(format!("Missing project section in {{{}}}", pyproj_path.display()));
+                return Err(AnvilError::Config(format!("Missing project section in {{{}}}", pyproj_path.display())));
             }
             if let Some(build_system_table) = y.get("build-system").and_then(|v| v.as_table()) {
                 if let Some(reqs) = build_system_table.get(ANVILPY_BUILD_REQS_KEYNAME) {
@@ -275,13 +276,13 @@ fn parse_pyproject_tomlvalue(pyproj_str: &str, pyproj_path: &PathBuf, start_time
                                 }
                                 _ => {
                                     error!("Unexpected item for reqs arrays: {{{}}}", item);
-                                    return Err("Unexpected item in reqs array".to_string());
+                                    return Err(AnvilError::Config("Unexpected item in reqs array".to_string()));
                                 }
                             }
                         }
                     } else {
                         error!("ANVILPY_BUILD_REQS is not an array");
-                        return Err("Reqs is not an array".to_string());
+                        return Err(AnvilError::Config("Reqs is not an array".to_string()));
                     }
                 } else {
                     warn!("Missing ANVILPY_BUILD_REQS definition.");
@@ -295,7 +296,7 @@ fn parse_pyproject_tomlvalue(pyproj_str: &str, pyproj_path: &PathBuf, start_time
                 }
             } else {
                 error!("Missing ANVILPY_BUILDSYSTEM section.");
-                return Err(format!("Missing build-backend section in {{{}}}", pyproj_path.display()));
+                return Err(AnvilError::Config(format!("Missing build-backend section in {{{}}}", pyproj_path.display())));
             }
             let elapsed = start_time.elapsed();
             debug!("Done parsing pyproject.toml. Elapsed: {:.2?}", elapsed);
@@ -305,12 +306,12 @@ fn parse_pyproject_tomlvalue(pyproj_str: &str, pyproj_path: &PathBuf, start_time
             let elapsed = start_time.elapsed();
             debug!("Done parsing pyproject.toml. Elapsed: {:.2?}", elapsed);
             error!("Failed parsing {{{}}}  as TOML. Err: [{}]", pyproj_str, e);
-            return Err("Failed parsing TOML".to_string());
+            return Err(AnvilError::stego_parse(pyproj_path, pyproj_str, e));
         }
     }
 }
 
-pub fn unpack_srcdist(pack_path: &PathBuf) -> Result<PathBuf,String> {
+pub fn unpack_srcdist(pack_path: &PathBuf) -> Result<PathBuf,AnvilError> {
     debug!("TODO: add unpack step");
     let tar_gz = File::open(pack_path);
     match tar_gz {
@@ -327,19 +328,19 @@ pub fn unpack_srcdist(pack_path: &PathBuf) -> Result<PathBuf,String> {
                 }
                 Err(e) => {
                     error!("Failed unpacking srcdist from {{{}}}. Err: {e}", pack_path.display());
-                    return Err("Failed unpacking srcdist".to_string());
+                    return Err(AnvilError::io(pack_path, e).context("Failed unpacking srcdist"));
                 }
             }
         }
         Err(e) => {
             error!("Failed opening srcdist pack at {{{}}}. Err: {e}", pack_path.display());
-            return Err("Failed opening srcdist".to_string());
+            return Err(AnvilError::io(pack_path, e));
         }
     }
 }
 
 
-pub fn gen_anvilpy_shim(shim_path: &PathBuf, module_path: &str, entrypoint_func: &str) -> Result<String,String> {
+pub fn gen_anvilpy_shim(shim_path: &PathBuf, module_path: &str, entrypoint_func: &str) -> Result<String,AnvilError> {
     trace!("Generating anvilpy shim. Target path: {{{}}} Module path: {{{}}} Function: {{{}}}", shim_path.display(), module_path, entrypoint_func);
     let output = File::create(shim_path);
     let shim_string = format!("#!/bin/python3\n\n##\n# Generated by invil v{INVIL_VERSION}\n# Repo at https://github.com/jgabaut/invil\n##\n\nimport sys\nimport re\nfrom {ANVILPY_UNPACKDIR_NAME}.{module_path} import {entrypoint_func}\n\nif __name__ == \'__main__\':\n    sys.argv[0] = re.sub(r'(-script\\.pyw|\\.exe)?$', '', sys.argv[0])\n    sys.exit({entrypoint_func}())\n");
@@ -352,7 +353,7 @@ pub fn gen_anvilpy_shim(shim_path: &PathBuf, module_path: &str, entrypoint_func:
                 }
                 Err(e) => {
                     error!("Failed printing shim file");
-                    return Err(e.to_string());
+                    return Err(AnvilError::io(shim_path, e));
                 }
             }
             if cfg!(target_os = "windows") {
@@ -376,14 +377,15 @@ pub fn gen_anvilpy_shim(shim_path: &PathBuf, module_path: &str, entrypoint_func:
                     }
                     Err(e) => {
                         error!("Failed getting metadata for shim. Err: {e}");
-                        return Err("Failed getting shim metadata".to_string());
+                        return Err(AnvilError::io(shim_path, e));
                     }
                 }
             }
             return Ok(format!("Generated {{{}}}", shim_path.display()));
         }
-        Err(_) => {
-            return Err("Failed gen of shim file".to_string());
+        Err(e) => {
+            error!("Failed creating shim file. Err: {e}");
+            return Err(AnvilError::io(shim_path, e));
         }
     }
 
@@ -398,12 +400,12 @@ fn touch_file(path: &Path) -> io::Result<()> {
     }
 }
 
-pub fn post_unpack(unpack_initpy_path: &PathBuf, bindir_path: &PathBuf, env: &AmbosoEnv) -> Result<String,String> {
+pub fn post_unpack(unpack_initpy_path: &PathBuf, bindir_path: &PathBuf, env: &AmbosoEnv) -> Result<String,AnvilError> {
     match touch_file(&unpack_initpy_path) {
         Ok(_) => {},
         Err(e) => {
             error!("Failed touch for {{{}}}. Err: {e}", unpack_initpy_path.display());
-            return Err("Failed touch for unpack initpy".to_string());
+            return Err(AnvilError::io(unpack_initpy_path, e));
         }
     }
 
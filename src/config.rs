@@ -0,0 +1,416 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use clap::ArgMatches;
+use clap::parser::ValueSource;
+use toml::{Table, Value};
+use toml_edit::{DocumentMut, Item};
+use crate::core::{Args, ConfigCommands, ANVIL_DEFAULT_CONF_PATH, ANVIL_EXIT_CONFIG, ANVIL_EXIT_FAILURE, ANVIL_EXIT_USAGE};
+use crate::error::{AnvilError, error_chain};
+
+pub const ANVIL_SYSTEM_CONF_PATH: &str = "/etc/anvil/anvil.toml";
+pub const ANVIL_PROJECT_CONF_NAME: &str = ".anvil.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigKind {
+    Str,
+    Bool,
+    Int,
+}
+
+#[derive(Debug)]
+pub struct ConfigKey {
+    /// Dotted name, `<section>.<key>` in the config files
+    pub name: &'static str,
+    /// Environment variable overriding the files
+    pub env: &'static str,
+    /// Id of the flag overriding everything
+    pub arg: &'static str,
+    pub kind: ConfigKind,
+    pub description: &'static str,
+}
+
+pub const CONFIG_KEYS: &[ConfigKey] = &[
+    ConfigKey { name: "anvil.version", env: "ANVIL_VERSION", arg: "anvil_version", kind: ConfigKind::Str, description: "Anvil version to run as" },
+    ConfigKey { name: "anvil.kern", env: "ANVIL_KERN", arg: "anvil_kern", kind: ConfigKind::Str, description: "Anvil kern to use" },
+    ConfigKey { name: "output.verbosity", env: "ANVIL_VERBOSITY", arg: "verbose", kind: ConfigKind::Int, description: "Log level, from 0 to 5" },
+    ConfigKey { name: "output.color", env: "ANVIL_COLOR", arg: "no_color", kind: ConfigKind::Bool, description: "Color log output" },
+    ConfigKey { name: "build.jobs", env: "ANVIL_JOBS", arg: "jobs", kind: ConfigKind::Int, description: "Jobs passed to make" },
+    ConfigKey { name: "build.builtin_make", env: "ANVIL_BUILTIN_MAKE", arg: "builtin_make", kind: ConfigKind::Bool, description: "Build with the built-in make executor" },
+    ConfigKey { name: "build.amboso_dir", env: "ANVIL_AMBOSO_DIR", arg: "amboso_dir", kind: ConfigKind::Str, description: "Directory hosting tags" },
+    ConfigKey { name: "build.builds_dir", env: "ANVIL_BUILDS_DIR", arg: "builds_dir", kind: ConfigKind::Str, description: "Directory hosting builds" },
+    ConfigKey { name: "build.cflags", env: "ANVIL_CFLAGS", arg: "cflags", kind: ConfigKind::Str, description: "CFLAGS for builds" },
+    ConfigKey { name: "build.configure", env: "ANVIL_CONFIGURE", arg: "config", kind: ConfigKind::Str, description: "Argument for the configure script" },
+    ConfigKey { name: "tests.timeout", env: "ANVIL_TEST_TIMEOUT", arg: "test_timeout", kind: ConfigKind::Int, description: "Seconds before a test is killed" },
+];
+
+pub fn config_key(name: &str) -> Option<&'static ConfigKey> {
+    CONFIG_KEYS.iter().find(|k| k.name == name)
+}
+
+/// Where a config value was set.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigOrigin {
+    Default,
+    File(PathBuf),
+    Env(&'static str),
+    CommandLine,
+}
+
+impl fmt::Display for ConfigOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigOrigin::Default => write!(f, "default"),
+            ConfigOrigin::File(p) => write!(f, "file:{}", p.display()),
+            ConfigOrigin::Env(var) => write!(f, "env:{var}"),
+            ConfigOrigin::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    pub value: Value,
+    pub origin: ConfigOrigin,
+}
+
+/// Scope written by `invil config set`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigScope {
+    System,
+    User,
+    Project,
+}
+
+/// Config values from all layers: defaults, system, user and project files, environment, then flags.
+#[derive(Debug, Default)]
+pub struct LayeredConfig {
+    entries: BTreeMap<&'static str, ConfigEntry>,
+    /// Warnings found while loading, to log once logging is set up
+    pub warnings: Vec<String>,
+}
+
+/// Parses `raw` as a value of `kind`, as passed in an environment variable or to `config set`.
+pub fn parse_config_value(kind: ConfigKind, raw: &str) -> Option<Value> {
+    match kind {
+        ConfigKind::Str => Some(Value::String(raw.to_string())),
+        ConfigKind::Bool => match raw.to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Some(Value::Boolean(true)),
+            "false" | "0" | "no" | "off" => Some(Value::Boolean(false)),
+            _ => None,
+        },
+        ConfigKind::Int => raw.parse::<u32>().ok().map(|n| Value::Integer(n.into())),
+    }
+}
+
+fn value_has_kind(value: &Value, kind: ConfigKind) -> bool {
+    match kind {
+        ConfigKind::Str => value.is_str(),
+        ConfigKind::Bool => value.is_bool(),
+        ConfigKind::Int => value.as_integer().is_some_and(|n| u32::try_from(n).is_ok()),
+    }
+}
+
+/// Prints a value as it would be passed on the command line.
+pub fn display_config_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+fn arg_value(args: &Args, key: &ConfigKey) -> Option<Value> {
+    let path_str = |p: &Option<PathBuf>| p.as_ref().map(|p| Value::String(p.display().to_string()));
+    match key.arg {
+        "anvil_version" => args.anvil_version.clone().map(Value::String),
+        "anvil_kern" => args.anvil_kern.clone().map(Value::String),
+        "verbose" => Some(Value::Integer(args.verbose.into())),
+        "no_color" => Some(Value::Boolean(!args.no_color)),
+        "jobs" => args.jobs.map(|j| Value::Integer(j.into())),
+        "builtin_make" => Some(Value::Boolean(args.builtin_make)),
+        "amboso_dir" => path_str(&args.amboso_dir),
+        "builds_dir" => path_str(&args.builds_dir),
+        "cflags" => args.cflags.clone().map(Value::String),
+        "config" => args.config.clone().map(Value::String),
+        "test_timeout" => args.test_timeout.map(|t| Value::Integer(t.into())),
+        _ => None,
+    }
+}
+
+fn set_arg_value(args: &mut Args, key: &ConfigKey, value: &Value) {
+    let as_u32 = |v: &Value| v.as_integer().and_then(|n| u32::try_from(n).ok());
+    match key.arg {
+        "anvil_version" => args.anvil_version = value.as_str().map(str::to_string),
+        "anvil_kern" => args.anvil_kern = value.as_str().map(str::to_string),
+        "verbose" => {
+            if let Some(v) = value.as_integer().and_then(|n| u8::try_from(n).ok()) {
+                args.verbose = v;
+            }
+        }
+        "no_color" => args.no_color = !value.as_bool().unwrap_or(true),
+        "jobs" => args.jobs = as_u32(value),
+        "builtin_make" => args.builtin_make = value.as_bool().unwrap_or(false),
+        "amboso_dir" => args.amboso_dir = value.as_str().map(PathBuf::from),
+        "builds_dir" => args.builds_dir = value.as_str().map(PathBuf::from),
+        "cflags" => args.cflags = value.as_str().map(str::to_string),
+        "config" => args.config = value.as_str().map(str::to_string),
+        "test_timeout" => args.test_timeout = as_u32(value),
+        _ => {}
+    }
+}
+
+impl LayeredConfig {
+    pub fn get(&self, name: &str) -> Option<&ConfigEntry> {
+        self.entries.get(name)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&'static str, &ConfigEntry)> {
+        self.entries.iter().map(|(k, v)| (*k, v))
+    }
+
+    fn set(&mut self, key: &'static ConfigKey, value: Value, origin: ConfigOrigin) {
+        self.entries.insert(key.name, ConfigEntry { value, origin });
+    }
+
+    /// Merges the flags in `matches` whose value came from `source`.
+    pub fn merge_matches(&mut self, args: &Args, matches: &ArgMatches, source: ValueSource) {
+        for key in CONFIG_KEYS {
+            if matches.value_source(key.arg) != Some(source) {
+                continue;
+            }
+            if let Some(value) = arg_value(args, key) {
+                let origin = if source == ValueSource::CommandLine { ConfigOrigin::CommandLine } else { ConfigOrigin::Default };
+                self.set(key, value, origin);
+            }
+        }
+    }
+
+    /// Merges a config file, with one table per section.
+    pub fn merge_toml(&mut self, path: &Path, contents: &str) -> Result<(), AnvilError> {
+        let table = contents.parse::<Table>().map_err(|e| AnvilError::Config(format!("{}: {e}", path.display())))?;
+        for (section, values) in table.iter() {
+            let Some(values) = values.as_table() else {
+                self.warnings.push(format!("{}: unknown config key {{{section}}}", path.display()));
+                continue;
+            };
+            for (name, value) in values.iter() {
+                let full_name = format!("{section}.{name}");
+                let Some(key) = config_key(&full_name) else {
+                    self.warnings.push(format!("{}: unknown config key {{{full_name}}}", path.display()));
+                    continue;
+                };
+                if !value_has_kind(value, key.kind) {
+                    return Err(AnvilError::Config(format!("{}: invalid value for {{{full_name}}}, expected {:?}", path.display(), key.kind)));
+                }
+                self.set(key, value.clone(), ConfigOrigin::File(path.to_path_buf()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Merges `path`, if it exists.
+    pub fn merge_file(&mut self, path: &Path) -> Result<(), AnvilError> {
+        if !path.is_file() {
+            return Ok(());
+        }
+        let contents = fs::read_to_string(path).map_err(|e| AnvilError::io(path, e))?;
+        self.merge_toml(path, &contents)
+    }
+
+    /// Merges the `ANVIL_*` variables in `vars`.
+    pub fn merge_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> Result<(), AnvilError> {
+        for (var, raw) in vars {
+            let Some(key) = CONFIG_KEYS.iter().find(|k| k.env == var) else {
+                continue;
+            };
+            match parse_config_value(key.kind, &raw) {
+                Some(value) => self.set(key, value, ConfigOrigin::Env(key.env)),
+                None => return Err(AnvilError::Config(format!("{var}: invalid value {{{raw}}} for {{{}}}, expected {:?}", key.name, key.kind))),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the config file for `scope`.
+pub fn config_path(scope: ConfigScope, args: &Args) -> Option<PathBuf> {
+    match scope {
+        ConfigScope::System => Some(PathBuf::from(ANVIL_SYSTEM_CONF_PATH)),
+        ConfigScope::User => dirs::home_dir().map(|h| h.join(ANVIL_DEFAULT_CONF_PATH)),
+        ConfigScope::Project => Some(args.stego_dir.clone().unwrap_or(PathBuf::from(".")).join(ANVIL_PROJECT_CONF_NAME)),
+    }
+}
+
+/// Loads all config layers for `args`, as parsed into `matches`.
+///
+/// Files and environment are skipped with `--strict`.
+pub fn load_config(args: &Args, matches: &ArgMatches) -> Result<LayeredConfig, AnvilError> {
+    let mut cfg = LayeredConfig::default();
+    cfg.merge_matches(args, matches, ValueSource::DefaultValue);
+    if !args.strict {
+        for scope in [ConfigScope::System, ConfigScope::User, ConfigScope::Project] {
+            if let Some(path) = config_path(scope, args) {
+                cfg.merge_file(&path)?;
+            }
+        }
+        cfg.merge_env(std::env::vars())?;
+    }
+    cfg.merge_matches(args, matches, ValueSource::CommandLine);
+    Ok(cfg)
+}
+
+/// Sets every configured value on `args`.
+pub fn apply_config(args: &mut Args, cfg: &LayeredConfig) {
+    for key in CONFIG_KEYS {
+        if let Some(entry) = cfg.get(key.name) {
+            set_arg_value(args, key, &entry.value);
+        }
+    }
+}
+
+/// Writes `name = raw` to the config file at `path`, keeping its formatting.
+pub fn set_config_value(path: &Path, name: &str, raw: &str) -> Result<(), AnvilError> {
+    let key = config_key(name).ok_or_else(|| AnvilError::Usage(format!("Unknown config key {{{name}}}")))?;
+    let value = parse_config_value(key.kind, raw)
+        .ok_or_else(|| AnvilError::Usage(format!("Invalid value {{{raw}}} for {{{name}}}, expected {:?}", key.kind)))?;
+    let contents = match fs::read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(AnvilError::io(path, e)),
+    };
+    let mut doc = contents.parse::<DocumentMut>().map_err(|e| AnvilError::Config(format!("{}: {e}", path.display())))?;
+    let (section, field) = name.split_once('.').expect("Config keys should be dotted");
+    let item = match value {
+        Value::String(s) => toml_edit::value(s),
+        Value::Boolean(b) => toml_edit::value(b),
+        Value::Integer(n) => toml_edit::value(n),
+        _ => unreachable!("Config values are strings, booleans or integers"),
+    };
+    if !doc.contains_table(section) {
+        doc.insert(section, Item::Table(toml_edit::Table::new()));
+    }
+    doc[section][field] = item;
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| AnvilError::io(dir, e))?;
+    }
+    fs::write(path, doc.to_string()).map_err(|e| AnvilError::io(path, e))
+}
+
+pub fn handle_config_subcommand(args: &Args, cfg: &LayeredConfig, op: &ConfigCommands) -> ExitCode {
+    match op {
+        ConfigCommands::Get { key, show_origin } => {
+            if config_key(key).is_none() {
+                error!("Unknown config key {{{key}}}");
+                return ExitCode::from(ANVIL_EXIT_USAGE);
+            }
+            match cfg.get(key) {
+                Some(entry) if *show_origin => println!("{}\t{}", entry.origin, display_config_value(&entry.value)),
+                Some(entry) => println!("{}", display_config_value(&entry.value)),
+                None => {
+                    debug!("{{{key}}} is not set");
+                    return ExitCode::from(ANVIL_EXIT_FAILURE);
+                }
+            }
+            ExitCode::SUCCESS
+        }
+        ConfigCommands::Set { key, value, global, system } => {
+            if config_key(key).is_none() {
+                error!("Unknown config key {{{key}}}");
+                return ExitCode::from(ANVIL_EXIT_USAGE);
+            }
+            let scope = if *system {
+                ConfigScope::System
+            } else if *global {
+                ConfigScope::User
+            } else {
+                ConfigScope::Project
+            };
+            let Some(path) = config_path(scope, args) else {
+                error!("Could not find the config file for {scope:?}");
+                return ExitCode::from(ANVIL_EXIT_CONFIG);
+            };
+            match set_config_value(&path, key, value) {
+                Ok(()) => {
+                    info!("Set {{{key}}} to {{{value}}} in {{{}}}", path.display());
+                    ExitCode::SUCCESS
+                }
+                Err(e) => {
+                    error!("Failed setting {{{key}}}. Err: {}", error_chain(&e));
+                    match e.root() {
+                        AnvilError::Usage(_) => ExitCode::from(ANVIL_EXIT_USAGE),
+                        _ => ExitCode::from(ANVIL_EXIT_CONFIG),
+                    }
+                }
+            }
+        }
+        ConfigCommands::List { show_origin } => {
+            for (name, entry) in cfg.entries() {
+                if *show_origin {
+                    println!("{}\t{name}={}", entry.origin, display_config_value(&entry.value));
+                } else {
+                    println!("{name}={}", display_config_value(&entry.value));
+                }
+            }
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use clap::{CommandFactory, FromArgMatches};
+
+    #[test]
+    fn test_config_layers() {
+        let matches = Args::command().get_matches_from(["invil", "-j", "8"]);
+        let mut args = Args::from_arg_matches(&matches).expect("Failed parsing args");
+        let mut cfg = LayeredConfig::default();
+        cfg.merge_matches(&args, &matches, ValueSource::DefaultValue);
+        cfg.merge_toml(Path::new("anvil.toml"), "[output]\nverbosity = 4\ncolor = false\n[build]\njobs = 2\ncflags = \"-O2\"\n[bogus]\nkey = 1\n").expect("Failed merging file");
+        cfg.merge_env([("ANVIL_VERBOSITY".to_string(), "5".to_string()), ("HOME".to_string(), "/".to_string())]).expect("Failed merging env");
+        cfg.merge_matches(&args, &matches, ValueSource::CommandLine);
+        apply_config(&mut args, &cfg);
+
+        assert_eq!(args.verbose, 5);
+        assert!(args.no_color);
+        assert_eq!(args.jobs, Some(8));
+        assert_eq!(args.cflags.as_deref(), Some("-O2"));
+        assert_eq!(cfg.get("output.verbosity").map(|e| e.origin.clone()), Some(ConfigOrigin::Env("ANVIL_VERBOSITY")));
+        assert_eq!(cfg.get("output.color").map(|e| e.origin.clone()), Some(ConfigOrigin::File(PathBuf::from("anvil.toml"))));
+        assert_eq!(cfg.get("build.jobs").map(|e| e.origin.clone()), Some(ConfigOrigin::CommandLine));
+        assert_eq!(cfg.get("build.amboso_dir").map(|e| e.origin.clone()), Some(ConfigOrigin::Default));
+        assert_eq!(cfg.warnings.len(), 1);
+
+        assert!(cfg.merge_toml(Path::new("anvil.toml"), "[build]\njobs = \"many\"\n").is_err());
+        assert!(cfg.merge_env([("ANVIL_COLOR".to_string(), "maybe".to_string())]).is_err());
+    }
+
+    #[test]
+    fn test_set_config_value() {
+        let dir = TempDir::new("config");
+        let path = dir.join(ANVIL_PROJECT_CONF_NAME);
+        fs::write(&path, "# build settings\n[build]\njobs = \"many\"\n").expect("Failed writing config");
+
+        assert!(matches!(set_config_value(&path, "bogus.key", "1"), Err(AnvilError::Usage(_))));
+        assert!(matches!(set_config_value(&path, "build.jobs", "many"), Err(AnvilError::Usage(_))));
+        set_config_value(&path, "build.jobs", "4").expect("Failed setting build.jobs");
+        assert_eq!(fs::read_to_string(&path).expect("Failed reading config"), "# build settings\n[build]\njobs = 4\n");
+    }
+}
@@ -14,9 +14,16 @@
 use clap::{Parser, Subcommand};
 use std::path::{Path, PathBuf};
 use std::collections::BTreeMap;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::env;
-use crate::ops::{do_build, do_run, do_delete, do_query, gen_header};
+use crate::ops::{do_build, do_run, do_delete, do_query, gen_header, delete_test_records};
+use crate::error::{AnvilError, error_chain};
+use crate::stego::parse_stego_str;
+use crate::semver::{SemVer, lenient_compare};
+use crate::query::{resolve_tag_query, TAG_QUERY_ALL};
+use crate::header::{HeaderLang, HeaderOptions, HEADER_LANG_NAMES, HEADER_EXTRA_FIELDS};
+use crate::graph::{GraphFormat, GRAPH_FORMAT_NAMES};
+use crate::tag::discover_git_tags;
 
 #[cfg(feature = "anvilPy")]
 use crate::anvil_py::{parse_pyproject_toml, AnvilPyEnv};
@@ -24,7 +31,6 @@ use crate::anvil_py::{parse_pyproject_toml, AnvilPyEnv};
 #[cfg(feature = "anvilCustom")]
 use crate::anvil_custom::{parse_anvilcustom_toml, AnvilCustomEnv};
 
-use crate::exit;
 use std::cmp::Ordering;
 use std::fs::{self, File};
 use git2::{Repository, Status, RepositoryInitOptions, ErrorCode};
@@ -35,7 +41,6 @@ use std::io::{Write, BufReader, BufRead};
 use crate::utils::{
     print_grouped_args,
 };
-use regex::Regex;
 use std::fmt;
 use std::io;
 
@@ -66,10 +71,16 @@ pub const MIN_AMBOSO_V_CUSTKERN: &str = "2.1.0";
 pub const MIN_AMBOSO_V_DENY_ANVILCUST: &str = "2.0.9";
 pub const MIN_AMBOSO_V_CHECK_DETACHED: &str = "2.0.11";
 pub const ANVIL_INTERPRETER_TAG_REGEX: &str = "stego.lock$";
+pub const DEFAULT_DISCOVER_PATTERN: &str = "*";
 pub const ANVIL_DEFAULT_CONF_PATH: &str = ".anvil/anvil.toml";
 pub const RULELINE_MARK_CHAR: char = '\t';
-pub const RULE_REGEX: &str = "^([[:graph:]^:]+:){1,1}([[:space:]]*[[:graph:]]*)*$";
 pub const RULEWARN_REGEX: &str = "^ +";
+pub const ANVIL_EXIT_SUCCESS: u8 = 0;
+pub const ANVIL_EXIT_FAILURE: u8 = 1;
+pub const ANVIL_EXIT_USAGE: u8 = 2;
+pub const ANVIL_EXIT_CONFIG: u8 = 3;
+pub const ANVIL_EXIT_BUILD: u8 = 4;
+pub const ANVIL_EXIT_TEST: u8 = 5;
 
 pub enum CutDirection {
     Before,
@@ -129,10 +140,22 @@ pub struct Args {
     #[arg(short = 'G', long, value_name = "C_HEADER_DIR", conflicts_with_all(["base","test","testmacro", "linter"]))]
     pub gen_c_header: Option<PathBuf>,
 
+    /// Language for the -G header: c, python, rust, go, sh, json, cmake or c++
+    #[arg(long, value_name = "LANG", value_parser = HEADER_LANG_NAMES.to_vec())]
+    pub lang: Option<String>,
+
     /// Act as stego linter for passed file
     #[arg(short = 'x', long, value_name = "LINT_TARGET", conflicts_with_all(["gen_c_header", "base", "test", "testmacro"]))]
     pub linter: Option<PathBuf>,
 
+    /// Output format for -x on a Makefile: json prints the parsed Makefile
+    #[arg(long, value_name = "FORMAT", value_parser = ["text", "json"], requires = "linter")]
+    pub format: Option<String>,
+
+    /// Prints the dependency graph of the Makefile passed to -x, with mtimes and out of date targets
+    #[arg(long, value_name = "GRAPH_FORMAT", value_parser = GRAPH_FORMAT_NAMES.to_vec(), requires = "linter", conflicts_with = "format")]
+    pub graph: Option<String>,
+
     /// Specify test mode
     #[arg(short = 'T', long, default_value = "false", conflicts_with_all(["base", "git", "testmacro", "gen_c_header", "linter", "init"]))]
     pub test: bool,
@@ -224,6 +247,14 @@ pub struct Args {
     #[arg(short = 'R', long, default_value = "false")]
     pub no_rebuild: bool,
 
+    /// Build with the built-in make executor, also used when make is missing
+    #[arg(long, default_value = "false")]
+    pub builtin_make: bool,
+
+    /// Directory relative paths start from, instead of the working directory
+    #[arg(skip)]
+    pub work_dir: Option<PathBuf>,
+
     /// Pass configuration argument
     #[arg(short = 'C', long, value_name = "CONFIG_ARG", allow_hyphen_values = true)]
     pub config: Option<String>,
@@ -236,6 +267,22 @@ pub struct Args {
     #[arg(short = 'e', long, default_value = "false")]
     pub strict: bool,
 
+    /// Pass jobs to make
+    #[arg(short = 'j', long, value_name = "JOBS")]
+    pub jobs: Option<u32>,
+
+    /// Kill tests running longer than the passed seconds
+    #[arg(long, value_name = "SECS")]
+    pub test_timeout: Option<u32>,
+
+    /// Use the commit time as gen time for generated headers. SOURCE_DATE_EPOCH still takes precedence
+    #[arg(long, default_value = "false")]
+    pub reproducible: bool,
+
+    /// Discover git mode tags from the repo, even without [versions] discover
+    #[arg(long, default_value = "false", conflicts_with_all(["strict"]))]
+    pub from_git: bool,
+
     //TODO: Handle -C flag for passing start time for recursive calls
 
     /// Subcommand
@@ -263,9 +310,11 @@ pub enum AmbosoLintMode {
     NajloFull,
     NajloDebug,
     NajloQuiet,
+    NajloJson,
+    NajloGraph(GraphFormat),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum AnvilKern {
     AmbosoC,
     AnvilPy,
@@ -293,6 +342,9 @@ pub struct AmbosoEnv {
     /// Path to stego.lock dir
     pub stego_dir: Option<PathBuf>,
 
+    /// Dir that paths from wd start from, the working directory when None
+    pub work_dir: Option<PathBuf>,
+
     /// Path to amboso dir from wd
     pub amboso_dir: Option<PathBuf>,
 
@@ -329,6 +381,27 @@ pub struct AmbosoEnv {
     /// Table with supported versions for git mode and description
     pub gitmode_versions_table: BTreeMap<SemVerKey, String>,
 
+    /// Pattern for git tags to discover as git mode versions
+    pub discover_pattern: Option<String>,
+
+    /// Git tag names for discovered versions, when they differ from the version
+    pub git_tag_names: BTreeMap<SemVerKey, String>,
+
+    /// Time before a test is killed
+    pub test_timeout: Option<Duration>,
+
+    /// Language for generated headers, when not the kern default
+    pub header_lang: Option<HeaderLang>,
+
+    /// Template for generated headers, replacing the built-in ones
+    pub header_template: Option<PathBuf>,
+
+    /// Use the commit time as gen time for generated headers
+    pub header_reproducible: bool,
+
+    /// Optional fields to include in generated headers
+    pub header_fields: Vec<String>,
+
     /// String used for configure command argument
     pub configure_arg: String,
 
@@ -380,6 +453,28 @@ pub struct AmbosoEnv {
     pub anvilcustom_env: Option<AnvilCustomEnv>,
 }
 
+impl AmbosoEnv {
+    /// Returns the options for generated headers, as set in stego.lock.
+    pub fn header_options(&self) -> HeaderOptions {
+        HeaderOptions {
+            lang: self.header_lang,
+            template: self.header_template.clone(),
+            reproducible: self.header_reproducible,
+            fields: self.header_fields.clone(),
+        }
+    }
+
+    /// Returns the git tag name for git mode version `tag`.
+    pub fn git_tag_name(&self, tag: &str) -> String {
+        self.git_tag_names.get(&SemVerKey(tag.to_string())).cloned().unwrap_or_else(|| tag.to_string())
+    }
+
+    /// Returns the dir that paths from wd start from.
+    pub fn work_dir(&self) -> &Path {
+        self.work_dir.as_deref().unwrap_or(Path::new("."))
+    }
+}
+
 pub struct AmbosoConf {
     /// Anvil kern
     pub anvil_kern: AnvilKern,
@@ -413,10 +508,189 @@ pub enum Commands {
     },
     /// Prints invil version
     Version,
+    /// Checks stego.lock for common mistakes
+    Lint {
+        /// treats findings of the passed level as errors
+        #[arg(long, value_name = "LEVEL", value_parser = ["warnings"])]
+        deny: Option<String>,
+        /// applies safe fixes before reporting
+        #[arg(long, default_value = "false")]
+        fix: bool,
+    },
+    /// Rewrites stego.lock in the canonical layout
+    Fmt {
+        /// only checks if stego.lock is formatted
+        #[arg(long, default_value = "false")]
+        check: bool,
+    },
+    /// Converts a legacy or older stego.lock to the current format
+    Migrate {
+        /// only prints the diff
+        #[arg(long, default_value = "false")]
+        dry_run: bool,
+    },
+    /// Cuts the next release: bumps versions, commits and tags
+    Release {
+        #[arg(value_parser = ["patch", "minor", "major"])]
+        level: String,
+        /// appends a pre-release identifier, like rc.1
+        #[arg(long)]
+        pre: Option<String>,
+        /// sets the version description, defaults to "Release <version>"
+        #[arg(short, long)]
+        message: Option<String>,
+        /// regenerates the header for the new tag in the passed dir, defaults to the -G dir
+        #[arg(long, value_name = "DIR")]
+        header: Option<PathBuf>,
+        /// builds the new tag
+        #[arg(short, long)]
+        build: bool,
+        /// only prints the next version
+        #[arg(long, default_value = "false")]
+        dry_run: bool,
+    },
+    /// Manages versions in stego.lock and their git tags
+    Tag {
+        #[command(subcommand)]
+        op: TagCommands,
+    },
+    /// Inspects and edits layered config
+    Config {
+        #[command(subcommand)]
+        op: ConfigCommands,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigCommands {
+    /// Prints the value for a key
+    Get {
+        key: String,
+        /// prints where the value was set
+        #[arg(long, default_value = "false")]
+        show_origin: bool,
+    },
+    /// Sets a key in the project config, next to stego.lock
+    Set {
+        key: String,
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+        /// sets the key in the user config
+        #[arg(long, default_value = "false", conflicts_with = "system")]
+        global: bool,
+        /// sets the key in the system config
+        #[arg(long, default_value = "false")]
+        system: bool,
+    },
+    /// Prints all set keys
+    List {
+        /// prints where each value was set
+        #[arg(long, default_value = "false")]
+        show_origin: bool,
+    },
 }
 
-pub fn handle_amboso_env(env: &mut AmbosoEnv, args: &mut Args) {
-    handle_subcommand(args, env);
+#[derive(Subcommand, Debug, Clone)]
+pub enum TagCommands {
+    /// Adds a version, creating its git tag or base mode dir
+    Add {
+        version: String,
+        description: String,
+        /// adds a base mode version
+        #[arg(long, default_value = "false")]
+        base: bool,
+    },
+    /// Removes a version and its git tag
+    Rm {
+        version: String,
+        /// removes a base mode version
+        #[arg(long, default_value = "false")]
+        base: bool,
+    },
+    /// Renames a version, moving its git tag or base mode dir
+    Rename {
+        old: String,
+        new: String,
+        /// renames a base mode version
+        #[arg(long, default_value = "false")]
+        base: bool,
+    },
+}
+
+/// Records `code` as the result of the current invocation, unless an earlier operation already failed.
+fn set_failure_code(exit_code: &mut ExitCode, code: u8) {
+    if *exit_code == ExitCode::SUCCESS && code != ANVIL_EXIT_SUCCESS {
+        *exit_code = ExitCode::from(code);
+    }
+}
+
+/// Returns the exit code for an error returned by [check_passed_args].
+pub fn check_exit_code(err: &AnvilError) -> u8 {
+    match err.root() {
+        AnvilError::UnknownTag(_) | AnvilError::Usage(_) => ANVIL_EXIT_USAGE,
+        AnvilError::StegoParse { .. } | AnvilError::MakefileParse { .. } | AnvilError::Config(_) | AnvilError::Io { .. } => ANVIL_EXIT_CONFIG,
+        _ => ANVIL_EXIT_FAILURE,
+    }
+}
+
+/// Returns the exit code for a failed build: [ANVIL_EXIT_BUILD] for a failed build step, otherwise the one from [check_exit_code].
+fn build_exit_code(err: &AnvilError) -> u8 {
+    match err.root() {
+        AnvilError::BuildStepFailed { .. } => ANVIL_EXIT_BUILD,
+        _ => check_exit_code(err),
+    }
+}
+
+/// Resolves the tag query in `args.tag` against the versions table for the current mode.
+///
+/// Returns every matching tag, and sets `args.tag` to the highest one, which is used by ops taking a single tag.
+fn resolve_args_tag_query(env: &AmbosoEnv, args: &mut Args) -> Result<Vec<String>, AnvilError> {
+    let table = match env.run_mode {
+        Some(AmbosoMode::GitMode) => &env.gitmode_versions_table,
+        Some(AmbosoMode::BaseMode) => &env.basemode_versions_table,
+        _ => return Ok(args.tag.iter().cloned().collect()),
+    };
+    match args.tag {
+        Some(ref query) => {
+            let tags = resolve_tag_query(query, table)?;
+            args.tag = tags.last().cloned();
+            Ok(tags)
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Returns a copy of `args` for each of `tags`, or just `args` when there is at most one tag.
+fn args_for_tags(args: &Args, tags: &[String]) -> Vec<Args> {
+    if tags.len() <= 1 {
+        return vec![args.clone()];
+    }
+    tags.iter().map(|tag| {
+        let mut tag_args = args.clone();
+        tag_args.tag = Some(tag.clone());
+        tag_args
+    }).collect()
+}
+
+pub fn handle_amboso_env(env: &mut AmbosoEnv, args: &mut Args) -> ExitCode {
+    if let Some(ec) = handle_subcommand(args, env) {
+        return ec;
+    }
+    let mut exit_code = ExitCode::SUCCESS;
+    if matches!(env.run_mode, Some(AmbosoMode::TestMode)) && args.tag.as_deref() == Some(TAG_QUERY_ALL) {
+        debug!("Running all tests for query {{{TAG_QUERY_ALL}}}");
+        env.run_mode = Some(AmbosoMode::TestMacro);
+        args.test = false;
+        args.testmacro = true;
+        args.tag = None;
+    }
+    let query_tags = match resolve_args_tag_query(env, args) {
+        Ok(tags) => tags,
+        Err(e) => {
+            error!("Failed resolving tag query. Err: {e}");
+            return ExitCode::from(ANVIL_EXIT_USAGE);
+        }
+    };
     match env.run_mode {
         Some(ref runmode) => {
             info!("Runmode: {:?}", runmode);
@@ -424,7 +698,7 @@ pub fn handle_amboso_env(env: &mut AmbosoEnv, args: &mut Args) {
                     AmbosoMode::TestMode | AmbosoMode::TestMacro => {
                         if !env.support_testmode {
                             error!("Test mode not supported for this project.");
-                            return
+                            return ExitCode::from(ANVIL_EXIT_CONFIG);
                         } else {
                             info!("Supported tests: {}", env.bonetests_table.len() + env.kulpotests_table.len());
                             for (k,v) in env.bonetests_table.iter() {
@@ -454,7 +728,14 @@ pub fn handle_amboso_env(env: &mut AmbosoEnv, args: &mut Args) {
                     AmbosoMode::TestMacro => {
                         // Listing all tag names is done later, in do_query
                     }
-                    _ => todo!("List flag for {:?} mode", env.run_mode),
+                    AmbosoMode::TestMode => {
+                        for k in env.bonetests_table.keys() {
+                            info!("Test: {{{}}}", k);
+                        }
+                        for k in env.kulpotests_table.keys() {
+                            info!("Error test: {{{}}}", k);
+                        }
+                    }
                 }
             } else if args.list_all {
                 for (k, v) in env.versions_table.iter() {
@@ -463,35 +744,44 @@ pub fn handle_amboso_env(env: &mut AmbosoEnv, args: &mut Args) {
             }
 
             if env.do_build {
-                let build_res = do_build(env,args);
-                match build_res {
-                    Ok(s) => {
-                        trace!("{}", s);
-                    }
-                    Err(e) => {
-                        warn!("do_build() failed in handle_amboso_env(). Err: {}", e);
+                for tag_args in args_for_tags(args, &query_tags) {
+                    let build_res = do_build(env,&tag_args);
+                    match build_res {
+                        Ok(s) => {
+                            trace!("{}", s);
+                        }
+                        Err(e) => {
+                            warn!("do_build() failed in handle_amboso_env(). Err: {}", error_chain(&e));
+                            set_failure_code(&mut exit_code, build_exit_code(&e));
+                        }
                     }
                 }
             }
             if env.do_run {
                 let run_res = do_run(env,args);
                 match run_res {
-                    Ok(s) => {
-                        trace!("{}", s);
+                    Ok(ec) => {
+                        trace!("Run exited with status: {}", ec);
+                        // Exit codes are truncated to the low byte, like a shell would
+                        set_failure_code(&mut exit_code, (ec & 0xff) as u8);
                     }
                     Err(e) => {
                         warn!("do_run() failed in handle_amboso_env(). Err: {}", e);
+                        set_failure_code(&mut exit_code, ANVIL_EXIT_FAILURE);
                     }
                 }
             }
             if env.do_delete {
-                let delete_res = do_delete(env,args);
-                match delete_res {
-                    Ok(s) => {
-                        trace!("{}", s);
-                    }
-                    Err(e) => {
-                        warn!("do_delete() failed in handle_amboso_env(). Err: {}", e);
+                for tag_args in args_for_tags(args, &query_tags) {
+                    let delete_res = do_delete(env,&tag_args);
+                    match delete_res {
+                        Ok(s) => {
+                            trace!("{}", s);
+                        }
+                        Err(e) => {
+                            warn!("do_delete() failed in handle_amboso_env(). Err: {}", e);
+                            set_failure_code(&mut exit_code, ANVIL_EXIT_FAILURE);
+                        }
                     }
                 }
             }
@@ -508,7 +798,8 @@ pub fn handle_amboso_env(env: &mut AmbosoEnv, args: &mut Args) {
                                     trace!("{}", s);
                                 }
                                 Err(e) => {
-                                    warn!("do_init(): Build failed for tag {{{}}}. Err: {}", tag,e);
+                                    warn!("do_init(): Build failed for tag {{{}}}. Err: {}", tag, error_chain(&e));
+                                    set_failure_code(&mut exit_code, build_exit_code(&e));
                                 }
                             }
                         }
@@ -524,16 +815,16 @@ pub fn handle_amboso_env(env: &mut AmbosoEnv, args: &mut Args) {
                                     trace!("{}", s);
                                 }
                                 Err(e) => {
-                                    warn!("do_init(): Build failed for tag {{{}}}. Err: {}", tag,e);
+                                    warn!("do_init(): Build failed for tag {{{}}}. Err: {}", tag, error_chain(&e));
+                                    set_failure_code(&mut exit_code, build_exit_code(&e));
                                 }
                             }
                         }
                     }
-                    AmbosoMode::TestMode => {
-                        todo!("Init op for test mode");
-                    }
-                    AmbosoMode::TestMacro => {
-                        todo!("Init op for test macro mode");
+                    AmbosoMode::TestMode | AmbosoMode::TestMacro => {
+                        // Rejected by validate_args(), use -b to record tests
+                        error!("Init op is not supported for {:?}", runmode);
+                        set_failure_code(&mut exit_code, ANVIL_EXIT_USAGE);
                     }
                 }
             }
@@ -551,6 +842,7 @@ pub fn handle_amboso_env(env: &mut AmbosoEnv, args: &mut Args) {
                                 }
                                 Err(e) => {
                                     warn!("do_purge(): Delete failed for tag {{{}}}. Err: {}", tag, e);
+                                    set_failure_code(&mut exit_code, ANVIL_EXIT_FAILURE);
                                 }
                             }
                         }
@@ -567,15 +859,56 @@ pub fn handle_amboso_env(env: &mut AmbosoEnv, args: &mut Args) {
                                 }
                                 Err(e) => {
                                     warn!("do_purge(): Delete failed for tag {{{}}}. Err: {}", tag, e);
+                                    set_failure_code(&mut exit_code, ANVIL_EXIT_FAILURE);
                                 }
                             }
                         }
                     }
                     AmbosoMode::TestMode => {
-                        todo!("Purge op for test mode");
+                        debug!("Doing purge for test mode");
+                        match args.tag {
+                            Some(ref q) => {
+                                match env.bonetests_table.get(q).or(env.kulpotests_table.get(q)) {
+                                    Some(test_path) => {
+                                        let delete_res = delete_test_records(test_path);
+                                        match delete_res {
+                                            Ok(s) => {
+                                                trace!("{}", s);
+                                            }
+                                            Err(e) => {
+                                                warn!("do_purge(): Delete failed for test {{{}}}. Err: {}", q, e);
+                                                set_failure_code(&mut exit_code, ANVIL_EXIT_FAILURE);
+                                            }
+                                        }
+                                    }
+                                    None => {
+                                        error!("Not a valid test: {{{}}}", q);
+                                        set_failure_code(&mut exit_code, ANVIL_EXIT_USAGE);
+                                    }
+                                }
+                            }
+                            None => {
+                                error!("Missing test name for purge op in test mode. Use -t to purge all test records.");
+                                set_failure_code(&mut exit_code, ANVIL_EXIT_USAGE);
+                            }
+                        }
+                        return exit_code;
                     }
                     AmbosoMode::TestMacro => {
-                        todo!("Purge op for test macro mode");
+                        debug!("Doing purge for test macro mode");
+                        for (test, test_path) in env.bonetests_table.iter().chain(env.kulpotests_table.iter()) {
+                            let delete_res = delete_test_records(test_path);
+                            match delete_res {
+                                Ok(s) => {
+                                    trace!("{}", s);
+                                }
+                                Err(e) => {
+                                    warn!("do_purge(): Delete failed for test {{{}}}. Err: {}", test, e);
+                                    set_failure_code(&mut exit_code, ANVIL_EXIT_FAILURE);
+                                }
+                            }
+                        }
+                        return exit_code;
                     }
                 }
             }
@@ -592,7 +925,21 @@ pub fn handle_amboso_env(env: &mut AmbosoEnv, args: &mut Args) {
                         }
                         Err(e) => {
                             error!("do_query() failed in handle_amboso_env(). Err: {}", e);
-                            exit(1);
+                            match runmode {
+                                AmbosoMode::TestMode | AmbosoMode::TestMacro => {
+                                    set_failure_code(&mut exit_code, ANVIL_EXIT_TEST);
+                                }
+                                AmbosoMode::GitMode | AmbosoMode::BaseMode => {
+                                    match e.root() {
+                                        AnvilError::BuildStepFailed { .. } => {
+                                            set_failure_code(&mut exit_code, ANVIL_EXIT_BUILD);
+                                        }
+                                        _ => {
+                                            set_failure_code(&mut exit_code, ANVIL_EXIT_FAILURE);
+                                        }
+                                    }
+                                }
+                            }
                         }
                     }
             /*
@@ -605,14 +952,19 @@ pub fn handle_amboso_env(env: &mut AmbosoEnv, args: &mut Args) {
                 }
             }
             */
+            exit_code
         }
         None => {
             error!("Invalid: None env.run_mode");
+            ExitCode::from(ANVIL_EXIT_USAGE)
         }
     }
 }
 
-fn handle_subcommand(args: &mut Args, env: &mut AmbosoEnv) {
+/// Handles the subcommands that are run in place of the op flags.
+///
+/// Returns the exit code for the invocation when a subcommand was handled, `None` otherwise.
+fn handle_subcommand(args: &mut Args, env: &mut AmbosoEnv) -> Option<ExitCode> {
     match &args.command {
         Some(Commands::Test { list, query, build}) => {
             if *build {
@@ -621,7 +973,7 @@ fn handle_subcommand(args: &mut Args, env: &mut AmbosoEnv) {
             if *list {
                 args.list = true;
             }
-            if let Some(q) = query {
+            if let Some(q) = query.as_ref().filter(|q| q.as_str() != TAG_QUERY_ALL) {
                 println!("query: {}", q);
                 args.test = true;
                 args.tag = Some(q.to_string());
@@ -630,173 +982,111 @@ fn handle_subcommand(args: &mut Args, env: &mut AmbosoEnv) {
                 args.testmacro = true;
                 env.run_mode = Some(AmbosoMode::TestMacro);
             }
+            if !env.support_testmode {
+                error!("Test mode not supported for this project.");
+                return Some(ExitCode::from(ANVIL_EXIT_CONFIG));
+            }
             let query_res = do_query(env,args);
             match query_res {
                 Ok(s) => {
                     trace!("{}", s);
-                    exit(1);
+                    Some(ExitCode::SUCCESS)
                 }
                 Err(e) => {
                     error!("do_query() failed in handle_amboso_env(). Err: {}", e);
-                    exit(1);
+                    Some(ExitCode::from(ANVIL_EXIT_TEST))
                 }
             }
         }
         Some(Commands::Build) => {
-            match env.run_mode {
+            let latest_tag = match env.run_mode {
                 Some(AmbosoMode::GitMode) => {
-                    let latest_tag = env.gitmode_versions_table.last_key_value(); //.max_by(|a, b| semver_compare(a.unwrap(), b));
-                    match latest_tag {
-                        Some(lt) => {
-                            info!("Latest tag: {}", lt.0);
-                            args.tag = Some(lt.0.to_string());
-                            let build_res = do_build(env, args);
-                            match build_res {
-                                Ok(s) => {
-                                    info!("Done quick build command. Res: {s}");
-                                    exit(0);
-                                }
-                                Err(e) => {
-                                    error!("Failed quick build command. Err: {e}");
-                                    exit(1);
-                                }
-                            }
-                        }
-                        None => {
-                            error!("Could not find latest tag");
-                            exit(1);
-                        }
-                    }
+                    env.gitmode_versions_table.last_key_value() //.max_by(|a, b| semver_compare(a.unwrap(), b));
                 }
                 Some(AmbosoMode::BaseMode) => {
-                    let latest_tag = env.basemode_versions_table.last_key_value(); //keys().max_by(|a, b| semver_compare(a, b));
-                    match latest_tag {
-                        Some(lt) => {
-                            info!("Latest tag: {}", lt.0);
-                            args.tag = Some(lt.0.to_string());
-                            let build_res = do_build(env, args);
-                            match build_res {
-                                Ok(s) => {
-                                    info!("Done quick build command. Res: {s}");
-                                    exit(0);
-                                }
-                                Err(e) => {
-                                    error!("Failed quick build command. Err: {e}");
-                                    exit(1);
-                                }
-                            }
+                    env.basemode_versions_table.last_key_value() //keys().max_by(|a, b| semver_compare(a, b));
+                }
+                Some(AmbosoMode::TestMode) | Some(AmbosoMode::TestMacro) => {
+                    // Rejected by validate_args()
+                    error!("Build command is not supported in test modes");
+                    return Some(ExitCode::from(ANVIL_EXIT_USAGE));
+                }
+                None => {
+                    error!("Missing runmode for build command");
+                    return Some(ExitCode::from(ANVIL_EXIT_USAGE));
+                }
+            };
+            match latest_tag {
+                Some(lt) => {
+                    info!("Latest tag: {}", lt.0);
+                    args.tag = Some(lt.0.to_string());
+                    let build_res = do_build(env, args);
+                    match build_res {
+                        Ok(s) => {
+                            info!("Done quick build command. Res: {s}");
+                            Some(ExitCode::SUCCESS)
                         }
-                        None => {
-                            error!("Could not find latest tag");
-                            exit(1);
+                        Err(e) => {
+                            error!("Failed quick build command. Err: {}", error_chain(&e));
+                            Some(ExitCode::from(build_exit_code(&e)))
                         }
                     }
                 }
-                Some(AmbosoMode::TestMode) => {
-                    todo!("Build command for test mode")
-                }
-                Some(AmbosoMode::TestMacro) => {
-                    todo!("Build command for test macro")
-                }
                 None => {
-                    error!("Missing runmode for build command");
-                    exit(0);
+                    error!("Could not find latest tag");
+                    Some(ExitCode::from(ANVIL_EXIT_CONFIG))
                 }
             }
         }
-        _ => {}
+        _ => None
     }
 }
 
-fn parse_version_core(version: &str) -> Vec<u64> {
-    version
-        .split('.')
-        .filter_map(|s| s.parse::<u64>().ok())
-        .collect()
-}
-
-fn parse_version_parts(version: &str) -> (Vec<u64>, String, String) {
-    let parts: Vec<&str> = version.splitn(2, '-').collect();
-    let version_core = parse_version_core(parts[0]);
-
-    let (pre_release, build) = if parts.len() == 2 {
-        let mut subparts = parts[1].splitn(2, '+');
-        let pre_release = subparts.next().unwrap_or_default();
-        let build = subparts.next().unwrap_or_default();
-        (pre_release.to_string(), build.to_string())
-    } else {
-        (String::new(), String::new())
-    };
-
-    (version_core, pre_release, build)
-}
-
+/// Compares two versions by SemVer 2.0.0 precedence, ignoring build metadata.
+///
+/// Strings that are not valid versions fall back to `lenient_compare`, and sort after valid ones.
 pub fn semver_compare(v1: &str, v2: &str) -> Ordering {
-    let (version_core1, pre_release1, build1) = parse_version_parts(v1);
-    let (version_core2, pre_release2, build2) = parse_version_parts(v2);
-
-    for (a, b) in version_core1.iter().zip(version_core2.iter()) {
-        match a.cmp(b) {
-            Ordering::Equal => continue,
-            other => return other,
-        }
-    }
-
-    // If version cores are equal, compare pre-release metadata
-    match (pre_release1.is_empty(), pre_release2.is_empty()) {
-        (true, true) => {} // Both are empty, continue
-        (true, false) => return Ordering::Greater, // v1 is normal, v2 has pre-release
-        (false, true) => return Ordering::Less, // v1 has pre-release, v2 is normal
-        (false, false) => match pre_release1.cmp(&pre_release2) {
-            Ordering::Equal => {}
-            other => return other,
-        },
+    match (SemVer::parse(v1), SemVer::parse(v2)) {
+        (Some(a), Some(b)) => a.precedence(&b),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => lenient_compare(v1, v2),
     }
+}
 
-    // If pre-release metadata is equal or both are empty, compare build metadata
-    match build1.cmp(&build2) {
-        Ordering::Equal => {}
-        other => return other,
+/// Returns true if `repo` has no uncommitted changes in the index or working directory.
+pub fn git_repo_status_clean(repo: &Repository) -> Result<bool,git2::Error> {
+    for entry in repo.statuses(None)?.iter() {
+        let status = entry.status();
+        if status.intersects(Status::WT_MODIFIED | Status::INDEX_MODIFIED | Status::INDEX_NEW) {
+            // There are uncommitted changes
+            info!("Uncommitted changes:");
+            info!("  {}", entry.path().unwrap_or_default());
+            return Ok(false);
+        }
+        if status.contains(Status::WT_NEW) {
+            // Untracked files are ignored, after 0.2.14,
+            // to behave like amboso.
+            debug!("Untracked entry found:");
+            debug!("  {}", entry.path().unwrap_or_default());
+        }
     }
-
-    // If everything is equal so far, compare lengths
-    version_core1.len().cmp(&version_core2.len())
+    // No uncommitted changes
+    Ok(true)
 }
 
-pub fn is_git_repo_clean(path: &PathBuf, args: &Args) -> Result<bool, String> {
+pub fn is_git_repo_clean(path: &PathBuf, args: &Args) -> Result<bool,AnvilError> {
     // Open the repository
     let repo = Repository::discover(path);
 
     match repo {
         Ok(r) => {
             // Check if there are any modified files in the working directory
-            let statuses = r.statuses(None);
-            match statuses {
-                Ok(s) => {
-                    for entry in s.iter() {
-                        match entry.status() {
-                            Status::WT_MODIFIED | Status::INDEX_MODIFIED | Status::INDEX_NEW => {
-                                // There are uncommitted changes
-                                info!("Uncommitted changes:");
-                                info!("  {}", entry.path().unwrap());
-                                return Ok(false);
-                            }
-                            Status::WT_NEW => {
-                                // Untracked files are ignored, after 0.2.14,
-                                // to behave like amboso.
-                                debug!("Untracked entry found:");
-                                debug!("  {}", entry.path().unwrap());
-                            }
-                            _ => (),
-                        }
-                    }
-
-                    // No uncommitted changes
-                    Ok(true)
-                }
+            match git_repo_status_clean(&r) {
+                Ok(clean) => Ok(clean),
                 Err(e) => {
                     error!("Failed getting repo statuses. Err: {e}");
-                    Err("Failed repo.statuses()".to_string())
+                    Err(e.into())
                 }
             }
         }
@@ -811,13 +1101,13 @@ pub fn is_git_repo_clean(path: &PathBuf, args: &Args) -> Result<bool, String> {
                     debug!("is_git_repo_clean():    Strict behaviour, quitting on missing repo");
                 }
             }
-            Err("Failed repo discovery".to_string())
+            Err(e.into())
         }
     }
 }
 
 
-fn check_stego_file(stego_path: &PathBuf, amboso_bin_path: &Path, builds_dir: &PathBuf, format: StegoFormat) -> Result<AmbosoEnv,String> {
+fn check_stego_file(stego_path: &PathBuf, amboso_bin_path: &Path, builds_dir: &PathBuf, work_dir: Option<&Path>, format: StegoFormat) -> Result<AmbosoEnv,AnvilError> {
     if stego_path.exists() {
         trace!("Found {}", stego_path.display());
         let res = match format {
@@ -827,6 +1117,9 @@ fn check_stego_file(stego_path: &PathBuf, amboso_bin_path: &Path, builds_dir: &P
         match res {
             Ok(mut a) => {
                 //trace!("Stego contents: {{{:#?}}}", a);
+                if let Some(w) = work_dir {
+                    a.tests_dir = a.tests_dir.map(|d| w.join(d));
+                }
                 if a.support_testmode {
                     match a.bonetests_dir {
                         Some(ref b) => {
@@ -834,7 +1127,7 @@ fn check_stego_file(stego_path: &PathBuf, amboso_bin_path: &Path, builds_dir: &P
                         }
                         None => {
                             error!("Missing bonetests_dir value");
-                            return Err("Missing bonetests_dir value".to_string());
+                            return Err(AnvilError::Config("Missing bonetests_dir value".to_string()));
                         }
 
                     };
@@ -844,7 +1137,7 @@ fn check_stego_file(stego_path: &PathBuf, amboso_bin_path: &Path, builds_dir: &P
                         }
                         None => {
                             error!("Missing kulpotests_dir value");
-                            return Err("Missing kulpotests_dir value".to_string());
+                            return Err(AnvilError::Config("Missing kulpotests_dir value".to_string()));
                         }
 
                     };
@@ -854,7 +1147,7 @@ fn check_stego_file(stego_path: &PathBuf, amboso_bin_path: &Path, builds_dir: &P
                         }
                         None => {
                             error!("Missing tests_dir value");
-                            return Err("Missing tests_dir value".to_string());
+                            return Err(AnvilError::Config("Missing tests_dir value".to_string()));
                         }
                     }
                     if a.support_testmode {
@@ -943,7 +1236,7 @@ fn check_stego_file(stego_path: &PathBuf, amboso_bin_path: &Path, builds_dir: &P
                     }
                     None => {
                         error!("Missing builds_dir value");
-                        return Err("Missing builds_dir value".to_string());
+                        return Err(AnvilError::Config("Missing builds_dir value".to_string()));
                     }
 
                 };
@@ -954,11 +1247,11 @@ fn check_stego_file(stego_path: &PathBuf, amboso_bin_path: &Path, builds_dir: &P
             }
         }
     } else {
-        Err(format!("Can't find {}.", stego_path.display()))
+        Err(AnvilError::Config(format!("Can't find {}.", stego_path.display())))
     }
 }
 
-pub fn check_amboso_dir(dir: &Path, args: &Args) -> Result<AmbosoEnv,String> {
+pub fn check_amboso_dir(dir: &Path, args: &Args) -> Result<AmbosoEnv,AnvilError> {
     if ! dir.exists() {
         if ! args.strict {
             debug!("No amboso_dir found at {{{}}}. Preparing it.", dir.display());
@@ -968,12 +1261,12 @@ pub fn check_amboso_dir(dir: &Path, args: &Args) -> Result<AmbosoEnv,String> {
                 }
                 Err(e) => {
                     error!("Failed creating amboso dir. Err: {e}");
-                    return Err("Failed creating amboso dir.".to_string());
+                    return Err(AnvilError::io(dir, e));
                 }
             }
         } else {
             debug!("check_amboso_dir():    Strict behaviour, quitting on missing amboso dir.");
-            return Err(format!("Can't find {}. Quitting.", dir.display()));
+            return Err(AnvilError::Config(format!("Can't find {}. Quitting.", dir.display())));
         }
     }
 
@@ -987,10 +1280,10 @@ pub fn check_amboso_dir(dir: &Path, args: &Args) -> Result<AmbosoEnv,String> {
             match semver_compare(&args.anvil_version.clone().unwrap(), MIN_AMBOSO_V_LEGACYPARSE) {
                 Ordering::Less => {
                     warn!("Trying to parse a legacy format stego.lock at {{{}}}", stego_path.display());
-                    check_stego_file(&stego_path, dir, &args.builds_dir.clone().expect("Failed initialising anvil_builds_dir"), StegoFormat::Legacy)
+                    check_stego_file(&stego_path, dir, &args.builds_dir.clone().expect("Failed initialising anvil_builds_dir"), args.work_dir.as_deref(), StegoFormat::Legacy)
                 }
                 Ordering::Greater | Ordering::Equal => {
-                    check_stego_file(&stego_path, dir, &args.builds_dir.clone().expect("Failed initilising anvil_builds_dir"), StegoFormat::Toml)
+                    check_stego_file(&stego_path, dir, &args.builds_dir.clone().expect("Failed initilising anvil_builds_dir"), args.work_dir.as_deref(), StegoFormat::Toml)
                 }
             }
         }
@@ -1002,7 +1295,7 @@ pub fn check_amboso_dir(dir: &Path, args: &Args) -> Result<AmbosoEnv,String> {
                     // We use the provided dir
                     stego_path = query_dir.clone();
                     stego_path.push("stego.lock");
-                    let amb_env = check_stego_file(&stego_path, dir, &args.builds_dir.clone().expect("Failed initialising anvil_builds_dir"), StegoFormat::Toml);
+                    let amb_env = check_stego_file(&stego_path, dir, &args.builds_dir.clone().expect("Failed initialising anvil_builds_dir"), args.work_dir.as_deref(), StegoFormat::Toml);
                     match amb_env {
                         Ok(a) => {
                             return Ok(a);
@@ -1029,166 +1322,50 @@ pub fn check_amboso_dir(dir: &Path, args: &Args) -> Result<AmbosoEnv,String> {
                     stego_path.push("stego.lock");
                 }
             }
-            check_stego_file(&stego_path, dir, &args.builds_dir.clone().expect("Failed initialing anvil_builds_dir"), StegoFormat::Toml)
+            check_stego_file(&stego_path, dir, &args.builds_dir.clone().expect("Failed initialing anvil_builds_dir"), args.work_dir.as_deref(), StegoFormat::Toml)
         }
     }
 }
 
-pub fn parse_invil_toml(invil_path: &PathBuf) -> Result<AmbosoConf, String> {
-    let start_time = Instant::now();
-    debug!("Checking global config file at {}", invil_path.display());
-    let invil = fs::read_to_string(invil_path);
-    match invil {
-        Ok(i) => {
-            parse_invil_tomlvalue(&i, start_time)
-        },
+/// Adds the git tags matching `env.discover_pattern` to the git mode versions.
+///
+/// Listed versions keep their description. Discovered ones use the annotated tag message.
+pub fn discover_versions(env: &mut AmbosoEnv) -> Result<(), AnvilError> {
+    let Some(ref pattern) = env.discover_pattern else {
+        return Ok(());
+    };
+    let stego_dir = env.stego_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    let repo = match Repository::discover(&stego_dir) {
+        Ok(r) => r,
         Err(e) => {
-            error!("Could not read anvil_conf.toml contents");
-            Err(e.to_string())
-        },
-    }
-}
-
-fn parse_invil_tomlvalue(invil_str: &str, start_time: Instant) -> Result<AmbosoConf, String> {
-    let toml_value = invil_str.parse::<Table>();
-    match toml_value {
-        Ok(y) => {
-            let mut anvil_conf: AmbosoConf = AmbosoConf {
-                anvil_version: EXPECTED_AMBOSO_API_LEVEL.to_string(),
-                anvil_kern: AnvilKern::AmbosoC,
-            };
-            if let Some(anvil_table) = y.get("anvil").and_then(|v| v.as_table()) {
-                if let Some(anvil_version) = anvil_table.get(ANVIL_VERSION_KEYNAME) {
-                    let anvil_v_str = anvil_version.as_str().expect("toml conversion failed");
-                    if is_semver(anvil_v_str) {
-                        if anvil_v_str.starts_with("2.0") {
-                            match anvil_v_str {
-                                "2.0.0" => {
-                                    info!("Running as 2.0, turning off extensions");
-                                    anvil_conf.anvil_kern = AnvilKern::AmbosoC;
-                                }
-                                "2.0.1" | "2.0.2" | "2.0.3" => {
-                                    info!("Running as <2.0.4");
-                                    anvil_conf.anvil_kern = AnvilKern::AmbosoC;
-                                }
-                                "2.0.4" | "2.0.5" | "2.0.6" | "2.0.7" | "2.0.8" | "2.0.9" | "2.0.10" | "2.0.11" | "2.0.12" => {
-                                    info!("Running as {{{}}}", anvil_v_str);
-                                    anvil_conf.anvil_kern = AnvilKern::AmbosoC;
-                                }
-                                _ => {
-                                    error!("Invalid anvil_version: {{{anvil_version}}}");
-                                    return Err("Invalid anvil_version".to_string());
-                                }
-                            }
-                            trace!("ANVIL_VERSION: {{{anvil_version}}}");
-                            anvil_conf.anvil_version = anvil_v_str.to_string();
-                        } else if anvil_v_str.starts_with("2.1") {
-                            trace!("Accepting preview version from stego.lock");
-                            match anvil_v_str {
-                                "2.1.0" | "2.1.1" | "2.1.2" | "2.1.3" => {
-                                    info!("Running as {{{}}}", anvil_v_str);
-                                }
-                                _ => {
-                                    error!("Invalid anvil_version: {{{anvil_version}}}");
-                                    return Err("Invalid anvil_version".to_string());
-                                }
-                            }
-                            trace!("ANVIL_VERSION: {{{anvil_version}}}");
-                            anvil_conf.anvil_version = anvil_v_str.to_string();
-                        } else {
-                            error!("Invalid anvil_version: {{{anvil_version}}}");
-                            return Err("Invalid anvil_version".to_string());
-                        }
-                    } else {
-                        error!("Invalid anvil_version: {{{}}}", anvil_v_str);
-                        return Err("Invalid anvil_version".to_string());
-                    }
-                } else {
-                    debug!("Missing ANVIL_VERSION definition.");
-                }
-
-                match semver_compare(&anvil_conf.anvil_version, MIN_AMBOSO_V_KERN) {
-                    Ordering::Less => {},
-                    Ordering::Equal | Ordering::Greater => {
-                        if let Some(anvil_kern) = anvil_table.get(ANVIL_KERN_KEYNAME) {
-                            match anvil_kern.as_str().expect("toml conversion failed") {
-                                "amboso-C" => {
-                                    anvil_conf.anvil_kern = AnvilKern::AmbosoC;
-                                }
-                                "anvilPy" => {
-                                    match semver_compare(&anvil_conf.anvil_version, MIN_AMBOSO_V_PYKERN) {
-                                        Ordering::Less => {
-                                            error!("Unsupported AnvilKern value: {{{anvil_kern}}}");
-                                            warn!("Try running as >={MIN_AMBOSO_V_PYKERN}");
-                                            warn!("Current anvil_version: {{{}}}", anvil_conf.anvil_version);
-                                            return Err("Unsupported anvil_kern".to_string());
-                                        },
-                                        Ordering::Equal | Ordering::Greater => {
-                                            match semver_compare(&anvil_conf.anvil_version, MIN_AMBOSO_V_DENY_ANVILPY) {
-                                                Ordering::Less => {
-                                                    return Err("Unsupported anvil_kern".to_string());
-                                                }
-                                                Ordering::Equal | Ordering::Greater => {
-                                                    warn!("The AnvilPy kern is experimental. Be careful.");
-                                                    anvil_conf.anvil_kern = AnvilKern::AnvilPy;
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                "custom" => {
-                                    match semver_compare(&anvil_conf.anvil_version, MIN_AMBOSO_V_CUSTKERN) {
-                                        Ordering::Less => {
-                                            error!("Unsupported AnvilKern value: {{{anvil_kern}}}");
-                                            warn!("Try running as >={MIN_AMBOSO_V_CUSTKERN}");
-                                            warn!("Current anvil_version: {{{}}}", anvil_conf.anvil_version);
-                                            return Err("Unsupported anvil_kern".to_string());
-                                        },
-                                        Ordering::Equal | Ordering::Greater => {
-                                            match semver_compare(&anvil_conf.anvil_version, MIN_AMBOSO_V_DENY_ANVILCUST) {
-                                                Ordering::Less => {
-                                                    return Err("Unsupported anvil_kern".to_string());
-                                                }
-                                                Ordering::Equal | Ordering::Greater => {
-                                                    warn!("The AnvilCustom kern is experimental. Be careful.");
-                                                    anvil_conf.anvil_kern = AnvilKern::Custom;
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                                _ => {
-                                    error!("Invalid AnvilKern value: {{{anvil_kern}}}");
-                                    return Err("Invalid anvil_kern".to_string());
-                                }
-                            }
-                        } else {
-                            debug!("Missing ANVIL_KERN definition.");
-                        }
-                    }
-                }
-            } else {
-                debug!("Missing ANVIL section.");
-            }
-            Ok(anvil_conf)
+            warn!("Can't discover git tags, no repo found for {{{}}}. Err: {e}", stego_dir.display());
+            return Ok(());
         }
-        Err(e) => {
-            let elapsed = start_time.elapsed();
-            debug!("Done parsing anvil.toml. Elapsed: {:.2?}", elapsed);
-            error!("Failed parsing {{{}}} as TOML. Err: [{e}]", invil_str);
-            Err("Failed parsing TOML".to_string())
+    };
+    let discovered = discover_git_tags(&repo, pattern)?;
+    debug!("Discovered {} git tags matching {{{pattern}}}", discovered.len());
+    for tag in discovered {
+        if tag.name != tag.version {
+            env.git_tag_names.insert(SemVerKey(tag.version.clone()), tag.name.clone());
+        }
+        if env.gitmode_versions_table.contains_key(&SemVerKey(tag.version.clone())) {
+            continue;
         }
+        trace!("Discovered tag: {{{}}}, Desc: {{{}}}", tag.name, tag.description);
+        env.versions_table.insert(SemVerKey(tag.version.clone()), tag.description.clone());
+        env.gitmode_versions_table.insert(SemVerKey(tag.version), tag.description);
     }
+    Ok(())
 }
 
-pub fn parse_stego_toml(stego_path: &PathBuf, amboso_dir_path: &Path, builds_dir: &PathBuf) -> Result<AmbosoEnv,String> {
+pub fn parse_stego_toml(stego_path: &PathBuf, amboso_dir_path: &Path, builds_dir: &PathBuf) -> Result<AmbosoEnv,AnvilError> {
     let start_time = Instant::now();
-    let stego = fs::read_to_string(stego_path).expect("Could not read {stego_path} contents");
+    let stego = fs::read_to_string(stego_path).map_err(|e| AnvilError::io(stego_path, e))?;
     //trace!("Stego contents: {{{}}}", stego);
     let mut stego_dir = stego_path.clone();
     if ! stego_dir.pop() {
         error!("Failed pop for {{{}}}", stego_dir.display());
-        return Err(format!("Unexpected stego_dir value: {{{}}}", stego_dir.display()));
+        return Err(AnvilError::Config(format!("Unexpected stego_dir value: {{{}}}", stego_dir.display())));
     }
     if stego_dir.to_str().expect("Could not stringify {stego_path}").is_empty() {
         stego_dir = PathBuf::from(".");
@@ -1197,19 +1374,20 @@ pub fn parse_stego_toml(stego_path: &PathBuf, amboso_dir_path: &Path, builds_dir
         trace!("Setting ANVIL_STEGODIR to {{{}}}", stego_dir.display());
     } else {
         error!("Failed setting ANVIL_BINDIR from passed stego_path: {{{}}}", stego_path.display());
-        return Err(format!("Could not get stego_dir from {{{}}}", stego_path.display()));
+        return Err(AnvilError::Config(format!("Could not get stego_dir from {{{}}}", stego_path.display())));
     }
-    parse_stego_tomlvalue(&stego, amboso_dir_path, stego_dir, builds_dir.to_path_buf(), start_time)
+    parse_stego_tomlvalue(&stego, stego_path, amboso_dir_path, stego_dir, builds_dir.to_path_buf(), start_time)
 }
 
-fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: PathBuf, builds_dir: PathBuf, start_time: Instant) -> Result<AmbosoEnv, String> {
-    let toml_value = stego_str.parse::<Table>();
-    match toml_value {
+fn parse_stego_tomlvalue(stego_str: &str, stego_path: &Path, amboso_dir_path: &Path, stego_dir: PathBuf, builds_dir: PathBuf, start_time: Instant) -> Result<AmbosoEnv,AnvilError> {
+    let stego = parse_stego_str(stego_path, stego_str);
+    match stego {
         Ok(y) => {
             let mut anvil_env: AmbosoEnv = AmbosoEnv {
                 run_mode : None,
                 amboso_dir: Some(amboso_dir_path.to_path_buf()),
                 stego_dir: Some(stego_dir),
+                work_dir: None,
                 builds_dir: Some(builds_dir),
                 source : None,
                 bin : None,
@@ -1221,6 +1399,13 @@ fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: Pat
                 versions_table: BTreeMap::new(),
                 basemode_versions_table: BTreeMap::new(),
                 gitmode_versions_table: BTreeMap::new(),
+                discover_pattern: None,
+                git_tag_names: BTreeMap::new(),
+                test_timeout: None,
+                header_lang: None,
+                header_template: None,
+                header_reproducible: false,
+                header_fields: Vec::new(),
                 support_testmode : true,
                 bonetests_table: BTreeMap::new(),
                 kulpotests_table: BTreeMap::new(),
@@ -1243,9 +1428,9 @@ fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: Pat
                 anvilcustom_env: None,
             };
             //trace!("Toml value: {{{}}}", y);
-            if let Some(anvil_table) = y.get("anvil").and_then(|v| v.as_table()) {
-                if let Some(anvil_version) = anvil_table.get(ANVIL_VERSION_KEYNAME) {
-                    let anvil_v_str = anvil_version.as_str().expect("toml conversion failed");
+            if let Some(ref anvil_table) = y.anvil {
+                if let Some(ref anvil_version) = anvil_table.version {
+                    let anvil_v_str = anvil_version.as_str();
                     if is_semver(anvil_v_str) {
                         if anvil_v_str.starts_with("2.0") {
                             match anvil_v_str {
@@ -1264,7 +1449,7 @@ fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: Pat
                                 }
                                 _ => {
                                     error!("Invalid anvil_version: {{{anvil_version}}}");
-                                    return Err("Invalid anvil_version".to_string());
+                                    return Err(AnvilError::Config("Invalid anvil_version".to_string()));
                                 }
                             }
                             trace!("ANVIL_VERSION: {{{anvil_version}}}");
@@ -1277,18 +1462,18 @@ fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: Pat
                                 }
                                 _ => {
                                     error!("Invalid anvil_version: {{{anvil_version}}}");
-                                    return Err("Invalid anvil_version".to_string());
+                                    return Err(AnvilError::Config("Invalid anvil_version".to_string()));
                                 }
                             }
                             trace!("ANVIL_VERSION: {{{anvil_version}}}");
                             anvil_env.anvil_version = anvil_v_str.to_string();
                         } else {
                             error!("Invalid anvil_version: {{{anvil_version}}}");
-                            return Err("Invalid anvil_version".to_string());
+                            return Err(AnvilError::Config("Invalid anvil_version".to_string()));
                         }
                     } else {
                         error!("Invalid anvil_version: {{{}}}", anvil_v_str);
-                        return Err("Invalid anvil_version".to_string());
+                        return Err(AnvilError::Config("Invalid anvil_version".to_string()));
                     }
                 } else {
                     debug!("Missing ANVIL_VERSION definition.");
@@ -1297,8 +1482,8 @@ fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: Pat
                 match semver_compare(&anvil_env.anvil_version, MIN_AMBOSO_V_KERN) {
                     Ordering::Less => {},
                     Ordering::Equal | Ordering::Greater => {
-                        if let Some(anvil_kern) = anvil_table.get(ANVIL_KERN_KEYNAME) {
-                            match anvil_kern.as_str().expect("toml conversion failed") {
+                        if let Some(ref anvil_kern) = anvil_table.kern {
+                            match anvil_kern.as_str() {
                                 "amboso-C" => {
                                     anvil_env.anvil_kern = AnvilKern::AmbosoC;
                                 }
@@ -1308,20 +1493,20 @@ fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: Pat
                                             error!("Unsupported AnvilKern value: {{{anvil_kern}}}");
                                             warn!("Try running as >={MIN_AMBOSO_V_PYKERN}");
                                             warn!("Current anvil_version: {{{}}}", anvil_env.anvil_version);
-                                            return Err("Unsupported anvil_kern".to_string());
+                                            return Err(AnvilError::Config("Unsupported anvil_kern".to_string()));
                                         },
                                         Ordering::Equal | Ordering::Greater => {
                                             match semver_compare(&anvil_env.anvil_version, MIN_AMBOSO_V_DENY_ANVILPY) {
                                                 Ordering::Less => {
                                                     if ! anvil_env.enable_extensions {
                                                         error!("Strict behaviour, refusing anvilPy kern.");
-                                                        return Err("Unsupported anvil_kern".to_string());
+                                                        return Err(AnvilError::Config("Unsupported anvil_kern".to_string()));
                                                     }
                                                 }
                                                 Ordering::Equal | Ordering::Greater => {
                                                     if ! anvil_env.enable_extensions {
                                                         error!("Strict behaviour, refusing anvilPy kern.");
-                                                        return Err("Unsupported anvil_kern".to_string());
+                                                        return Err(AnvilError::Config("Unsupported anvil_kern".to_string()));
                                                     }
                                                 }
                                             }
@@ -1336,20 +1521,20 @@ fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: Pat
                                             error!("Unsupported AnvilKern value: {{{anvil_kern}}}");
                                             warn!("Try running as >={MIN_AMBOSO_V_CUSTKERN}");
                                             warn!("Current anvil_version: {{{}}}", anvil_env.anvil_version);
-                                            return Err("Unsupported anvil_kern".to_string());
+                                            return Err(AnvilError::Config("Unsupported anvil_kern".to_string()));
                                         },
                                         Ordering::Equal | Ordering::Greater => {
                                             match semver_compare(&anvil_env.anvil_version, MIN_AMBOSO_V_DENY_ANVILCUST) {
                                                 Ordering::Less => {
                                                     if ! anvil_env.enable_extensions {
                                                         error!("Strict behaviour, refusing anvilCustom kern.");
-                                                        return Err("Unsupported anvil_kern".to_string());
+                                                        return Err(AnvilError::Config("Unsupported anvil_kern".to_string()));
                                                     }
                                                 }
                                                 Ordering::Equal | Ordering::Greater => {
                                                     if ! anvil_env.enable_extensions {
                                                         error!("Strict behaviour, refusing anvilCustom kern.");
-                                                        return Err("Unsupported anvil_kern".to_string());
+                                                        return Err(AnvilError::Config("Unsupported anvil_kern".to_string()));
                                                     }
                                                 }
                                             }
@@ -1360,7 +1545,7 @@ fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: Pat
                                 }
                                 _ => {
                                     error!("Invalid AnvilKern value: {{{anvil_kern}}}");
-                                    return Err("Invalid anvil_kern".to_string());
+                                    return Err(AnvilError::Config("Invalid anvil_kern".to_string()));
                                 }
                             }
                         } else {
@@ -1371,41 +1556,40 @@ fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: Pat
             } else {
                 debug!("Missing ANVIL section.");
             }
-            if let Some(build_table) = y.get("build").and_then(|v| v.as_table()) {
-                if let Some(source_name) = build_table.get(ANVIL_SOURCE_KEYNAME) {
+            if let Some(ref build_table) = y.build {
+                if let Some(ref source_name) = build_table.source {
                     trace!("ANVIL_SOURCE: {{{source_name}}}");
-                    anvil_env.source = Some(source_name.as_str().expect("toml conversion failed").to_string());
+                    anvil_env.source = Some(source_name.clone());
                 } else {
                     warn!("Missing ANVIL_SOURCE definition.");
                 }
-                if let Some(binary_name) = build_table.get(ANVIL_BIN_KEYNAME) {
+                if let Some(ref binary_name) = build_table.bin {
                     trace!("ANVIL_BIN: {{{binary_name}}}");
-                    anvil_env.bin = Some(binary_name.as_str().expect("toml conversion failed").to_string());
+                    anvil_env.bin = Some(binary_name.clone());
                 } else {
                     warn!("Missing ANVIL_BIN definition.");
                 }
-                if let Some(anvil_make_vers_tag) = build_table.get(ANVIL_MAKE_VERS_KEYNAME) {
+                if let Some(ref anvil_make_vers_tag) = build_table.makevers {
                     trace!("ANVIL_MAKE_VERS: {{{anvil_make_vers_tag}}}");
-                    anvil_env.mintag_make = Some(anvil_make_vers_tag.as_str().expect("toml conversion failed").to_string());
+                    anvil_env.mintag_make = Some(anvil_make_vers_tag.clone());
                 } else {
                     warn!("Missing ANVIL_MAKE_VERS definition.");
                 }
-                if let Some(anvil_automake_vers_tag) = build_table.get(ANVIL_AUTOMAKE_VERS_KEYNAME) {
+                if let Some(ref anvil_automake_vers_tag) = build_table.automakevers {
                     trace!("ANVIL_AUTOMAKE_VERS: {{{anvil_automake_vers_tag}}}");
-                    anvil_env.mintag_automake = Some(anvil_automake_vers_tag.as_str().expect("toml conversion failed").to_string());
+                    anvil_env.mintag_automake = Some(anvil_automake_vers_tag.clone());
                 } else {
                     warn!("Missing ANVIL_AUTOMAKE_VERS definition.");
                 }
-                if let Some(anvil_builds_dir) = build_table.get(ANVIL_BUILDS_DIR_KEYNAME) {
+                if let Some(ref anvil_builds_dir) = build_table.dir {
                     trace!("ANVIL_BUILDS_DIR: {{{anvil_builds_dir}}}");
-                    anvil_env.builds_dir = Some(anvil_builds_dir.as_str().expect("toml conversion failed").into());
+                    anvil_env.builds_dir = Some(anvil_builds_dir.into());
                 }
-                if let Some(anvil_testsdir) = build_table.get(ANVIL_TESTSDIR_KEYNAME) {
+                if let Some(ref anvil_testsdir) = build_table.tests {
                     trace!("ANVIL_TESTDIR: {{{anvil_testsdir}}}");
                     let mut path = PathBuf::new();
                     path.push(".");
-                    let testdir_lit = anvil_testsdir.as_str().expect("toml conversion failed").to_string();
-                    path.push(testdir_lit);
+                    path.push(anvil_testsdir);
                     anvil_env.tests_dir = Some(path);
                 } else {
                     warn!("Missing ANVIL_TESTDIR definition.");
@@ -1414,24 +1598,22 @@ fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: Pat
             } else {
                 warn!("Missing ANVIL_BUILD section.");
             }
-            if let Some(tests_table) = y.get("tests").and_then(|v| v.as_table()) {
-                if let Some(anvil_bonetests_dir) = tests_table.get(ANVIL_BONEDIR_KEYNAME) {
+            if let Some(ref tests_table) = y.tests {
+                if let Some(ref anvil_bonetests_dir) = tests_table.testsdir {
                     trace!("ANVIL_BONEDIR: {{{anvil_bonetests_dir}}}");
                     let mut path = PathBuf::new();
                     path.push(".");
-                    let bonetestdir_lit = anvil_bonetests_dir.as_str().expect("toml conversion failed").to_string();
-                    path.push(bonetestdir_lit);
+                    path.push(anvil_bonetests_dir);
                     anvil_env.bonetests_dir = Some(path);
                 } else {
                     warn!("Missing ANVIL_BONEDIR definition.");
                     anvil_env.support_testmode = false;
                 }
-                if let Some(anvil_kulpotests_dir) = tests_table.get(ANVIL_KULPODIR_KEYNAME) {
+                if let Some(ref anvil_kulpotests_dir) = tests_table.errortestsdir {
                     trace!("ANVIL_KULPODIR: {{{anvil_kulpotests_dir}}}");
                     let mut path = PathBuf::new();
                     path.push(".");
-                    let kulpotestdir_lit = anvil_kulpotests_dir.as_str().expect("toml conversion failed").to_string();
-                    path.push(kulpotestdir_lit);
+                    path.push(anvil_kulpotests_dir);
                     anvil_env.kulpotests_dir = Some(path);
                 } else {
                     warn!("Missing ANVIL_KULPODIR definition.");
@@ -1441,8 +1623,36 @@ fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: Pat
                 warn!("Missing ANVIL_TESTS section.");
                 anvil_env.support_testmode = false;
             }
-            if let Some(versions_tab) = y.get("versions").and_then(|v| v.as_table()) {
-                anvil_env.versions_table = versions_tab.iter().map(|(key, value)| (SemVerKey(key.to_string()), value.as_str().unwrap().to_string()))
+            if let Some(ref header_table) = y.header {
+                if let Some(ref lang) = header_table.lang {
+                    match HeaderLang::parse(lang) {
+                        Some(l) => {
+                            trace!("Header lang: {{{lang}}}");
+                            anvil_env.header_lang = Some(l);
+                        }
+                        None => {
+                            error!("Invalid header lang: {{{lang}}}");
+                            return Err(AnvilError::Config(format!("Invalid header lang: {{{lang}}}")));
+                        }
+                    }
+                }
+                if let Some(ref template) = header_table.template {
+                    // Relative to the stego.lock dir, like the other paths
+                    let path = anvil_env.stego_dir.as_ref().map(|d| d.join(template)).unwrap_or_else(|| PathBuf::from(template));
+                    trace!("Header template: {{{}}}", path.display());
+                    anvil_env.header_template = Some(path);
+                }
+                if let Some(ref fields) = header_table.fields {
+                    if let Some(unknown) = fields.iter().find(|f| !HEADER_EXTRA_FIELDS.contains(&f.as_str())) {
+                        error!("Invalid header field: {{{unknown}}}. Known: {}", HEADER_EXTRA_FIELDS.join(", "));
+                        return Err(AnvilError::Config(format!("Invalid header field: {{{unknown}}}")));
+                    }
+                    anvil_env.header_fields = fields.clone();
+                }
+            }
+            if let Some(ref versions_tab) = y.versions {
+                anvil_env.discover_pattern = versions_tab.discover.clone();
+                anvil_env.versions_table = versions_tab.tags.iter().map(|(key, value)| (SemVerKey(key.to_string()), value.to_string()))
                     .collect();
                 if anvil_env.versions_table.is_empty() {
                     warn!("versions_table is empty.");
@@ -1452,27 +1662,27 @@ fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: Pat
                             let trimmed_key = key.to_string().trim_start_matches('B').to_string();
                             if ! is_semver(&trimmed_key) {
                                 error!("Invalid semver key: {{{}}}", trimmed_key);
-                                return Err("Invalid semver key".to_string());
+                                return Err(AnvilError::Config("Invalid semver key".to_string()));
                             }
                             let ins_res = anvil_env.basemode_versions_table.insert(SemVerKey(trimmed_key.clone()), value.clone());
                             match ins_res {
                                 None => {},
                                 Some(old) => {
                                     error!("parse_stego_toml(): A value was already present for key {{{}}} and was replaced. {{{} => {}}}", trimmed_key, old, value);
-                                    return Err("Basemode version conflict".to_string());
+                                    return Err(AnvilError::Config("Basemode version conflict".to_string()));
                                 }
                             }
                         } else {
                             if ! is_semver(&key.to_string()) {
                                 error!("Invalid semver key: {{{}}}", key);
-                                return Err("Invalid semver key".to_string());
+                                return Err(AnvilError::Config("Invalid semver key".to_string()));
                             }
                             let ins_res = anvil_env.gitmode_versions_table.insert(SemVerKey(key.to_string()), value.clone());
                             match ins_res {
                                 None => {},
                                 Some(old) => {
                                     error!("parse_stego_toml(): A value was already present for key {{{}}} and was replaced. {{{} => {}}}", key, old, value);
-                                    return Err("Gitmode version conflict".to_string());
+                                    return Err(AnvilError::Config("Gitmode version conflict".to_string()));
                                 }
                             }
                         }
@@ -1488,8 +1698,7 @@ fn parse_stego_tomlvalue(stego_str: &str, amboso_dir_path: &Path, stego_dir: Pat
         Err(e) => {
             let elapsed = start_time.elapsed();
             debug!("Done parsing stego.toml. Elapsed: {:.2?}", elapsed);
-            error!("Failed parsing {{{}}} as TOML. Err: [{e}]", stego_str);
-            Err("Failed parsing TOML".to_string())
+            Err(e)
         }
     }
 }
@@ -2028,7 +2237,40 @@ errortestsdir = \"errors\"\n
     }
 }
 
-pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
+/// Rejects mode and operation combinations that clap can't express as conflicts.
+pub fn validate_args(args: &Args) -> Result<(),AnvilError> {
+    let test_mode = args.test || args.testmacro;
+    match args.command {
+        Some(Commands::Build) => {
+            if test_mode {
+                error!("The build subcommand is not supported in test modes. Use -b to record tests.");
+                return Err(AnvilError::Usage("Build subcommand in test mode".to_string()));
+            }
+        }
+        Some(Commands::Test { .. }) => {
+            if args.base || args.git {
+                error!("The test subcommand can't be used with a git or base mode flag.");
+                return Err(AnvilError::Usage("Test subcommand with a non-test mode".to_string()));
+            }
+            if args.run || args.delete || args.init || args.purge {
+                error!("The test subcommand does not support -r, -d, -i or -p. Use -T or -t instead.");
+                return Err(AnvilError::Usage("Unsupported op for test subcommand".to_string()));
+            }
+        }
+        _ => {}
+    }
+    if test_mode && (args.run || args.delete || args.init) {
+        error!("-r, -d and -i are not supported in test modes.");
+        return Err(AnvilError::Usage("Unsupported op for test mode".to_string()));
+    }
+    if args.test && args.purge && args.tag.is_none() {
+        error!("Missing test name for -p in test mode. Use -t to purge all test records.");
+        return Err(AnvilError::Usage("Missing test name for purge".to_string()));
+    }
+    Ok(())
+}
+
+pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,AnvilError> {
 
     let start_time = Instant::now();
 
@@ -2045,6 +2287,7 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
         run_mode : None,
         amboso_dir: None,
         stego_dir: None,
+        work_dir: None,
         builds_dir: None,
         source : None,
         bin : None,
@@ -2056,6 +2299,13 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
         versions_table: BTreeMap::new(),
         basemode_versions_table: BTreeMap::new(),
         gitmode_versions_table: BTreeMap::new(),
+        discover_pattern: None,
+        git_tag_names: BTreeMap::new(),
+        test_timeout: None,
+        header_lang: None,
+        header_template: None,
+        header_reproducible: false,
+        header_fields: Vec::new(),
         support_testmode : true,
         bonetests_table: BTreeMap::new(),
         kulpotests_table: BTreeMap::new(),
@@ -2100,7 +2350,7 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
                     }
                     _ => {
                         error!("Invalid anvil_version: {{{}}}", x);
-                        return Err("Invalid anvil_version".to_string());
+                        return Err(AnvilError::Config("Invalid anvil_version".to_string()));
                     }
                 }
                 trace!("ANVIL_VERSION: {{{x}}}");
@@ -2121,7 +2371,7 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
                     }
                     _ => {
                         error!("Invalid anvil_version: {{{}}}", x);
-                        return Err("Invalid anvil_version".to_string());
+                        return Err(AnvilError::Config("Invalid anvil_version".to_string()));
                     }
                 }
                 trace!("ANVIL_VERSION: {{{x}}}");
@@ -2135,19 +2385,19 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
                             }
                             Ordering::Less => {
                                 error!("Invalid anvil_version: {{{}}}", x);
-                                return Err("Invalid anvil_version".to_string());
+                                return Err(AnvilError::Config("Invalid anvil_version".to_string()));
                             }
                         }
                     }
                     _ => {
                         error!("Invalid anvil_version: {{{}}}", x);
-                        return Err("Invalid anvil_version".to_string());
+                        return Err(AnvilError::Config("Invalid anvil_version".to_string()));
                     }
                 }
             }
         } else {
             error!("Invalid anvil_version: {{{}}}", x);
-            return Err("Invalid anvil_version".to_string());
+            return Err(AnvilError::Config("Invalid anvil_version".to_string()));
         }
     }
 
@@ -2172,58 +2422,24 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
     if args.ignore_gitcheck || args.base {
         info!("Ignoring git check.");
     } else {
-        let gitcheck_res = is_git_repo_clean(&PathBuf::from("./"), args);
+        let gitcheck_res = is_git_repo_clean(&args.work_dir.clone().unwrap_or(PathBuf::from("./")), args);
         match gitcheck_res {
             Ok(s) => {
                 if s {
                     debug!("Repo is clean.");
                 } else {
                     warn!("Repo has uncommitted changes.");
-                    return Err("Dirty repo with git mode on".to_string());
+                    return Err(AnvilError::Config("Dirty repo with git mode on".to_string()));
                 }
             }
             Err(e) => {
                 error!("Failed git check");
-                return Err(e.to_string());
+                return Err(e);
             }
         }
     }
 
-    //Get AmbosoConf
-    //
-    if !args.strict {
-        let user_home_dir = dirs::home_dir();
-        match user_home_dir {
-            Some(_) => {},
-            None => {
-                error!("Could not retrieve user's home directory");
-                return Err("Could not find $HOME".to_string());
-            }
-        }
-        let mut invil_conf_path = user_home_dir.expect("Failed getting user's home directory");
-        invil_conf_path.push(ANVIL_DEFAULT_CONF_PATH);
-        if invil_conf_path.exists() {
-            let res = parse_invil_toml(&invil_conf_path);
-            match res {
-                Ok(c) => {
-                    match args.anvil_version {
-                        Some(_) => {},
-                        None => { args.anvil_version = Some(c.anvil_version);},
-                    }
-                    match args.anvil_kern {
-                        Some(_) => {},
-                        None => { args.anvil_kern = Some(c.anvil_kern.to_string()); },
-                    }
-                }
-                Err(e) => {
-                    error!("Failed parsing anvil config file.");
-                    return Err(e.to_string());
-                }
-            }
-        } else {
-            debug!("Could not read global conf from {{{}}}", invil_conf_path.display());
-        }
-    }
+    // Layered config, including the global anvil.toml, is applied to args by config::apply_config()
 
     match &args.builds_dir {
         Some(x) => {
@@ -2251,6 +2467,13 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
                     if let Some(ref p) = a.stego_dir {
                         debug!("{}", format!("stego_dir: {}", p.display()));
                     }
+                    if args.from_git && a.discover_pattern.is_none() {
+                        a.discover_pattern = Some(DEFAULT_DISCOVER_PATTERN.to_string());
+                    }
+                    if a.discover_pattern.is_some() && !args.strict {
+                        discover_versions(&mut a)?;
+                    }
+                    a.work_dir = args.work_dir.clone();
                     anvil_env = a;
                 }
                 Err(e) => {
@@ -2261,7 +2484,7 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
         }
         None => {
             error!("Missing amboso dir argument. Quitting.");
-            return Err("Missing amboso_dir arg".to_string());
+            return Err(AnvilError::Usage("Missing amboso_dir arg".to_string()));
         }
     }
 
@@ -2303,7 +2526,7 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
                             }
                             if anvilpy_env.build_sys.backend != "setuptools.build_meta" {
                                 error!("Unexpected build system: {{{}}}", anvilpy_env.build_sys.backend);
-                                return Err("Unexpected build system".to_string());
+                                return Err(AnvilError::Config("Unexpected build system".to_string()));
                             }
                             anvil_env.anvilpy_env = Some(anvilpy_env);
                         }
@@ -2316,7 +2539,7 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
             #[cfg(not(feature = "anvilPy"))] {
                 // Handle AnvilPy case when the feature is not enabled
                 error!("AnvilPy kern feature is not enabled");
-                return Err("AnvilPy kern feauture is not enabled".to_string());
+                return Err(AnvilError::Config("AnvilPy kern feauture is not enabled".to_string()));
             }
         }
         AnvilKern::Custom => {
@@ -2354,24 +2577,40 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
             #[cfg(not(feature = "anvilCustom"))] {
                 // Handle AnvilCustom case when the feature is not enabled
                 error!("AnvilCustom kern feature is not enabled");
-                return Err("AnvilCustom kern feauture is not enabled".to_string());
+                return Err(AnvilError::Config("AnvilCustom kern feauture is not enabled".to_string()));
             }
         }
     }
 
+    anvil_env.header_reproducible = args.reproducible;
+
     match args.gen_c_header {
         Some(ref x) => {
             match args.tag {
-                Some (ref query) => {
-                    debug!("TODO: check if query is not a valid tag?");
+                Some (ref tag_query) => {
+                    let query = match resolve_tag_query(tag_query, &anvil_env.gitmode_versions_table) {
+                        Ok(tags) => tags.last().cloned().unwrap_or_default(),
+                        Err(e) => {
+                            return Err(e);
+                        }
+                    };
+                    let query = &query;
+                    let git_tag = anvil_env.git_tag_name(query);
                     match anvil_env.bin {
                         Some (ref binname) => {
                            info!("Generating C header for {{{}}} to dir: {{{}}}", query, x.display());
-                           let res = gen_header(x, anvil_env.anvil_kern, query, binname);
+                           let mut opts = anvil_env.header_options();
+                           if let Some(lang) = args.lang.as_deref().and_then(HeaderLang::parse) {
+                               // An explicit --lang asks for the built-in output
+                               opts.lang = Some(lang);
+                               opts.template = None;
+                           }
+                           let res = gen_header(x, anvil_env.anvil_kern, query, &git_tag, binname, &opts);
                             match res {
                                 Ok(_) => {
                                     info!("C header gen successful for {{{}}}.", query);
-                                    exit(0);
+                                    // No run_mode is set, so nothing else is done for this env
+                                    return Ok(anvil_env);
                                 }
                                 Err(e) => {
                                     error!("C header gen failed for {{{}}}.\nError was:    {e}", query);
@@ -2381,13 +2620,13 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
                         }
                         None => {
                             error!("Missing bin name for C header gen mode");
-                            return Err("Missing bin name for C header gen".to_string());
+                            return Err(AnvilError::Usage("Missing bin name for C header gen".to_string()));
                         }
                     }
                 }
                 None => {
                     error!("Missing query tag for C header gen mode");
-                    return Err("Missing query tag for C header gen".to_string());
+                    return Err(AnvilError::Usage("Missing query tag for C header gen".to_string()));
                 }
             }
         }
@@ -2403,7 +2642,7 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
         }
         None => {
             error!("Missing amboso_dir. Quitting.");
-            return Err("anvil_env.amboso_dir was empty".to_string());
+            return Err(AnvilError::Config("anvil_env.amboso_dir was empty".to_string()));
         }
     }
 
@@ -2426,7 +2665,7 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
                         debug!("TODO:    Validate kazoj_dir");
                     } else {
                         warn!("stego.lock tests dir {{{}}} was invalid", x.display());
-                        args.kazoj_dir = Some(PathBuf::from("./kazoj"));
+                        args.kazoj_dir = Some(args.work_dir.as_deref().unwrap_or(Path::new(".")).join("kazoj"));
                         if args.kazoj_dir.as_ref().unwrap().exists() {
                             debug!("{} exists", args.kazoj_dir.as_ref().unwrap().display());
                             debug!("TODO:    Validate kazoj_dir");
@@ -2439,7 +2678,7 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
                 }
                 None => {
                     warn!("Could not find tests dir in {{stego.lock}}.");
-                    args.kazoj_dir = Some(PathBuf::from("./kazoj"));
+                    args.kazoj_dir = Some(args.work_dir.as_deref().unwrap_or(Path::new(".")).join("kazoj"));
                     if args.kazoj_dir.as_ref().unwrap().exists() {
                         debug!("{} exists", args.kazoj_dir.as_ref().unwrap().display());
                         debug!("TODO:    Validate kazoj_dir");
@@ -2473,7 +2712,7 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
                 }
                 None => {
                     error!("stego.lock did not have a valid source arg. Quitting.");
-                    return Err("Could not determine anvil_env.source".to_string());
+                    return Err(AnvilError::Config("Could not determine anvil_env.source".to_string()));
                 }
             }
             debug!("TODO:  Validate source")
@@ -2494,7 +2733,7 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
                 }
                 None => {
                     error!("stego.lock did not have a valid bin arg. Quitting.");
-                    return Err("Could not determine anvil_env.bin arg".to_string());
+                    return Err(AnvilError::Config("Could not determine anvil_env.bin arg".to_string()));
                 }
             }
             debug!("TODO:  Validate execname")
@@ -2541,6 +2780,11 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
         anvil_env.cflags_arg = x.to_string();
     }
 
+    if let Some(secs) = args.test_timeout {
+        trace!("Test timeout: {{{secs}}}s");
+        anvil_env.test_timeout = Some(Duration::from_secs(secs.into()));
+    }
+
     if let Some(ref x) = args.config {
         let mut backcomp_wanted = true;
         let amboso_config_flag_arg_isfile = "AMBOSO_CONFIG_FLAG_ARG_ISFILE";
@@ -2573,7 +2817,7 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
                 }
                 Err(e) => {
                     error!("Failed reading config file from {{{}}}. Err: {e}", x);
-                    return Err("Failed reading config file".to_string());
+                    return Err(AnvilError::Config("Failed reading config file".to_string()));
                 }
             }
         } else {
@@ -2605,21 +2849,15 @@ pub fn check_passed_args(args: &mut Args) -> Result<AmbosoEnv,String> {
     Ok(anvil_env)
 }
 
-fn is_semver(input: &str) -> bool {
-    let full_regex = Regex::new(
-        r"^(0|[1-9]\d*)\.(0|[1-9]\d*)\.(0|[1-9]\d*)(?:-((?:0|[1-9]\d*|\d*[a-zA-Z-][0-9a-zA-Z-]*)(?:\.(?:0|[1-9]\d*|\d*[a-zA-Z-][0-9a-zA-Z-]*))*))?(?:\+([0-9a-zA-Z-]+(?:\.[0-9a-zA-Z-]+)*))?$",
-    )
-    .expect("Failed to create regex");
-    let strict_regex = Regex::new(r"^(0|[1-9]\d*)\.(0|[1-9]\d*)\.(0|[1-9]\d*)$").expect("Failed to create regex");
-
-    if strict_regex.is_match(input) {
-        true
-    } else {
-        if full_regex.is_match(input) {
+/// Returns true if `input` is a SemVer version without pre-release or build metadata, as expected for tags.
+pub fn is_semver(input: &str) -> bool {
+    match SemVer::parse(input) {
+        Some(v) if v.is_release() => true,
+        Some(_) => {
             error!("Prerelease or build metadata is not allowed in a strict SemVer key.");
-            return false;
+            false
         }
-        false
+        None => false,
     }
 }
 
@@ -2628,7 +2866,8 @@ pub struct SemVerKey(pub String);
 
 impl Ord for SemVerKey {
     fn cmp(&self, other: &Self) -> Ordering {
-        semver_compare(&self.0, &other.0)
+        // Break precedence ties on the string, to stay consistent with Eq
+        semver_compare(&self.0, &other.0).then_with(|| self.0.cmp(&other.0))
     }
 }
 
@@ -2663,6 +2902,29 @@ impl fmt::Display for AnvilKern {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn test_git_repo_status_clean() {
+        let dir = TempDir::new("repo-status");
+        let repo = Repository::init(&dir).unwrap();
+        fs::write(dir.join("main.c"), "int main(void) { return 0; }\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("main.c")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("invil", "invil@example.com").unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "Init", &tree, &[]).unwrap();
+
+        fs::write(dir.join("untracked.c"), "").unwrap();
+        assert!(git_repo_status_clean(&repo).unwrap());
+        // Staged, then modified again
+        fs::write(dir.join("main.c"), "int main(void) { return 1; }\n").unwrap();
+        index.add_path(Path::new("main.c")).unwrap();
+        index.write().unwrap();
+        fs::write(dir.join("main.c"), "int main(void) { return 2; }\n").unwrap();
+        assert!(!git_repo_status_clean(&repo).unwrap());
+    }
 
     #[test]
     fn test_is_semver() {
@@ -2676,6 +2938,18 @@ mod tests {
         assert_eq!(is_semver("1.2.03"), false);
     }
 
+    #[test]
+    fn test_check_exit_code() {
+        assert_eq!(check_exit_code(&AnvilError::UnknownTag("9.9.9".to_string())), ANVIL_EXIT_USAGE);
+        assert_eq!(check_exit_code(&AnvilError::Usage("Missing amboso_dir arg".to_string())), ANVIL_EXIT_USAGE);
+        assert_eq!(check_exit_code(&AnvilError::Config("Invalid anvil_kern".to_string()).context("Check fail")), ANVIL_EXIT_CONFIG);
+        assert_eq!(check_exit_code(&AnvilError::not_found("bin")), ANVIL_EXIT_CONFIG);
+        assert_eq!(check_exit_code(&AnvilError::Msg("Test macro had some failures".to_string())), ANVIL_EXIT_FAILURE);
+        let build_fail = AnvilError::BuildStepFailed { step: "make".to_string(), code: Some(2), stderr: String::new() };
+        assert_eq!(build_exit_code(&build_fail.context("Build failed")), ANVIL_EXIT_BUILD);
+        assert_eq!(build_exit_code(&AnvilError::UnknownTag("9.9.9".to_string())), ANVIL_EXIT_USAGE);
+    }
+
     #[test]
     fn test_semver_compare() {
 
@@ -2694,22 +2968,27 @@ mod tests {
         assert_eq!(semver_compare("1.0.0+pr123", "1.0.0+build456"), Ordering::Equal);
 
         // Test case 4: Test with both pre-release and build metadata
-        assert_eq!(semver_compare("1.0.0-pr1+build123", "1.0.0-pr1+build456"), Ordering::Less);
+        assert_eq!(semver_compare("1.0.0-pr1+build123", "1.0.0-pr1+build456"), Ordering::Equal);
         assert_eq!(semver_compare("1.0.0-pr2+build123", "1.0.0-pr1+build456"), Ordering::Greater);
         assert_eq!(semver_compare("1.0.0-pr2+build456", "1.0.0-pr1+build123"), Ordering::Greater);
 
         // Test case 5: Test with only version core and some extension
         assert_eq!(semver_compare("1.0.0", "1.0.0-pr1+build456"), Ordering::Greater);
-        assert_eq!(semver_compare("1.0.0", "1.0.0+build456"), Ordering::Greater);
+        assert_eq!(semver_compare("1.0.0", "1.0.0+build456"), Ordering::Equal);
         assert_eq!(semver_compare("1.0.0", "1.0.0-patch123"), Ordering::Greater);
 
+        // Test case 6: Numeric pre-release identifiers compare by value
+        assert_eq!(semver_compare("1.0.0-rc.10", "1.0.0-rc.9"), Ordering::Greater);
+        assert_eq!(semver_compare("1.0.0-rc.1", "1.0.0-rc.a"), Ordering::Less);
+        assert_eq!(semver_compare("1.0.0-rc", "1.0.0-rc.1"), Ordering::Less);
+
     }
 
 }
 
-pub fn lex_stego_toml(stego_path: &PathBuf) -> Result<String,String> {
+pub fn lex_stego_toml(stego_path: &PathBuf) -> Result<String,AnvilError> {
     let start_time = Instant::now();
-    let stego = fs::read_to_string(stego_path).expect("Could not read {stego_path} contents");
+    let stego = fs::read_to_string(stego_path).map_err(|e| AnvilError::io(stego_path, e))?;
     trace!("Stego contents: {{{}}}", stego);
     let toml_value = stego.parse::<Table>();
     let allow_nonstr_values = false;
@@ -2764,7 +3043,7 @@ pub fn lex_stego_toml(stego_path: &PathBuf) -> Result<String,String> {
             let elapsed = start_time.elapsed();
             debug!("Done lexing stego.toml. Elapsed: {:.2?}", elapsed);
             error!("Failed lexing {{{}}} as TOML. Err: [{}]", stego, e);
-            Err("Failed lexing TOML".to_string())
+            Err(AnvilError::stego_parse(stego_path, &stego, e))
         }
     }
 }
@@ -2783,18 +3062,18 @@ pub fn cut_line_at_char(line: &str, delimiter: char, direction: CutDirection) ->
     }
 }
 
-pub fn parse_legacy_stego(stego_path: &PathBuf) -> Result<AmbosoEnv,String> {
+pub fn parse_legacy_stego(stego_path: &PathBuf) -> Result<AmbosoEnv,AnvilError> {
     let start_time = Instant::now();
     let mut stego_dir = stego_path.clone();
     if ! stego_dir.pop() {
         error!("Failed pop for {{{}}}", stego_dir.display());
-        return Err(format!("Unexpected stego_dir value: {{{}}}", stego_dir.display()));
+        return Err(AnvilError::Config(format!("Unexpected stego_dir value: {{{}}}", stego_dir.display())));
     }
     if stego_dir.exists() {
         trace!("Setting ANVIL_BINDIR to {{{}}}", stego_dir.display());
     } else {
         error!("Failed setting ANVIL_BINDIR from passed stego_path: {{{}}}", stego_path.display());
-        return Err(format!("Could not get stego_dir from {{{}}}", stego_path.display()));
+        return Err(AnvilError::Config(format!("Could not get stego_dir from {{{}}}", stego_path.display())));
     }
 
     // Check if the file exists
@@ -2805,6 +3084,7 @@ pub fn parse_legacy_stego(stego_path: &PathBuf) -> Result<AmbosoEnv,String> {
             run_mode : None,
             amboso_dir: Some(stego_dir),
             stego_dir: None,
+            work_dir: None,
             builds_dir: None,
             source : None,
             bin : None,
@@ -2816,6 +3096,13 @@ pub fn parse_legacy_stego(stego_path: &PathBuf) -> Result<AmbosoEnv,String> {
             versions_table: BTreeMap::new(),
             basemode_versions_table: BTreeMap::new(),
             gitmode_versions_table: BTreeMap::new(),
+            discover_pattern: None,
+            git_tag_names: BTreeMap::new(),
+            test_timeout: None,
+            header_lang: None,
+            header_template: None,
+            header_reproducible: false,
+            header_fields: Vec::new(),
             support_testmode : true,
             bonetests_table: BTreeMap::new(),
             kulpotests_table: BTreeMap::new(),
@@ -2853,28 +3140,28 @@ pub fn parse_legacy_stego(stego_path: &PathBuf) -> Result<AmbosoEnv,String> {
                 } else if cur_line == AMBOSO_SOURCE_LEGACY_POS {
                     if stripped_line.is_empty() {
                         error!("Found empty ANVIL_SOURCE definition.");
-                        return Err("Invalid ANVIL_SOURCE definition".to_string());
+                        return Err(AnvilError::Config("Invalid ANVIL_SOURCE definition".to_string()));
                     }
                     debug!("Found ANVIL_SOURCE legacy def -> {{{}}}", stripped_line);
                     anvil_env.source = Some(stripped_line.clone());
                 } else if cur_line == AMBOSO_BIN_LEGACY_POS {
                     if stripped_line.is_empty() {
                         error!("Found empty ANVIL_BIN definition.");
-                        return Err("Invalid ANVIL_BIN definition".to_string());
+                        return Err(AnvilError::Config("Invalid ANVIL_BIN definition".to_string()));
                     }
                     debug!("Found ANVIL_BIN legacy def -> {{{}}}", stripped_line);
                     anvil_env.bin = Some(stripped_line.clone());
                 } else if cur_line == AMBOSO_MAKEVERS_LEGACY_POS {
                     if stripped_line.is_empty() {
                         error!("Found empty ANVIL_MAKEVERS definition.");
-                        return Err("Invalid ANVIL_MAKEVERS definition".to_string());
+                        return Err(AnvilError::Config("Invalid ANVIL_MAKEVERS definition".to_string()));
                     }
                     debug!("Found ANVIL_MAKEVERS legacy def -> {{{}}}", stripped_line);
                     anvil_env.mintag_make = Some(stripped_line.clone());
                 } else if cur_line == AMBOSO_TESTS_LEGACY_POS {
                     if stripped_line.is_empty() {
                         error!("Found empty ANVIL_TESTDIR definition.");
-                        return Err("Invalid ANVIL_TESTDIR definition".to_string());
+                        return Err(AnvilError::Config("Invalid ANVIL_TESTDIR definition".to_string()));
                     }
                     debug!("Found ANVIL_TESTDIR legacy def -> {{{}}}", stripped_line);
                     anvil_env.tests_dir = Some(stripped_line.clone().into());
@@ -2900,14 +3187,14 @@ pub fn parse_legacy_stego(stego_path: &PathBuf) -> Result<AmbosoEnv,String> {
                                 } else if cur_kazoj_line == (AMBOSO_BONE_LEGACY_POS +1) {
                                     if kazoj_stripped_line.is_empty() {
                                         error!("Found empty ANVIL_BONEDIR definition.");
-                                        return Err("Invalid ANVIL_BONEDIR definition".to_string());
+                                        return Err(AnvilError::Config("Invalid ANVIL_BONEDIR definition".to_string()));
                                     }
                                     debug!("Found legacy tests dir def -> {{{}}}", kazoj_stripped_line);
                                     anvil_env.bonetests_dir = Some(kazoj_stripped_line.into());
                                 } else if cur_kazoj_line == (AMBOSO_KULPO_LEGACY_POS +1) {
                                     if kazoj_stripped_line.is_empty() {
                                         error!("Found empty ANVIL_KULPODIR definition.");
-                                        return Err("Invalid ANVIL_KULPODIR definition".to_string());
+                                        return Err(AnvilError::Config("Invalid ANVIL_KULPODIR definition".to_string()));
                                     }
                                     debug!("Found legacy errortests dir def -> {{{}}}", kazoj_stripped_line);
                                     anvil_env.kulpotests_dir = Some(kazoj_stripped_line.into());
@@ -2915,18 +3202,18 @@ pub fn parse_legacy_stego(stego_path: &PathBuf) -> Result<AmbosoEnv,String> {
                                 cur_kazoj_line += 1;
                             } else {
                                 error!("Failed getting kazoj_line from {{{}}} -> {{{}}}", kazoj_path.clone().display(), cur_kazoj_line);
-                                return Err(format!("Can't read line {{{}}} from {{{}}}.", cur_kazoj_line, kazoj_path.display()));
+                                return Err(AnvilError::Config(format!("Can't read line {{{}}} from {{{}}}.", cur_kazoj_line, kazoj_path.display())));
                             }
                         }
                     } else {
                         warn!("Can't find kazoj.lock, expected at: {{{}}}", kazoj_path.clone().display());
-                        return Err(format!("Can't find kazoj.lock at {{{}}}", kazoj_path.display()));
+                        return Err(AnvilError::Config(format!("Can't find kazoj.lock at {{{}}}", kazoj_path.display())));
                     }
 
                 } else if cur_line == AMBOSO_AUTOMAKEVERS_LEGACY_POS {
                     if stripped_line.is_empty() {
                         error!("Found empty ANVIL_AUTOMAKEVERS definition.");
-                        return Err("Invalid ANVIL_AUTOMAKEVERS definition".to_string());
+                        return Err(AnvilError::Config("Invalid ANVIL_AUTOMAKEVERS definition".to_string()));
                     }
                     debug!("Found ANVIL_AUTOMAKEVERS legacy def -> {{{}}}", stripped_line);
                     anvil_env.mintag_automake = Some(stripped_line.clone());
@@ -2942,7 +3229,7 @@ pub fn parse_legacy_stego(stego_path: &PathBuf) -> Result<AmbosoEnv,String> {
                 cur_line += 1;
             } else {
                 error!("Failed reading line {{{cur_line}}} from {{{}}}", stego_path.display());
-                return  Err(format!("Error while reading {{{}}}", stego_path.display()));
+                return  Err(AnvilError::Config(format!("Error while reading {{{}}}", stego_path.display())));
             }
         }
 
@@ -2951,27 +3238,27 @@ pub fn parse_legacy_stego(stego_path: &PathBuf) -> Result<AmbosoEnv,String> {
                 let trimmed_key = key.to_string().trim_start_matches('?').to_string();
                 if ! is_semver(&trimmed_key) {
                     error!("Invalid semver key: {{{}}}", trimmed_key);
-                    return Err("Invalid semver key".to_string());
+                    return Err(AnvilError::Config("Invalid semver key".to_string()));
                 }
                 let ins_res = anvil_env.basemode_versions_table.insert(SemVerKey(trimmed_key.clone()), value.clone());
                 match ins_res {
                     None => {},
                     Some(old) => {
                         error!("parse_legacy_stego(): A value was already present for key {{{}}} and was replaced. {{{} => {}}}", trimmed_key, old, value);
-                        return Err("Basemode version conflict".to_string());
+                        return Err(AnvilError::Config("Basemode version conflict".to_string()));
                     }
                 }
             } else {
                 if ! is_semver(&key.to_string()) {
                     error!("Invalid semver key: {{{}}}", key);
-                    return Err("Invalid semver key".to_string());
+                    return Err(AnvilError::Config("Invalid semver key".to_string()));
                 }
                 let ins_res = anvil_env.gitmode_versions_table.insert(SemVerKey(key.to_string()), value.clone());
                 match ins_res {
                     None => {},
                     Some(old) => {
                         error!("parse_legacy_stego(): A value was already present for key {{{}}} and was replaced. {{{} => {}}}", key, old, value);
-                        return Err("Gitmode version conflict".to_string());
+                        return Err(AnvilError::Config("Gitmode version conflict".to_string()));
                     }
                 }
             }
@@ -2980,6 +3267,6 @@ pub fn parse_legacy_stego(stego_path: &PathBuf) -> Result<AmbosoEnv,String> {
         Ok(anvil_env)
     } else {
         error!("Failed opening stego.lock at path from {{{}}}", stego_path.display());
-        Err(format!("Can't find stego.lock, expected at {{{}}}", stego_path.display()))
+        Err(AnvilError::Config(format!("Can't find stego.lock, expected at {{{}}}", stego_path.display())))
     }
 }
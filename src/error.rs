@@ -0,0 +1,249 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum AnvilError {
+    /// A TOML file could not be parsed
+    StegoParse {
+        path: PathBuf,
+        line: usize,
+        col: usize,
+        snippet: String,
+        source: Box<toml::de::Error>,
+    },
+    /// A Makefile could not be parsed. `path` is empty when parsing a string
+    MakefileParse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// The queried tag is not in the versions table for the current mode
+    UnknownTag(String),
+    /// The passed arguments or query are invalid
+    Usage(String),
+    /// `stego.lock`, a config file or the environment are invalid or incomplete
+    Config(String),
+    /// An external command could not be spawned
+    MissingTool {
+        tool: String,
+        source: io::Error,
+    },
+    /// An external build command ran and failed. Its stderr was already printed
+    BuildStepFailed {
+        step: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+    Git(git2::Error),
+    /// A test output did not match its recording
+    TestMismatch {
+        test: PathBuf,
+        stream: String,
+    },
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// An operation failed because of `source`
+    Context {
+        context: String,
+        source: Box<AnvilError>,
+    },
+    Msg(String),
+}
+
+impl AnvilError {
+
+    /// Builds a StegoParse error, resolving the error span in `contents` to a line and column.
+    pub fn stego_parse(path: &Path, contents: &str, source: toml::de::Error) -> AnvilError {
+        let (line, col) = match source.span() {
+            Some(span) => line_col_at(contents, span.start),
+            None => (0, 0),
+        };
+        AnvilError::StegoParse {
+            path: path.to_path_buf(),
+            line,
+            col,
+            snippet: render_snippet(contents, line, col),
+            source: Box::new(source),
+        }
+    }
+
+    pub fn io(path: impl AsRef<Path>, source: io::Error) -> AnvilError {
+        AnvilError::Io {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    /// Builds an Io error for `path`, which does not exist.
+    pub fn not_found(path: impl AsRef<Path>) -> AnvilError {
+        AnvilError::io(path, io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    /// Builds an Io error for `path`, which exists but is not a regular file.
+    pub fn not_a_file(path: impl AsRef<Path>) -> AnvilError {
+        AnvilError::io(path, io::Error::other("not a file"))
+    }
+
+    pub fn missing_tool(tool: impl AsRef<OsStr>, source: io::Error) -> AnvilError {
+        AnvilError::MissingTool {
+            tool: tool.as_ref().to_string_lossy().to_string(),
+            source,
+        }
+    }
+
+    /// Wraps the error, keeping it as source.
+    pub fn context(self, context: impl Into<String>) -> AnvilError {
+        AnvilError::Context {
+            context: context.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /// Returns the innermost error wrapped with [AnvilError::context].
+    pub fn root(&self) -> &AnvilError {
+        match self {
+            AnvilError::Context { source, .. } => source.root(),
+            e => e,
+        }
+    }
+}
+
+/// Returns the 1-based line and column for the byte offset `pos` in `contents`.
+pub fn line_col_at(contents: &str, pos: usize) -> (usize, usize) {
+    let mut end = pos.min(contents.len());
+    while !contents.is_char_boundary(end) {
+        end -= 1;
+    }
+    let before = &contents[..end];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let col = before[line_start..].chars().count() + 1;
+    (line, col)
+}
+
+/// Renders the source line at `line` with a caret under `col`.
+pub fn render_snippet(contents: &str, line: usize, col: usize) -> String {
+    let src_line = contents.lines().nth(line.saturating_sub(1)).unwrap_or("");
+    let width = line.to_string().len();
+    format!("{:width$} |\n{line} | {src_line}\n{:width$} | {:>col$}", "", "", "^")
+}
+
+/// Renders a `path:line:col: message` diagnostic for the byte offset `pos` in `contents`, followed by a snippet.
+pub fn render_diagnostic(path: &Path, contents: &str, pos: usize, message: &str) -> String {
+    let (line, col) = line_col_at(contents, pos);
+    format!("{}:{line}:{col}: {message}\n{}", path.display(), render_snippet(contents, line, col))
+}
+
+impl fmt::Display for AnvilError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AnvilError::StegoParse { path, line, col, snippet, source } => {
+                write!(f, "{}:{}:{}: {}\n{}", path.display(), line, col, source.message(), snippet)
+            }
+            AnvilError::MakefileParse { path, line, message } if path.as_os_str().is_empty() => {
+                write!(f, "Makefile line {line}: {message}")
+            }
+            AnvilError::MakefileParse { path, line, message } => write!(f, "{}:{line}: {message}", path.display()),
+            AnvilError::UnknownTag(tag) => write!(f, "{{{tag}}} was not a valid tag"),
+            AnvilError::Usage(m) | AnvilError::Config(m) => write!(f, "{m}"),
+            AnvilError::MissingTool { tool, .. } => write!(f, "Failed to execute {{{tool}}}"),
+            AnvilError::BuildStepFailed { step, code, .. } => {
+                match code {
+                    Some(c) => write!(f, "{{{step}}} failed with status: {c}"),
+                    None => write!(f, "{{{step}}} was terminated by a signal"),
+                }
+            }
+            AnvilError::Git(e) => write!(f, "Git error: {}", e.message()),
+            AnvilError::TestMismatch { test, stream } => write!(f, "{stream} mismatch for {{{}}}", test.display()),
+            AnvilError::Io { path, .. } => write!(f, "I/O error on {{{}}}", path.display()),
+            AnvilError::Context { context, .. } => write!(f, "{context}"),
+            AnvilError::Msg(m) => write!(f, "{m}"),
+        }
+    }
+}
+
+impl Error for AnvilError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AnvilError::StegoParse { source, .. } => Some(source),
+            AnvilError::MissingTool { source, .. } => Some(source),
+            AnvilError::Git(e) => Some(e),
+            AnvilError::Io { source, .. } => Some(source),
+            AnvilError::Context { source, .. } => Some(source.as_ref()),
+            AnvilError::MakefileParse { .. } | AnvilError::UnknownTag(_) | AnvilError::Usage(_) | AnvilError::Config(_) | AnvilError::BuildStepFailed { .. }
+                | AnvilError::TestMismatch { .. } | AnvilError::Msg(_) => None,
+        }
+    }
+}
+
+impl From<git2::Error> for AnvilError {
+    fn from(e: git2::Error) -> AnvilError {
+        AnvilError::Git(e)
+    }
+}
+
+/// Formats `err` followed by the first line of each of its sources.
+pub fn error_chain(err: &dyn Error) -> String {
+    let mut res = err.to_string();
+    let mut cur = err.source();
+    while let Some(e) = cur {
+        res.push_str(&format!("\n  Caused by: {}", e.to_string().lines().next().unwrap_or("")));
+        cur = e.source();
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stego_parse_position() {
+        let contents = "[build]\nsource = \"main.c\"\nbin = \n";
+        let e = contents.parse::<toml::Table>().unwrap_err();
+        let err = AnvilError::stego_parse(Path::new("stego.lock"), contents, e);
+        match err {
+            AnvilError::StegoParse { line, col, .. } => {
+                assert_eq!(line, 3);
+                assert_eq!(col, 7);
+            }
+            _ => panic!("Expected StegoParse"),
+        }
+        assert!(err.to_string().starts_with("stego.lock:3:7: "));
+        assert!(err.to_string().ends_with("3 | bin = \n  |       ^"));
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn test_line_col_at() {
+        assert_eq!(line_col_at("abc", 0), (1, 1));
+        assert_eq!(line_col_at("ab\ncd", 4), (2, 2));
+        assert_eq!(line_col_at("é\nx", 1), (1, 1));
+    }
+
+    #[test]
+    fn test_context_keeps_source() {
+        let step = AnvilError::BuildStepFailed { step: "make".to_string(), code: Some(2), stderr: String::new() };
+        let err = step.context("Build failed for {0.1.0}");
+        assert!(matches!(err.root(), AnvilError::BuildStepFailed { code: Some(2), .. }));
+        assert_eq!(error_chain(&err), "Build failed for {0.1.0}\n  Caused by: {make} failed with status: 2");
+    }
+}
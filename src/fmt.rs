@@ -0,0 +1,260 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use toml_edit::{DocumentMut, Item, Table, Value};
+use crate::core::{Args, SemVerKey, ANVIL_EXIT_CONFIG, ANVIL_EXIT_FAILURE, ANVIL_VERSION_KEYNAME};
+use crate::error::{AnvilError, error_chain};
+use crate::stego::{deserialize_stego, STEGO_ANVIL_SECTION, STEGO_BUILD_SECTION, STEGO_TESTS_SECTION, STEGO_HEADER_SECTION, STEGO_LINT_SECTION, STEGO_VERSIONS_SECTION, STEGO_DISCOVER_KEYNAME};
+
+/// Canonical section order. Other sections follow, in their original order.
+pub const STEGO_SECTION_ORDER: &[&str] = &[STEGO_ANVIL_SECTION, STEGO_BUILD_SECTION, STEGO_TESTS_SECTION, STEGO_HEADER_SECTION, STEGO_LINT_SECTION, STEGO_VERSIONS_SECTION];
+
+/// Parses `contents` into an editable document, after checking it is a valid `stego.lock`.
+pub fn parse_stego_document(stego_path: &Path, contents: &str) -> Result<DocumentMut, AnvilError> {
+    deserialize_stego(stego_path, contents)?;
+    contents.parse::<DocumentMut>().map_err(|e| AnvilError::Config(format!("{}: {e}", stego_path.display())))
+}
+
+/// Orders `[versions]` keys: `discover` first, then base mode tags, then git mode tags, each by semver.
+pub fn version_key_cmp(a: &str, b: &str) -> Ordering {
+    let (a_discover, b_discover) = (a == STEGO_DISCOVER_KEYNAME, b == STEGO_DISCOVER_KEYNAME);
+    if a_discover || b_discover {
+        return b_discover.cmp(&a_discover);
+    }
+    let (a_base, a_tag) = match a.strip_prefix('B') {
+        Some(t) => (true, t),
+        None => (false, a),
+    };
+    let (b_base, b_tag) = match b.strip_prefix('B') {
+        Some(t) => (true, t),
+        None => (false, b),
+    };
+    b_base.cmp(&a_base).then_with(|| SemVerKey(a_tag.to_string()).cmp(&SemVerKey(b_tag.to_string())))
+}
+
+/// Returns true if `[versions]` is missing or already sorted.
+pub fn versions_sorted(doc: &DocumentMut) -> bool {
+    let Some(versions) = doc.get(STEGO_VERSIONS_SECTION).and_then(Item::as_table) else {
+        return true;
+    };
+    let keys: Vec<&str> = versions.iter().map(|(k, _)| k).collect();
+    keys.windows(2).all(|w| version_key_cmp(w[0], w[1]) != Ordering::Greater)
+}
+
+/// Sorts `[versions]`, keeping each key's comments attached to it.
+///
+/// Returns true if the order changed.
+pub fn sort_versions(doc: &mut DocumentMut) -> bool {
+    if versions_sorted(doc) {
+        return false;
+    }
+    if let Some(versions) = doc.get_mut(STEGO_VERSIONS_SECTION).and_then(Item::as_table_mut) {
+        versions.sort_values_by(|k1, _, k2, _| version_key_cmp(k1, k2));
+    }
+    true
+}
+
+/// Returns true if `[anvil]` sets `version`.
+pub fn has_anvil_version(doc: &DocumentMut) -> bool {
+    doc.get(STEGO_ANVIL_SECTION)
+        .and_then(Item::as_table)
+        .is_some_and(|t| t.contains_key(ANVIL_VERSION_KEYNAME))
+}
+
+/// Sets `[anvil] version` to `version`, creating the section before all others if needed.
+///
+/// Returns false if a version was already set.
+pub fn add_anvil_version(doc: &mut DocumentMut, version: &str) -> bool {
+    if has_anvil_version(doc) {
+        return false;
+    }
+    if !doc.contains_key(STEGO_ANVIL_SECTION) {
+        let first_pos = doc.iter()
+            .filter_map(|(_, i)| i.as_table().and_then(Table::position))
+            .min()
+            .unwrap_or(0);
+        let mut anvil = Table::new();
+        anvil.set_position(Some(first_pos - 1));
+        doc.insert(STEGO_ANVIL_SECTION, Item::Table(anvil));
+    }
+    if let Some(anvil) = doc.get_mut(STEGO_ANVIL_SECTION).and_then(Item::as_table_mut) {
+        anvil.insert(ANVIL_VERSION_KEYNAME, toml_edit::value(version));
+    }
+    true
+}
+
+/// Keeps only the comment lines of a decor string, trimmed.
+fn comment_lines(raw: Option<&str>) -> String {
+    raw.unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|l| l.starts_with('#'))
+        .map(|l| format!("{l}\n"))
+        .collect()
+}
+
+/// Keeps only a trailing comment of a decor string, with a single leading space.
+fn trailing_comment(raw: Option<&str>) -> String {
+    match raw.map(str::trim) {
+        Some(c) if c.starts_with('#') => format!(" {c}"),
+        _ => String::new(),
+    }
+}
+
+fn sections_by_position(doc: &DocumentMut) -> Vec<String> {
+    let mut names: Vec<(String, isize)> = doc.iter()
+        .filter_map(|(k, i)| i.as_table().map(|t| (k.to_string(), t.position().unwrap_or(isize::MAX))))
+        .collect();
+    names.sort_by_key(|(_, pos)| *pos);
+    names.into_iter().map(|(name, _)| name).collect()
+}
+
+fn order_sections(doc: &mut DocumentMut) {
+    let mut names = sections_by_position(doc);
+    names.sort_by_key(|name| STEGO_SECTION_ORDER.iter().position(|s| s == name).unwrap_or(STEGO_SECTION_ORDER.len()));
+    for (i, name) in names.iter().enumerate() {
+        if let Some(t) = doc.get_mut(name).and_then(Item::as_table_mut) {
+            t.set_position(Some(i as isize));
+        }
+    }
+}
+
+/// Puts a blank line before each header and after it, one key per line, normalised quoting.
+fn normalise_table(table: &mut Table, first: bool) {
+    let comments = comment_lines(table.decor().prefix().and_then(|p| p.as_str()));
+    let header_comment = trailing_comment(table.decor().suffix().and_then(|s| s.as_str()));
+    table.decor_mut().set_prefix(if first { comments } else { format!("\n{comments}") });
+    table.decor_mut().set_suffix(header_comment);
+    for (i, (mut key, item)) in table.iter_mut().enumerate() {
+        let comments = comment_lines(key.leaf_decor().prefix().and_then(|p| p.as_str()));
+        key.fmt();
+        key.leaf_decor_mut().set_prefix(if i == 0 { format!("\n{comments}") } else { comments });
+        key.leaf_decor_mut().set_suffix(" ");
+        if let Some(value) = item.as_value_mut() {
+            let comment = trailing_comment(value.decor().suffix().and_then(|s| s.as_str()));
+            if let Value::String(s) = value {
+                s.fmt();
+            }
+            value.decor_mut().set_prefix(" ");
+            value.decor_mut().set_suffix(comment);
+        }
+    }
+}
+
+/// Rewrites `doc` in the canonical `stego.lock` layout.
+pub fn format_stego_document(doc: &mut DocumentMut) {
+    let has_root_keys = doc.iter().any(|(_, i)| i.is_value());
+    // Comments above the first section are the file header and stay on top
+    let file_header = match sections_by_position(doc).first() {
+        Some(name) if !has_root_keys => doc.get_mut(name).and_then(Item::as_table_mut).map(|t| {
+            let header = comment_lines(t.decor().prefix().and_then(|p| p.as_str()));
+            t.decor_mut().set_prefix("");
+            header
+        }),
+        _ => None,
+    };
+    sort_versions(doc);
+    order_sections(doc);
+    for (i, name) in sections_by_position(doc).iter().enumerate() {
+        if let Some(t) = doc.get_mut(name).and_then(Item::as_table_mut) {
+            normalise_table(t, i == 0 && !has_root_keys);
+            if i == 0 {
+                if let Some(ref header) = file_header {
+                    let prefix = comment_lines(t.decor().prefix().and_then(|p| p.as_str()));
+                    t.decor_mut().set_prefix(format!("{header}{prefix}"));
+                }
+            }
+        }
+    }
+    let trailing = comment_lines(doc.trailing().as_str());
+    doc.set_trailing(if trailing.is_empty() { trailing } else { format!("\n{trailing}") });
+}
+
+/// Formats the contents of a `stego.lock` in the canonical layout.
+pub fn format_stego(stego_path: &Path, contents: &str) -> Result<String, AnvilError> {
+    let mut doc = parse_stego_document(stego_path, contents)?;
+    format_stego_document(&mut doc);
+    Ok(doc.to_string())
+}
+
+/// Rewrites the stego.lock in the passed stego dir in the canonical layout.
+///
+/// With `check`, only reports if the file would change.
+pub fn handle_fmt_subcommand(args: &Args, check: bool) -> ExitCode {
+    let stego_path = args.stego_dir.clone().unwrap_or(PathBuf::from(".")).join("stego.lock");
+    let contents = match fs::read_to_string(&stego_path) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Failed reading {{{}}}. Err: {e}", stego_path.display());
+            return ExitCode::from(ANVIL_EXIT_CONFIG);
+        }
+    };
+    let formatted = match format_stego(&stego_path, &contents) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Failed formatting {{{}}}. Err: {}", stego_path.display(), error_chain(&e));
+            return ExitCode::from(ANVIL_EXIT_CONFIG);
+        }
+    };
+    if formatted == contents {
+        debug!("{{{}}} is already formatted", stego_path.display());
+        return ExitCode::SUCCESS;
+    }
+    if check {
+        println!("{}: not formatted", stego_path.display());
+        return ExitCode::from(ANVIL_EXIT_CONFIG);
+    }
+    match fs::write(&stego_path, formatted) {
+        Ok(()) => {
+            info!("Formatted {{{}}}", stego_path.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            error!("Failed writing {{{}}}. Err: {e}", stego_path.display());
+            ExitCode::from(ANVIL_EXIT_FAILURE)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_stego() {
+        let contents = "# project stego\n[versions]\n\"0.2.0\" = 'second'\n# first git tag\n\"0.1.0\"=\"first\" # keep me\n\"B0.0.9\" = \"base\"\n\n\n[build]\nsource=\"main.c\"\nbin = \"main\"\n";
+        let formatted = format_stego(Path::new("stego.lock"), contents).expect("Failed formatting");
+        assert_eq!(formatted, "# project stego\n[build]\n\nsource = \"main.c\"\nbin = \"main\"\n\n[versions]\n\n\"B0.0.9\" = \"base\"\n# first git tag\n\"0.1.0\" = \"first\" # keep me\n\"0.2.0\" = \"second\"\n");
+        assert_eq!(format_stego(Path::new("stego.lock"), &formatted).expect("Failed formatting"), formatted);
+        assert_eq!(version_key_cmp("discover", "B0.0.9"), Ordering::Less);
+        assert_eq!(version_key_cmp("0.1.0", "discover"), Ordering::Greater);
+    }
+
+    #[test]
+    fn test_format_repo_stego_is_stable() {
+        let contents = fs::read_to_string("stego.lock").expect("Failed reading stego.lock");
+        assert_eq!(format_stego(Path::new("stego.lock"), &contents).expect("Failed formatting"), contents);
+    }
+
+    #[test]
+    fn test_add_anvil_version() {
+        let mut doc = parse_stego_document(Path::new("stego.lock"), "[build]\nsource = \"main.c\"\n").expect("Failed parsing");
+        assert!(add_anvil_version(&mut doc, "2.1.3"));
+        assert!(!add_anvil_version(&mut doc, "2.1.3"));
+        assert!(doc.to_string().starts_with("[anvil]\nversion = \"2.1.3\"\n"));
+    }
+}
@@ -0,0 +1,337 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use serde::Serialize;
+use crate::error::AnvilError;
+use crate::makefile::{Makefile, mtime_secs, pattern_stem};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+    Json,
+}
+
+pub const GRAPH_FORMAT_NAMES: &[&str] = &["dot", "mermaid", "json"];
+
+impl GraphFormat {
+    pub fn parse(name: &str) -> Option<GraphFormat> {
+        match name {
+            "dot" => Some(GraphFormat::Dot),
+            "mermaid" => Some(GraphFormat::Mermaid),
+            "json" => Some(GraphFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// A target or prerequisite in the dependency graph
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GraphNode {
+    pub name: String,
+    /// Seconds since the epoch, when the file exists
+    pub mtime: Option<u64>,
+    /// Listed in `.PHONY`
+    pub phony: bool,
+    /// Whether make would remake it
+    pub out_of_date: bool,
+    /// Why it is out of date
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GraphEdge {
+    pub target: String,
+    pub prerequisite: String,
+    pub order_only: bool,
+    /// Found through a pattern rule
+    pub implicit: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MakeGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+struct GraphBuilder<'a> {
+    dir: &'a Path,
+    nodes: BTreeMap<String, GraphNode>,
+    /// Prerequisites of each target, in order
+    deps: BTreeMap<String, Vec<GraphEdge>>,
+}
+
+impl GraphBuilder<'_> {
+    fn add_node(&mut self, name: &str) {
+        if !self.nodes.contains_key(name) {
+            let node = GraphNode {
+                name: name.to_string(),
+                mtime: mtime_secs(&self.dir.join(name)),
+                phony: false,
+                out_of_date: false,
+                reason: None,
+            };
+            self.nodes.insert(name.to_string(), node);
+        }
+    }
+
+    fn add_edge(&mut self, target: &str, prerequisite: &str, order_only: bool, implicit: bool) {
+        self.add_node(target);
+        self.add_node(prerequisite);
+        let edge = GraphEdge { target: target.to_string(), prerequisite: prerequisite.to_string(), order_only, implicit };
+        self.deps.entry(target.to_string()).or_default().push(edge);
+    }
+
+    /// Marks `name` and its prerequisites out of date as make would see them.
+    ///
+    /// `visiting` guards against dependency cycles, which make drops.
+    fn resolve(&mut self, name: &str, done: &mut BTreeMap<String, bool>, visiting: &mut Vec<String>) -> bool {
+        if let Some(&res) = done.get(name) {
+            return res;
+        }
+        if visiting.iter().any(|v| v == name) {
+            warn!("Circular dependency {{{name}}} dropped");
+            return false;
+        }
+        visiting.push(name.to_string());
+        let edges = self.deps.get(name).cloned().unwrap_or_default();
+        let (mtime, phony) = match self.nodes.get(name) {
+            Some(n) => (n.mtime, n.phony),
+            None => (None, false),
+        };
+        let mut reason = if phony {
+            Some("phony".to_string())
+        } else if mtime.is_none() {
+            Some("missing".to_string())
+        } else {
+            None
+        };
+        for edge in edges.iter() {
+            let remade = self.resolve(&edge.prerequisite, done, visiting);
+            if reason.is_some() || edge.order_only {
+                continue;
+            }
+            let prereq_mtime = self.nodes.get(&edge.prerequisite).and_then(|n| n.mtime);
+            if remade {
+                reason = Some(format!("prerequisite {} is out of date", edge.prerequisite));
+            } else if let (Some(t), Some(p)) = (mtime, prereq_mtime) {
+                if p > t {
+                    reason = Some(format!("prerequisite {} is newer", edge.prerequisite));
+                }
+            }
+        }
+        visiting.pop();
+        // A missing file nothing builds is an error for make, not something to remake
+        let buildable = phony || self.deps.contains_key(name);
+        if !buildable && mtime.is_none() {
+            reason = None;
+        }
+        let out_of_date = reason.is_some();
+        if let Some(node) = self.nodes.get_mut(name) {
+            node.out_of_date = out_of_date;
+            node.reason = reason;
+        }
+        done.insert(name.to_string(), out_of_date);
+        out_of_date
+    }
+}
+
+/// Builds the dependency graph of `mk`, with files looked up in `dir`.
+///
+/// Pattern rules are applied to files without a rule, when their prerequisites exist or are targets.
+/// References to variables are not expanded.
+pub fn make_graph(mk: &Makefile, dir: &Path) -> MakeGraph {
+    let mut builder = GraphBuilder { dir, nodes: BTreeMap::new(), deps: BTreeMap::new() };
+    let mut order: Vec<String> = Vec::new();
+    let special = |t: &str| t.starts_with('.') && !t.contains('/');
+    let phony: Vec<&String> = mk.rules.iter()
+        .filter(|r| r.targets.iter().any(|t| t == ".PHONY"))
+        .flat_map(|r| r.prerequisites.iter())
+        .collect();
+    for rule in mk.rules.iter().filter(|r| !r.is_pattern()) {
+        for t in rule.targets.iter().filter(|t| !special(t)) {
+            builder.add_node(t);
+            builder.deps.entry(t.clone()).or_default();
+            for p in rule.prerequisites_for(t).iter() {
+                builder.add_edge(t, p, false, false);
+            }
+            for p in rule.order_only.iter() {
+                builder.add_edge(t, p, true, false);
+            }
+            order.push(t.clone());
+        }
+    }
+    // Files without a rule may be built by a pattern rule
+    let patterns: Vec<_> = mk.rules.iter()
+        .filter(|r| r.is_pattern())
+        .flat_map(|r| r.targets.iter().map(move |t| (t, r)))
+        .collect();
+    let mut pending: Vec<String> = builder.nodes.keys().filter(|n| !builder.deps.contains_key(*n)).cloned().collect();
+    while let Some(name) = pending.pop() {
+        for (pattern, rule) in patterns.iter() {
+            let Some(stem) = pattern_stem(pattern, &name) else {
+                continue;
+            };
+            let prereqs: Vec<String> = rule.prerequisites.iter().map(|p| p.replacen('%', stem, 1)).collect();
+            let found = prereqs.iter().all(|p| builder.nodes.contains_key(p) || builder.dir.join(p).exists());
+            if !found {
+                continue;
+            }
+            builder.deps.entry(name.clone()).or_default();
+            for p in prereqs.iter() {
+                if !builder.nodes.contains_key(p) {
+                    pending.push(p.clone());
+                }
+                builder.add_edge(&name, p, false, true);
+            }
+            break;
+        }
+    }
+    for name in phony {
+        if let Some(node) = builder.nodes.get_mut(name) {
+            node.phony = true;
+        }
+    }
+    let mut done = BTreeMap::new();
+    let names: Vec<String> = builder.nodes.keys().cloned().collect();
+    for name in names.iter() {
+        builder.resolve(name, &mut done, &mut Vec::new());
+    }
+
+    // Targets in file order first, then the other files
+    let mut graph = MakeGraph::default();
+    for name in order.iter().chain(names.iter()) {
+        if let Some(node) = builder.nodes.remove(name) {
+            graph.nodes.push(node);
+        }
+    }
+    for name in order.iter().chain(names.iter()) {
+        if let Some(edges) = builder.deps.remove(name) {
+            graph.edges.extend(edges);
+        }
+    }
+    graph
+}
+
+fn node_label(node: &GraphNode, sep: &str) -> String {
+    // Phony targets are always out of date, with phony as the reason
+    let mut label = node.name.clone();
+    if let Some(mtime) = node.mtime {
+        label.push_str(&format!("{sep}mtime {mtime}"));
+    }
+    if let Some(ref reason) = node.reason {
+        label.push_str(&format!("{sep}out of date: {reason}"));
+    }
+    label
+}
+
+fn dot_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Renders `graph` in graphviz dot, with out of date targets in red.
+pub fn render_dot(graph: &MakeGraph) -> String {
+    let mut res = String::from("digraph Makefile {\n    rankdir=LR;\n    node [shape=box];\n");
+    for node in graph.nodes.iter() {
+        let label = dot_quote(&node_label(node, "\n")).replace('\n', "\\n");
+        let mut attrs = format!("label={label}");
+        if node.out_of_date {
+            attrs.push_str(", color=red");
+        }
+        if node.phony {
+            attrs.push_str(", style=dashed");
+        }
+        let _ = writeln!(res, "    {} [{attrs}];", dot_quote(&node.name));
+    }
+    for edge in graph.edges.iter() {
+        let style = match (edge.order_only, edge.implicit) {
+            (true, _) => " [style=dotted]",
+            (false, true) => " [style=dashed]",
+            (false, false) => "",
+        };
+        let _ = writeln!(res, "    {} -> {}{style};", dot_quote(&edge.target), dot_quote(&edge.prerequisite));
+    }
+    res.push_str("}\n");
+    res
+}
+
+/// Renders `graph` as a mermaid flowchart, with out of date targets in the `outdated` class.
+pub fn render_mermaid(graph: &MakeGraph) -> String {
+    let mut res = String::from("graph LR\n");
+    let ids: BTreeMap<&str, usize> = graph.nodes.iter().enumerate().map(|(i, n)| (n.name.as_str(), i)).collect();
+    for (i, node) in graph.nodes.iter().enumerate() {
+        let label = node_label(node, "<br/>").replace('"', "#quot;");
+        let class = if node.out_of_date { ":::outdated" } else { "" };
+        let _ = writeln!(res, "    n{i}[\"{label}\"]{class}");
+    }
+    for edge in graph.edges.iter() {
+        let arrow = if edge.order_only || edge.implicit { "-.->" } else { "-->" };
+        let _ = writeln!(res, "    n{} {arrow} n{}", ids[edge.target.as_str()], ids[edge.prerequisite.as_str()]);
+    }
+    res.push_str("    classDef outdated stroke:#d00,stroke-width:2px\n");
+    res
+}
+
+/// Renders `graph` in `format`.
+pub fn render_graph(graph: &MakeGraph, format: GraphFormat) -> Result<String, AnvilError> {
+    match format {
+        GraphFormat::Dot => Ok(render_dot(graph)),
+        GraphFormat::Mermaid => Ok(render_mermaid(graph)),
+        GraphFormat::Json => serde_json::to_string_pretty(graph).map_err(|e| AnvilError::Msg(format!("Failed json output for graph. Err: {e}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::SystemTime;
+    use crate::makefile::parse_makefile_str;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn test_make_graph() {
+        let dir = TempDir::new("graph");
+        for name in ["main.c", "util.c", "util.o", "hello"] {
+            fs::write(dir.join(name), "").expect("Failed writing test file");
+        }
+        let old = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        for name in ["util.o", "hello"] {
+            fs::File::options().write(true).open(dir.join(name)).and_then(|f| f.set_modified(old)).expect("Failed setting mtime");
+        }
+        let mk = parse_makefile_str(".PHONY: all\nall: hello\nhello: main.o util.o | bin\n\tcc -o $@ $^\n%.o: %.c\n\tcc -c $<\nbin:\n\tmkdir bin\n").expect("Failed parse");
+        let graph = make_graph(&mk, &dir);
+
+        let node = |name: &str| graph.nodes.iter().find(|n| n.name == name).expect("Missing node");
+        assert_eq!(node("all").reason.as_deref(), Some("phony"));
+        assert_eq!(node("main.o").reason.as_deref(), Some("missing"));
+        assert_eq!(node("util.o").reason.as_deref(), Some("prerequisite util.c is newer"));
+        assert_eq!(node("hello").reason.as_deref(), Some("prerequisite main.o is out of date"));
+        assert!(!node("main.c").out_of_date);
+        assert_eq!(node("util.o").mtime, Some(1_000_000));
+        let implicit: Vec<(&str, &str)> = graph.edges.iter().filter(|e| e.implicit).map(|e| (e.target.as_str(), e.prerequisite.as_str())).collect();
+        assert_eq!(implicit.len(), 2);
+        assert!(implicit.contains(&("main.o", "main.c")));
+        assert!(graph.edges.iter().any(|e| e.target == "hello" && e.prerequisite == "bin" && e.order_only));
+
+        let dot = render_dot(&graph);
+        assert!(dot.contains("    \"all\" -> \"hello\";\n"));
+        assert!(dot.contains("    \"hello\" -> \"bin\" [style=dotted];\n"));
+        let mermaid = render_mermaid(&graph);
+        assert!(mermaid.starts_with("graph LR\n    n0[\"all<br/>out of date: phony\"]:::outdated\n"));
+        assert!(mermaid.contains("    n0 --> n1\n"));
+    }
+}
@@ -0,0 +1,767 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use git2::{Commit, DescribeOptions, ObjectType, Repository, TreeWalkMode, TreeWalkResult};
+use crate::core::{AnvilKern, INVIL_VERSION, INVIL_OS, EXPECTED_AMBOSO_API_LEVEL, git_repo_status_clean};
+use crate::error::AnvilError;
+
+/// Languages a version header can be generated for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeaderLang {
+    C,
+    Python,
+    Rust,
+    Go,
+    Sh,
+    Json,
+    Cmake,
+    Cpp,
+}
+
+pub const HEADER_LANG_NAMES: &[&str] = &["c", "python", "rust", "go", "sh", "json", "cmake", "c++"];
+
+impl HeaderLang {
+    /// Parses a language name, as passed to `--lang` or `[header] lang`.
+    pub fn parse(name: &str) -> Option<HeaderLang> {
+        match name {
+            "c" => Some(HeaderLang::C),
+            "python" | "py" => Some(HeaderLang::Python),
+            "rust" | "rs" => Some(HeaderLang::Rust),
+            "go" => Some(HeaderLang::Go),
+            "sh" => Some(HeaderLang::Sh),
+            "json" => Some(HeaderLang::Json),
+            "cmake" => Some(HeaderLang::Cmake),
+            "c++" | "cpp" => Some(HeaderLang::Cpp),
+            _ => None,
+        }
+    }
+
+    /// Returns `s` as a string literal in this language.
+    pub fn quote(&self, s: &str) -> String {
+        match self {
+            HeaderLang::C | HeaderLang::Cpp => c_quote(s),
+            HeaderLang::Python => py_quote(s),
+            HeaderLang::Rust => rust_quote(s),
+            HeaderLang::Go => go_quote(s),
+            HeaderLang::Sh => sh_quote(s),
+            HeaderLang::Json => json_quote(s),
+            HeaderLang::Cmake => cmake_quote(s),
+        }
+    }
+
+    /// Returns the language used when none is selected, if `kern` has one.
+    pub fn for_kern(kern: AnvilKern) -> Option<HeaderLang> {
+        match kern {
+            AnvilKern::AmbosoC => Some(HeaderLang::C),
+            AnvilKern::AnvilPy => Some(HeaderLang::Python),
+            AnvilKern::Custom => None,
+        }
+    }
+}
+
+impl fmt::Display for HeaderLang {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            HeaderLang::C => "C",
+            HeaderLang::Python => "python",
+            HeaderLang::Rust => "rust",
+            HeaderLang::Go => "go",
+            HeaderLang::Sh => "sh",
+            HeaderLang::Json => "json",
+            HeaderLang::Cmake => "cmake",
+            HeaderLang::Cpp => "c++",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Options for header generation
+#[derive(Debug, Clone, Default)]
+pub struct HeaderOptions {
+    /// Output language. When None, the kern default is used
+    pub lang: Option<HeaderLang>,
+    /// Template used instead of the built-in output
+    pub template: Option<PathBuf>,
+    /// Use the commit time as gen time, when SOURCE_DATE_EPOCH is not set
+    pub reproducible: bool,
+    /// Optional fields to include in built-in headers, from [`HEADER_EXTRA_FIELDS`]
+    pub fields: Vec<String>,
+}
+
+/// Environment variable overriding the gen time, see <https://reproducible-builds.org/specs/source-date-epoch/>
+pub const SOURCE_DATE_EPOCH_VAR: &str = "SOURCE_DATE_EPOCH";
+
+/// Version metadata written to a generated header
+///
+/// Fields that can't be read are left empty.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderInfo {
+    pub bin_name: String,
+    pub tag: String,
+    pub commit_id: String,
+    pub short_id: String,
+    pub commit_time: String,
+    pub author: String,
+    pub commit_message: String,
+    pub gen_time: String,
+    /// Whether the repo has uncommitted changes, `None` when its status can't be read
+    pub dirty: Option<bool>,
+    /// `git describe --tags` style string, like `v0.2.0-3-gabc1234`
+    pub describe: String,
+    /// Current branch, empty when HEAD is detached
+    pub branch: String,
+    pub committer_email: String,
+    /// Number of commits reachable from the commit
+    pub commit_count: String,
+    /// Submodules in the commit tree, as `path=id` separated by spaces
+    pub submodules: String,
+}
+
+fn fill_from_commit(info: &mut HeaderInfo, r: &Repository, commit: &Commit) {
+    if let Ok(msg) = commit.message() {
+        info!("Commit message: {{{}}}", msg);
+        info.commit_message = msg.to_string();
+    }
+    info.commit_id = commit.id().to_string();
+    info!("Commit id: {{{}}}", info.commit_id);
+    if let Some(short_id) = commit.as_object().short_id().ok().and_then(|b| b.as_str().ok().map(str::to_string)) {
+        info.short_id = short_id;
+    }
+    let author = commit.author();
+    match author.name() {
+        Ok(name) => {
+            info.author = name.to_string();
+            info!("Commit author: {{{}}}", info.author);
+        }
+        Err(e) => {
+            warn!("Commit author is empty: {}, {}", e, info.author);
+        }
+    }
+    info.commit_time = commit.time().seconds().to_string();
+    info!("Commit time: {{{}}}", info.commit_time);
+    if let Ok(email) = commit.committer().email() {
+        info.committer_email = email.to_string();
+    }
+    match commit.as_object().describe(DescribeOptions::new().describe_tags()).and_then(|d| d.format(None)) {
+        Ok(describe) => info.describe = describe,
+        Err(e) => debug!("No describe string for {{{}}}. Err: {e}", info.commit_id),
+    }
+    match r.revwalk().and_then(|mut walk| walk.push(commit.id()).map(|_| walk)) {
+        Ok(walk) => info.commit_count = walk.count().to_string(),
+        Err(e) => warn!("Failed counting commits for {{{}}}. Err: {e}", info.commit_id),
+    }
+    let mut submodules = Vec::new();
+    if let Ok(tree) = commit.tree() {
+        // Submodules are recorded in the tree as commit entries
+        let _ = tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+            if entry.kind() == Some(ObjectType::Commit) {
+                submodules.push(format!("{}{}={}", root, entry.name().unwrap_or_default(), entry.id()));
+            }
+            TreeWalkResult::Ok
+        });
+    }
+    info.submodules = submodules.join(" ");
+}
+
+/// Returns true if the repo has uncommitted changes, as checked for git mode, or `None` if its status can't be read.
+fn repo_is_dirty(r: &Repository) -> Option<bool> {
+    match git_repo_status_clean(r) {
+        Ok(clean) => Some(!clean),
+        Err(e) => {
+            warn!("Failed getting repo statuses. Err: {e}");
+            None
+        }
+    }
+}
+
+/// Returns the gen time: `source_date_epoch` when it is a valid timestamp, else the commit time when `reproducible`, else now.
+pub fn gen_time(source_date_epoch: Option<&str>, reproducible: bool, commit_time: &str) -> String {
+    if let Some(epoch) = source_date_epoch {
+        match epoch.trim().parse::<u64>() {
+            Ok(t) => {
+                debug!("Using {SOURCE_DATE_EPOCH_VAR} as gen time: {{{t}}}");
+                return t.to_string();
+            }
+            Err(e) => {
+                warn!("Ignoring invalid {SOURCE_DATE_EPOCH_VAR}: {{{epoch}}}. Err: {e}");
+            }
+        }
+    }
+    if reproducible {
+        debug!("Using commit time as gen time: {{{commit_time}}}");
+        return commit_time.to_string();
+    }
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(t) => format!("{}", t.as_secs()),
+        Err(e) => {
+            error!("Failed getting gen timestamp. Err: {e}");
+            String::new()
+        }
+    }
+}
+
+/// Collects header metadata for `target_tag` from the repo containing `target_path`.
+///
+/// The commit is looked up as `refs/tags/{git_tag}`. When the tag is missing, the commit fields are left empty.
+pub fn header_info(target_path: &Path, target_tag: &str, git_tag: &str, bin_name: &str, reproducible: bool) -> Result<HeaderInfo,AnvilError> {
+    let mut info = HeaderInfo {
+        bin_name: bin_name.to_string(),
+        tag: target_tag.to_string(),
+        ..Default::default()
+    };
+    let r = match Repository::discover(target_path) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Failed discovering repo for {{{}}}. Err: {e}", target_path.display());
+            return Err(e.into());
+        }
+    };
+    info.dirty = repo_is_dirty(&r);
+    if let Ok(head) = r.head() {
+        if head.is_branch() {
+            info.branch = head.shorthand().unwrap_or_default().to_string();
+        }
+    }
+    if target_tag.is_empty() {
+        error!("Invalid empty tag request");
+        return Err(AnvilError::Usage("Invalid empty tag request".to_string()));
+    }
+    let lookup_name = format!("refs/tags/{}", git_tag);
+    match r.find_reference(&lookup_name) {
+        Ok(refr) => {
+            if !refr.is_tag() {
+                error!("{target_tag} is not a reference");
+                return Err(AnvilError::Usage(format!("Requested tag is not a reference: {target_tag}")));
+            }
+            match refr.peel_to_commit() {
+                Ok(commit) => fill_from_commit(&mut info, &r, &commit),
+                Err(e) => {
+                    error!("Failed peel to commit for {{{}}}. Err: {e}", target_tag);
+                    return Err(AnvilError::Git(e));
+                }
+            }
+        }
+        Err(e) => {
+            warn!("Failed getting tag {{{target_tag}}}, commit fields are left empty. Err: {e}");
+        }
+    }
+    info.gen_time = gen_time(env::var(SOURCE_DATE_EPOCH_VAR).ok().as_deref(), reproducible, &info.commit_time);
+    Ok(info)
+}
+
+/// Replaces the characters not allowed in an identifier.
+fn ident(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' }).collect()
+}
+
+/// Returns `s` as a C or C++ string literal. Bytes outside printable ASCII are octal escapes.
+///
+/// A `?` following another one is escaped, so no trigraph is formed.
+pub fn c_quote(s: &str) -> String {
+    let mut res = String::from("\"");
+    let mut prev = 0;
+    for b in s.bytes() {
+        match b {
+            b'?' if prev == b'?' => res.push_str("\\?"),
+            b'"' => res.push_str("\\\""),
+            b'\\' => res.push_str("\\\\"),
+            b'\n' => res.push_str("\\n"),
+            b'\r' => res.push_str("\\r"),
+            b'\t' => res.push_str("\\t"),
+            0x20..=0x7e => res.push(b as char),
+            _ => res.push_str(&format!("\\{b:03o}")),
+        }
+        prev = b;
+    }
+    res.push('"');
+    res
+}
+
+/// Returns `s` as a Rust string literal.
+pub fn rust_quote(s: &str) -> String {
+    format!("{s:?}")
+}
+
+/// Returns `s` as a Python string literal.
+pub fn py_quote(s: &str) -> String {
+    let mut res = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if c.is_control() => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+/// Returns `s` as a Go interpreted string literal.
+pub fn go_quote(s: &str) -> String {
+    let mut res = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if c.is_control() => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+/// Returns `s` as a JSON string.
+pub fn json_quote(s: &str) -> String {
+    let mut res = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+/// Returns `s` as a single quoted shell word.
+pub fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Returns `s` as a CMake quoted argument.
+pub fn cmake_quote(s: &str) -> String {
+    let mut res = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '$' => res.push_str("\\$"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+/// Placeholders accepted by header templates, as `{{name}}`
+pub const HEADER_PLACEHOLDERS: &[&str] = &["bin", "api_level", "tag", "commit_id", "short_id", "date", "author", "message", "gen_time", "invil_version", "os", "dirty", "describe", "branch", "committer_email", "commit_count", "submodules"];
+
+/// Fields only written to built-in headers when listed in `[header] fields`
+pub const HEADER_EXTRA_FIELDS: &[&str] = &["short_id", "describe", "branch", "dirty", "committer_email", "commit_count", "submodules"];
+
+impl HeaderInfo {
+    /// Returns each field, then the `extra` ones, with its `ANVIL__{bin}__` style name and its snake case name.
+    fn fields(&self, extra: &[String]) -> Vec<(String, String, String)> {
+        let bin = ident(&self.bin_name);
+        let mut fields: Vec<(String, String, String)> = [
+            ("ANVIL__API_LEVEL__STRING".to_string(), "anvil_api_level", EXPECTED_AMBOSO_API_LEVEL),
+            (format!("ANVIL__{bin}__VERSION_STRING"), "anvil_version", &self.tag),
+            (format!("ANVIL__{bin}__VERSION_DESC"), "anvil_version_desc", &self.commit_id),
+            (format!("ANVIL__{bin}__VERSION_DATE"), "anvil_version_date", &self.commit_time),
+            (format!("ANVIL__{bin}__VERSION_AUTHOR"), "anvil_version_author", &self.author),
+            (format!("ANVIL__{bin}__HEADER_GENTIME"), "anvil_header_gentime", &self.gen_time),
+            ("INVIL__VERSION__STRING".to_string(), "invil_version", INVIL_VERSION),
+            ("INVIL__OS__STRING".to_string(), "invil_os", INVIL_OS),
+            ("INVIL__COMMIT__DESC__STRING".to_string(), "invil_commit_desc", &self.commit_message),
+        ].into_iter().map(|(macro_name, name, value)| (macro_name, name.to_string(), value.to_string())).collect();
+        fields.extend(self.extra_fields(extra));
+        fields
+    }
+
+    /// Returns the `extra` fields, named as in [`HeaderInfo::fields`].
+    fn extra_fields(&self, extra: &[String]) -> Vec<(String, String, String)> {
+        let bin = ident(&self.bin_name);
+        extra.iter()
+            .filter_map(|name| self.placeholder(name).map(|value| (format!("ANVIL__{bin}__{}", name.to_uppercase()), format!("anvil_{name}"), value)))
+            .collect()
+    }
+
+    /// Returns the value for template placeholder `name`.
+    pub fn placeholder(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "bin" => &self.bin_name,
+            "api_level" => EXPECTED_AMBOSO_API_LEVEL,
+            "tag" => &self.tag,
+            "commit_id" => &self.commit_id,
+            "short_id" => &self.short_id,
+            "date" => &self.commit_time,
+            "author" => &self.author,
+            "message" => &self.commit_message,
+            "gen_time" => &self.gen_time,
+            "invil_version" => INVIL_VERSION,
+            "os" => INVIL_OS,
+            "dirty" => match self.dirty {
+                Some(true) => "true",
+                Some(false) => "false",
+                None => "",
+            },
+            "describe" => &self.describe,
+            "branch" => &self.branch,
+            "committer_email" => &self.committer_email,
+            "commit_count" => &self.commit_count,
+            "submodules" => &self.submodules,
+            _ => return None,
+        };
+        Some(value.to_string())
+    }
+}
+
+/// Renders `template`, replacing each `{{name}}` placeholder with its value from `info`.
+///
+/// `{{name|lang}}` writes the value as a string literal for `lang`, quotes included.
+pub fn render_template(template: &str, info: &HeaderInfo) -> Result<String,AnvilError> {
+    let mut res = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        res.push_str(&rest[..start]);
+        let line = template[..template.len() - rest.len() + start].matches('\n').count() + 1;
+        let Some(end) = rest[start..].find("}}") else {
+            error!("Unterminated placeholder at line {line} of header template");
+            return Err(AnvilError::Config(format!("Unterminated placeholder at line {line} of header template")));
+        };
+        let (name, lang) = match rest[start + 2..start + end].split_once('|') {
+            Some((name, lang)) => match HeaderLang::parse(lang.trim()) {
+                Some(l) => (name.trim(), Some(l)),
+                None => {
+                    error!("Unknown language {{{}}} at line {line} of header template. Known: {}", lang.trim(), HEADER_LANG_NAMES.join(", "));
+                    return Err(AnvilError::Config(format!("Unknown language in header template: {{{}}}", lang.trim())));
+                }
+            },
+            None => (rest[start + 2..start + end].trim(), None),
+        };
+        match info.placeholder(name) {
+            Some(value) => match lang {
+                Some(l) => res.push_str(&l.quote(&value)),
+                None => res.push_str(&value),
+            },
+            None => {
+                error!("Unknown placeholder {{{name}}} at line {line} of header template. Known: {}", HEADER_PLACEHOLDERS.join(", "));
+                return Err(AnvilError::Config(format!("Unknown placeholder in header template: {{{name}}}")));
+            }
+        }
+        rest = &rest[start + end + 2..];
+    }
+    res.push_str(rest);
+    Ok(res)
+}
+
+/// Returns the name of the file generated from `template`: its file name, without a `.tmpl` extension.
+pub fn template_output_name(template: &Path) -> String {
+    let name = template.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    match name.strip_suffix(".tmpl") {
+        Some(stripped) => stripped.to_string(),
+        None => name,
+    }
+}
+
+/// Renders the files for `info` in `lang`, as pairs of file name and contents. `extra` names the optional fields to include.
+pub fn render_header(lang: HeaderLang, info: &HeaderInfo, extra: &[String]) -> Vec<(String, String)> {
+    let bin_name = &info.bin_name;
+    match lang {
+        HeaderLang::C => vec![
+            (format!("anvil__{bin_name}.h"), render_c_header(info, extra)),
+            (format!("anvil__{bin_name}.c"), render_c_impl(info)),
+        ],
+        HeaderLang::Python => vec![(format!("anvil__{bin_name}.py"), render_python(info, extra))],
+        HeaderLang::Rust => vec![(format!("anvil__{bin_name}.rs"), render_rust(info, extra))],
+        HeaderLang::Go => vec![(format!("anvil__{bin_name}.go"), render_go(info, extra))],
+        HeaderLang::Sh => vec![(format!("anvil__{bin_name}.sh"), render_sh(info, extra))],
+        HeaderLang::Json => vec![(format!("anvil__{bin_name}.json"), render_json(info, extra))],
+        HeaderLang::Cmake => vec![(format!("anvil__{bin_name}.cmake"), render_cmake(info, extra))],
+        HeaderLang::Cpp => vec![(format!("anvil__{bin_name}.hpp"), render_cpp(info, extra))],
+    }
+}
+
+fn render_c_header(info: &HeaderInfo, extra: &[String]) -> String {
+    let bin_name = &info.bin_name;
+    let bin = ident(bin_name);
+    let api_level = c_quote(EXPECTED_AMBOSO_API_LEVEL);
+    let target_tag = c_quote(&info.tag);
+    let id = c_quote(&info.commit_id);
+    let commit_time = c_quote(&info.commit_time);
+    let head_author_name = c_quote(&info.author);
+    let fgen_time = c_quote(&info.gen_time);
+    let invil_version = c_quote(INVIL_VERSION);
+    let invil_os = c_quote(INVIL_OS);
+    let commit_message = c_quote(&info.commit_message);
+    let extra_decls: String = info.extra_fields(extra).into_iter()
+        .map(|(name, field, value)| format!("static const char {name}[] = {}; /**< Represents {field} for [anvil__{bin_name}.h] generated header.*/\n\n", c_quote(&value)))
+        .collect();
+    format!("//Generated by invil v{INVIL_VERSION}\n
+//Repo at https://github.com/jgabaut/invil\n
+#ifndef ANVIL__{bin}__\n
+#define ANVIL__{bin}__\n
+static const char ANVIL__API_LEVEL__STRING[] = {api_level}; /**< Represents amboso version used for [anvil__{bin_name}.h] generated header.*/\n
+static const char ANVIL__{bin}__VERSION_STRING[] = {target_tag}; /**< Represents current version for [anvil__{bin_name}.h] generated header.*/\n
+static const char ANVIL__{bin}__VERSION_DESC[] = {id}; /**< Represents current version info for [anvil__{bin_name}.h] generated header.*/\n
+static const char ANVIL__{bin}__VERSION_DATE[] = {commit_time}; /**< Represents date for current version for [anvil__{bin_name}.h] generated header.*/\n
+static const char ANVIL__{bin}__VERSION_AUTHOR[] = {head_author_name}; /**< Represents author for current version for [anvil__{bin_name}.h] generated header.*/\n
+static const char ANVIL__{bin}__HEADER_GENTIME[] = {fgen_time}; /**< Represents gen time for [anvil__{bin_name}.h] generated header.*/\n
+const char *get_ANVIL__API__LEVEL__(void); /**< Returns a version string for amboso API of [anvil__{bin_name}.h] generated header.*/\n
+const char *get_ANVIL__VERSION__(void); /**< Returns a version string for [anvil__{bin_name}.h] generated header.*/\n
+const char *get_ANVIL__VERSION__DESC__(void); /**< Returns a version info string for [anvil__{bin_name}.h] generated header.*/\n
+const char *get_ANVIL__VERSION__DATE__(void); /**< Returns a version date string for [anvil__{bin_name}.h] generated header.*/\n
+const char *get_ANVIL__VERSION__AUTHOR__(void); /**< Returns a version author string for [anvil__{bin_name}.h] generated header.*/\n
+const char *get_ANVIL__HEADER__GENTIME__(void); /**< Returns a string for time of gen for [anvil__{bin_name}.h] generated header.*/\n
+{extra_decls}#ifndef INVIL__{bin}__HEADER__
+#define INVIL__{bin}__HEADER__
+static const char INVIL__VERSION__STRING[] = {invil_version}; /**< Represents invil version used for [anvil__{bin_name}.h] generated header.*/\n
+static const char INVIL__OS__STRING[] = {invil_os}; /**< Represents build os used for [anvil__{bin_name}.h] generated header.*/\n
+static const char INVIL__COMMIT__DESC__STRING[] = {commit_message}; /**< Represents message for HEAD commit used for [anvil__{bin_name}.h] generated header.*/\n
+const char *get_INVIL__API__LEVEL__(void); /**< Returns a version string for invil version of [anvil__{bin_name}.h] generated header.*/\n
+const char *get_INVIL__OS__(void); /**< Returns a version string for os used for [anvil__{bin_name}.h] generated header.*/\n
+const char *get_INVIL__COMMIT__DESC__(void); /**< Returns a string for HEAD commit message used for [anvil__{bin_name}.h] generated header.*/\n
+#endif // INVIL__{bin}__HEADER__
+#endif\n")
+}
+
+fn render_c_impl(info: &HeaderInfo) -> String {
+    let bin_name = &info.bin_name;
+    let bin = ident(bin_name);
+    format!("//Generated by invil v{INVIL_VERSION}\n
+#include \"anvil__{bin_name}.h\"\n
+const char *get_ANVIL__VERSION__(void)
+{{
+    return ANVIL__{bin}__VERSION_STRING;
+}}\n
+const char *get_ANVIL__API__LEVEL__(void)
+{{
+    return ANVIL__API_LEVEL__STRING;
+}}\n
+const char *get_ANVIL__VERSION__DESC__(void)
+{{
+    return ANVIL__{bin}__VERSION_DESC;
+}}\n
+const char *get_ANVIL__VERSION__DATE__(void)
+{{
+    return ANVIL__{bin}__VERSION_DATE;
+}}\n
+const char *get_ANVIL__VERSION__AUTHOR__(void)
+{{
+    return ANVIL__{bin}__VERSION_AUTHOR;
+}}\n
+const char *get_ANVIL__HEADER__GENTIME__(void)
+{{
+    return ANVIL__{bin}__HEADER_GENTIME;
+}}\n
+#ifdef INVIL__{bin}__HEADER__
+const char *get_INVIL__API__LEVEL__(void)
+{{
+    return INVIL__VERSION__STRING;
+}}\n
+const char *get_INVIL__COMMIT__DESC__(void)
+{{
+    return INVIL__COMMIT__DESC__STRING;
+}}\n
+const char *get_INVIL__OS__(void)
+{{
+    return INVIL__OS__STRING;
+}}
+#endif\n")
+}
+
+fn render_python(info: &HeaderInfo, extra: &[String]) -> String {
+    let bin_name = &info.bin_name;
+    let bin = ident(bin_name);
+    let api_level = py_quote(EXPECTED_AMBOSO_API_LEVEL);
+    let target_tag = py_quote(&info.tag);
+    let id = py_quote(&info.commit_id);
+    let commit_time = py_quote(&info.commit_time);
+    let head_author_name = py_quote(&info.author);
+    let fgen_time = py_quote(&info.gen_time);
+    let extra_decls: String = info.extra_fields(extra).into_iter()
+        .map(|(name, _, value)| format!("\n{name} = {}\n", py_quote(&value)))
+        .collect();
+    format!("# Generated by invil v{INVIL_VERSION}\n
+# Repo at https://github.com/jgabaut/invil\n
+ANVIL__API_LEVEL__STRING = {api_level}\n
+ANVIL__{bin}__VERSION_STRING = {target_tag}\n
+ANVIL__{bin}__VERSION_DESC = {id}\n
+ANVIL__{bin}__VERSION_DATE = {commit_time}\n
+ANVIL__{bin}__VERSION_AUTHOR = {head_author_name}\n
+ANVIL__{bin}__HEADER_GENTIME = {fgen_time}\n
+def get_ANVIL_API_LEVEL__() -> str:\n    return ANVIL__API_LEVEL_STRING\n
+def get_ANVIL__VERSION__() -> str:\n    return ANVIL__{bin}__VERSION_STRING\n
+def get_ANVIL__VERSION__DESC__() -> str:\n    return ANVIL__{bin}__VERSION_DESC\n
+def get_ANVIL__VERSION__DATE__() -> str:\n    return ANVIL__{bin}__VERSION_DATE\n
+def get_ANVIL__VERSION__AUTHOR__() -> str:\n    return ANVIL__{bin}__VERSION_AUTHOR\n
+def get_ANVIL__VERSION__HEADER_GENTIME__() -> str:\n    return ANVIL__{bin}__HEADER_GENTIME\n{extra_decls}")
+}
+
+fn render_rust(info: &HeaderInfo, extra: &[String]) -> String {
+    let mut res = format!("// Generated by invil v{INVIL_VERSION}\n// Repo at https://github.com/jgabaut/invil\n\n");
+    for (_, name, value) in info.fields(extra) {
+        res.push_str(&format!("pub const {}: &str = {};\n", name.to_uppercase(), rust_quote(&value)));
+    }
+    res
+}
+
+fn render_go(info: &HeaderInfo, extra: &[String]) -> String {
+    let mut res = format!("// Code generated by invil v{INVIL_VERSION}. DO NOT EDIT.\n// Repo at https://github.com/jgabaut/invil\n\npackage main\n\nconst (\n");
+    for (_, name, value) in info.fields(extra) {
+        let go_name: String = name.split('_').map(|w| w[..1].to_uppercase() + &w[1..]).collect();
+        res.push_str(&format!("\t{go_name} = {}\n", go_quote(&value)));
+    }
+    res.push_str(")\n");
+    res
+}
+
+fn render_sh(info: &HeaderInfo, extra: &[String]) -> String {
+    let mut res = format!("# Generated by invil v{INVIL_VERSION}\n# Repo at https://github.com/jgabaut/invil\n\n");
+    for (name, _, value) in info.fields(extra) {
+        res.push_str(&format!("{name}={}\n", sh_quote(&value)));
+    }
+    res
+}
+
+fn render_json(info: &HeaderInfo, extra: &[String]) -> String {
+    let fields: Vec<String> = info.fields(extra).into_iter()
+        .map(|(_, name, value)| format!("  \"{name}\": {}", json_quote(&value)))
+        .collect();
+    format!("{{\n{}\n}}\n", fields.join(",\n"))
+}
+
+fn render_cmake(info: &HeaderInfo, extra: &[String]) -> String {
+    let mut res = format!("# Generated by invil v{INVIL_VERSION}\n# Repo at https://github.com/jgabaut/invil\n\n");
+    for (name, _, value) in info.fields(extra) {
+        res.push_str(&format!("set({name} {})\n", cmake_quote(&value)));
+    }
+    res
+}
+
+fn render_cpp(info: &HeaderInfo, extra: &[String]) -> String {
+    let mut res = format!("// Generated by invil v{INVIL_VERSION}\n// Repo at https://github.com/jgabaut/invil\n\n#pragma once\n\n#include <string_view>\n\nnamespace anvil__{} {{\n\n", ident(&info.bin_name));
+    for (_, name, value) in info.fields(extra) {
+        res.push_str(&format!("inline constexpr std::string_view {} = {};\n", name.to_uppercase(), c_quote(&value)));
+    }
+    res.push_str(&format!("\n}} // namespace anvil__{}\n", ident(&info.bin_name)));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> HeaderInfo {
+        HeaderInfo {
+            bin_name: "hello-world".to_string(),
+            tag: "0.2.0".to_string(),
+            commit_id: "abc123".to_string(),
+            short_id: "abc".to_string(),
+            commit_time: "1700000000".to_string(),
+            author: "O'Brien".to_string(),
+            commit_message: "Say \"hi\"\n".to_string(),
+            gen_time: "1700000001".to_string(),
+            dirty: Some(false),
+            describe: "v0.2.0-1-gabc".to_string(),
+            branch: "main".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn render(lang: HeaderLang) -> String {
+        render_header(lang, &info(), &[]).into_iter().map(|(_, contents)| contents).collect()
+    }
+
+    #[test]
+    fn test_render_header() {
+        assert_eq!(HeaderLang::parse("c++"), Some(HeaderLang::Cpp));
+        assert_eq!(HeaderLang::parse("java"), None);
+        assert!(render(HeaderLang::C).contains("ANVIL__hello_world__VERSION_STRING[] = \"0.2.0\""));
+        assert!(render(HeaderLang::C).contains("#include \"anvil__hello-world.h\"\n"));
+        assert!(!render(HeaderLang::C).contains("__hello-world__"));
+        assert!(render(HeaderLang::Python).contains("    return ANVIL__hello_world__VERSION_STRING\n"));
+        assert!(render(HeaderLang::Rust).contains("pub const ANVIL_VERSION: &str = \"0.2.0\";\n"));
+        assert!(render(HeaderLang::Rust).contains("pub const INVIL_COMMIT_DESC: &str = \"Say \\\"hi\\\"\\n\";\n"));
+        assert!(render(HeaderLang::Go).contains("\tAnvilVersionAuthor = \"O'Brien\"\n"));
+        assert!(render(HeaderLang::Sh).contains("ANVIL__hello_world__VERSION_AUTHOR='O'\\''Brien'\n"));
+        assert!(render(HeaderLang::Json).contains("  \"invil_commit_desc\": \"Say \\\"hi\\\"\\n\"\n}"));
+        assert!(render(HeaderLang::Cmake).contains("set(ANVIL__hello_world__VERSION_DESC \"abc123\")\n"));
+        assert!(render(HeaderLang::Cpp).contains("namespace anvil__hello_world {"));
+        assert!(render(HeaderLang::Cpp).contains("inline constexpr std::string_view ANVIL_VERSION_DATE = \"1700000000\";\n"));
+    }
+
+    #[test]
+    fn test_render_extra_fields() {
+        let extra = vec!["describe".to_string(), "dirty".to_string()];
+        let files = render_header(HeaderLang::C, &info(), &extra);
+        assert!(files[0].1.contains("static const char ANVIL__hello_world__DESCRIBE[] = \"v0.2.0-1-gabc\";"));
+        let files = render_header(HeaderLang::Json, &info(), &extra);
+        assert!(files[0].1.ends_with("  \"anvil_describe\": \"v0.2.0-1-gabc\",\n  \"anvil_dirty\": \"false\"\n}\n"));
+        let files = render_header(HeaderLang::Python, &info(), &extra);
+        assert!(files[0].1.ends_with("ANVIL__hello_world__DIRTY = \"false\"\n"));
+        // Missing fields are empty
+        let info = HeaderInfo { bin_name: "a".to_string(), ..Default::default() };
+        assert!(render_header(HeaderLang::Rust, &info, &[])[0].1.contains("pub const ANVIL_VERSION_DESC: &str = \"\";"));
+        assert_eq!(info.placeholder("dirty").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn test_quote() {
+        let s = "a \"b\" \\ c\nd é ??=";
+        assert_eq!(c_quote(s), "\"a \\\"b\\\" \\\\ c\\nd \\303\\251 ?\\?=\"");
+        assert_eq!(py_quote(s), "\"a \\\"b\\\" \\\\ c\\nd é ??=\"");
+        assert_eq!(rust_quote(s), "\"a \\\"b\\\" \\\\ c\\nd é ??=\"");
+        assert_eq!(go_quote("a\u{1}\"é"), "\"a\\u0001\\\"é\"");
+        assert_eq!(json_quote("a\u{1}\\é\n"), "\"a\\u0001\\\\é\\n\"");
+        assert_eq!(sh_quote("it's \"$HOME\"\n"), "'it'\\''s \"$HOME\"\n'");
+        assert_eq!(cmake_quote("${X} \"é\"\\"), "\"\\${X} \\\"é\\\"\\\\\"");
+        // Every field is escaped in the built-in headers
+        let info = HeaderInfo { bin_name: "a".to_string(), tag: "1\"".to_string(), author: "Zoë \"Z\"".to_string(), commit_message: "fix\\n??!\n".to_string(), ..Default::default() };
+        let c = render_header(HeaderLang::C, &info, &[]).remove(0).1;
+        assert!(c.contains("ANVIL__a__VERSION_STRING[] = \"1\\\"\";"));
+        assert!(c.contains("ANVIL__a__VERSION_AUTHOR[] = \"Zo\\303\\253 \\\"Z\\\"\";"));
+        assert!(c.contains("INVIL__COMMIT__DESC__STRING[] = \"fix\\\\n?\\?!\\n\";"));
+        let py = render_header(HeaderLang::Python, &info, &[]).remove(0).1;
+        assert!(py.contains("ANVIL__a__VERSION_AUTHOR = \"Zoë \\\"Z\\\"\"\n"));
+        assert_eq!(render_template("{{author|sh}} {{ tag | json }}", &info).unwrap(), "'Zoë \"Z\"' \"1\\\"\"");
+        assert!(render_template("{{author|java}}", &info).is_err());
+    }
+
+    #[test]
+    fn test_render_template() {
+        let res = render_template("#define V \"{{tag}}\" /* {{ short_id }}, dirty: {{dirty}} */\n", &info());
+        assert_eq!(res.unwrap(), "#define V \"0.2.0\" /* abc, dirty: false */\n");
+        assert!(render_template("{{nope}}", &info()).is_err());
+        assert!(render_template("a\n{{tag", &info()).is_err());
+        assert_eq!(template_output_name(Path::new("tools/version.h.tmpl")), "version.h");
+    }
+
+    #[test]
+    fn test_gen_time() {
+        assert_eq!(gen_time(Some("1600000000"), true, "1700000000"), "1600000000");
+        assert_eq!(gen_time(Some("yesterday"), true, "1700000000"), "1700000000");
+        assert_eq!(gen_time(None, true, "1700000000"), "1700000000");
+        assert_ne!(gen_time(None, false, "1700000000"), "1700000000");
+    }
+}
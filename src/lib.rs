@@ -0,0 +1,42 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#[macro_use] extern crate log;
+
+pub mod config;
+pub mod core;
+pub mod error;
+pub mod fmt;
+pub mod graph;
+pub mod header;
+pub mod lint;
+pub mod make;
+pub mod makefile;
+pub mod migrate;
+pub mod ops;
+pub mod utils;
+pub mod project;
+pub mod query;
+pub mod release;
+pub mod semver;
+pub mod stego;
+pub mod tag;
+#[cfg(feature = "anvilPy")]
+pub mod anvil_py;
+#[cfg(feature = "anvilCustom")]
+pub mod anvil_custom;
+
+pub use crate::project::{Project, BuildOutcome, TestCase, TestKind, TestOutcome};
+pub use crate::core::AmbosoMode;
+pub use crate::error::AnvilError;
@@ -0,0 +1,687 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use git2::Repository;
+use is_executable::is_executable;
+use toml_edit::DocumentMut;
+use crate::core::{Args, ANVIL_EXIT_CONFIG, ANVIL_EXIT_FAILURE, EXPECTED_AMBOSO_API_LEVEL, is_semver, semver_compare};
+use crate::error::{AnvilError, error_chain, render_diagnostic};
+use crate::fmt::{parse_stego_document, versions_sorted, sort_versions, has_anvil_version, add_anvil_version};
+use crate::makefile::{AssignOp, Makefile, Reference, parse_makefile, pattern_stem, references};
+use crate::stego::{StegoLock, StegoLint, deserialize_stego, parse_stego_str, unknown_key_warnings, key_offset, suggest, STEGO_BUILD_SECTION, STEGO_DISCOVER_KEYNAME, STEGO_LINT_SECTION, STEGO_VERSIONS_SECTION};
+use crate::tag::discover_git_tags;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LintSeverity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for LintSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LintSeverity::Info => write!(f, "info"),
+            LintSeverity::Warning => write!(f, "warning"),
+            LintSeverity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl LintSeverity {
+    pub fn parse(level: &str) -> Option<LintSeverity> {
+        match level {
+            "info" => Some(LintSeverity::Info),
+            "warning" => Some(LintSeverity::Warning),
+            "error" => Some(LintSeverity::Error),
+            _ => None,
+        }
+    }
+}
+
+/// Level in `[lint]` turning a rule off
+pub const LINT_LEVEL_OFF: &str = "off";
+const LINT_LEVELS: &[&str] = &[LINT_LEVEL_OFF, "info", "warning", "error"];
+
+#[derive(Debug)]
+pub struct LintRule {
+    pub name: &'static str,
+    pub severity: LintSeverity,
+    pub description: &'static str,
+    /// Whether `invil lint --fix` can apply a safe fix
+    pub fixable: bool,
+}
+
+pub const LINT_UNKNOWN_KEY: &str = "unknown-key";
+pub const LINT_INVALID_TAG: &str = "invalid-tag";
+pub const LINT_MISSING_GIT_TAG: &str = "missing-git-tag";
+pub const LINT_MAKEVERS_ORDER: &str = "makevers-after-automakevers";
+pub const LINT_MISSING_SOURCE: &str = "missing-source";
+pub const LINT_MISSING_TESTS_DIR: &str = "missing-tests-dir";
+pub const LINT_DUPLICATE_TAG: &str = "duplicate-tag";
+pub const LINT_NON_EXECUTABLE_TEST: &str = "non-executable-test";
+pub const LINT_EMPTY_DESCRIPTION: &str = "empty-description";
+pub const LINT_UNSORTED_VERSIONS: &str = "unsorted-versions";
+pub const LINT_MISSING_ANVIL_VERSION: &str = "missing-anvil-version";
+pub const LINT_UNLISTED_GIT_TAG: &str = "unlisted-git-tag";
+pub const LINT_MAKE_UNDECLARED_PHONY: &str = "make-undeclared-phony";
+pub const LINT_MAKE_MISSING_REBUILD: &str = "make-missing-rebuild";
+pub const LINT_MAKE_MISSING_PREREQUISITE: &str = "make-missing-prerequisite";
+pub const LINT_MAKE_DUPLICATE_RECIPE: &str = "make-duplicate-recipe";
+pub const LINT_MAKE_UNDEFINED_VARIABLE: &str = "make-undefined-variable";
+pub const LINT_MAKE_SHELL_CALL: &str = "make-shell-call";
+
+pub const LINT_RULES: &[LintRule] = &[
+    LintRule { name: LINT_UNKNOWN_KEY, severity: LintSeverity::Warning, description: "Unknown section or key", fixable: false },
+    LintRule { name: LINT_INVALID_TAG, severity: LintSeverity::Error, description: "Version key is not a valid semver", fixable: false },
+    LintRule { name: LINT_MISSING_GIT_TAG, severity: LintSeverity::Error, description: "Git mode tag does not exist in the repo", fixable: false },
+    LintRule { name: LINT_MAKEVERS_ORDER, severity: LintSeverity::Warning, description: "makevers is greater than automakevers", fixable: false },
+    LintRule { name: LINT_MISSING_SOURCE, severity: LintSeverity::Error, description: "Source file is missing at a tag", fixable: false },
+    LintRule { name: LINT_MISSING_TESTS_DIR, severity: LintSeverity::Warning, description: "Tests directory does not exist", fixable: false },
+    LintRule { name: LINT_DUPLICATE_TAG, severity: LintSeverity::Warning, description: "Version is listed both as base and git mode tag", fixable: false },
+    LintRule { name: LINT_NON_EXECUTABLE_TEST, severity: LintSeverity::Warning, description: "Test file is not executable", fixable: false },
+    LintRule { name: LINT_EMPTY_DESCRIPTION, severity: LintSeverity::Warning, description: "Version description is empty", fixable: false },
+    LintRule { name: LINT_UNSORTED_VERSIONS, severity: LintSeverity::Info, description: "Versions are not sorted", fixable: true },
+    LintRule { name: LINT_MISSING_ANVIL_VERSION, severity: LintSeverity::Info, description: "No [anvil] version, the -a value is used", fixable: true },
+    LintRule { name: LINT_UNLISTED_GIT_TAG, severity: LintSeverity::Warning, description: "Git tag matching [versions] discover is not listed", fixable: false },
+    LintRule { name: LINT_MAKE_UNDECLARED_PHONY, severity: LintSeverity::Warning, description: "Makefile target looks phony but is not declared .PHONY", fixable: false },
+    LintRule { name: LINT_MAKE_MISSING_REBUILD, severity: LintSeverity::Warning, description: "Makefile has no rebuild target, which make build mode runs", fixable: false },
+    LintRule { name: LINT_MAKE_MISSING_PREREQUISITE, severity: LintSeverity::Warning, description: "Makefile prerequisite is neither a file nor a target", fixable: false },
+    LintRule { name: LINT_MAKE_DUPLICATE_RECIPE, severity: LintSeverity::Warning, description: "Makefile target has more than one recipe", fixable: false },
+    LintRule { name: LINT_MAKE_UNDEFINED_VARIABLE, severity: LintSeverity::Warning, description: "Makefile variable is used but never set", fixable: false },
+    LintRule { name: LINT_MAKE_SHELL_CALL, severity: LintSeverity::Info, description: "Makefile uses $(shell ...), which runs whenever it is read", fixable: false },
+];
+
+pub fn lint_rule(name: &str) -> Option<&'static LintRule> {
+    LINT_RULES.iter().find(|r| r.name == name)
+}
+
+#[derive(Debug)]
+pub struct LintFinding {
+    pub rule: &'static str,
+    pub severity: LintSeverity,
+    /// Message, possibly followed by a source snippet
+    pub message: String,
+}
+
+impl fmt::Display for LintFinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.rule, self.message)
+    }
+}
+
+/// Paths used to resolve what `stego.lock` refers to.
+#[derive(Debug)]
+pub struct LintContext {
+    pub stego_path: PathBuf,
+    pub amboso_dir: PathBuf,
+}
+
+struct Linter<'a> {
+    ctx: &'a LintContext,
+    contents: &'a str,
+    findings: Vec<LintFinding>,
+}
+
+/// Returns a finding for `rule`, at its default severity.
+fn finding(rule: &'static str, message: String) -> LintFinding {
+    let severity = lint_rule(rule).map(|r| r.severity).unwrap_or(LintSeverity::Warning);
+    LintFinding { rule, severity, message }
+}
+
+/// Applies the levels set in `[lint]`, dropping findings for rules turned off.
+pub fn apply_lint_levels(findings: Vec<LintFinding>, lint: Option<&StegoLint>) -> Vec<LintFinding> {
+    let Some(lint) = lint else {
+        return findings;
+    };
+    findings.into_iter()
+        .filter_map(|mut f| {
+            match lint.levels.get(f.rule).map(String::as_str) {
+                Some(LINT_LEVEL_OFF) => return None,
+                Some(level) => f.severity = LintSeverity::parse(level).unwrap_or(f.severity),
+                None => {}
+            }
+            Some(f)
+        })
+        .collect()
+}
+
+impl Linter<'_> {
+    fn push(&mut self, rule: &'static str, message: String) {
+        self.findings.push(finding(rule, message));
+    }
+
+    fn push_with(&mut self, rule: &'static str, severity: LintSeverity, message: String) {
+        self.findings.push(LintFinding { rule, severity, message });
+    }
+
+    /// Renders `message` at `key` in `[section]` when it can be found.
+    fn at_key(&self, section: &str, key: &str, message: &str) -> String {
+        match key_offset(self.contents, section, key) {
+            Some(pos) => render_diagnostic(&self.ctx.stego_path, self.contents, pos, message),
+            None => format!("{}: {message}", self.ctx.stego_path.display()),
+        }
+    }
+}
+
+/// Runs every lint rule on the `stego.lock` at `ctx.stego_path`.
+///
+/// Returns an error only when the file can't be read or parsed.
+pub fn lint_stego(ctx: &LintContext) -> Result<Vec<LintFinding>, AnvilError> {
+    let contents = fs::read_to_string(&ctx.stego_path).map_err(|e| AnvilError::io(&ctx.stego_path, e))?;
+    let stego = parse_stego_str(&ctx.stego_path, &contents)?;
+    let mut linter = Linter { ctx, contents: &contents, findings: Vec::new() };
+
+    for w in unknown_key_warnings(&ctx.stego_path, &contents) {
+        linter.findings.push(LintFinding { rule: LINT_UNKNOWN_KEY, severity: LintSeverity::Warning, message: w });
+    }
+    lint_versions(&mut linter, &stego);
+    lint_build(&mut linter, &stego);
+    lint_tests(&mut linter, &stego);
+    lint_layout(&mut linter);
+    lint_levels(&mut linter, &stego);
+    Ok(apply_lint_levels(linter.findings, stego.lint.as_ref()))
+}
+
+fn lint_levels(linter: &mut Linter, stego: &StegoLock) {
+    let Some(ref lint) = stego.lint else {
+        return;
+    };
+    let names: Vec<&str> = LINT_RULES.iter().map(|r| r.name).collect();
+    for (rule, level) in lint.levels.iter() {
+        if lint_rule(rule).is_none() {
+            let mut message = format!("unknown lint rule: {{{rule}}}");
+            if let Some(s) = suggest(rule, &names) {
+                message.push_str(&format!(", did you mean {{{s}}}?"));
+            }
+            let msg = linter.at_key(STEGO_LINT_SECTION, rule, &message);
+            linter.push(LINT_UNKNOWN_KEY, msg);
+        } else if !LINT_LEVELS.contains(&level.as_str()) {
+            let msg = linter.at_key(STEGO_LINT_SECTION, rule, &format!("unknown level {{{level}}} for {{{rule}}}, expected one of: {}", LINT_LEVELS.join(", ")));
+            linter.push(LINT_UNKNOWN_KEY, msg);
+        }
+    }
+}
+
+fn lint_versions(linter: &mut Linter, stego: &StegoLock) {
+    let Some(ref versions) = stego.versions else {
+        return;
+    };
+    let repo = match linter.ctx.stego_path.parent() {
+        Some(dir) => Repository::discover(dir).ok(),
+        None => None,
+    };
+    if repo.is_none() {
+        debug!("No repo found for {{{}}}, skipping git tag checks", linter.ctx.stego_path.display());
+    }
+    let makevers = stego.build.as_ref().and_then(|b| b.makevers.clone());
+    let source = stego.build.as_ref().and_then(|b| b.source.clone());
+    let discovered = match (&versions.discover, &repo) {
+        (Some(pattern), Some(repo)) => discover_git_tags(repo, pattern).unwrap_or_else(|e| {
+            warn!("Failed discovering git tags for {{{pattern}}}. Err: {e}");
+            Vec::new()
+        }),
+        _ => Vec::new(),
+    };
+
+    for (key, desc) in versions.tags.iter() {
+        if desc.trim().is_empty() {
+            let msg = linter.at_key(STEGO_VERSIONS_SECTION, key, &format!("empty description for {{{key}}}"));
+            linter.push(LINT_EMPTY_DESCRIPTION, msg);
+        }
+        let (is_base, tag) = match key.strip_prefix('B') {
+            Some(t) => (true, t),
+            None => (false, key.as_str()),
+        };
+        if !is_semver(tag) {
+            let msg = linter.at_key(STEGO_VERSIONS_SECTION, key, &format!("{{{tag}}} is not a valid semver"));
+            linter.push(LINT_INVALID_TAG, msg);
+            continue;
+        }
+        if is_base {
+            if versions.tags.contains_key(tag) {
+                let msg = linter.at_key(STEGO_VERSIONS_SECTION, key, &format!("{{{tag}}} is listed both as base and git mode tag"));
+                linter.push(LINT_DUPLICATE_TAG, msg);
+            }
+            if let Some(ref src) = source {
+                let mut src_path = linter.ctx.amboso_dir.clone();
+                src_path.push(format!("v{tag}"));
+                src_path.push(src);
+                if !src_path.exists() {
+                    let msg = linter.at_key(STEGO_VERSIONS_SECTION, key, &format!("missing source {{{}}} for base tag {{{tag}}}", src_path.display()));
+                    linter.push(LINT_MISSING_SOURCE, msg);
+                }
+            }
+            continue;
+        }
+        let Some(ref repo) = repo else {
+            continue;
+        };
+        let git_tag = discovered.iter().find(|d| d.version == tag).map_or(tag, |d| d.name.as_str());
+        let reference = match repo.find_reference(&format!("refs/tags/{git_tag}")) {
+            Ok(r) => r,
+            Err(_) => {
+                let msg = linter.at_key(STEGO_VERSIONS_SECTION, key, &format!("tag {{{tag}}} does not exist in the repo"));
+                linter.push(LINT_MISSING_GIT_TAG, msg);
+                continue;
+            }
+        };
+        if let Some(ref src) = source {
+            let found = match reference.peel_to_tree() {
+                Ok(tree) => tree.get_path(Path::new(src)).is_ok(),
+                Err(e) => {
+                    warn!("Failed reading tree for {{{tag}}}. Err: {e}");
+                    true
+                }
+            };
+            if !found {
+                // Tags before makevers are built with a single compiler call, so they need the source
+                let single_file = match makevers {
+                    Some(ref m) => semver_compare(tag, m) == Ordering::Less,
+                    None => true,
+                };
+                let severity = if single_file { LintSeverity::Error } else { LintSeverity::Warning };
+                let msg = linter.at_key(STEGO_VERSIONS_SECTION, key, &format!("missing source {{{src}}} at tag {{{tag}}}"));
+                linter.push_with(LINT_MISSING_SOURCE, severity, msg);
+            }
+        }
+    }
+    for tag in discovered {
+        if !versions.tags.contains_key(&tag.version) {
+            let msg = linter.at_key(STEGO_VERSIONS_SECTION, STEGO_DISCOVER_KEYNAME, &format!("git tag {{{}}} is discovered but not listed in [versions]", tag.name));
+            linter.push(LINT_UNLISTED_GIT_TAG, msg);
+        }
+    }
+}
+
+fn lint_build(linter: &mut Linter, stego: &StegoLock) {
+    let Some(ref build) = stego.build else {
+        return;
+    };
+    if let (Some(make), Some(automake)) = (&build.makevers, &build.automakevers) {
+        if is_semver(make) && is_semver(automake) && semver_compare(make, automake) == Ordering::Greater {
+            let msg = linter.at_key(STEGO_BUILD_SECTION, "makevers", &format!("makevers {{{make}}} is greater than automakevers {{{automake}}}"));
+            linter.push(LINT_MAKEVERS_ORDER, msg);
+        }
+    }
+}
+
+fn lint_tests(linter: &mut Linter, stego: &StegoLock) {
+    let Some(tests_dir) = stego.build.as_ref().and_then(|b| b.tests.clone()) else {
+        return;
+    };
+    let stego_dir = linter.ctx.stego_path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    let tests_path = stego_dir.join(&tests_dir);
+    if !tests_path.is_dir() {
+        let msg = linter.at_key(STEGO_BUILD_SECTION, "tests", &format!("tests dir {{{}}} does not exist", tests_path.display()));
+        linter.push(LINT_MISSING_TESTS_DIR, msg);
+        return;
+    }
+    let Some(ref tests) = stego.tests else {
+        return;
+    };
+    for (key, dir) in [("testsdir", &tests.testsdir), ("errortestsdir", &tests.errortestsdir)] {
+        let Some(dir) = dir else {
+            continue;
+        };
+        let dir_path = tests_path.join(dir);
+        let entries = match fs::read_dir(&dir_path) {
+            Ok(e) => e,
+            Err(_) => {
+                let msg = linter.at_key("tests", key, &format!("tests dir {{{}}} does not exist", dir_path.display()));
+                linter.push(LINT_MISSING_TESTS_DIR, msg);
+                continue;
+            }
+        };
+        let mut paths: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+        paths.sort();
+        for path in paths {
+            let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+            if name.starts_with('.') || name.ends_with(".stdout") || name.ends_with(".stderr") || path.is_dir() {
+                continue;
+            }
+            if !is_executable(&path) {
+                linter.push(LINT_NON_EXECUTABLE_TEST, format!("{{{}}} is not executable and will be skipped", path.display()));
+            }
+        }
+    }
+}
+
+fn lint_layout(linter: &mut Linter) {
+    let Ok(doc) = linter.contents.parse::<DocumentMut>() else {
+        return;
+    };
+    if !versions_sorted(&doc) {
+        let msg = format!("{}: [versions] is not sorted", linter.ctx.stego_path.display());
+        linter.push(LINT_UNSORTED_VERSIONS, msg);
+    }
+    if !has_anvil_version(&doc) {
+        let msg = format!("{}: missing [anvil] version", linter.ctx.stego_path.display());
+        linter.push(LINT_MISSING_ANVIL_VERSION, msg);
+    }
+}
+
+/// Target names that are usually not files
+const PHONY_LOOKING_TARGETS: &[&str] = &[
+    "all", "clean", "distclean", "mostlyclean", "install", "uninstall", "check", "test", "tests",
+    "rebuild", "dist", "help", "run", "debug", "release", "doc", "docs", "format", "lint",
+];
+
+/// Variables make defines, or that usually come from the environment
+const MAKE_BUILTIN_VARIABLES: &[&str] = &[
+    "MAKE", "MAKEFLAGS", "MAKECMDGOALS", "MAKEFILE_LIST", "MAKEFILES", "MAKELEVEL", "CURDIR", "SHELL",
+    ".DEFAULT_GOAL", ".VARIABLES", ".RECIPEPREFIX", "VPATH", "CC", "CXX", "CPP", "AR", "AS", "LD", "RM",
+    "LEX", "YACC", "CFLAGS", "CXXFLAGS", "CPPFLAGS", "LDFLAGS", "LDLIBS", "ARFLAGS", "ASFLAGS",
+    "TARGET_ARCH", "OUTPUT_OPTION", "HOME", "PATH", "PWD", "USER",
+];
+
+/// Sources make's built-in rules compile to a `.o`
+const MAKE_OBJECT_SOURCES: &[&str] = &["c", "cc", "cpp", "C", "s", "S"];
+
+/// Returns true if `prereq` matches the target of a pattern rule.
+fn matches_pattern_rule(mk: &Makefile, prereq: &str) -> bool {
+    mk.rules.iter()
+        .filter(|r| r.is_pattern())
+        .flat_map(|r| r.targets.iter())
+        .any(|t| pattern_stem(t, prereq).is_some())
+}
+
+/// Returns true if `prereq` is an object make can build from a source in `dir` with a built-in rule.
+fn has_object_source(dir: &Path, prereq: &str) -> bool {
+    let Some(stem) = prereq.strip_suffix(".o") else {
+        return false;
+    };
+    MAKE_OBJECT_SOURCES.iter().any(|ext| dir.join(format!("{stem}.{ext}")).exists())
+}
+
+/// Returns true if `a` and `b` are lines in different branches of the same conditional.
+fn in_exclusive_branches(mk: &Makefile, a: usize, b: usize) -> bool {
+    mk.conditionals.iter().any(|c| c.contains(a) && c.contains(b) && c.branch(a) != c.branch(b))
+}
+
+/// Runs the Makefile rules on `mk`, parsed from `makefile_path`. Prerequisites are looked up in `dir`.
+pub fn lint_makefile(makefile_path: &Path, dir: &Path, mk: &Makefile) -> Vec<LintFinding> {
+    let mut findings = Vec::new();
+    let at = |line: usize, message: String| format!("{}:{line}: {message}", makefile_path.display());
+    let targets: BTreeSet<&str> = mk.rules.iter().flat_map(|r| r.targets.iter().map(String::as_str)).collect();
+    let phony: BTreeSet<&str> = mk.rules.iter()
+        .filter(|r| r.targets.iter().any(|t| t == ".PHONY"))
+        .flat_map(|r| r.prerequisites.iter().map(String::as_str))
+        .collect();
+
+    let mut reported = BTreeSet::new();
+    for rule in mk.rules.iter() {
+        for t in rule.targets.iter() {
+            if PHONY_LOOKING_TARGETS.contains(&t.as_str()) && !phony.contains(t.as_str()) && reported.insert(t.as_str()) {
+                findings.push(finding(LINT_MAKE_UNDECLARED_PHONY, at(rule.span.start_line, format!("target {{{t}}} looks phony but is not declared .PHONY"))));
+            }
+        }
+    }
+    if !targets.contains("rebuild") {
+        findings.push(finding(LINT_MAKE_MISSING_REBUILD, format!("{}: no {{rebuild}} target, which make build mode runs by default", makefile_path.display())));
+    }
+    for rule in mk.rules.iter().filter(|r| !r.targets.iter().all(|t| t.starts_with('.'))) {
+        let prerequisites: BTreeSet<String> = match rule.target_pattern {
+            Some(_) => rule.targets.iter().flat_map(|t| rule.prerequisites_for(t)).collect(),
+            None => rule.prerequisites.iter().cloned().collect(),
+        };
+        for p in prerequisites.iter().chain(rule.order_only.iter()) {
+            if p.contains(['$', '%', '*', '?', '['])
+                || targets.contains(p.as_str())
+                || dir.join(p).exists()
+                || matches_pattern_rule(mk, p)
+                || has_object_source(dir, p) {
+                continue;
+            }
+            findings.push(finding(LINT_MAKE_MISSING_PREREQUISITE, at(rule.span.start_line, format!("prerequisite {{{p}}} of {{{}}} is neither a file nor a target", rule.targets.join(" ")))));
+        }
+    }
+    let mut recipes: BTreeMap<&str, usize> = BTreeMap::new();
+    // Double-colon rules each keep their recipe, and make picks one of many pattern rules
+    for rule in mk.rules.iter().filter(|r| !r.recipe.is_empty() && !r.double_colon && !r.is_pattern()) {
+        for t in rule.targets.iter().filter(|t| !t.starts_with('.')) {
+            match recipes.get(t.as_str()) {
+                Some(&first) if !in_exclusive_branches(mk, first, rule.span.start_line) => {
+                    findings.push(finding(LINT_MAKE_DUPLICATE_RECIPE, at(rule.span.start_line, format!("target {{{t}}} already has a recipe at line {first}"))));
+                }
+                Some(_) => {}
+                None => {
+                    recipes.insert(t, rule.span.start_line);
+                }
+            }
+        }
+    }
+
+    let mut texts: Vec<(usize, String)> = Vec::new();
+    texts.extend(mk.variables.iter().map(|v| (v.span.start_line, v.value.clone())));
+    for rule in mk.rules.iter() {
+        let deps = rule.targets.iter().chain(rule.prerequisites.iter()).chain(rule.order_only.iter());
+        texts.push((rule.span.start_line, deps.cloned().collect::<Vec<_>>().join(" ")));
+        texts.extend(rule.recipe.iter().map(|r| (r.line, r.text.clone())));
+    }
+    texts.extend(mk.includes.iter().map(|i| (i.span.start_line, i.paths.join(" "))));
+    texts.extend(mk.conditionals.iter().map(|c| (c.span.start_line, c.condition.clone())));
+    texts.extend(mk.expressions.iter().map(|e| (e.span.start_line, e.text.clone())));
+    texts.sort_by_key(|(line, _)| *line);
+
+    // Included files and automake's configure substitutions may set any variable
+    let is_automake = makefile_path.extension().is_some_and(|e| e == "am");
+    let check_vars = mk.includes.is_empty() && !is_automake;
+    let mut defined: BTreeSet<&str> = mk.variables.iter().map(|v| v.name.as_str()).collect();
+    for (_, text) in texts.iter() {
+        for func in ["$(foreach ", "$(let "] {
+            for (pos, _) in text.match_indices(func) {
+                let args = &text[pos + func.len()..];
+                defined.extend(args.split(',').next().unwrap_or_default().split_whitespace());
+            }
+        }
+    }
+    for v in mk.variables.iter().filter(|v| v.op == AssignOp::Shell) {
+        findings.push(finding(LINT_MAKE_SHELL_CALL, at(v.span.start_line, format!("{{{}}} is set with !=, which runs every time the Makefile is read", v.name))));
+    }
+    let mut seen = BTreeSet::new();
+    for (line, text) in texts.iter() {
+        for r in references(text) {
+            match r {
+                Reference::Function("shell") => {
+                    findings.push(finding(LINT_MAKE_SHELL_CALL, at(*line, "$(shell ...) runs every time the Makefile is read".to_string())));
+                }
+                Reference::Function(_) => {}
+                Reference::Variable(name) => {
+                    let automatic = name.starts_with(['@', '<', '^', '*', '%', '?', '+', '|']) || name.chars().all(|c| c.is_ascii_digit());
+                    if check_vars && !automatic && !defined.contains(name) && !MAKE_BUILTIN_VARIABLES.contains(&name) && seen.insert(name) {
+                        findings.push(finding(LINT_MAKE_UNDEFINED_VARIABLE, at(*line, format!("variable {{{name}}} is used but never set"))));
+                    }
+                }
+            }
+        }
+    }
+    findings
+}
+
+/// Lints the Makefile at `makefile_path`, with the levels in `[lint]` from the `stego.lock` next to it, if any.
+pub fn lint_makefile_file(makefile_path: &Path) -> Result<Vec<LintFinding>, AnvilError> {
+    let mk = parse_makefile(makefile_path)?;
+    let dir = match makefile_path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let stego_path = dir.join("stego.lock");
+    let stego = if stego_path.is_file() {
+        let contents = fs::read_to_string(&stego_path).map_err(|e| AnvilError::io(&stego_path, e))?;
+        Some(deserialize_stego(&stego_path, &contents)?)
+    } else {
+        None
+    };
+    let lint = stego.as_ref().and_then(|s| s.lint.as_ref());
+    Ok(apply_lint_levels(lint_makefile(makefile_path, dir, &mk), lint))
+}
+
+/// Applies the safe fixes for `findings` to `contents`.
+///
+/// Returns `None` if there was nothing to fix.
+pub fn fix_stego(stego_path: &Path, contents: &str, findings: &[LintFinding], anvil_version: &str) -> Result<Option<String>, AnvilError> {
+    let mut doc = parse_stego_document(stego_path, contents)?;
+    let mut fixed = false;
+    for f in findings {
+        match f.rule {
+            LINT_UNSORTED_VERSIONS => fixed |= sort_versions(&mut doc),
+            LINT_MISSING_ANVIL_VERSION => fixed |= add_anvil_version(&mut doc, anvil_version),
+            _ => {}
+        }
+    }
+    Ok(if fixed { Some(doc.to_string()) } else { None })
+}
+
+/// Prints lint findings for the stego.lock in the passed stego dir.
+///
+/// With `fix`, safe fixes are applied first and the remaining findings printed.
+/// Fails on errors, or on warnings when `deny_warnings` is set.
+pub fn handle_lint_subcommand(args: &Args, deny_warnings: bool, fix: bool) -> ExitCode {
+    let stego_dir = args.stego_dir.clone().unwrap_or(PathBuf::from("."));
+    let ctx = LintContext {
+        stego_path: stego_dir.join("stego.lock"),
+        amboso_dir: args.amboso_dir.clone().unwrap_or(PathBuf::from("./bin")),
+    };
+    let mut findings = match lint_stego(&ctx) {
+        Ok(f) => f,
+        Err(e) => {
+            error!("Lint failed for {{{}}}. Err: {}", ctx.stego_path.display(), error_chain(&e));
+            return ExitCode::from(ANVIL_EXIT_CONFIG);
+        }
+    };
+    if fix {
+        let anvil_version = args.anvil_version.clone().unwrap_or(EXPECTED_AMBOSO_API_LEVEL.to_string());
+        let fixed = fs::read_to_string(&ctx.stego_path)
+            .map_err(|e| AnvilError::io(&ctx.stego_path, e))
+            .and_then(|contents| fix_stego(&ctx.stego_path, &contents, &findings, &anvil_version));
+        match fixed {
+            Ok(Some(contents)) => {
+                if let Err(e) = fs::write(&ctx.stego_path, contents) {
+                    error!("Failed writing {{{}}}. Err: {e}", ctx.stego_path.display());
+                    return ExitCode::from(ANVIL_EXIT_FAILURE);
+                }
+                let count = findings.iter().filter(|f| lint_rule(f.rule).is_some_and(|r| r.fixable)).count();
+                info!("Fixed {count} findings in {{{}}}", ctx.stego_path.display());
+                findings = match lint_stego(&ctx) {
+                    Ok(f) => f,
+                    Err(e) => {
+                        error!("Lint failed for {{{}}}. Err: {}", ctx.stego_path.display(), error_chain(&e));
+                        return ExitCode::from(ANVIL_EXIT_CONFIG);
+                    }
+                };
+            }
+            Ok(None) => debug!("Nothing to fix in {{{}}}", ctx.stego_path.display()),
+            Err(e) => {
+                error!("Fix failed for {{{}}}. Err: {}", ctx.stego_path.display(), error_chain(&e));
+                return ExitCode::from(ANVIL_EXIT_CONFIG);
+            }
+        }
+    }
+    let errors = findings.iter().filter(|f| f.severity == LintSeverity::Error).count();
+    let warnings = findings.iter().filter(|f| f.severity == LintSeverity::Warning).count();
+    for f in findings.iter() {
+        println!("{f}");
+    }
+    println!("{}: {errors} errors, {warnings} warnings", ctx.stego_path.display());
+    if errors > 0 || (deny_warnings && warnings > 0) {
+        ExitCode::from(ANVIL_EXIT_CONFIG)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn test_lint_stego_rules() {
+        let dir = TempDir::new("lint");
+        let stego_path = dir.join("stego.lock");
+        let contents = "[build]\nsource = \"main.c\"\nbin = \"main\"\nmakevers = \"0.2.0\"\nautomakevers = \"0.1.0\"\ntests = \"kazoj\"\n\n[versions]\n\"B0.1.0\" = \"\"\n\"0.x\" = \"bad\"\n";
+        fs::write(&stego_path, contents).expect("Failed writing test stego.lock");
+        let ctx = LintContext { stego_path, amboso_dir: dir.join("bin") };
+        let findings = lint_stego(&ctx).expect("Lint should not fail");
+
+        let rules: Vec<&str> = findings.iter().map(|f| f.rule).collect();
+        assert!(rules.contains(&LINT_EMPTY_DESCRIPTION));
+        assert!(rules.contains(&LINT_MISSING_SOURCE));
+        assert!(rules.contains(&LINT_INVALID_TAG));
+        assert!(rules.contains(&LINT_MAKEVERS_ORDER));
+        assert!(rules.contains(&LINT_MISSING_TESTS_DIR));
+        let invalid = findings.iter().find(|f| f.rule == LINT_INVALID_TAG).expect("Missing invalid-tag finding");
+        assert_eq!(invalid.severity, LintSeverity::Error);
+        assert!(invalid.to_string().starts_with("error[invalid-tag]: "));
+    }
+
+    #[test]
+    fn test_lint_makefile_rules() {
+        let contents = "\
+SRC := main.c
+OUT = $(shell uname)
+.PHONY: all
+all: hello
+hello: $(SRC) main.o missing.h
+\t$(CC) $(EXTRA) -o $@ $^ $(foreach f,$(SRC),$(f))
+ifeq ($(OUT),Linux)
+clean:
+\trm hello
+else
+clean:
+\trm hello.exe
+endif
+%.o: %.c
+\t$(CC) -c $<
+hello:
+\techo again
+";
+        let path = Path::new("Makefile");
+        let mk = crate::makefile::parse_makefile_str(contents).expect("Failed parse");
+        let findings = lint_makefile(path, Path::new("invil_missing_dir"), &mk);
+        let messages: Vec<String> = findings.iter().map(|f| f.to_string()).collect();
+        assert_eq!(messages, [
+            "warning[make-undeclared-phony]: Makefile:8: target {clean} looks phony but is not declared .PHONY",
+            "warning[make-missing-rebuild]: Makefile: no {rebuild} target, which make build mode runs by default",
+            "warning[make-missing-prerequisite]: Makefile:5: prerequisite {missing.h} of {hello} is neither a file nor a target",
+            "warning[make-duplicate-recipe]: Makefile:16: target {hello} already has a recipe at line 5",
+            "info[make-shell-call]: Makefile:2: $(shell ...) runs every time the Makefile is read",
+            "warning[make-undefined-variable]: Makefile:6: variable {EXTRA} is used but never set",
+        ]);
+
+        let stego = deserialize_stego(Path::new("stego.lock"), "[lint]\nmake-shell-call = \"off\"\nmake-missing-rebuild = \"error\"\n").expect("Failed parse");
+        let findings = apply_lint_levels(findings, stego.lint.as_ref());
+        assert_eq!(findings.len(), 5);
+        assert!(findings.iter().all(|f| f.rule != LINT_MAKE_SHELL_CALL));
+        assert_eq!(findings[1].severity, LintSeverity::Error);
+    }
+
+    #[test]
+    fn test_fix_stego() {
+        let path = Path::new("stego.lock");
+        let contents = "[versions]\n\"0.2.0\" = \"b\"\n\"0.1.0\" = \"a\"\n";
+        let findings: Vec<LintFinding> = [LINT_UNSORTED_VERSIONS, LINT_MISSING_ANVIL_VERSION].iter()
+            .map(|rule| LintFinding { rule, severity: LintSeverity::Info, message: String::new() })
+            .collect();
+        let fixed = fix_stego(path, contents, &findings, "2.1.3").expect("Fix should not fail").expect("Expected a fix");
+        assert_eq!(fixed, "[anvil]\nversion = \"2.1.3\"\n[versions]\n\"0.1.0\" = \"a\"\n\"0.2.0\" = \"b\"\n");
+        assert!(fix_stego(path, &fixed, &[], "2.1.3").expect("Fix should not fail").is_none());
+    }
+}
@@ -12,39 +12,48 @@
  *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-mod core;
-mod ops;
-mod utils;
-#[cfg(feature = "anvilPy")]
-mod anvil_py;
-#[cfg(feature = "anvilCustom")]
-mod anvil_custom;
-
 #[macro_use] extern crate log;
 use simplelog::*;
-use std::process::{ExitCode, exit};
+use std::process::ExitCode;
 use std::fs::File;
-use crate::core::{Args, Commands,
+use invil::core::{Args, Commands, ConfigCommands,
     INVIL_NAME,
     INVIL_VERSION,
     INVIL_LOG_FILE,
+    ANVIL_EXIT_CONFIG,
+    ANVIL_EXIT_USAGE,
     check_passed_args,
+    check_exit_code,
+    validate_args,
     handle_amboso_env,
     handle_init_subcommand,
     AmbosoLintMode
 };
-use crate::utils::{
+use invil::utils::{
     print_warranty_info,
     prog_name,
 };
-use crate::ops::{
+use invil::error::error_chain;
+use invil::lint::handle_lint_subcommand;
+use invil::graph::GraphFormat;
+use invil::fmt::handle_fmt_subcommand;
+use invil::migrate::handle_migrate_subcommand;
+use invil::tag::handle_tag_subcommand;
+use invil::release::{handle_release_subcommand, ReleaseOptions};
+use invil::config::{load_config, apply_config, handle_config_subcommand, LayeredConfig};
+use invil::ops::{
     handle_linter_flag,
 };
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 
 fn main() -> ExitCode {
 
-    let mut args: Args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args: Args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let config_res = load_config(&args, &matches);
+    if let Ok(ref config) = config_res {
+        apply_config(&mut args, config);
+    }
 
     let log_level;
 
@@ -135,6 +144,23 @@ fn main() -> ExitCode {
         }
     }
 
+    let config = match config_res {
+        Ok(c) => {
+            for w in c.warnings.iter() {
+                warn!("{w}");
+            }
+            c
+        }
+        Err(e) => {
+            error!("Failed loading config. Err: {}", error_chain(&e));
+            // config set must still run, so a broken file can be fixed
+            if !matches!(args.command, Some(Commands::Config { op: ConfigCommands::Set { .. } })) {
+                return ExitCode::from(ANVIL_EXIT_CONFIG);
+            }
+            LayeredConfig::default()
+        }
+    };
+
     //Debug pretty-print of args
     //trace!("Args: {:#?}\n", args);
     trace!("Log level: {}\n", log_level);
@@ -163,6 +189,33 @@ fn main() -> ExitCode {
             println!("{INVIL_VERSION}\n");
             return ExitCode::SUCCESS;
         }
+        Some(Commands::Lint { ref deny, fix }) => {
+            return handle_lint_subcommand(&args, deny.is_some(), fix);
+        }
+        Some(Commands::Fmt { check }) => {
+            return handle_fmt_subcommand(&args, check);
+        }
+        Some(Commands::Migrate { dry_run }) => {
+            return handle_migrate_subcommand(&args, dry_run);
+        }
+        Some(Commands::Tag { ref op }) => {
+            return handle_tag_subcommand(&args, op);
+        }
+        Some(Commands::Config { ref op }) => {
+            return handle_config_subcommand(&args, &config, op);
+        }
+        Some(Commands::Release { ref level, ref pre, ref message, ref header, build, dry_run }) => {
+            let opts = ReleaseOptions {
+                level: level.clone(),
+                pre: pre.clone(),
+                description: message.clone(),
+                header_dir: header.clone().or(args.gen_c_header.clone()),
+                reproducible: args.reproducible,
+                build,
+                dry_run,
+            };
+            return handle_release_subcommand(&args, &opts);
+        }
         _ => {} //Other subcommands may be handled later, in handle_amboso_env()
     }
 
@@ -192,6 +245,12 @@ fn main() -> ExitCode {
                     }
                 }
             }
+            if args.format.as_deref() == Some("json") {
+                lint_mode = AmbosoLintMode::NajloJson;
+            }
+            if let Some(format) = args.graph.as_deref().and_then(GraphFormat::parse) {
+                lint_mode = AmbosoLintMode::NajloGraph(format);
+            }
             let res = handle_linter_flag(x, &lint_mode);
             match res {
                 Ok(s) => {
@@ -206,7 +265,7 @@ fn main() -> ExitCode {
                     return ExitCode::SUCCESS;
                 }
                 Err(e) => {
-                    error!("handle_linter_flag() failed. Err: {e}");
+                    error!("handle_linter_flag() failed. Err: {}", error_chain(&e));
                     return ExitCode::FAILURE;
                 }
             }
@@ -216,6 +275,11 @@ fn main() -> ExitCode {
         }
     }
 
+    if let Err(e) = validate_args(&args) {
+        error!("validate_args() failed with: \"{}\"", error_chain(&e));
+        return ExitCode::from(ANVIL_EXIT_USAGE);
+    }
+
     let res_check = check_passed_args(&mut args);
 
     match res_check {
@@ -227,12 +291,12 @@ fn main() -> ExitCode {
             }
             match env.run_mode {
                 Some(_) => {
-                    handle_amboso_env(&mut env, &mut args);
+                    let exit_code = handle_amboso_env(&mut env, &mut args);
                     let elapsed_handling_args = env.start_time.elapsed();
                     if args.watch {
                         info!("Done handling args. Elapsed: {:.2?}", elapsed_handling_args);
                     }
-                    ExitCode::SUCCESS
+                    exit_code
                 }
                 None => {
                     let elapsed_no_runmode = env.start_time.elapsed();
@@ -244,8 +308,8 @@ fn main() -> ExitCode {
             }
         }
         Err(e) => {
-            error!("check_passed_args() failed with: \"{}\"",e);
-            ExitCode::FAILURE
+            error!("check_passed_args() failed with: \"{}\"", error_chain(&e));
+            ExitCode::from(check_exit_code(&e))
         }
     }
 }
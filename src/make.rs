@@ -0,0 +1,1275 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Output};
+use crate::error::AnvilError;
+use crate::makefile::{AssignOp, MAKE_FUNCTIONS, RecipeLine, Rule, Span, Variable, mtime, parse_makefile, pattern_stem, word_stem};
+
+/// Makefile names, in the order make looks for them
+pub const MAKEFILE_NAMES: &[&str] = &["GNUmakefile", "makefile", "Makefile"];
+
+/// Prefix of the messages make would print as `make:`
+const MAKE_MSG_PREFIX: &str = "invil-make";
+
+/// Expansion depth past which a variable is assumed to reference itself
+const MAX_EXPANSION_DEPTH: usize = 64;
+
+/// Chained implicit rules tried for a file, like `%: %.o` then `%.o: %.c`
+const MAX_IMPLICIT_CHAIN: usize = 2;
+
+const BUILTIN_VARIABLES: &[(&str, &str)] = &[
+    ("CC", "cc"),
+    ("CXX", "g++"),
+    ("CPP", "$(CC) -E"),
+    ("AR", "ar"),
+    ("ARFLAGS", "rv"),
+    ("AS", "as"),
+    ("RM", "rm -f"),
+    ("SHELL", "/bin/sh"),
+    ("MAKE", "make"),
+    ("OUTPUT_OPTION", "-o $@"),
+];
+
+/// Target, prerequisite and recipe of the implicit rules, in the order they are tried
+const BUILTIN_RULES: &[(&str, &str, &str)] = &[
+    ("%.o", "%.c", "$(CC) $(CFLAGS) $(CPPFLAGS) $(TARGET_ARCH) -c $(OUTPUT_OPTION) $<"),
+    ("%.o", "%.cc", "$(CXX) $(CXXFLAGS) $(CPPFLAGS) $(TARGET_ARCH) -c $(OUTPUT_OPTION) $<"),
+    ("%.o", "%.cpp", "$(CXX) $(CXXFLAGS) $(CPPFLAGS) $(TARGET_ARCH) -c $(OUTPUT_OPTION) $<"),
+    ("%", "%.c", "$(CC) $(CFLAGS) $(CPPFLAGS) $(LDFLAGS) $(TARGET_ARCH) $^ $(LOADLIBES) $(LDLIBS) -o $@"),
+    ("%", "%.o", "$(CC) $(LDFLAGS) $(TARGET_ARCH) $^ $(LOADLIBES) $(LDLIBS) -o $@"),
+];
+
+/// Arguments to make understood by the built-in executor.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MakeOptions {
+    pub goals: Vec<String>,
+    /// `VAR=value` arguments, which win over assignments in the Makefile
+    pub overrides: Vec<(String, String)>,
+    /// Prints recipes without running them, like `make -n`
+    pub dry_run: bool,
+    /// Doesn't echo recipes, like `make -s`
+    pub silent: bool,
+}
+
+impl MakeOptions {
+    /// Reads make arguments: goals, `VAR=value`, `-n` and `-s`. Other options are ignored.
+    pub fn from_args<S: AsRef<str>>(args: &[S]) -> MakeOptions {
+        let mut opts = MakeOptions::default();
+        for arg in args.iter().map(|a| a.as_ref().trim()) {
+            match arg {
+                "" => {}
+                "-n" | "--dry-run" | "--just-print" | "--recon" => opts.dry_run = true,
+                "-s" | "--silent" | "--quiet" => opts.silent = true,
+                a if a.starts_with('-') => debug!("Ignoring make option {{{a}}} in the built-in make"),
+                a => match a.split_once('=') {
+                    Some((name, value)) if !name.is_empty() && !name.contains(char::is_whitespace) => {
+                        let name = name.trim_end_matches([':', '+', '?']);
+                        opts.overrides.push((name.to_string(), value.to_string()));
+                    }
+                    _ => opts.goals.push(a.to_string()),
+                },
+            }
+        }
+        opts
+    }
+}
+
+/// Exit code and output of a build command.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BuildOutput {
+    pub code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+impl From<Output> for BuildOutput {
+    fn from(output: Output) -> BuildOutput {
+        BuildOutput { code: output.status.code(), stdout: output.stdout, stderr: output.stderr }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Origin {
+    Default,
+    Environment,
+    File,
+    CommandLine,
+    Override,
+    Automatic,
+}
+
+impl Origin {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Origin::Default => "default",
+            Origin::Environment => "environment",
+            Origin::File => "file",
+            Origin::CommandLine => "command line",
+            Origin::Override => "override",
+            Origin::Automatic => "automatic",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Var {
+    value: String,
+    /// Set with `=`, expanded on each use
+    recursive: bool,
+    origin: Origin,
+}
+
+impl Var {
+    fn simple(value: impl Into<String>, origin: Origin) -> Var {
+        Var { value: value.into(), recursive: false, origin }
+    }
+}
+
+type Vars = BTreeMap<String, Var>;
+
+/// Why a build stopped
+enum MakeError {
+    /// A recipe failed or a target has no rule: make prints `*** <message>` and exits with 2
+    Failed(String),
+    Anvil(AnvilError),
+}
+
+impl From<AnvilError> for MakeError {
+    fn from(e: AnvilError) -> MakeError {
+        MakeError::Anvil(e)
+    }
+}
+
+/// What remaking a target takes
+struct Job {
+    prerequisites: Vec<String>,
+    order_only: Vec<String>,
+    recipe: Vec<RecipeLine>,
+    /// Makefile defining the recipe, for messages
+    file: String,
+    stem: String,
+}
+
+/// A make-compatible executor for simple Makefiles.
+///
+/// Targets are remade when missing, phony, or older than a prerequisite. Recipe lines run one `$(SHELL) -c` each,
+/// in the Makefile's directory. Unsupported functions and directives are reported as errors.
+pub struct BuiltinMake {
+    dir: PathBuf,
+    opts: MakeOptions,
+    vars: Vars,
+    exported: BTreeSet<String>,
+    export_all: bool,
+    /// Rules with their targets and prerequisites expanded, and the Makefile defining them
+    rules: Vec<(Rule, String)>,
+    builtin_rules: Vec<(Rule, String)>,
+    target_vars: Vec<Variable>,
+    phony: BTreeSet<String>,
+    silent_targets: BTreeSet<String>,
+    silent_all: bool,
+    oneshell: bool,
+    default_goal: Option<String>,
+    /// Targets already considered, and whether they were remade
+    done: BTreeMap<String, bool>,
+    building: Vec<String>,
+    /// Recipe lines run or printed so far
+    ran: usize,
+    /// Shared, so `$(info)` can write to it while expanding
+    out: RefCell<BuildOutput>,
+}
+
+/// Runs the Makefile in `dir` with the built-in executor, as `make` would with `opts`.
+///
+/// Errors are for Makefiles the executor can't read. Failed recipes give exit code 2, like make.
+pub fn run_builtin_make(dir: &Path, opts: MakeOptions) -> Result<BuildOutput, AnvilError> {
+    let makefile = MAKEFILE_NAMES.iter()
+        .map(|n| dir.join(n))
+        .find(|p| p.is_file())
+        .ok_or_else(|| AnvilError::io(dir, io::Error::new(io::ErrorKind::NotFound, "no Makefile found")))?;
+    info!("Running the built-in make on {{{}}}", makefile.display());
+    BuiltinMake::load(&makefile, opts)?.run()
+}
+
+impl BuiltinMake {
+    /// Reads `makefile` and its includes, evaluating assignments and conditionals in order.
+    pub fn load(makefile: &Path, opts: MakeOptions) -> Result<BuiltinMake, AnvilError> {
+        let dir = match makefile.parent() {
+            Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let mut make = BuiltinMake {
+            dir,
+            opts,
+            vars: Vars::new(),
+            exported: BTreeSet::new(),
+            export_all: false,
+            rules: Vec::new(),
+            builtin_rules: Vec::new(),
+            target_vars: Vec::new(),
+            phony: BTreeSet::new(),
+            silent_targets: BTreeSet::new(),
+            silent_all: false,
+            oneshell: false,
+            default_goal: None,
+            done: BTreeMap::new(),
+            building: Vec::new(),
+            ran: 0,
+            out: RefCell::default(),
+        };
+        for (name, value) in BUILTIN_VARIABLES.iter() {
+            make.vars.insert(name.to_string(), Var { value: value.to_string(), recursive: true, origin: Origin::Default });
+        }
+        // Like make, SHELL is never taken from the environment
+        for (name, value) in env::vars().filter(|(n, _)| n != "SHELL") {
+            make.vars.insert(name, Var::simple(value, Origin::Environment));
+        }
+        for (name, value) in make.opts.overrides.iter() {
+            make.vars.insert(name.clone(), Var { value: value.clone(), recursive: true, origin: Origin::CommandLine });
+        }
+        for (target, prerequisite, recipe) in BUILTIN_RULES.iter() {
+            let rule = Rule {
+                targets: vec![target.to_string()],
+                prerequisites: vec![prerequisite.to_string()],
+                order_only: Vec::new(),
+                target_pattern: None,
+                double_colon: false,
+                recipe: vec![RecipeLine { text: recipe.to_string(), line: 0 }],
+                span: Span { start_line: 0, end_line: 0 },
+            };
+            make.builtin_rules.push((rule, "<builtin>".to_string()));
+        }
+        make.read(makefile)?;
+        Ok(make)
+    }
+
+    fn read(&mut self, path: &Path) -> Result<(), AnvilError> {
+        enum Item {
+            Variable(usize),
+            Rule(usize),
+            Include(usize),
+            Conditional(usize),
+            Expression(usize),
+        }
+        let mk = parse_makefile(path)?;
+        let file = path.strip_prefix(&self.dir).unwrap_or(path).display().to_string();
+        let list = match self.vars.get("MAKEFILE_LIST") {
+            Some(v) if v.origin == Origin::File => format!("{} {file}", v.value),
+            _ => file.clone(),
+        };
+        self.vars.insert("MAKEFILE_LIST".to_string(), Var::simple(list, Origin::File));
+
+        let mut items: Vec<(usize, Item)> = Vec::new();
+        items.extend(mk.variables.iter().enumerate().map(|(i, v)| (v.span.start_line, Item::Variable(i))));
+        items.extend(mk.rules.iter().enumerate().map(|(i, r)| (r.span.start_line, Item::Rule(i))));
+        items.extend(mk.includes.iter().enumerate().map(|(i, inc)| (inc.span.start_line, Item::Include(i))));
+        items.extend(mk.conditionals.iter().enumerate().map(|(i, c)| (c.span.start_line, Item::Conditional(i))));
+        items.extend(mk.expressions.iter().enumerate().map(|(i, e)| (e.span.start_line, Item::Expression(i))));
+        items.sort_by_key(|(line, _)| *line);
+
+        // Branch taken by each conditional, None when it is skipped or no branch holds
+        let mut taken: Vec<Option<usize>> = vec![None; mk.conditionals.len()];
+        let active = |line: usize, taken: &[Option<usize>]| {
+            mk.conditionals.iter().enumerate().all(|(i, c)| !c.contains(line) || taken[i] == Some(c.branch(line)))
+        };
+
+        for (line, item) in items.into_iter() {
+            if !active(line, &taken) {
+                continue;
+            }
+            match item {
+                Item::Conditional(i) => {
+                    let c = &mk.conditionals[i];
+                    let mut branches = vec![(c.kind.as_str(), c.condition.as_str())];
+                    for cond in c.else_conditions.iter() {
+                        branches.push(cond.split_once(char::is_whitespace).unwrap_or((cond.as_str(), "")));
+                    }
+                    for (branch, (kind, condition)) in branches.into_iter().enumerate() {
+                        if kind.is_empty() || self.eval_condition(kind, condition.trim())? {
+                            taken[i] = Some(branch);
+                            break;
+                        }
+                    }
+                }
+                Item::Variable(i) => {
+                    let v = &mk.variables[i];
+                    if !v.targets.is_empty() {
+                        self.target_vars.push(v.clone());
+                        continue;
+                    }
+                    if v.modifiers.iter().any(|m| m == "export") {
+                        self.exported.insert(v.name.clone());
+                    }
+                    let current = self.vars.get(&v.name);
+                    if let Some(var) = self.assigned(current, v, &Vars::new())? {
+                        self.vars.insert(v.name.clone(), var);
+                    }
+                }
+                Item::Rule(i) => {
+                    let rule = &mk.rules[i];
+                    let recipe = rule.recipe.iter().filter(|r| active(r.line, &taken)).cloned().collect();
+                    self.add_rule(rule, recipe, &file)?;
+                }
+                Item::Include(i) => {
+                    let inc = &mk.includes[i];
+                    for p in words(&self.expand(&inc.paths.join(" "))?) {
+                        let include_path = self.dir.join(&p);
+                        if include_path.is_file() {
+                            self.read(&include_path)?;
+                        } else if inc.optional {
+                            debug!("Skipping missing include {{{p}}}");
+                        } else {
+                            return Err(AnvilError::not_found(&include_path).context(format!("{file}:{line}: Failed including {{{p}}}")));
+                        }
+                    }
+                }
+                Item::Expression(i) => {
+                    let text = self.expand(&mk.expressions[i].text)?;
+                    let mut names = text.split_whitespace();
+                    match names.next() {
+                        None => {}
+                        Some("export") => {
+                            let names: Vec<&str> = names.collect();
+                            if names.is_empty() {
+                                self.export_all = true;
+                            }
+                            self.exported.extend(names.into_iter().map(String::from));
+                        }
+                        Some("unexport") => {
+                            for name in names {
+                                self.exported.remove(name);
+                            }
+                        }
+                        Some("undefine") => {
+                            for name in names {
+                                self.vars.remove(name);
+                            }
+                        }
+                        Some(_) => return Err(AnvilError::MakefileParse {
+                            path: path.to_path_buf(),
+                            line,
+                            message: "missing separator, or unsupported by the built-in make".to_string(),
+                        }),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn eval_condition(&self, kind: &str, condition: &str) -> Result<bool, AnvilError> {
+        match kind {
+            "ifdef" | "ifndef" => {
+                let name = self.expand(condition)?;
+                let defined = self.vars.get(name.trim()).is_some_and(|v| !v.value.is_empty());
+                Ok(defined == (kind == "ifdef"))
+            }
+            "ifeq" | "ifneq" => {
+                let (a, b) = split_eq_args(condition)
+                    .ok_or_else(|| AnvilError::Config(format!("Invalid {kind} condition {{{condition}}}")))?;
+                let equal = self.expand(a)? == self.expand(b)?;
+                Ok(equal == (kind == "ifeq"))
+            }
+            _ => Err(AnvilError::Config(format!("{{{kind} {condition}}} is not supported by the built-in make"))),
+        }
+    }
+
+    fn add_rule(&mut self, rule: &Rule, recipe: Vec<RecipeLine>, file: &str) -> Result<(), AnvilError> {
+        let targets = words(&self.expand(&rule.targets.join(" "))?);
+        let prerequisites = words(&self.expand(&rule.prerequisites.join(" "))?);
+        let order_only = words(&self.expand(&rule.order_only.join(" "))?);
+        let target_pattern = match rule.target_pattern {
+            Some(ref p) => Some(self.expand(p)?.trim().to_string()),
+            None => None,
+        };
+        match targets.first().map(String::as_str) {
+            Some(".PHONY") => {
+                self.phony.extend(prerequisites);
+                return Ok(());
+            }
+            Some(".SILENT") => {
+                self.silent_all |= prerequisites.is_empty();
+                self.silent_targets.extend(prerequisites);
+                return Ok(());
+            }
+            Some(".ONESHELL") => {
+                self.oneshell = true;
+                return Ok(());
+            }
+            Some(".EXPORT_ALL_VARIABLES") => {
+                self.export_all = true;
+                return Ok(());
+            }
+            Some(t) if t.starts_with('.') && t.chars().skip(1).all(|c| c.is_ascii_uppercase() || c == '_') => {
+                debug!("Ignoring special target {{{t}}} in the built-in make");
+                return Ok(());
+            }
+            _ => {}
+        }
+        let rule = Rule { targets, prerequisites, order_only, target_pattern, double_colon: rule.double_colon, recipe, span: rule.span };
+        if self.default_goal.is_none() && !rule.is_pattern() {
+            self.default_goal = rule.targets.iter().find(|t| !t.starts_with('.') || t.contains('/')).cloned();
+        }
+        self.rules.push((rule, file.to_string()));
+        Ok(())
+    }
+
+    /// Returns the value `v` assigns, given the `current` one. None when the assignment doesn't apply.
+    fn assigned(&self, current: Option<&Var>, v: &Variable, locals: &Vars) -> Result<Option<Var>, AnvilError> {
+        let overriding = v.modifiers.iter().any(|m| m == "override");
+        if current.is_some_and(|c| c.origin == Origin::CommandLine) && !overriding {
+            return Ok(None);
+        }
+        let origin = if overriding { Origin::Override } else { Origin::File };
+        let var = match v.op {
+            AssignOp::Recursive => Var { value: v.value.clone(), recursive: true, origin },
+            AssignOp::Simple | AssignOp::PosixSimple => Var::simple(self.expand_with(&v.value, locals)?, origin),
+            AssignOp::Immediate => Var { value: self.expand_with(&v.value, locals)?.replace('$', "$$"), recursive: true, origin },
+            AssignOp::Conditional => match current {
+                Some(_) => return Ok(None),
+                None => Var { value: v.value.clone(), recursive: true, origin },
+            },
+            AssignOp::Append => match current {
+                Some(c) => {
+                    let added = if c.recursive { v.value.clone() } else { self.expand_with(&v.value, locals)? };
+                    let value = if c.value.is_empty() { added } else { format!("{} {added}", c.value) };
+                    Var { value, recursive: c.recursive, origin: if overriding { origin } else { c.origin } }
+                }
+                None => Var { value: v.value.clone(), recursive: true, origin },
+            },
+            AssignOp::Shell => Var::simple(self.shell(&self.expand_with(&v.value, locals)?)?, origin),
+        };
+        Ok(Some(var))
+    }
+
+    /// Expands the variable and function references in `text`.
+    pub fn expand(&self, text: &str) -> Result<String, AnvilError> {
+        self.expand_with(text, &Vars::new())
+    }
+
+    fn expand_with(&self, text: &str, locals: &Vars) -> Result<String, AnvilError> {
+        self.expand_in(text, locals, 0)
+    }
+
+    fn expand_in(&self, text: &str, locals: &Vars, depth: usize) -> Result<String, AnvilError> {
+        if depth > MAX_EXPANSION_DEPTH {
+            return Err(AnvilError::Config(format!("Recursive variable references itself (eventually) in {{{text}}}")));
+        }
+        let mut res = String::new();
+        let mut rest = text;
+        while let Some(at) = rest.find('$') {
+            res.push_str(&rest[..at]);
+            let after = &rest[at + 1..];
+            match after.chars().next() {
+                None => rest = after,
+                Some('$') => {
+                    res.push('$');
+                    rest = &after[1..];
+                }
+                Some(open @ ('(' | '{')) => {
+                    let close = if open == '(' { ')' } else { '}' };
+                    let end = matching_close(after, open, close)
+                        .ok_or_else(|| AnvilError::Config(format!("Unterminated variable reference in {{{text}}}")))?;
+                    res.push_str(&self.expand_reference(&after[1..end], locals, depth)?);
+                    rest = &after[end + 1..];
+                }
+                Some(c) => {
+                    res.push_str(&self.lookup(&c.to_string(), locals, depth)?);
+                    rest = &after[c.len_utf8()..];
+                }
+            }
+        }
+        res.push_str(rest);
+        Ok(res)
+    }
+
+    /// Expands what is between the parentheses of `$(...)`: a function call, a substitution reference or a variable.
+    fn expand_reference(&self, inner: &str, locals: &Vars, depth: usize) -> Result<String, AnvilError> {
+        if let Some((name, args)) = inner.split_once(char::is_whitespace) {
+            if MAKE_FUNCTIONS.contains(&name) {
+                return self.call_function(name, args.trim_start(), locals, depth);
+            }
+        }
+        let name = self.expand_in(inner, locals, depth)?;
+        if let Some((var, subst)) = name.split_once(':') {
+            if let Some((from, to)) = subst.split_once('=') {
+                let (from, to) = if from.contains('%') {
+                    (from.to_string(), to.to_string())
+                } else {
+                    (format!("%{from}"), format!("%{to}"))
+                };
+                let value = self.lookup(var, locals, depth)?;
+                return Ok(join_words(value.split_whitespace().map(|w| patsubst_word(&from, &to, w))));
+            }
+        }
+        self.lookup(&name, locals, depth)
+    }
+
+    fn lookup(&self, name: &str, locals: &Vars, depth: usize) -> Result<String, AnvilError> {
+        if let Some(v) = locals.get(name).or_else(|| self.vars.get(name)) {
+            return if v.recursive { self.expand_in(&v.value, locals, depth + 1) } else { Ok(v.value.clone()) };
+        }
+        // $(@D), $(<F) and the other directory and file parts of automatic variables
+        let mut chars = name.chars();
+        if let (Some(auto), Some(part @ ('D' | 'F')), None) = (chars.next(), chars.next(), chars.next()) {
+            if let Some(v) = locals.get(&auto.to_string()).filter(|v| v.origin == Origin::Automatic) {
+                return Ok(join_words(v.value.split_whitespace().map(|w| match (part, w.rfind('/')) {
+                    ('D', Some(0)) => "/".to_string(),
+                    ('D', Some(i)) => w[..i].to_string(),
+                    ('D', None) => ".".to_string(),
+                    (_, i) => w[i.map_or(0, |i| i + 1)..].to_string(),
+                })));
+            }
+        }
+        Ok(String::new())
+    }
+
+    fn call_function(&self, name: &str, args: &str, locals: &Vars, depth: usize) -> Result<String, AnvilError> {
+        let arity = match name {
+            "strip" | "sort" | "words" | "firstword" | "lastword" | "dir" | "notdir" | "suffix" | "basename"
+                | "wildcard" | "realpath" | "abspath" | "value" | "origin" | "shell" | "info" | "warning" | "error" => 1,
+            "findstring" | "filter" | "filter-out" | "word" | "addsuffix" | "addprefix" | "join" => 2,
+            "subst" | "patsubst" | "wordlist" | "if" | "foreach" => 3,
+            "or" | "and" | "call" => usize::MAX,
+            _ => return Err(AnvilError::Config(format!("Function {{{name}}} is not supported by the built-in make"))),
+        };
+        let raw = split_args(args, arity);
+        let arg = |i: usize| match raw.get(i) {
+            Some(a) => self.expand_in(a, locals, depth),
+            None => Ok(String::new()),
+        };
+        let res = match name {
+            "subst" => {
+                let (from, to, text) = (arg(0)?, arg(1)?, arg(2)?);
+                if from.is_empty() { text } else { text.replace(&from, &to) }
+            }
+            "patsubst" => {
+                let (pattern, replacement) = (arg(0)?, arg(1)?);
+                join_words(arg(2)?.split_whitespace().map(|w| patsubst_word(&pattern, &replacement, w)))
+            }
+            "strip" => join_words(arg(0)?.split_whitespace()),
+            "findstring" => {
+                let find = arg(0)?;
+                if arg(1)?.contains(&find) { find } else { String::new() }
+            }
+            "filter" | "filter-out" => {
+                let patterns = arg(0)?;
+                let keep = name == "filter";
+                join_words(arg(1)?.split_whitespace()
+                    .filter(|w| patterns.split_whitespace().any(|p| word_stem(p, w).is_some()) == keep))
+            }
+            "sort" => join_words(arg(0)?.split_whitespace().collect::<BTreeSet<_>>()),
+            "word" => {
+                let n = word_index(&arg(0)?, name)?;
+                arg(1)?.split_whitespace().nth(n - 1).unwrap_or_default().to_string()
+            }
+            "wordlist" => {
+                let (start, end) = (word_index(&arg(0)?, name)?, arg(1)?.trim().parse::<usize>().unwrap_or(0));
+                join_words(arg(2)?.split_whitespace().skip(start - 1).take((end + 1).saturating_sub(start)))
+            }
+            "words" => arg(0)?.split_whitespace().count().to_string(),
+            "firstword" => arg(0)?.split_whitespace().next().unwrap_or_default().to_string(),
+            "lastword" => arg(0)?.split_whitespace().last().unwrap_or_default().to_string(),
+            "dir" => join_words(arg(0)?.split_whitespace().map(|w| match w.rfind('/') {
+                Some(i) => w[..=i].to_string(),
+                None => "./".to_string(),
+            })),
+            "notdir" => join_words(arg(0)?.split_whitespace().map(|w| w.rsplit('/').next().unwrap_or(w))),
+            "suffix" => join_words(arg(0)?.split_whitespace().filter_map(|w| suffix_at(w).map(|i| &w[i..]))),
+            "basename" => join_words(arg(0)?.split_whitespace().map(|w| &w[..suffix_at(w).unwrap_or(w.len())])),
+            "addsuffix" => {
+                let suffix = arg(0)?;
+                join_words(arg(1)?.split_whitespace().map(|w| format!("{w}{suffix}")))
+            }
+            "addprefix" => {
+                let prefix = arg(0)?;
+                join_words(arg(1)?.split_whitespace().map(|w| format!("{prefix}{w}")))
+            }
+            "join" => {
+                let (a, b) = (words(&arg(0)?), words(&arg(1)?));
+                join_words((0..a.len().max(b.len())).map(|i| {
+                    format!("{}{}", a.get(i).map_or("", String::as_str), b.get(i).map_or("", String::as_str))
+                }))
+            }
+            "wildcard" => join_words(arg(0)?.split_whitespace().flat_map(|p| self.glob(p))),
+            "realpath" => join_words(arg(0)?.split_whitespace()
+                .filter_map(|w| fs::canonicalize(self.dir.join(w)).ok())
+                .map(|p| p.display().to_string())),
+            "abspath" => {
+                let base = env::current_dir().map_err(|e| AnvilError::io(".", e))?.join(&self.dir);
+                join_words(arg(0)?.split_whitespace().map(|w| normalize(&base.join(w)).display().to_string()))
+            }
+            "if" => {
+                if !arg(0)?.trim().is_empty() { arg(1)? } else { arg(2)? }
+            }
+            "or" => {
+                for i in 0..raw.len() {
+                    let value = arg(i)?;
+                    if !value.trim().is_empty() {
+                        return Ok(value);
+                    }
+                }
+                String::new()
+            }
+            "and" => {
+                let mut value = String::new();
+                for i in 0..raw.len() {
+                    value = arg(i)?;
+                    if value.trim().is_empty() {
+                        return Ok(String::new());
+                    }
+                }
+                value
+            }
+            "foreach" => {
+                let (var, list) = (arg(0)?, arg(1)?);
+                let text = raw.get(2).copied().unwrap_or_default();
+                let mut res = Vec::new();
+                for word in list.split_whitespace() {
+                    let mut scope = locals.clone();
+                    scope.insert(var.trim().to_string(), Var::simple(word, Origin::Automatic));
+                    res.push(self.expand_in(text, &scope, depth)?);
+                }
+                join_words(res)
+            }
+            "call" => {
+                let function = arg(0)?.trim().to_string();
+                let mut scope = locals.clone();
+                scope.insert("0".to_string(), Var::simple(function.as_str(), Origin::Automatic));
+                for i in 1..raw.len() {
+                    scope.insert(i.to_string(), Var::simple(arg(i)?, Origin::Automatic));
+                }
+                match self.vars.get(&function) {
+                    Some(v) if v.recursive => self.expand_in(&v.value, &scope, depth + 1)?,
+                    Some(v) => v.value.clone(),
+                    None => String::new(),
+                }
+            }
+            "value" => {
+                let var = arg(0)?;
+                locals.get(var.trim()).or_else(|| self.vars.get(var.trim())).map(|v| v.value.clone()).unwrap_or_default()
+            }
+            "origin" => {
+                let var = arg(0)?;
+                locals.get(var.trim()).or_else(|| self.vars.get(var.trim())).map_or("undefined", |v| v.origin.as_str()).to_string()
+            }
+            "shell" => self.shell(&arg(0)?)?,
+            "info" => {
+                let text = arg(0)?;
+                self.out.borrow_mut().stdout.extend(format!("{text}\n").into_bytes());
+                String::new()
+            }
+            "warning" => {
+                warn!("{}", arg(0)?);
+                String::new()
+            }
+            _ => {
+                let text = arg(0)?;
+                error!("*** {text}.  Stop.");
+                return Err(AnvilError::BuildStepFailed { step: "make".to_string(), code: Some(2), stderr: text });
+            }
+        };
+        Ok(res)
+    }
+
+    /// Runs `command` with `$(SHELL)`, returning its output with newlines turned into spaces.
+    fn shell(&self, command: &str) -> Result<String, AnvilError> {
+        let shell = self.shell_path(&Vars::new())?;
+        let output = Command::new(&shell)
+            .arg("-c")
+            .arg(command)
+            .current_dir(&self.dir)
+            .output()
+            .map_err(|e| AnvilError::missing_tool(&shell, e))?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(stdout.trim_end_matches('\n').replace('\n', " "))
+    }
+
+    fn shell_path(&self, locals: &Vars) -> Result<String, AnvilError> {
+        let shell = self.expand_with("$(SHELL)", locals)?;
+        Ok(if shell.trim().is_empty() { "/bin/sh".to_string() } else { shell.trim().to_string() })
+    }
+
+    /// Expands a `wildcard` pattern relative to the Makefile's directory.
+    fn glob(&self, pattern: &str) -> Vec<String> {
+        let is_glob = |s: &str| s.contains(['*', '?', '[']);
+        if !is_glob(pattern) {
+            return if self.dir.join(pattern).exists() { vec![pattern.to_string()] } else { Vec::new() };
+        }
+        let mut found = vec![if pattern.starts_with('/') { "/".to_string() } else { String::new() }];
+        for component in pattern.split('/').filter(|c| !c.is_empty()) {
+            let mut next = Vec::new();
+            for prefix in found.into_iter() {
+                if !is_glob(component) {
+                    next.push(format!("{prefix}{component}/"));
+                    continue;
+                }
+                let base = self.dir.join(if prefix.is_empty() { "." } else { prefix.as_str() });
+                let Ok(entries) = fs::read_dir(&base) else { continue };
+                let mut names: Vec<String> = entries.filter_map(|e| e.ok())
+                    .map(|e| e.file_name().to_string_lossy().to_string())
+                    .filter(|n| (!n.starts_with('.') || component.starts_with('.')) && fnmatch(component, n))
+                    .collect();
+                names.sort();
+                next.extend(names.into_iter().map(|n| format!("{prefix}{n}/")));
+            }
+            found = next;
+        }
+        found.into_iter()
+            .map(|p| p.trim_end_matches('/').to_string())
+            .filter(|p| !p.is_empty() && self.dir.join(p).exists())
+            .collect()
+    }
+
+    /// Builds the goals, returning the exit code and output make would give.
+    pub fn run(mut self) -> Result<BuildOutput, AnvilError> {
+        let goals = if !self.opts.goals.is_empty() {
+            self.opts.goals.clone()
+        } else {
+            let default_goal = self.expand("$(.DEFAULT_GOAL)")?;
+            match default_goal.split_whitespace().next().map(String::from).or(self.default_goal.clone()) {
+                Some(goal) => vec![goal],
+                None => {
+                    self.message("*** No targets.  Stop.");
+                    self.out.get_mut().code = Some(2);
+                    return Ok(self.out.into_inner());
+                }
+            }
+        };
+        for goal in goals.iter() {
+            let ran = self.ran;
+            match self.update(goal, None) {
+                Ok(_) => {
+                    if self.ran == ran {
+                        let msg = if !self.phony.contains(goal) && self.dir.join(goal).exists() {
+                            format!("{MAKE_MSG_PREFIX}: '{goal}' is up to date.\n")
+                        } else {
+                            format!("{MAKE_MSG_PREFIX}: Nothing to be done for '{goal}'.\n")
+                        };
+                        self.out.get_mut().stdout.extend(msg.into_bytes());
+                    }
+                }
+                Err(MakeError::Failed(msg)) => {
+                    self.message(&format!("*** {msg}"));
+                    self.out.get_mut().code = Some(2);
+                    return Ok(self.out.into_inner());
+                }
+                Err(MakeError::Anvil(e)) => return Err(e),
+            }
+        }
+        self.out.get_mut().code = Some(0);
+        Ok(self.out.into_inner())
+    }
+
+    fn message(&mut self, msg: &str) {
+        self.out.get_mut().stderr.extend(format!("{MAKE_MSG_PREFIX}: {msg}\n").into_bytes());
+    }
+
+    /// Brings `target` up to date, returning true if it was remade.
+    fn update(&mut self, target: &str, parent: Option<&str>) -> Result<bool, MakeError> {
+        if let Some(remade) = self.done.get(target) {
+            return Ok(*remade);
+        }
+        if self.building.iter().any(|b| b == target) {
+            warn!("Circular {} <- {target} dependency dropped", parent.unwrap_or_default());
+            return Ok(false);
+        }
+        self.building.push(target.to_string());
+        let res = self.update_target(target, parent);
+        self.building.pop();
+        let remade = res?;
+        self.done.insert(target.to_string(), remade);
+        Ok(remade)
+    }
+
+    fn update_target(&mut self, target: &str, parent: Option<&str>) -> Result<bool, MakeError> {
+        let explicit: Vec<(Rule, String)> = self.rules.iter()
+            .filter(|(r, _)| !r.is_pattern() && r.targets.iter().any(|t| t == target))
+            .cloned()
+            .collect();
+        if explicit.iter().any(|(r, _)| r.double_colon) {
+            let mut remade = false;
+            for (rule, file) in explicit.into_iter().filter(|(r, _)| r.double_colon) {
+                let job = Job {
+                    prerequisites: rule.prerequisites_for(target),
+                    order_only: rule.order_only,
+                    recipe: rule.recipe,
+                    file,
+                    stem: String::new(),
+                };
+                remade |= self.remake(target, &job, true)?;
+            }
+            return Ok(remade);
+        }
+
+        let mut job = Job { prerequisites: Vec::new(), order_only: Vec::new(), recipe: Vec::new(), file: String::new(), stem: String::new() };
+        for (rule, file) in explicit.iter() {
+            job.prerequisites.extend(rule.prerequisites_for(target));
+            job.order_only.extend(rule.order_only.iter().cloned());
+            if !rule.recipe.is_empty() {
+                if !job.recipe.is_empty() {
+                    warn!("{file}: overriding recipe for target '{target}'");
+                }
+                job.recipe = rule.recipe.clone();
+                job.file = file.clone();
+                job.stem = rule.target_pattern.as_deref()
+                    .and_then(|p| pattern_stem(p, target))
+                    .unwrap_or_default()
+                    .to_string();
+            }
+        }
+        if job.recipe.is_empty() {
+            if let Some((rule, file, stem)) = self.find_implicit(target, 0) {
+                let mut prerequisites: Vec<String> = rule.prerequisites.iter().map(|p| p.replacen('%', &stem, 1)).collect();
+                prerequisites.append(&mut job.prerequisites);
+                job.prerequisites = prerequisites;
+                job.order_only.extend(rule.order_only.iter().map(|p| p.replacen('%', &stem, 1)));
+                job.recipe = rule.recipe;
+                job.file = file;
+                job.stem = stem;
+            }
+        }
+        if explicit.is_empty() && job.recipe.is_empty() {
+            if self.phony.contains(target) {
+                return Ok(true);
+            }
+            if self.dir.join(target).exists() {
+                return Ok(false);
+            }
+            return Err(MakeError::Failed(match parent {
+                Some(p) => format!("No rule to make target '{target}', needed by '{p}'.  Stop."),
+                None => format!("No rule to make target '{target}'.  Stop."),
+            }));
+        }
+        self.remake(target, &job, false)
+    }
+
+    /// Returns the first pattern rule with a recipe that can make `target`, with its stem.
+    fn find_implicit(&self, target: &str, depth: usize) -> Option<(Rule, String, String)> {
+        if depth > MAX_IMPLICIT_CHAIN {
+            return None;
+        }
+        let pattern_rules = self.rules.iter().filter(|(r, _)| r.is_pattern()).chain(self.builtin_rules.iter());
+        for (rule, file) in pattern_rules.filter(|(r, _)| !r.recipe.is_empty()) {
+            for pattern in rule.targets.iter() {
+                let Some(stem) = pattern_stem(pattern, target) else { continue };
+                let can_make = rule.prerequisites.iter().all(|p| {
+                    let name = p.replacen('%', stem, 1);
+                    self.dir.join(&name).exists()
+                        || self.rules.iter().any(|(r, _)| !r.is_pattern() && r.targets.contains(&name))
+                        || self.find_implicit(&name, depth + 1).is_some()
+                });
+                if can_make {
+                    return Some((rule.clone(), file.clone(), stem.to_string()));
+                }
+            }
+        }
+        None
+    }
+
+    /// Updates the prerequisites of `target`, then runs the recipe if any of them is newer than it.
+    fn remake(&mut self, target: &str, job: &Job, double_colon: bool) -> Result<bool, MakeError> {
+        for p in job.prerequisites.iter() {
+            self.update(p, Some(target))?;
+        }
+        for p in job.order_only.iter() {
+            self.update(p, Some(target))?;
+        }
+        let phony = self.phony.contains(target);
+        let target_mtime = if phony { None } else { mtime(&self.dir.join(target)) };
+        let mut newer: Vec<String> = Vec::new();
+        for p in job.prerequisites.iter() {
+            let is_newer = match target_mtime {
+                None => true,
+                Some(t) => self.done.get(p) == Some(&true) || self.phony.contains(p) || mtime(&self.dir.join(p)).is_some_and(|m| m > t),
+            };
+            if is_newer && !newer.contains(p) {
+                newer.push(p.clone());
+            }
+        }
+        let out_of_date = phony || target_mtime.is_none() || !newer.is_empty() || (double_colon && job.prerequisites.is_empty());
+        if !out_of_date {
+            return Ok(false);
+        }
+        if !job.recipe.is_empty() {
+            self.run_recipe(target, job, &newer)?;
+        }
+        Ok(true)
+    }
+
+    /// Returns the target-specific variables of the targets being built, the one needing them last.
+    ///
+    /// Like make, prerequisites inherit the variables of the targets they are built for.
+    fn target_locals(&self) -> Result<Vars, AnvilError> {
+        let mut locals = Vars::new();
+        for target in self.building.iter() {
+            let applies = |t: &String| t == target || (t.contains('%') && pattern_stem(t, target).is_some());
+            for v in self.target_vars.iter().filter(|v| v.targets.iter().any(applies)) {
+                let current = locals.get(&v.name).or_else(|| self.vars.get(&v.name)).cloned();
+                if let Some(var) = self.assigned(current.as_ref(), v, &locals)? {
+                    locals.insert(v.name.clone(), var);
+                }
+            }
+        }
+        Ok(locals)
+    }
+
+    fn run_recipe(&mut self, target: &str, job: &Job, newer: &[String]) -> Result<(), MakeError> {
+        let mut locals = self.target_locals()?;
+        let mut unique: Vec<&str> = Vec::new();
+        for p in job.prerequisites.iter() {
+            if !unique.contains(&p.as_str()) {
+                unique.push(p);
+            }
+        }
+        let automatic = [
+            ("@", target.to_string()),
+            ("<", job.prerequisites.first().cloned().unwrap_or_default()),
+            ("^", unique.join(" ")),
+            ("+", job.prerequisites.join(" ")),
+            ("?", newer.join(" ")),
+            ("*", job.stem.clone()),
+            ("|", job.order_only.join(" ")),
+        ];
+        for (name, value) in automatic.into_iter() {
+            locals.insert(name.to_string(), Var::simple(value, Origin::Automatic));
+        }
+        let shell = self.shell_path(&locals)?;
+        let exported: Vec<String> = if self.export_all {
+            self.vars.iter().filter(|(_, v)| v.origin != Origin::Default).map(|(n, _)| n.clone()).collect()
+        } else {
+            self.exported.iter().cloned().collect()
+        };
+        let mut envs = Vec::new();
+        for name in exported.into_iter() {
+            let value = self.lookup(&name, &locals, 0)?;
+            envs.push((name, value));
+        }
+
+        // Each line may expand to several, as with canned recipes from `define`
+        let mut commands: Vec<(String, usize)> = Vec::new();
+        for line in job.recipe.iter() {
+            let text = self.expand_with(&line.text, &locals)?;
+            commands.extend(split_command_lines(&text).into_iter().map(|c| (c, line.line)));
+        }
+        if self.oneshell && !commands.is_empty() {
+            let line = commands[0].1;
+            let script = commands.iter().enumerate()
+                .map(|(i, (c, _))| if i == 0 { c.as_str() } else { c.trim_start_matches(['@', '-', '+']) })
+                .collect::<Vec<_>>()
+                .join("\n");
+            commands = vec![(script, line)];
+        }
+
+        for (command, line) in commands.into_iter() {
+            let mut silent = self.opts.silent || self.silent_all || self.silent_targets.contains(target);
+            let (mut ignore, mut force) = (false, false);
+            let mut cmd = command.as_str();
+            while let Some(c) = cmd.chars().next() {
+                match c {
+                    '@' => silent = true,
+                    '-' => ignore = true,
+                    '+' => force = true,
+                    c if c.is_whitespace() => {}
+                    _ => break,
+                }
+                cmd = &cmd[c.len_utf8()..];
+            }
+            if cmd.is_empty() {
+                continue;
+            }
+            // make drops the tab starting each continuation line
+            let cmd = cmd.replace("\\\n\t", "\\\n");
+            self.ran += 1;
+            if !silent || (self.opts.dry_run && !force) {
+                self.out.get_mut().stdout.extend(format!("{cmd}\n").into_bytes());
+            }
+            if self.opts.dry_run && !force {
+                continue;
+            }
+            debug!("Running {{{shell} -c {cmd}}} for {{{target}}}");
+            let output = Command::new(&shell)
+                .arg("-c")
+                .arg(&cmd)
+                .current_dir(&self.dir)
+                .envs(envs.iter().map(|(k, v)| (k, v)))
+                .output()
+                .map_err(|e| AnvilError::missing_tool(&shell, e))?;
+            self.out.get_mut().stdout.extend(output.stdout);
+            self.out.get_mut().stderr.extend(output.stderr);
+            if !output.status.success() {
+                let status = match output.status.code() {
+                    Some(c) => format!("Error {c}"),
+                    None => "Terminated".to_string(),
+                };
+                let at = format!("[{}:{line}: {target}] {status}", job.file);
+                if !ignore {
+                    return Err(MakeError::Failed(at));
+                }
+                self.message(&format!("{at} (ignored)"));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split_whitespace().map(String::from).collect()
+}
+
+fn join_words<I: IntoIterator<Item = S>, S: AsRef<str>>(words: I) -> String {
+    let mut res = String::new();
+    for w in words.into_iter().filter(|w| !w.as_ref().is_empty()) {
+        if !res.is_empty() {
+            res.push(' ');
+        }
+        res.push_str(w.as_ref());
+    }
+    res
+}
+
+/// Returns the index in `s` of the `close` matching the `open` at its start.
+fn matching_close(s: &str, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// Splits function arguments at the top-level commas, the last of `max` arguments keeping any other comma.
+fn split_args(text: &str, max: usize) -> Vec<&str> {
+    let mut res = Vec::new();
+    let mut depth = 0i32;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' | '{' => depth += 1,
+            ')' | '}' => depth -= 1,
+            ',' if depth == 0 && res.len() + 1 < max => {
+                res.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    res.push(&text[start..]);
+    res
+}
+
+/// Splits the arguments of `ifeq (a,b)` or `ifeq "a" "b"`.
+fn split_eq_args(condition: &str) -> Option<(&str, &str)> {
+    let condition = condition.trim();
+    if let Some(inner) = condition.strip_prefix('(').and_then(|c| c.strip_suffix(')')) {
+        let args = split_args(inner, 2);
+        return match args[..] {
+            [a, b] => Some((a.trim(), b.trim())),
+            _ => None,
+        };
+    }
+    let quoted = |s: &str| -> Option<(usize, usize)> {
+        let quote = s.chars().next().filter(|q| *q == '"' || *q == '\'')?;
+        let end = s[1..].find(quote)?;
+        Some((1, end + 1))
+    };
+    let (start, end) = quoted(condition)?;
+    let rest = condition[end + 1..].trim_start();
+    let (start2, end2) = quoted(rest)?;
+    Some((&condition[start..end], &rest[start2..end2]))
+}
+
+fn patsubst_word(pattern: &str, replacement: &str, word: &str) -> String {
+    match word_stem(pattern, word) {
+        Some(stem) if pattern.contains('%') => replacement.replacen('%', stem, 1),
+        Some(_) => replacement.to_string(),
+        None => word.to_string(),
+    }
+}
+
+fn word_index(arg: &str, function: &str) -> Result<usize, AnvilError> {
+    match arg.trim().parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(AnvilError::Config(format!("Invalid first argument {{{}}} to the {function} function", arg.trim()))),
+    }
+}
+
+/// Returns where the suffix of the file name in `word` starts.
+fn suffix_at(word: &str) -> Option<usize> {
+    let dot = word.rfind('.')?;
+    match word.rfind('/') {
+        Some(slash) if slash > dot => None,
+        _ => Some(dot),
+    }
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    let mut res = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                res.pop();
+            }
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// Splits an expanded recipe line at the newlines not escaped by a backslash.
+fn split_command_lines(text: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut current = String::new();
+    for c in text.chars() {
+        if c == '\n' && !current.ends_with('\\') {
+            res.push(std::mem::take(&mut current));
+        } else {
+            current.push(c);
+        }
+    }
+    res.push(current);
+    res
+}
+
+/// Matches a file name against a shell pattern with `*`, `?` and `[...]`.
+fn fnmatch(pattern: &str, name: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    fnmatch_at(&p, &n)
+}
+
+fn fnmatch_at(p: &[char], n: &[char]) -> bool {
+    match p.first() {
+        None => n.is_empty(),
+        Some('*') => (0..=n.len()).any(|i| fnmatch_at(&p[1..], &n[i..])),
+        Some('?') => !n.is_empty() && fnmatch_at(&p[1..], &n[1..]),
+        Some('[') => {
+            let Some(c) = n.first() else { return false };
+            let Some(end) = p.iter().skip(2).position(|c| *c == ']').map(|i| i + 2) else {
+                return *c == '[' && fnmatch_at(&p[1..], &n[1..]);
+            };
+            let (negated, class) = match p[1] {
+                '!' | '^' => (true, &p[2..end]),
+                _ => (false, &p[1..end]),
+            };
+            let mut matched = false;
+            let mut i = 0;
+            while i < class.len() {
+                if i + 2 < class.len() && class[i + 1] == '-' {
+                    matched |= class[i] <= *c && *c <= class[i + 2];
+                    i += 3;
+                } else {
+                    matched |= class[i] == *c;
+                    i += 1;
+                }
+            }
+            matched != negated && fnmatch_at(&p[end + 1..], &n[1..])
+        }
+        Some(c) => n.first() == Some(c) && fnmatch_at(&p[1..], &n[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_builtin_make() {
+        let dir = TempDir::new("builtin-make");
+        fs::write(dir.join("a.in"), "a\n").unwrap();
+        fs::write(dir.join("Makefile"), "\
+SRCS := a.in
+OUTS = $(SRCS:.in=.out)
+MODE ?= fast
+ifeq ($(MODE),fast)
+FLAG = -f
+else ifeq ($(MODE),slow)
+FLAG = -s
+else
+FLAG = -x
+endif
+.PHONY: all clean
+all: $(OUTS) stamp
+%.out: %.in
+\t@cat $< > $@
+\techo $(FLAG) $(patsubst %.in,%,$^) >> $@
+stamp: $(OUTS)
+\ttouch $@
+clean:
+\t-rm -f $(OUTS) stamp missing/file
+").unwrap();
+        let run = |args: &[&str]| run_builtin_make(&dir, MakeOptions::from_args(args)).unwrap();
+
+        let dry = run(&["-n", "-j4"]);
+        assert_eq!(dry.code, Some(0));
+        assert_eq!(String::from_utf8_lossy(&dry.stdout), "cat a.in > a.out\necho -f a >> a.out\ntouch stamp\n");
+        assert!(!dir.join("a.out").exists());
+
+        let built = run(&["MODE=slow"]);
+        assert_eq!(built.code, Some(0));
+        assert_eq!(fs::read_to_string(dir.join("a.out")).unwrap(), "a\n-s a\n");
+        assert!(dir.join("stamp").exists());
+
+        let up_to_date = run(&["stamp"]);
+        assert_eq!(String::from_utf8_lossy(&up_to_date.stdout), "invil-make: 'stamp' is up to date.\n");
+
+        // A newer prerequisite remakes what depends on it
+        thread::sleep(Duration::from_millis(20));
+        fs::write(dir.join("a.in"), "b\n").unwrap();
+        let rebuilt = run(&["-n", "stamp"]);
+        assert_eq!(String::from_utf8_lossy(&rebuilt.stdout), "cat a.in > a.out\necho -f a >> a.out\ntouch stamp\n");
+
+        let missing = run(&["nothing"]);
+        assert_eq!(missing.code, Some(2));
+        assert_eq!(String::from_utf8_lossy(&missing.stderr), "invil-make: *** No rule to make target 'nothing'.  Stop.\n");
+
+        let clean = run(&["clean"]);
+        assert_eq!(clean.code, Some(0));
+        assert!(!dir.join("stamp").exists());
+
+        let make = BuiltinMake::load(&dir.join("Makefile"), MakeOptions::default()).unwrap();
+        assert_eq!(make.expand("$(words $(SRCS) b) $(if $(FLAG),yes,no) $(foreach w,x y,<$(w)>)").unwrap(), "2 yes <x> <y>");
+        assert_eq!(make.expand("$(addprefix p-,$(basename dir/x.c y)) $(filter %.c,a.c b.h) $$").unwrap(), "p-dir/x p-y a.c $");
+        assert!(make.expand("$(eval X = 1)").is_err());
+    }
+
+    #[test]
+    fn test_builtin_make_output() {
+        let dir = TempDir::new("builtin-make-output");
+        fs::write(dir.join("Makefile"), "\
+$(info reading)
+all:
+\t$(info building $@)@echo done
+\t\u{a0}@echo spaced
+").unwrap();
+
+        let out = run_builtin_make(&dir, MakeOptions::default()).unwrap();
+        assert_eq!(out.code, Some(0));
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "reading\nbuilding all\ndone\nspaced\n");
+    }
+}
@@ -0,0 +1,747 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+use serde::Serialize;
+use crate::core::RULELINE_MARK_CHAR;
+use crate::error::AnvilError;
+
+/// Lines of a Makefile element, 1-based and inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Span {
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AssignOp {
+    /// `=`, expanded when used
+    Recursive,
+    /// `:=`, expanded when defined
+    Simple,
+    /// `::=`, the POSIX spelling of `:=`
+    PosixSimple,
+    /// `:::=`, expanded when defined and escaped for later expansion
+    Immediate,
+    /// `?=`, only set when undefined
+    Conditional,
+    /// `+=`
+    Append,
+    /// `!=`, set to the output of a shell command
+    Shell,
+}
+
+impl AssignOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AssignOp::Recursive => "=",
+            AssignOp::Simple => ":=",
+            AssignOp::PosixSimple => "::=",
+            AssignOp::Immediate => ":::=",
+            AssignOp::Conditional => "?=",
+            AssignOp::Append => "+=",
+            AssignOp::Shell => "!=",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Variable {
+    pub name: String,
+    pub op: AssignOp,
+    /// Lines of a `define` are joined with newlines
+    pub value: String,
+    /// Set with `export` or `override`
+    pub modifiers: Vec<String>,
+    /// Targets the value is specific to, empty for a global variable
+    pub targets: Vec<String>,
+    /// Set with `define` and `endef`
+    pub define: bool,
+    pub span: Span,
+}
+
+/// Prints the variable as it would be written in a Makefile.
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.targets.is_empty() {
+            write!(f, "{}: ", self.targets.join(" "))?;
+        }
+        for m in self.modifiers.iter() {
+            write!(f, "{m} ")?;
+        }
+        if self.define {
+            write!(f, "define {} {}\n{}\nendef", self.name, self.op.as_str(), self.value)
+        } else {
+            write!(f, "{} {} {}", self.name, self.op.as_str(), self.value)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RecipeLine {
+    pub text: String,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Rule {
+    pub targets: Vec<String>,
+    pub prerequisites: Vec<String>,
+    /// Prerequisites after `|`
+    pub order_only: Vec<String>,
+    /// Target pattern of a static pattern rule, `targets: target-pattern: prerequisites`
+    pub target_pattern: Option<String>,
+    /// Declared with `::`, each rule keeping its own recipe
+    pub double_colon: bool,
+    pub recipe: Vec<RecipeLine>,
+    pub span: Span,
+}
+
+/// Returns the stem `name` matches for a `%` pattern in a rule. Rules never match an empty stem.
+pub fn pattern_stem<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+    if !pattern.contains('%') {
+        return None;
+    }
+    word_stem(pattern, name).filter(|stem| !stem.is_empty())
+}
+
+/// Returns the stem `word` matches for a pattern in functions like `$(patsubst)` and `$(filter)`.
+///
+/// The stem may be empty. A pattern without `%` matches an equal word, with an empty stem.
+pub fn word_stem<'a>(pattern: &str, word: &'a str) -> Option<&'a str> {
+    match pattern.split_once('%') {
+        Some((prefix, suffix)) if word.len() >= prefix.len() + suffix.len() => {
+            word.strip_prefix(prefix)?.strip_suffix(suffix)
+        }
+        Some(_) => None,
+        None => (pattern == word).then_some(""),
+    }
+}
+
+/// Returns the mtime of `path`, if it exists.
+pub fn mtime(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).ok()?.modified().ok()
+}
+
+/// Returns the mtime of `path` in seconds since the epoch, if it exists.
+pub fn mtime_secs(path: &Path) -> Option<u64> {
+    mtime(path)?.duration_since(SystemTime::UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+impl Rule {
+    /// Returns true for implicit pattern rules, like `%.o: %.c`.
+    pub fn is_pattern(&self) -> bool {
+        self.target_pattern.is_none() && self.targets.iter().any(|t| t.contains('%'))
+    }
+
+    /// Returns the prerequisites of `target`, with the stem substituted for static pattern rules.
+    pub fn prerequisites_for(&self, target: &str) -> Vec<String> {
+        let stem = self.target_pattern.as_deref().and_then(|p| pattern_stem(p, target));
+        match stem {
+            Some(stem) => self.prerequisites.iter().map(|p| p.replacen('%', stem, 1)).collect(),
+            None => self.prerequisites.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Include {
+    pub paths: Vec<String>,
+    /// `-include` or `sinclude`: missing files are not an error
+    pub optional: bool,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Conditional {
+    /// `ifeq`, `ifneq`, `ifdef`, `ifndef`, or automake's `if`
+    pub kind: String,
+    pub condition: String,
+    /// Lines of each `else`, which may start another condition
+    pub else_lines: Vec<usize>,
+    /// What follows each `else`, like `ifeq (a,b)`, empty for a plain `else`
+    pub else_conditions: Vec<String>,
+    /// From the opening directive to `endif`
+    pub span: Span,
+}
+
+impl Conditional {
+    /// Returns the branch `line` is in, 0 before the first `else`.
+    pub fn branch(&self, line: usize) -> usize {
+        self.else_lines.iter().filter(|e| line > **e).count()
+    }
+
+    /// Returns true if `line` is between the opening directive and `endif`.
+    pub fn contains(&self, line: usize) -> bool {
+        self.span.start_line < line && line < self.span.end_line
+    }
+}
+
+/// A line that is not an assignment, rule, recipe or directive, like `export` or `vpath`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Expression {
+    pub text: String,
+    pub span: Span,
+}
+
+/// A parsed Makefile. Elements are in file order, and conditionals don't hide their contents.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Makefile {
+    pub variables: Vec<Variable>,
+    pub rules: Vec<Rule>,
+    pub includes: Vec<Include>,
+    pub conditionals: Vec<Conditional>,
+    pub expressions: Vec<Expression>,
+}
+
+/// Conditional directives. `if` is automake's, used in `Makefile.am`
+const CONDITIONAL_DIRECTIVES: &[&str] = &["ifeq", "ifneq", "ifdef", "ifndef", "if"];
+
+/// A line after joining continuations
+struct LogicalLine {
+    text: String,
+    span: Span,
+}
+
+/// Joins backslash continued lines. Recipe lines keep the backslash-newline, as make passes them to the shell.
+fn logical_lines(contents: &str) -> Vec<LogicalLine> {
+    let mut res: Vec<LogicalLine> = Vec::new();
+    let mut pending: Option<LogicalLine> = None;
+    for (i, line) in contents.lines().enumerate() {
+        let line_no = i + 1;
+        let cur = match pending.take() {
+            Some(mut p) => {
+                if p.text.starts_with(RULELINE_MARK_CHAR) {
+                    p.text.push('\n');
+                    p.text.push_str(line);
+                } else {
+                    p.text.pop();
+                    let trimmed = p.text.trim_end().len();
+                    p.text.truncate(trimmed);
+                    p.text.push(' ');
+                    p.text.push_str(line.trim_start());
+                }
+                p.span.end_line = line_no;
+                p
+            }
+            None => LogicalLine { text: line.to_string(), span: Span { start_line: line_no, end_line: line_no } },
+        };
+        // An even number of trailing backslashes is escaped
+        let backslashes = cur.text.chars().rev().take_while(|c| *c == '\\').count();
+        if backslashes % 2 == 1 {
+            pending = Some(cur);
+        } else {
+            res.push(cur);
+        }
+    }
+    if let Some(p) = pending {
+        res.push(p);
+    }
+    res
+}
+
+/// Removes a `#` comment, unless escaped as `\#`.
+fn strip_comment(line: &str) -> String {
+    let mut res = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'#') => {
+                res.push('#');
+                chars.next();
+            }
+            '#' => break,
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+/// Returns the byte offset of the first `:` or assignment operator outside `$(...)` and `${...}`, with its kind.
+fn find_separator(line: &str) -> Option<(usize, Option<AssignOp>)> {
+    let bytes = line.as_bytes();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'$' if i + 1 < bytes.len() && matches!(bytes[i + 1], b'(' | b'{') => {
+                depth += 1;
+                i += 1;
+            }
+            b'(' | b'{' if depth > 0 => depth += 1,
+            b')' | b'}' if depth > 0 => depth -= 1,
+            b':' if depth == 0 => {
+                for op in [AssignOp::Immediate, AssignOp::PosixSimple, AssignOp::Simple] {
+                    if line[i..].starts_with(op.as_str()) {
+                        return Some((i, Some(op)));
+                    }
+                }
+                return Some((i, None));
+            }
+            b'=' if depth == 0 => {
+                let op = match i.checked_sub(1).map(|p| bytes[p]) {
+                    Some(b'?') => Some((i - 1, AssignOp::Conditional)),
+                    Some(b'+') => Some((i - 1, AssignOp::Append)),
+                    Some(b'!') => Some((i - 1, AssignOp::Shell)),
+                    _ => None,
+                };
+                return Some(op.map(|(at, op)| (at, Some(op))).unwrap_or((i, Some(AssignOp::Recursive))));
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Splits the `export` and `override` modifiers off `line`.
+fn split_modifiers(line: &str) -> (Vec<String>, &str) {
+    let mut modifiers = Vec::new();
+    let mut rest = line.trim_start();
+    while let Some((word, after)) = rest.split_once(char::is_whitespace) {
+        if word != "export" && word != "override" {
+            break;
+        }
+        modifiers.push(word.to_string());
+        rest = after.trim_start();
+    }
+    (modifiers, rest)
+}
+
+/// Splits `s` on whitespace outside `$(...)` and `${...}`.
+fn words(s: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut cur = String::new();
+    let mut depth = 0;
+    let mut prev = ' ';
+    for c in s.chars() {
+        match c {
+            '(' | '{' if depth > 0 || prev == '$' => depth += 1,
+            ')' | '}' if depth > 0 => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => {
+                if !cur.is_empty() {
+                    res.push(std::mem::take(&mut cur));
+                }
+                prev = c;
+                continue;
+            }
+            _ => {}
+        }
+        cur.push(c);
+        prev = c;
+    }
+    if !cur.is_empty() {
+        res.push(cur);
+    }
+    res
+}
+
+/// A `define` being read
+struct PendingDefine {
+    variable: Variable,
+    body: Vec<String>,
+    /// Nested `define`s, which are kept in the body
+    depth: usize,
+}
+
+/// Parses a `define` line, `[modifiers] define NAME [op]`, returning None for other lines.
+fn parse_define(line: &str, span: Span) -> Option<Variable> {
+    let (modifiers, rest) = split_modifiers(line);
+    let rest = rest.strip_prefix("define")?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let rest = rest.trim();
+    let (name, op) = match find_separator(rest) {
+        Some((at, Some(op))) if rest[at + op.as_str().len()..].trim().is_empty() => (rest[..at].trim(), op),
+        _ => (rest, AssignOp::Recursive),
+    };
+    Some(Variable { name: name.to_string(), op, value: String::new(), modifiers, targets: Vec::new(), define: true, span })
+}
+
+/// A line with a single colon separator
+enum RuleLine {
+    Rule(Rule),
+    /// A target-specific variable, `targets: [modifiers] NAME op value`
+    Variable(Variable),
+}
+
+/// Parses the part of a rule line after the targets and the colons.
+fn parse_rule(targets: Vec<String>, after: &str, double_colon: bool, span: Span) -> RuleLine {
+    let semicolon = after.find(';');
+    let (modifiers, assignment) = split_modifiers(after);
+    if let Some((at, Some(op))) = find_separator(assignment) {
+        let offset = after.len() - assignment.len();
+        if semicolon.is_none_or(|s| s > offset + at) {
+            let name = assignment[..at].trim().to_string();
+            let value = assignment[at + op.as_str().len()..].trim().to_string();
+            return RuleLine::Variable(Variable { name, op, value, modifiers, targets, define: false, span });
+        }
+    }
+    let (deps, inline_recipe) = match after.split_once(';') {
+        Some((d, r)) => (d, Some(r.trim())),
+        None => (after, None),
+    };
+    let (target_pattern, deps) = match find_separator(deps) {
+        Some((at, None)) => (Some(deps[..at].trim().to_string()), &deps[at + 1..]),
+        _ => (None, deps),
+    };
+    let (prerequisites, order_only) = match deps.split_once('|') {
+        Some((p, o)) => (words(p), words(o)),
+        None => (words(deps), Vec::new()),
+    };
+    let mut recipe = Vec::new();
+    if let Some(r) = inline_recipe.filter(|r| !r.is_empty()) {
+        recipe.push(RecipeLine { text: r.to_string(), line: span.start_line });
+    }
+    RuleLine::Rule(Rule { targets, prerequisites, order_only, target_pattern, double_colon, recipe, span })
+}
+
+/// Parses the contents of a Makefile.
+pub fn parse_makefile_str(contents: &str) -> Result<Makefile, AnvilError> {
+    parse_makefile_source(Path::new(""), contents)
+}
+
+/// Parses `contents`, read from the Makefile at `path`. The path is only used to report errors.
+pub fn parse_makefile_source(path: &Path, contents: &str) -> Result<Makefile, AnvilError> {
+    let parse_error = |line: usize, message: String| AnvilError::MakefileParse { path: path.to_path_buf(), line, message };
+    let mut mk = Makefile::default();
+    let mut open_conditionals: Vec<usize> = Vec::new();
+    // Index of the rule receiving recipe lines
+    let mut current_rule: Option<usize> = None;
+    let mut define: Option<PendingDefine> = None;
+    for logical in logical_lines(contents) {
+        let span = logical.span;
+        if let Some(mut pending) = define.take() {
+            let trimmed = logical.text.trim();
+            let directive = strip_comment(trimmed);
+            if directive.trim() == "endef" {
+                if pending.depth == 0 {
+                    pending.variable.value = pending.body.join("\n");
+                    pending.variable.span.end_line = span.end_line;
+                    mk.variables.push(pending.variable);
+                    continue;
+                }
+                pending.depth -= 1;
+            } else if parse_define(trimmed, span).is_some() {
+                pending.depth += 1;
+            }
+            pending.body.push(logical.text);
+            define = Some(pending);
+            continue;
+        }
+        if let Some(recipe) = logical.text.strip_prefix(RULELINE_MARK_CHAR) {
+            if let Some(r) = current_rule {
+                if !recipe.trim().is_empty() {
+                    mk.rules[r].recipe.push(RecipeLine { text: recipe.to_string(), line: span.start_line });
+                }
+                continue;
+            }
+        }
+        let stripped = strip_comment(&logical.text);
+        let line = stripped.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (first, rest) = match line.split_once(char::is_whitespace) {
+            Some((f, r)) => (f, r.trim()),
+            None => (line, ""),
+        };
+        match first {
+            "include" | "-include" | "sinclude" => {
+                mk.includes.push(Include { paths: words(rest), optional: first != "include", span });
+                current_rule = None;
+                continue;
+            }
+            _ if CONDITIONAL_DIRECTIVES.contains(&first) => {
+                open_conditionals.push(mk.conditionals.len());
+                mk.conditionals.push(Conditional { kind: first.to_string(), condition: rest.to_string(), else_lines: Vec::new(), else_conditions: Vec::new(), span });
+                continue;
+            }
+            "else" => {
+                let Some(&c) = open_conditionals.last() else {
+                    error!("Line {}: else without a conditional", span.start_line);
+                    return Err(parse_error(span.start_line, "else without a conditional".to_string()));
+                };
+                mk.conditionals[c].else_lines.push(span.start_line);
+                mk.conditionals[c].else_conditions.push(rest.to_string());
+                continue;
+            }
+            "endif" => {
+                let Some(c) = open_conditionals.pop() else {
+                    error!("Line {}: endif without a conditional", span.start_line);
+                    return Err(parse_error(span.start_line, "endif without a conditional".to_string()));
+                };
+                mk.conditionals[c].span.end_line = span.end_line;
+                continue;
+            }
+            "endef" => {
+                error!("Line {}: endef without define", span.start_line);
+                return Err(parse_error(span.start_line, "endef without define".to_string()));
+            }
+            _ => {}
+        }
+        if let Some(variable) = parse_define(line, span) {
+            define = Some(PendingDefine { variable, body: Vec::new(), depth: 0 });
+            current_rule = None;
+            continue;
+        }
+        let (modifiers, assignment) = split_modifiers(line);
+        match find_separator(assignment) {
+            Some((at, Some(op))) => {
+                let name = assignment[..at].trim().to_string();
+                let value = assignment[at + op.as_str().len()..].trim().to_string();
+                mk.variables.push(Variable { name, op, value, modifiers, targets: Vec::new(), define: false, span });
+                current_rule = None;
+            }
+            Some((at, None)) if modifiers.is_empty() => {
+                let targets = words(&line[..at]);
+                let double_colon = line[at + 1..].starts_with(':');
+                let after = if double_colon { &line[at + 2..] } else { &line[at + 1..] };
+                match parse_rule(targets, after, double_colon, span) {
+                    RuleLine::Rule(rule) => {
+                        mk.rules.push(rule);
+                        current_rule = Some(mk.rules.len() - 1);
+                    }
+                    RuleLine::Variable(variable) => {
+                        mk.variables.push(variable);
+                        current_rule = None;
+                    }
+                }
+            }
+            _ => {
+                mk.expressions.push(Expression { text: line.to_string(), span });
+                current_rule = None;
+            }
+        }
+    }
+    if let Some(pending) = define {
+        let line = pending.variable.span.start_line;
+        error!("Line {line}: define without endef");
+        return Err(parse_error(line, "define without endef".to_string()));
+    }
+    if let Some(&c) = open_conditionals.last() {
+        let line = mk.conditionals[c].span.start_line;
+        error!("Line {line}: {} without endif", mk.conditionals[c].kind);
+        return Err(parse_error(line, format!("{} without endif", mk.conditionals[c].kind)));
+    }
+    Ok(mk)
+}
+
+/// Reads and parses the Makefile at `path`.
+pub fn parse_makefile(path: &Path) -> Result<Makefile, AnvilError> {
+    let contents = fs::read_to_string(path).map_err(|e| AnvilError::io(path, e))?;
+    parse_makefile_source(path, &contents)
+}
+
+/// A `$(...)` or `${...}` in a Makefile line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reference<'a> {
+    Variable(&'a str),
+    Function(&'a str),
+}
+
+/// Make functions, told apart from variables by the whitespace after their name
+pub const MAKE_FUNCTIONS: &[&str] = &[
+    "subst", "patsubst", "strip", "findstring", "filter", "filter-out", "sort", "word", "wordlist", "words",
+    "firstword", "lastword", "dir", "notdir", "suffix", "basename", "addsuffix", "addprefix", "join", "wildcard",
+    "realpath", "abspath", "if", "or", "and", "intcmp", "foreach", "file", "call", "value", "eval", "origin",
+    "flavor", "error", "warning", "info", "shell", "guile", "let",
+];
+
+/// Returns the references in `text`, nested ones included, in order.
+///
+/// `$$` is skipped, as are references with a computed name.
+pub fn references(text: &str) -> Vec<Reference<'_>> {
+    let bytes = text.as_bytes();
+    let mut res = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'$' {
+            i += 1;
+            continue;
+        }
+        match bytes.get(i + 1) {
+            Some(b'$') => i += 2,
+            Some(b'(' | b'{') => {
+                let start = i + 2;
+                let end = text[start..]
+                    .find(|c: char| c.is_whitespace() || matches!(c, ':' | ')' | '}' | ',' | '$'))
+                    .map_or(text.len(), |e| start + e);
+                let name = &text[start..end];
+                match text[end..].chars().next() {
+                    Some(c) if c.is_whitespace() && MAKE_FUNCTIONS.contains(&name) => res.push(Reference::Function(name)),
+                    Some(':' | ')' | '}') if !name.is_empty() => res.push(Reference::Variable(name)),
+                    _ => {}
+                }
+                // Keep scanning inside, for nested references
+                i = start;
+            }
+            _ => i += 1,
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+# Sample
+CC ?= gcc
+CFLAGS := -Wall \\
+    -O2
+export PREFIX = /usr/local
+SRCS += main.c # sources
+include config.mk
+-include local.mk
+
+.PHONY: all clean
+
+all: hello $(addsuffix .o, a b) | bin
+\t$(CC) $(CFLAGS) -o bin/hello main.c
+
+ifeq ($(DEBUG),1)
+hello: main.c util.h ; $(CC) -g -o $@ $<
+else
+hello: main.c
+\t$(CC) -o $@ $< \\
+\t  -lm
+endif
+
+clean:
+\trm -f hello # comment kept for the shell
+";
+
+    #[test]
+    fn test_parse_makefile() {
+        let mk = parse_makefile_str(SAMPLE).expect("Failed parse");
+        let vars: Vec<(&str, AssignOp, &str)> = mk.variables.iter().map(|v| (v.name.as_str(), v.op, v.value.as_str())).collect();
+        assert_eq!(vars, [
+            ("CC", AssignOp::Conditional, "gcc"),
+            ("CFLAGS", AssignOp::Simple, "-Wall -O2"),
+            ("PREFIX", AssignOp::Recursive, "/usr/local"),
+            ("SRCS", AssignOp::Append, "main.c"),
+        ]);
+        assert_eq!(mk.variables[1].span, Span { start_line: 3, end_line: 4 });
+        assert_eq!(mk.variables[2].modifiers, ["export"]);
+        assert_eq!(mk.includes.len(), 2);
+        assert!(mk.includes[1].optional);
+        assert_eq!(mk.rules.len(), 5);
+        let all = &mk.rules[1];
+        assert_eq!(all.targets, ["all"]);
+        assert_eq!(all.prerequisites, ["hello", "$(addsuffix .o, a b)"]);
+        assert_eq!(all.order_only, ["bin"]);
+        assert_eq!(all.recipe[0].text, "$(CC) $(CFLAGS) -o bin/hello main.c");
+        assert_eq!(mk.rules[2].recipe[0].text, "$(CC) -g -o $@ $<");
+        assert_eq!(mk.rules[3].recipe[0].text, "$(CC) -o $@ $< \\\n\t  -lm");
+        assert_eq!(mk.rules[4].recipe[0].text, "rm -f hello # comment kept for the shell");
+        assert_eq!(mk.conditionals[0].kind, "ifeq");
+        assert_eq!(mk.conditionals[0].else_lines, [17]);
+        assert_eq!(mk.conditionals[0].span, Span { start_line: 15, end_line: 21 });
+        assert!(parse_makefile_str("ifdef X\nA = 1\n").is_err());
+        assert!(parse_makefile_str("endif\n").is_err());
+        let am = parse_makefile_str("if DEBUG_BUILD\nAM_CFLAGS += -g\nelse\nAM_CFLAGS += -O2\nendif\n").expect("Failed automake parse");
+        assert_eq!(am.conditionals[0].kind, "if");
+        assert_eq!(am.conditionals[0].condition, "DEBUG_BUILD");
+    }
+
+    #[test]
+    fn test_parse_gnu_syntax() {
+        let contents = "\
+CC ::= gcc
+UNAME != uname -s
+OBJS = a.o b.o
+define RUN =
+\t@echo $(1)
+define INNER
+endef
+endef
+hello.o: override CFLAGS += -g
+$(OBJS): %.o: %.c | build
+\t$(CC) -c $< -o $@
+check:: hello
+\t./hello
+t: ; echo a=b
+ifeq ($(UNAME),Linux)
+LIBS = -lm
+else ifeq ($(UNAME),Darwin)
+LIBS =
+else
+LIBS = -lws2
+endif
+";
+        let mk = parse_makefile_str(contents).expect("Failed parse");
+        let ops: Vec<(&str, AssignOp)> = mk.variables.iter().map(|v| (v.name.as_str(), v.op)).collect();
+        assert_eq!(&ops[..5], [
+            ("CC", AssignOp::PosixSimple),
+            ("UNAME", AssignOp::Shell),
+            ("OBJS", AssignOp::Recursive),
+            ("RUN", AssignOp::Recursive),
+            ("CFLAGS", AssignOp::Append),
+        ]);
+        let run = &mk.variables[3];
+        assert!(run.define);
+        assert_eq!(run.value, "\t@echo $(1)\ndefine INNER\nendef");
+        assert_eq!(run.span, Span { start_line: 4, end_line: 8 });
+        let cflags = &mk.variables[4];
+        assert_eq!(cflags.targets, ["hello.o"]);
+        assert_eq!(cflags.modifiers, ["override"]);
+        assert_eq!(cflags.to_string(), "hello.o: override CFLAGS += -g");
+
+        let objs = &mk.rules[0];
+        assert_eq!(objs.target_pattern.as_deref(), Some("%.o"));
+        assert_eq!(objs.order_only, ["build"]);
+        assert_eq!(objs.prerequisites_for("a.o"), ["a.c"]);
+        assert!(!objs.is_pattern());
+        assert!(mk.rules[1].double_colon);
+        assert_eq!(mk.rules[2].recipe[0].text, "echo a=b");
+        let cond = &mk.conditionals[0];
+        assert_eq!(cond.else_lines, [17, 19]);
+        assert_eq!(cond.else_conditions, ["ifeq ($(UNAME),Darwin)", ""]);
+        assert_eq!((cond.branch(16), cond.branch(18), cond.branch(20)), (0, 1, 2));
+
+        assert!(parse_makefile_str("define X\nfoo\n").is_err());
+        assert!(parse_makefile_str("endef\n").is_err());
+        match parse_makefile_source(Path::new("Makefile"), "A = 1\nifdef X\nelse\nelse\nendif\nendif\n") {
+            Err(e @ AnvilError::MakefileParse { line: 6, .. }) => assert_eq!(e.to_string(), "Makefile:6: endif without a conditional"),
+            other => panic!("Expected MakefileParse, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_references() {
+        assert_eq!(references("$(CC) ${CFLAGS} $$HOME $(SRCS:.c=.o) $(shell ls $(DIR)) $($(X)_Y)"), [
+            Reference::Variable("CC"),
+            Reference::Variable("CFLAGS"),
+            Reference::Variable("SRCS"),
+            Reference::Function("shell"),
+            Reference::Variable("DIR"),
+            Reference::Variable("X"),
+        ]);
+    }
+    #[test]
+    fn test_pattern_stems() {
+        assert_eq!(pattern_stem("%.o", "main.o"), Some("main"));
+        assert_eq!(pattern_stem("%.o", ".o"), None);
+        assert_eq!(pattern_stem("main.o", "main.o"), None);
+        assert_eq!(word_stem("%.o", ".o"), Some(""));
+        assert_eq!(word_stem("main.o", "main.o"), Some(""));
+        assert_eq!(word_stem("%.c", "main.o"), None);
+    }
+}
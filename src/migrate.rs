@@ -0,0 +1,251 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use toml_edit::{DocumentMut, Item, Table, value};
+use crate::core::{Args, ANVIL_EXIT_CONFIG, ANVIL_EXIT_FAILURE, EXPECTED_AMBOSO_API_LEVEL,
+    ANVIL_VERSION_KEYNAME, ANVIL_SOURCE_KEYNAME, ANVIL_BIN_KEYNAME, ANVIL_MAKE_VERS_KEYNAME,
+    ANVIL_AUTOMAKE_VERS_KEYNAME, ANVIL_TESTSDIR_KEYNAME, ANVIL_BONEDIR_KEYNAME, ANVIL_KULPODIR_KEYNAME,
+    AMBOSO_BUILD_LEGACY_POS, AMBOSO_SOURCE_LEGACY_POS, AMBOSO_BIN_LEGACY_POS, AMBOSO_MAKEVERS_LEGACY_POS,
+    AMBOSO_TESTS_LEGACY_POS, AMBOSO_AUTOMAKEVERS_LEGACY_POS, AMBOSO_VERSIONS_LEGACY_POS,
+    AMBOSO_BONE_LEGACY_POS, AMBOSO_KULPO_LEGACY_POS, cut_line_at_char, CutDirection, is_semver, semver_compare};
+use crate::error::{AnvilError, error_chain};
+use crate::fmt::{add_anvil_version, format_stego_document, parse_stego_document};
+use crate::stego::{deserialize_stego, STEGO_ANVIL_SECTION, STEGO_BUILD_SECTION, STEGO_TESTS_SECTION, STEGO_VERSIONS_SECTION};
+use crate::utils::line_diff;
+
+/// Splits a legacy line into its trimmed value and comment.
+fn split_legacy_line(line: &str) -> (String, String) {
+    let value = cut_line_at_char(line, '#', CutDirection::Before).trim().to_string();
+    let comment = cut_line_at_char(line, '#', CutDirection::After).trim().to_string();
+    (value, comment)
+}
+
+fn commented(mut item: Item, comment: &str) -> Item {
+    if !comment.is_empty() {
+        if let Some(v) = item.as_value_mut() {
+            v.decor_mut().set_suffix(format!(" # {comment}"));
+        }
+    }
+    item
+}
+
+fn section(position: isize, comment: &str) -> Table {
+    let mut table = Table::new();
+    table.set_position(Some(position));
+    if !comment.is_empty() {
+        table.decor_mut().set_prefix(format!("# {comment}\n"));
+    }
+    table
+}
+
+/// Reads `testsdir` and `errortestsdir` from a legacy `kazoj.lock`.
+fn parse_legacy_kazoj(kazoj_path: &Path) -> Result<Table, AnvilError> {
+    let contents = fs::read_to_string(kazoj_path).map_err(|e| AnvilError::io(kazoj_path, e))?;
+    let mut tests = section(2, "");
+    for (i, line) in contents.lines().enumerate() {
+        let (val, comment) = split_legacy_line(line);
+        let key = match i as u64 {
+            n if n == AMBOSO_BONE_LEGACY_POS + 1 => ANVIL_BONEDIR_KEYNAME,
+            n if n == AMBOSO_KULPO_LEGACY_POS + 1 => ANVIL_KULPODIR_KEYNAME,
+            _ => continue,
+        };
+        if val.is_empty() {
+            return Err(AnvilError::Config(format!("{}:{}: empty {key} definition", kazoj_path.display(), i + 1)));
+        }
+        tests.insert(key, commented(value(val), &comment));
+    }
+    Ok(tests)
+}
+
+/// Converts a positional pre-1.8 `stego.lock` to a TOML document.
+///
+/// Comments become trailing comments of the matching key, while version comments are their descriptions.
+pub fn migrate_legacy_stego(stego_path: &Path, contents: &str) -> Result<DocumentMut, AnvilError> {
+    let mut anvil = section(0, "");
+    anvil.insert(ANVIL_VERSION_KEYNAME, value(EXPECTED_AMBOSO_API_LEVEL));
+    let mut build = section(1, "");
+    let mut versions = section(3, "");
+    let mut tests = None;
+
+    for (i, line) in contents.lines().enumerate() {
+        let (val, comment) = split_legacy_line(line);
+        let pos = i as u64;
+        let key = match pos {
+            AMBOSO_BUILD_LEGACY_POS => {
+                build = section(1, &comment);
+                continue;
+            }
+            AMBOSO_VERSIONS_LEGACY_POS => {
+                versions = section(3, &comment);
+                continue;
+            }
+            AMBOSO_SOURCE_LEGACY_POS => ANVIL_SOURCE_KEYNAME,
+            AMBOSO_BIN_LEGACY_POS => ANVIL_BIN_KEYNAME,
+            AMBOSO_MAKEVERS_LEGACY_POS => ANVIL_MAKE_VERS_KEYNAME,
+            AMBOSO_TESTS_LEGACY_POS => ANVIL_TESTSDIR_KEYNAME,
+            AMBOSO_AUTOMAKEVERS_LEGACY_POS => ANVIL_AUTOMAKE_VERS_KEYNAME,
+            _ => {
+                if val.is_empty() {
+                    continue;
+                }
+                let (tag, version_key) = match val.strip_prefix('?') {
+                    Some(t) => (t, format!("B{t}")),
+                    None => (val.as_str(), val.clone()),
+                };
+                if !is_semver(tag) {
+                    return Err(AnvilError::Config(format!("{}:{}: invalid semver key {{{tag}}}", stego_path.display(), i + 1)));
+                }
+                if versions.contains_key(&version_key) {
+                    return Err(AnvilError::Config(format!("{}:{}: {{{val}}} is listed twice", stego_path.display(), i + 1)));
+                }
+                versions.insert(&version_key, value(comment));
+                continue;
+            }
+        };
+        if val.is_empty() {
+            return Err(AnvilError::Config(format!("{}:{}: empty {key} definition", stego_path.display(), i + 1)));
+        }
+        if pos == AMBOSO_TESTS_LEGACY_POS {
+            let stego_dir = stego_path.parent().map(Path::to_path_buf).unwrap_or_default();
+            let kazoj_path = stego_dir.join(&val).join("kazoj.lock");
+            match parse_legacy_kazoj(&kazoj_path) {
+                Ok(t) => tests = Some(t),
+                Err(e) => warn!("Skipping [tests] section. Err: {e}"),
+            }
+        }
+        build.insert(key, commented(value(val), &comment));
+    }
+    if contents.lines().count() as u64 <= AMBOSO_AUTOMAKEVERS_LEGACY_POS {
+        return Err(AnvilError::Config(format!("{}: too few lines for a legacy stego.lock", stego_path.display())));
+    }
+
+    let mut doc = DocumentMut::new();
+    doc.insert(STEGO_ANVIL_SECTION, Item::Table(anvil));
+    doc.insert(STEGO_BUILD_SECTION, Item::Table(build));
+    if let Some(tests) = tests {
+        doc.insert(STEGO_TESTS_SECTION, Item::Table(tests));
+    }
+    doc.insert(STEGO_VERSIONS_SECTION, Item::Table(versions));
+    format_stego_document(&mut doc);
+    Ok(doc)
+}
+
+/// Returns the contents of `stego.lock` migrated to `EXPECTED_AMBOSO_API_LEVEL`.
+///
+/// TOML files only get their `[anvil] version` raised, so everything else is kept as is.
+pub fn migrate_stego(stego_path: &Path, contents: &str) -> Result<String, AnvilError> {
+    let toml_err = match deserialize_stego(stego_path, contents) {
+        Ok(_) => {
+            let mut doc = parse_stego_document(stego_path, contents)?;
+            let current = doc.get(STEGO_ANVIL_SECTION)
+                .and_then(|a| a.get(ANVIL_VERSION_KEYNAME))
+                .and_then(Item::as_str)
+                .map(str::to_string);
+            match current {
+                Some(ref v) if is_semver(v) && semver_compare(v, EXPECTED_AMBOSO_API_LEVEL) != Ordering::Less => {
+                    debug!("{{{}}} already expects anvil {{{v}}}", stego_path.display());
+                    return Ok(contents.to_string());
+                }
+                _ => {}
+            }
+            let version = doc.get_mut(STEGO_ANVIL_SECTION)
+                .and_then(Item::as_table_mut)
+                .and_then(|a| a.get_mut(ANVIL_VERSION_KEYNAME))
+                .and_then(Item::as_value_mut);
+            match version {
+                Some(v) => {
+                    let decor = v.decor().clone();
+                    *v = EXPECTED_AMBOSO_API_LEVEL.into();
+                    *v.decor_mut() = decor;
+                }
+                None => {
+                    add_anvil_version(&mut doc, EXPECTED_AMBOSO_API_LEVEL);
+                }
+            }
+            return Ok(doc.to_string());
+        }
+        Err(e) => e,
+    };
+    match migrate_legacy_stego(stego_path, contents) {
+        Ok(doc) => Ok(doc.to_string()),
+        Err(e) => {
+            debug!("Not a legacy stego.lock. Err: {e}");
+            Err(toml_err)
+        }
+    }
+}
+
+/// Migrates the stego.lock in the passed stego dir, printing a diff first.
+///
+/// With `dry_run`, only the diff is printed.
+pub fn handle_migrate_subcommand(args: &Args, dry_run: bool) -> ExitCode {
+    let stego_path = args.stego_dir.clone().unwrap_or(PathBuf::from(".")).join("stego.lock");
+    let contents = match fs::read_to_string(&stego_path) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Failed reading {{{}}}. Err: {e}", stego_path.display());
+            return ExitCode::from(ANVIL_EXIT_CONFIG);
+        }
+    };
+    let migrated = match migrate_stego(&stego_path, &contents) {
+        Ok(m) => m,
+        Err(e) => {
+            error!("Failed migrating {{{}}}. Err: {}", stego_path.display(), error_chain(&e));
+            return ExitCode::from(ANVIL_EXIT_CONFIG);
+        }
+    };
+    if migrated == contents {
+        info!("{{{}}} is up to date", stego_path.display());
+        return ExitCode::SUCCESS;
+    }
+    println!("--- {}\n+++ {}", stego_path.display(), stego_path.display());
+    print!("{}", line_diff(&contents, &migrated));
+    if dry_run {
+        return ExitCode::SUCCESS;
+    }
+    match fs::write(&stego_path, migrated) {
+        Ok(()) => {
+            info!("Migrated {{{}}} to anvil {{{EXPECTED_AMBOSO_API_LEVEL}}}", stego_path.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            error!("Failed writing {{{}}}. Err: {e}", stego_path.display());
+            ExitCode::from(ANVIL_EXIT_FAILURE)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_legacy_stego() {
+        let contents = "#build\nmain.c # entry point\nmain\n0.1.0\nkazoj\n0.2.0\n#versions\n?0.0.9#base release\n0.1.0#first git tag\n";
+        let migrated = migrate_stego(Path::new("missing/stego.lock"), contents).expect("Failed migrating");
+        assert_eq!(migrated, "[anvil]\n\nversion = \"2.1.3\"\n\n# build\n[build]\n\nsource = \"main.c\" # entry point\nbin = \"main\"\nmakevers = \"0.1.0\"\ntests = \"kazoj\"\nautomakevers = \"0.2.0\"\n\n# versions\n[versions]\n\n\"B0.0.9\" = \"base release\"\n\"0.1.0\" = \"first git tag\"\n");
+        deserialize_stego(Path::new("stego.lock"), &migrated).expect("Migrated stego.lock should parse");
+    }
+
+    #[test]
+    fn test_migrate_toml_stego() {
+        let contents = "# keep me\n[anvil]\nversion = \"2.0.0\" # old\n\n[build]\nsource = \"main.c\"\n";
+        let migrated = migrate_stego(Path::new("stego.lock"), contents).expect("Failed migrating");
+        assert_eq!(migrated, "# keep me\n[anvil]\nversion = \"2.1.3\" # old\n\n[build]\nsource = \"main.c\"\n");
+        assert_eq!(migrate_stego(Path::new("stego.lock"), &migrated).expect("Failed migrating"), migrated);
+    }
+}
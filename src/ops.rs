@@ -11,46 +11,75 @@
  *  You should have received a copy of the GNU General Public License
  *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
-use crate::core::{Args, AmbosoEnv, AmbosoMode, AmbosoLintMode, AnvilKern, INVIL_VERSION, INVIL_OS, EXPECTED_AMBOSO_API_LEVEL, parse_stego_toml, lex_stego_toml, SemVerKey, ANVIL_INTERPRETER_TAG_REGEX, RULE_REGEX, RULELINE_MARK_CHAR, RULEWARN_REGEX, cut_line_at_char, CutDirection, semver_compare, MIN_AMBOSO_V_PYKERN, MIN_AMBOSO_V_CHECK_DETACHED};
+use crate::core::{Args, AmbosoEnv, AmbosoMode, AmbosoLintMode, AnvilKern, parse_stego_toml, lex_stego_toml, SemVerKey, ANVIL_INTERPRETER_TAG_REGEX, RULEWARN_REGEX, semver_compare, MIN_AMBOSO_V_PYKERN, MIN_AMBOSO_V_CHECK_DETACHED};
 use crate::utils::try_parse_stego;
-
-use std::process::{Command, Stdio, exit};
-use std::io::{self, Write, BufRead};
+use crate::error::AnvilError;
+use crate::makefile::{mtime_secs, parse_makefile, parse_makefile_source};
+use crate::graph::{make_graph, render_graph};
+use crate::lint::{LintSeverity, lint_makefile_file};
+use crate::make::{BuildOutput, MakeOptions, run_builtin_make};
+use crate::header::{HeaderLang, HeaderOptions, header_info, render_header, render_template, template_output_name};
+
+use std::process::{Command, Output, Stdio};
+use std::os::unix::process::ExitStatusExt;
+use std::io::{self, Write, Read};
 use std::path::{Path, PathBuf};
 use is_executable::is_executable;
 use std::collections::BTreeMap;
 use std::fs::{self, File};
 use git2::Repository;
 use std::env;
-use std::time::SystemTime;
+use std::thread;
+use std::time::{Duration, Instant};
 use regex::Regex;
 use std::cmp::Ordering;
 
 #[cfg(feature = "anvilPy")]
 use crate::anvil_py::{ANVILPY_UNPACKDIR_NAME,unpack_srcdist, post_unpack};
 
+/// Returns the error for build command `step`, which failed with `output`.
+fn build_step_failed(step: &str, output: &Output) -> AnvilError {
+    AnvilError::BuildStepFailed {
+        step: step.to_string(),
+        code: output.status.code(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    }
+}
+
+/// Returns the error for git command `step`, which failed with `output`.
+fn git_step_failed(step: &str, output: &Output) -> AnvilError {
+    let status = match output.status.code() {
+        Some(code) => format!("exited with status {code}"),
+        None => "was terminated by a signal".to_string(),
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let msg = match stderr.trim() {
+        "" => format!("{{{step}}} {status}"),
+        err => format!("{{{step}}} {status}: {err}"),
+    };
+    AnvilError::Git(git2::Error::from_str(&msg))
+}
 
-pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
+pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,AnvilError> {
     match args.tag {
         Some(ref query) => {
             match env.run_mode.as_ref().unwrap() {
                 AmbosoMode::GitMode => {
                     if ! env.gitmode_versions_table.contains_key(&SemVerKey(query.to_string())) {
                         error!("{{{}}} was not a valid tag.",query);
-                        return Err("Invalid tag".to_string())
+                        return Err(AnvilError::UnknownTag(query.to_string()))
                     }
                 }
                 AmbosoMode::BaseMode => {
                     if ! env.basemode_versions_table.contains_key(&SemVerKey(query.to_string())) {
                         error!("{{{}}} was not a valid tag.",query);
-                        return Err("Invalid tag".to_string())
+                        return Err(AnvilError::UnknownTag(query.to_string()))
                     }
                 }
-                AmbosoMode::TestMode => {
-                    todo!("Build op for test mode");
-                }
-                AmbosoMode::TestMacro => {
-                    todo!("Build op for test macro");
+                AmbosoMode::TestMode | AmbosoMode::TestMacro => {
+                    // In test modes, -b asks to record test output. That is done by do_query().
+                    debug!("Skipping build op for {:?}, recording is done on query", env.run_mode.as_ref().unwrap());
+                    return Ok("Recording is done on query".to_string());
                 }
             }
             info!("Trying to build {{{:?}}}", query);
@@ -68,7 +97,7 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                     }
                     Err(e) => {
                         error!("Failed creating query target dir. Err: {e}");
-                        return Err("Failed creating query target dir.".to_string());
+                        return Err(AnvilError::io(&queried_path, e));
                     }
                 }
             }
@@ -87,7 +116,7 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                         }
                     } else {
                         error!("{} is not a file", queried_path.display());
-                        return Err("Not a file".to_string())
+                        return Err(AnvilError::not_a_file(&queried_path))
                     }
                 } else {
                     trace!("No file found for {{{}}}", queried_path.display());
@@ -95,18 +124,18 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
 
                 let mut use_make = false;
                 if env.anvil_kern == AnvilKern::AmbosoC {
-                    use_make = query >= &env.mintag_make.clone().unwrap();
+                    use_make = semver_compare(query, &env.mintag_make.clone().unwrap()) != Ordering::Less;
 
                     if use_make && !env.support_makemode {
                         error!("Can't build {{{}}}, as makemode is not supported by the project", query);
-                        return Err("Missing makemode support".to_string());
+                        return Err(AnvilError::Config("Missing makemode support".to_string()));
                     }
 
-                    let use_automake = query >= &env.mintag_automake.clone().unwrap();
+                    let use_automake = semver_compare(query, &env.mintag_automake.clone().unwrap()) != Ordering::Less;
 
                     if use_automake && !env.support_automakemode {
                         error!("Can't build {{{}}}, as automakemode is not supported by the project", query);
-                        return Err("Missing automakemode support".to_string());
+                        return Err(AnvilError::Config("Missing automakemode support".to_string()));
                     } else if use_automake {
                         match env.run_mode.as_ref().unwrap() {
                             AmbosoMode::GitMode => {
@@ -125,7 +154,7 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                         .arg("-c")
                                         .arg(autoconf_bootstrap_cmd)
                                         .output()
-                                        .expect("failed to execute process");
+                                        .map_err(|e| AnvilError::missing_tool("sh", e))?;
 
                                     match output.status.code() {
                                         Some(autotools_bootstrap_ec) => {
@@ -135,14 +164,18 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                                 error!("Automake bootstrap failed with status: {}", autotools_bootstrap_ec.to_string());
                                                 io::stdout().write_all(&output.stdout).unwrap();
                                                 io::stderr().write_all(&output.stderr).unwrap();
-                                                return Err("Automake bootstrap failed".to_string());
+                                                return Err(AnvilError::BuildStepFailed {
+                                                    step: autoconf_bootstrap_cmd.to_string(),
+                                                    code: Some(autotools_bootstrap_ec),
+                                                    stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                                                });
                                             }
                                         }
                                         None => {
                                             error!("Automake prep command failed");
                                             io::stdout().write_all(&output.stdout).unwrap();
                                             io::stderr().write_all(&output.stderr).unwrap();
-                                            return Err("Automake prep command failed".to_string());
+                                            return Err(build_step_failed(autoconf_bootstrap_cmd, &output));
                                         }
                                     }
                                     debug!("Running \'./configure \"{}\"\'", env.configure_arg);
@@ -151,7 +184,7 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                     let output = Command::new(autoconf_configure_cmd)
                                         .arg(env.configure_arg.clone())
                                         .output()
-                                        .expect("failed to execute process");
+                                        .map_err(|e| AnvilError::missing_tool(autoconf_configure_cmd, e))?;
 
                                     match output.status.code() {
                                         Some(autotools_config_ec) => {
@@ -161,26 +194,32 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                                 error!("Automake failed with status: {}", autotools_config_ec.to_string());
                                                 io::stdout().write_all(&output.stdout).unwrap();
                                                 io::stderr().write_all(&output.stderr).unwrap();
-                                                return Err("Automake config failed".to_string());
+                                                return Err(AnvilError::BuildStepFailed {
+                                                    step: autoconf_configure_cmd.to_string(),
+                                                    code: Some(autotools_config_ec),
+                                                    stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                                                });
                                             }
                                         }
                                         None => {
                                             error!("Automake config command failed");
                                             io::stdout().write_all(&output.stdout).unwrap();
                                             io::stderr().write_all(&output.stderr).unwrap();
-                                            return Err("Automake config command failed".to_string());
+                                            return Err(build_step_failed(autoconf_configure_cmd, &output));
                                         }
                                     }
 
                                 };
                             }
                             _ => {
-                                todo!("automake prep for {:?}", env.run_mode.as_ref().unwrap());
+                                error!("Can't build {{{}}}, automake prep is only supported in git mode", query);
+                                return Err(AnvilError::Config("Automake prep is only supported in git mode".to_string()));
                             }
                         }
                     }
                 }
 
+                let step;
                 let output = if cfg!(target_os = "windows") {
                     todo!("Support windows build");
                     /*
@@ -195,7 +234,7 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                             let head_was_detached = false; // We don't worry about HEAD if we're not in git
                                                            // mode
                             let do_postbuild = true; // We try to do postbuild
-                            let build_path = PathBuf::from(format!("./{}/v{}/",env.amboso_dir.as_ref().unwrap().display(), args.tag.as_ref().unwrap()));
+                            let build_path = env.amboso_dir.as_ref().unwrap().join(format!("v{}", args.tag.as_ref().unwrap()));
                             let mut source_path = build_path.clone();
                             source_path.push(env.source.clone().unwrap());
                             let mut bin_path = build_path.clone();
@@ -232,68 +271,45 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                 AnvilKern::AmbosoC => {
                                     if use_make {
                                         trace!("Using make mode");
-                                        let current_dir = env::current_dir().expect("failed getting current directory");
-                                        let cd_output = env::set_current_dir(&build_path);
-                                        if cd_output.is_ok() {
-                                            match build_step(args, env, cflg_str, query, bin_path, build_path, env.bin.clone().unwrap(), head_was_detached, do_postbuild) {
-                                                Ok(s) => {
-                                                    trace!("{s}");
-                                                    let cdback_output = env::set_current_dir(&current_dir);
-                                                    if cdback_output.is_ok() {
-                                                        return Ok(s);
-                                                    } else {
-                                                        return Err(format!("cdback to {{{}}} failed", current_dir.display()));
-                                                    }
-                                                }
-                                                Err(e) => {
-                                                    return Err(format!("Build failed for {{{query}}}. Err: {e}"));
-                                                }
+                                        match build_step(args, env, &build_path, cflg_str, query, bin_path, build_path.clone(), env.bin.clone().unwrap(), head_was_detached, do_postbuild) {
+                                            Ok(s) => {
+                                                trace!("{s}");
+                                                return Ok(s);
+                                            }
+                                            Err(e) => {
+                                                return Err(e.context(format!("Build failed for {{{query}}}")));
                                             }
-                                        } else {
-                                            error!("cd to build_path {} failed", build_path.display());
-                                            return Err("Failed cd to build_path".to_string());
                                         }
                                     } else {
 
                                         let single_mode_cmd = cc_str;
                                         trace!("Using single file mode: \'{} {} {} -o {} -lm\'", single_mode_cmd, cflg_str, source_path.display(), bin_path.display());
-                                        Command::new(single_mode_cmd)
+                                        step = single_mode_cmd.clone();
+                                        Command::new(&single_mode_cmd)
                                             .arg(cflg_str)
                                             .arg(source_path)
                                             .arg("-o")
                                             .arg(bin_path)
                                             .arg("-lm")
                                             .output()
-                                            .expect("failed to execute process")
+                                            .map_err(|e| AnvilError::missing_tool(&single_mode_cmd, e))?
                                     }
                                 }
                                 AnvilKern::AnvilPy | AnvilKern::Custom => {
-                                    let current_dir = env::current_dir().expect("failed getting current directory");
-                                    let cd_output = env::set_current_dir(&build_path);
-                                    if cd_output.is_ok() {
-                                        match build_step(args, env, cflg_str, query, bin_path, build_path, env.bin.clone().unwrap(), head_was_detached, do_postbuild) {
-                                            Ok(s) => {
-                                                trace!("{s}");
-                                                let cdback_output = env::set_current_dir(&current_dir);
-                                                if cdback_output.is_ok() {
-                                                    return Ok(s);
-                                                } else {
-                                                    return Err(format!("cdback to {{{}}} failed", current_dir.display()));
-                                                }
-                                            }
-                                            Err(e) => {
-                                                return Err(format!("Build failed for {{{query}}}. Err: {e}"));
-                                            }
+                                    match build_step(args, env, &build_path, cflg_str, query, bin_path, build_path.clone(), env.bin.clone().unwrap(), head_was_detached, do_postbuild) {
+                                        Ok(s) => {
+                                            trace!("{s}");
+                                            return Ok(s);
+                                        }
+                                        Err(e) => {
+                                            return Err(e.context(format!("Build failed for {{{query}}}")));
                                         }
-                                    } else {
-                                        error!("cd to build_path {} failed", build_path.display());
-                                        return Err("Failed cd to build_path".to_string());
                                     }
                                 }
                             }
                         }
                         AmbosoMode::GitMode => {
-                            let build_path = PathBuf::from(format!("{}/v{}/",env.amboso_dir.as_ref().unwrap().display(), args.tag.as_ref().unwrap()));
+                            let build_path = env.amboso_dir.as_ref().unwrap().join(format!("v{}", args.tag.as_ref().unwrap()));
                             let mut source_path = build_path.clone();
                             source_path.push(env.source.clone().unwrap());
                             let mut bin_path = build_path.clone();
@@ -305,7 +321,7 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                 "".to_string()
                             };
 
-                            let repo = Repository::discover(".").expect("Failed to open repository");
+                            let repo = Repository::discover(env.work_dir())?;
 
                             let head_was_detached = match semver_compare(&env.anvil_version, MIN_AMBOSO_V_CHECK_DETACHED) {
                                 Ordering::Less => {
@@ -332,15 +348,17 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                             };
                             let do_postbuild = true;
 
-                            trace!("Git mode, checking out {}",query);
-                            trace!("Running \'git checkout {}\'", query);
+                            let git_tag = env.git_tag_name(query);
+                            trace!("Git mode, checking out {}",git_tag);
+                            trace!("Running \'git checkout {}\'", git_tag);
 
                             let output = Command::new("git")
+                                .current_dir(env.work_dir())
                                 .arg("checkout")
-                                .arg(query)
+                                .arg(&git_tag)
                                 .stderr(Stdio::null())
                                 .output()
-                                .expect("failed to execute process");
+                                .map_err(|e| AnvilError::missing_tool("git", e))?;
 
                             match output.status.code() {
                                 Some(checkout_ec) => {
@@ -348,12 +366,13 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                         debug!("Checkout succeded with status: {}", checkout_ec.to_string());
                                         trace!("Running \'git submodule update --init --recursive\'");
                                         let output = Command::new("git")
+                                            .current_dir(env.work_dir())
                                             .arg("submodule")
                                             .arg("update")
                                             .arg("--init")
                                             .arg("--recursive")
                                             .output()
-                                            .expect("failed to execute process");
+                                            .map_err(|e| AnvilError::missing_tool("git", e))?;
                                         match output.status.code() {
                                             Some(gsinit_ec) => {
                                                 if gsinit_ec == 0 {
@@ -361,33 +380,33 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                                     trace!("Build step");
                                                     trace!("cflg_str: {{{cflg_str}}}");
                                                     trace!("bin_path: {{{}}}", bin_path.display());
-                                                    match build_step(args, env, cflg_str, query, bin_path, build_path, env.bin.clone().unwrap(), head_was_detached, do_postbuild) {
+                                                    match build_step(args, env, env.work_dir(), cflg_str, query, bin_path, build_path, env.bin.clone().unwrap(), head_was_detached, do_postbuild) {
                                                         Ok(s) => {
                                                             trace!("{s}");
                                                         }
                                                         Err(e) => {
-                                                            return Err(format!("Build failed for {{{query}}}. Err: {e}"));
+                                                            return Err(e.context(format!("Build failed for {{{query}}}")));
                                                         }
                                                     }
                                                 } else {
                                                     warn!("Submodule init failed with status: {}", gsinit_ec.to_string());
                                                     io::stdout().write_all(&output.stdout).unwrap();
                                                     io::stderr().write_all(&output.stderr).unwrap();
-                                                    return Err("Submodule init failed".to_string());
+                                                    return Err(git_step_failed("git submodule update --init --recursive", &output));
                                                 }
                                             }
                                             None => {
                                                 error!("git submodule init command failed");
                                                 io::stdout().write_all(&output.stdout).unwrap();
                                                 io::stderr().write_all(&output.stderr).unwrap();
-                                                return Err("git submodule init command failed".to_string());
+                                                return Err(git_step_failed("git submodule update --init --recursive", &output));
                                             }
                                         }
                                     } else {
                                         warn!("Checkout failed with status: {}", checkout_ec.to_string());
                                         io::stdout().write_all(&output.stdout).unwrap();
                                         io::stderr().write_all(&output.stderr).unwrap();
-                                        return Err("Checkout failed".to_string());
+                                        return Err(git_step_failed(&format!("git checkout {git_tag}"), &output));
                                     }
                                     io::stdout().write_all(&output.stdout).unwrap();
                                     io::stderr().write_all(&output.stderr).unwrap();
@@ -397,66 +416,71 @@ pub fn do_build(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                     error!("Git checkout command failed");
                                     io::stdout().write_all(&output.stdout).unwrap();
                                     io::stderr().write_all(&output.stderr).unwrap();
-                                    return Err("Git checkout command failed".to_string());
+                                    return Err(git_step_failed(&format!("git checkout {git_tag}"), &output));
                                 }
                             }
                         }
-                        _ => {
-                            todo!("Build op for test modes");
+                        AmbosoMode::TestMode | AmbosoMode::TestMacro => {
+                            error!("Unexpected build op for {:?}", env.run_mode.as_ref().unwrap());
+                            return Err(AnvilError::Usage("Build op is not supported in test modes".to_string()));
                         }
                     }
                 };
+                io::stdout().write_all(&output.stdout).unwrap();
+                io::stderr().write_all(&output.stderr).unwrap();
                 match output.status.code() {
-                    Some(x) => {
-                        if x == 0 {
-                            info!("Build succeded with status: {}", x.to_string());
-                        } else {
-                            warn!("Build failed with status: {}", x.to_string());
-                        }
-                        io::stdout().write_all(&output.stdout).unwrap();
-                        io::stderr().write_all(&output.stderr).unwrap();
+                    Some(0) => {
+                        info!("Build succeded with status: 0");
                         Ok("Build done".to_string())
                     }
+                    Some(x) => {
+                        warn!("Build failed with status: {}", x.to_string());
+                        Err(AnvilError::BuildStepFailed {
+                            step,
+                            code: Some(x),
+                            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                        })
+                    }
                     None => {
                         error!("Build command failed");
-                        io::stdout().write_all(&output.stdout).unwrap();
-                        io::stderr().write_all(&output.stderr).unwrap();
-                        Err("Build command failed".to_string())
+                        Err(AnvilError::BuildStepFailed {
+                            step,
+                            code: None,
+                            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                        })
                     }
                 }
             } else {
                 warn!("No directory found for {{{}}}", queried_path.display());
-                Err("No dir found".to_string())
+                Err(AnvilError::not_found(&queried_path))
             }
         }
         None => {
             warn!("No tag provided.");
-            Err("No tag provided".to_string())
+            Err(AnvilError::Usage("No tag provided".to_string()))
         }
     }
 }
 
-pub fn do_run(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
+pub fn do_run(env: &AmbosoEnv, args: &Args) -> Result<i32,AnvilError> {
     match args.tag {
         Some(ref q) => {
             match env.run_mode.as_ref().unwrap() {
                 AmbosoMode::GitMode => {
                     if ! env.gitmode_versions_table.contains_key(&SemVerKey(q.to_string())) {
                         error!("{{{}}} was not a valid tag.",q);
-                        return Err("Invalid tag".to_string())
+                        return Err(AnvilError::UnknownTag(q.to_string()))
                     }
                 }
                 AmbosoMode::BaseMode => {
                     if ! env.basemode_versions_table.contains_key(&SemVerKey(q.to_string())) {
                         error!("{{{}}} was not a valid tag.",q);
-                        return Err("Invalid tag".to_string())
+                        return Err(AnvilError::UnknownTag(q.to_string()))
                     }
                 }
-                AmbosoMode::TestMode => {
-                    todo!("Run op for test mode");
-                }
-                AmbosoMode::TestMacro => {
-                    todo!("Run op for test macro");
+                AmbosoMode::TestMode | AmbosoMode::TestMacro => {
+                    error!("Run op is not supported for {:?}", env.run_mode.as_ref().unwrap());
+                    return Err(AnvilError::Usage("Run op is not supported in test modes".to_string()));
                 }
             }
             info!("Trying to run {{{:?}}}", q);
@@ -473,82 +497,96 @@ pub fn do_run(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                         trace!("{} is a file", queried_path.display());
                     } else {
                         error!("{} is not a file", queried_path.display());
-                        return Err("Not a file".to_string())
+                        return Err(AnvilError::not_a_file(&queried_path))
                     }
                 } else {
                     warn!("No file found for {{{}}}", queried_path.display());
                     if ! env.do_build {
                         warn!("Try running with -b to build");
                     }
-                    return Err("File not found".to_string());
+                    return Err(AnvilError::not_found(&queried_path));
                 }
 
-                let output = if cfg!(target_os = "windows") {
+                let status = if cfg!(target_os = "windows") {
                     todo!("Support windows run");
                     /*
                      * Command::new("cmd")
                      *   .args(["/C", "echo hello"])
-                     *   .output()
+                     *   .status()
                      *   .expect("failed to execute process")
                      */
                 } else {
-                    let bin_path = PathBuf::from(format!("./{}/v{}/{}",env.amboso_dir.as_ref().unwrap().display(), args.tag.as_ref().unwrap(), env.bin.clone().unwrap()));
-                    trace!("Running \'{}\'", bin_path.display());
-                    Command::new(bin_path)
-                    .output()
-                    .expect("failed to execute process")
+                    let bin_path = env.amboso_dir.as_ref().unwrap().join(format!("v{}", args.tag.as_ref().unwrap())).join(env.bin.clone().unwrap());
+                    let mut cmd = Command::new(bin_path);
+                    // Interactive programs need the terminal, so we don't capture anything here.
+                    cmd.current_dir(env.work_dir())
+                        .args(&args.extra_args)
+                        .stdin(Stdio::inherit())
+                        .stdout(Stdio::inherit())
+                        .stderr(Stdio::inherit());
+                    debug!("Running \'{:?}\'", cmd);
+                    match cmd.status() {
+                        Ok(s) => s,
+                        Err(e) => {
+                            error!("Failed starting {{{}}}. Err: {e}", queried_path.display());
+                            return Err(AnvilError::missing_tool(&queried_path, e));
+                        }
+                    }
                 };
-                match output.status.code() {
+                match status.code() {
                     Some(x) => {
                         if x == 0 {
-                            info!("Run succeded with status: {}", x.to_string());
+                            info!("Run succeded with status: {}", x);
                         } else {
-                            warn!("Run failed with status: {}", x.to_string());
+                            warn!("Run failed with status: {}", x);
                         }
-                        io::stdout().write_all(&output.stdout).unwrap();
-                        io::stderr().write_all(&output.stderr).unwrap();
-                        Ok("Run done".to_string())
+                        Ok(x)
                     }
                     None => {
-                        error!("Run command for {{{}}} failed", args.tag.as_ref().unwrap());
-                        io::stdout().write_all(&output.stdout).unwrap();
-                        io::stderr().write_all(&output.stderr).unwrap();
-                        Err("Run command failed".to_string())
+                        match status.signal() {
+                            Some(sig) => {
+                                warn!("Run for {{{}}} was terminated by signal: {}", args.tag.as_ref().unwrap(), sig);
+                                // Follow the shell convention for processes killed by a signal
+                                Ok(128 + sig)
+                            }
+                            None => {
+                                error!("Run command for {{{}}} failed", args.tag.as_ref().unwrap());
+                                Err(AnvilError::Msg("Run command failed".to_string()))
+                            }
+                        }
                     }
                 }
             } else {
                 warn!("No directory found for {{{}}}", queried_path.display());
-                Err("No dir found".to_string())
+                Err(AnvilError::not_found(&queried_path))
             }
         }
         None => {
             warn!("No tag provided.");
-            Err("No tag provided".to_string())
+            Err(AnvilError::Usage("No tag provided".to_string()))
         }
     }
 }
 
-pub fn do_delete(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
+pub fn do_delete(env: &AmbosoEnv, args: &Args) -> Result<String,AnvilError> {
     match args.tag {
         Some(ref q) => {
             match env.run_mode.as_ref().unwrap() {
                 AmbosoMode::GitMode => {
                     if ! env.gitmode_versions_table.contains_key(&SemVerKey(q.to_string())) {
                         error!("{{{}}} was not a valid tag.",q);
-                        return Err("Invalid tag".to_string())
+                        return Err(AnvilError::UnknownTag(q.to_string()))
                     }
                 }
                 AmbosoMode::BaseMode => {
                     if ! env.basemode_versions_table.contains_key(&SemVerKey(q.to_string())) {
                         error!("{{{}}} was not a valid tag.",q);
-                        return Err("Invalid tag".to_string())
+                        return Err(AnvilError::UnknownTag(q.to_string()))
                     }
                 }
-                AmbosoMode::TestMode => {
-                    todo!("Delete op for test mode");
-                }
-                AmbosoMode::TestMacro => {
-                    todo!("Delete op for test macro");
+                AmbosoMode::TestMode | AmbosoMode::TestMacro => {
+                    error!("Delete op is not supported for {:?}, use -p to delete test records", env.run_mode.as_ref().unwrap());
+                    return Err(AnvilError::Usage("Delete op is not supported in test modes".to_string()));
                 }
             }
             info!("Trying to delete {{{:?}}}", q);
@@ -565,11 +603,11 @@ pub fn do_delete(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                         trace!("{} is a file", queried_path.display());
                     } else {
                         error!("{} is not a file", queried_path.display());
-                        return Err("Not a file".to_string())
+                        return Err(AnvilError::not_a_file(&queried_path))
                     }
                 } else {
                     warn!("No file found for {{{}}}", queried_path.display());
-                    return Err("File not found".to_string());
+                    return Err(AnvilError::not_found(&queried_path));
                 }
 
                 let output = if cfg!(target_os = "windows") {
@@ -581,13 +619,14 @@ pub fn do_delete(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                      *   .expect("failed to execute process")
                      */
                 } else {
-                    let bin_path = PathBuf::from(format!("./{}/v{}/{}",env.amboso_dir.as_ref().unwrap().display(), args.tag.as_ref().unwrap(), env.bin.clone().unwrap()));
+                    let bin_path = env.amboso_dir.as_ref().unwrap().join(format!("v{}", args.tag.as_ref().unwrap())).join(env.bin.clone().unwrap());
                     trace!("Running \'rm -f {}\'", bin_path.display());
                     Command::new("rm")
+                    .current_dir(env.work_dir())
                     .arg("-f")
                     .arg(bin_path)
                     .output()
-                    .expect("failed to execute process")
+                    .map_err(|e| AnvilError::missing_tool("rm", e))?
                 };
                 match output.status.code() {
                     Some(x) => {
@@ -604,45 +643,45 @@ pub fn do_delete(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                         error!("Delete command for {{{}}} failed", args.tag.as_ref().unwrap());
                         io::stdout().write_all(&output.stdout).unwrap();
                         io::stderr().write_all(&output.stderr).unwrap();
-                        Err("Delete command failed".to_string())
+                        Err(build_step_failed("rm", &output))
                     }
                 }
             } else {
                 warn!("No directory found for {{{}}}", queried_path.display());
-                Err("No dir found".to_string())
+                Err(AnvilError::not_found(&queried_path))
             }
         }
         None => {
             warn!("No tag provided.");
-            Err("No tag provided".to_string())
+            Err(AnvilError::Usage("No tag provided".to_string()))
         }
     }
 }
 
-pub fn do_query(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
+pub fn do_query(env: &AmbosoEnv, args: &Args) -> Result<String,AnvilError> {
     match args.tag {
         Some(ref q) => {
             let interpreter_regex = Regex::new(ANVIL_INTERPRETER_TAG_REGEX).expect("Failed to create ruleline regex");
             if interpreter_regex.is_match(q) {
                 info!("Running as interpreter for {{{q}}}");
-                handle_running_make();
+                return make_run_result(args.builtin_make);
             }
             match env.run_mode.as_ref().unwrap() {
                 AmbosoMode::GitMode => {
                     if ! env.gitmode_versions_table.contains_key(&SemVerKey(q.to_string())) {
                         error!("{{{}}} was not a valid tag.",q);
-                        return Err("Invalid tag".to_string())
+                        return Err(AnvilError::UnknownTag(q.to_string()))
                     }
                 }
                 AmbosoMode::BaseMode => {
                     if ! env.basemode_versions_table.contains_key(&SemVerKey(q.to_string())) {
                         error!("{{{}}} was not a valid tag.",q);
-                        return Err("Invalid tag".to_string())
+                        return Err(AnvilError::UnknownTag(q.to_string()))
                     }
                 }
-                AmbosoMode::TestMode => {
+                AmbosoMode::TestMode | AmbosoMode::TestMacro => {
                     if ! env.support_testmode {
-                        return Err("Missing testmode support".to_string());
+                        return Err(AnvilError::Config("Missing testmode support".to_string()));
                     } else {
                         let do_record = if env.do_build {
                             info!("Recording test {{{:?}}}", q);
@@ -655,7 +694,7 @@ pub fn do_query(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                         let queried_path;
                         if ! env.bonetests_table.contains_key(q) && ! env.kulpotests_table.contains_key(q) {
                             error!("Not a valid test: {{{}}}", q);
-                            return Err("Invalid test query".to_string());
+                            return Err(AnvilError::Usage("Invalid test query".to_string()));
                         } else if env.bonetests_table.contains_key(q) {
                             queried_path = env.bonetests_table.get(q);
                         } else {
@@ -670,7 +709,7 @@ pub fn do_query(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                     info!("{} is a file", qp.display());
                                     if is_executable(qp) {
                                         debug!("{} is executable", qp.display());
-                                        let test_res = run_test(qp, do_record);
+                                        let test_res = run_test(qp, env.work_dir(), do_record, env.test_timeout);
 
                                         return test_res;
                                     } else {
@@ -679,24 +718,21 @@ pub fn do_query(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                     }
                                 } else {
                                         debug!("{} is not a file", qp.display());
-                                        return Err("Not a file".to_string())
+                                        return Err(AnvilError::not_a_file(qp))
                                 }
                             } else {
                                 warn!("No file found for {{{}}}", qp.display());
-                                return Err("No file found".to_string())
+                                return Err(AnvilError::not_found(qp))
                             }
 
                             }
                             None => {
                                 error!("Not a valid test path.");
-                                return Err("Invalid test query".to_string());
+                                return Err(AnvilError::Usage("Invalid test query".to_string()));
                             }
                         }
                     }
                 }
-                AmbosoMode::TestMacro => {
-                    todo!("Support query in test macro");
-                }
             }
             info!("Querying info for {{{:?}}}", q);
 
@@ -721,22 +757,22 @@ pub fn do_query(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
 
                     } else {
                         debug!("{} is not a file", queried_path.display());
-                        Err("Not a file".to_string())
+                        Err(AnvilError::not_a_file(&queried_path))
                     }
                 } else {
                     warn!("No file found for {{{}}}", queried_path.display());
-                    Err("No file found".to_string())
+                    Err(AnvilError::not_found(&queried_path))
                 }
             } else {
                 warn!("No directory found for {{{}}}", queried_path.display());
-                Err("No dir found".to_string())
+                Err(AnvilError::not_found(&queried_path))
             }
         }
         None => {
             match env.run_mode.as_ref().unwrap() {
                 AmbosoMode::TestMacro => {
                     if ! env.support_testmode {
-                        return Err("Missing testmode support".to_string());
+                        return Err(AnvilError::Config("Missing testmode support".to_string()));
                     } else {
                         let do_list = if args.list {
                             info!("Listing test names");
@@ -775,7 +811,7 @@ pub fn do_query(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                     info!("{} is a file", test.display());
                                     if is_executable(test) {
                                         debug!("{} is executable", test.display());
-                                        let test_res = run_test(test, do_record);
+                                        let test_res = run_test(test, env.work_dir(), do_record, env.test_timeout);
 
                                         if args.watch {
                                             let test_elapsed = env.start_time.elapsed();
@@ -797,28 +833,34 @@ pub fn do_query(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                     }
                                 } else {
                                         debug!("{} is not a file", test.display());
-                                        return Err("Not a file".to_string());
+                                        return Err(AnvilError::not_a_file(test));
                                 }
                             } else {
                                 warn!("No file found for {{{}}}", test.display());
-                                return Err("No file found".to_string())
+                                return Err(AnvilError::not_found(test))
                             }
                         }
                         debug!("Done test macro");
                         info!("Successes: {tot_successes}");
                         error!("Failures: {tot_failures}");
                         if tot_failures != 0 {
-                            return Err("Test macro had some failures".to_string());
+                            return Err(AnvilError::Msg("Test macro had some failures".to_string()));
                         } else {
                             return Ok("Done test macro run".to_string());
                         }
                     }
                 }
+                AmbosoMode::TestMode => {
+                    if args.list {
+                        // Test names were already listed in handle_amboso_env()
+                        return Ok("Done listing all tests".to_string());
+                    }
+                }
                 AmbosoMode::GitMode | AmbosoMode::BaseMode => {
                     if ! env.do_init && ! env.do_purge && ! args.list && ! args.list_all {
                         match env.anvil_kern {
                             AnvilKern::AmbosoC => {
-                                handle_running_make();
+                                return make_run_result(args.builtin_make);
                             }
                             AnvilKern::AnvilPy | AnvilKern::Custom => {
                                 let head_was_detached = false;
@@ -846,20 +888,71 @@ pub fn do_query(env: &AmbosoEnv, args: &Args) -> Result<String,String> {
                                 let mut bin_path = build_path.clone();
                                 bin_path.push(env.bin.clone().unwrap());
 
-                                return build_step(args, env, cflg_str, "", bin_path, build_path, env.bin.clone().unwrap(), head_was_detached, do_postbuild)
+                                return build_step(args, env, Path::new("."), cflg_str, "", bin_path, build_path, env.bin.clone().unwrap(), head_was_detached, do_postbuild)
                             }
                         }
                     }
                 }
-                _ => {}
             }
             warn!("No tag provided for query op.");
-            Err("No tag provided.".to_string())
+            Err(AnvilError::Usage("No tag provided.".to_string()))
         }
     }
 }
 
-pub fn run_test(test_path: &PathBuf, record: bool) -> Result<String,String> {
+/// Deletes the recorded stdout and stderr for the passed test, if present.
+pub fn delete_test_records(test_path: &Path) -> Result<String,AnvilError> {
+    let mut deleted = 0;
+    for record_path in [test_path.with_extension("k.stdout"), test_path.with_extension("k.stderr")] {
+        if record_path.is_file() {
+            trace!("Deleting {{{}}}", record_path.display());
+            match fs::remove_file(&record_path) {
+                Ok(_) => {
+                    deleted += 1;
+                }
+                Err(e) => {
+                    error!("Failed deleting record {{{}}}. Err: {e}", record_path.display());
+                    return Err(AnvilError::io(&record_path, e));
+                }
+            }
+        } else {
+            debug!("No record found at {{{}}}", record_path.display());
+        }
+    }
+    info!("Deleted {deleted} records for {{{}}}", test_path.display());
+    Ok(format!("Deleted {deleted} records"))
+}
+
+/// Runs `cmd` to completion like `Command::output()`, killing it after `timeout`.
+fn output_with_timeout(cmd: &mut Command, timeout: Option<Duration>) -> io::Result<Output> {
+    let Some(timeout) = timeout else {
+        return cmd.output();
+    };
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    // Pipes are drained while waiting, so a chatty child can't block on a full pipe
+    let mut stdout = child.stdout.take().expect("stdout should be piped");
+    let mut stderr = child.stderr.take().expect("stderr should be piped");
+    let stdout_reader = thread::spawn(move || { let mut buf = Vec::new(); stdout.read_to_end(&mut buf).map(|_| buf) });
+    let stderr_reader = thread::spawn(move || { let mut buf = Vec::new(); stderr.read_to_end(&mut buf).map(|_| buf) });
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(io::ErrorKind::TimedOut, format!("timed out after {}s", timeout.as_secs())));
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let stdout = stdout_reader.join().expect("stdout reader panicked")?;
+    let stderr = stderr_reader.join().expect("stderr reader panicked")?;
+    Ok(Output { status, stdout, stderr })
+}
+
+/// Runs the test at `test_path` from `work_dir`, recording its output with `record` or comparing it with the recorded one.
+pub fn run_test(test_path: &PathBuf, work_dir: &Path, record: bool, timeout: Option<Duration>) -> Result<String,AnvilError> {
     let output = if cfg!(target_os = "windows") {
         todo!("Support windows tests");
         /*
@@ -870,9 +963,13 @@ pub fn run_test(test_path: &PathBuf, record: bool) -> Result<String,String> {
          */
     } else {
         trace!("Running \'{}\'", test_path.display());
-        Command::new(test_path)
-        .output()
-        .expect("failed to execute process")
+        match output_with_timeout(Command::new(test_path).current_dir(work_dir), timeout) {
+            Ok(o) => o,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                return Err(AnvilError::io(test_path, e).context(format!("Test {{{}}} timed out", test_path.display())));
+            }
+            Err(e) => return Err(AnvilError::missing_tool(test_path, e)),
+        }
     };
     match output.status.code() {
         Some(x) => {
@@ -899,34 +996,29 @@ pub fn run_test(test_path: &PathBuf, record: bool) -> Result<String,String> {
                             warn!("Stdout did not match!");
                             if record {
                                 info!("Recording stdout");
-                                let write_res = fs::write(stdout_record_path,output.stdout);
+                                let write_res = fs::write(&stdout_record_path, output.stdout);
                                 match write_res {
                                     Ok(_) => {
                                         debug!("Recorded stdout");
                                     }
                                     Err(e) => {
                                         error!("Failed recording stdout. Err: {e}");
-                                        return Err("Failed recording stdout".to_string());
+                                        return Err(AnvilError::io(&stdout_record_path, e));
                                     }
                                 }
                             } else {
                                 warn!("Expected: {{\"\n{}\"}}", stdout_record);
                                 match std::str::from_utf8(&output.stdout) {
-                                    Ok(v) => {
-                                        info!("Found: {{\"\n{}\"}}", v);
-                                        return Err("Stdout mismatch".to_string());
-                                    }
-                                    Err(e) => {
-                                        error!("Failed parsing output.stdout. Err: {e}");
-                                        return Err("Failed parsing output.stdout".to_string());
-                                    }
+                                    Ok(v) => info!("Found: {{\"\n{}\"}}", v),
+                                    Err(e) => error!("Failed parsing output.stdout. Err: {e}"),
                                 }
+                                return Err(AnvilError::TestMismatch { test: test_path.clone(), stream: "stdout".to_string() });
                             }
                         }
                     }
                     Err(e) => {
                         error!("Failed reading stdout record for {{{}}}. Err: {e}", stdout_record_path.display());
-                        return Err("Failed reading stdout record".to_string());
+                        return Err(AnvilError::io(&stdout_record_path, e));
                     }
                 }
             } else {
@@ -946,34 +1038,29 @@ pub fn run_test(test_path: &PathBuf, record: bool) -> Result<String,String> {
                             info!("Stderr matched!");
                         } else if record {
                             info!("Recording stderr");
-                            let write_res = fs::write(stderr_record_path,output.stderr);
+                            let write_res = fs::write(&stderr_record_path, output.stderr);
                             match write_res {
                                 Ok(_) => {
                                     debug!("Recorded stderr");
                                 }
                                 Err(e) => {
                                     error!("Failed recording stderr. Err: {e}");
-                                    return Err("Failed recording stderr".to_string());
+                                    return Err(AnvilError::io(&stderr_record_path, e));
                                 }
                             }
                         } else {
                             warn!("Stderr did not match!");
                             warn!("Expected: {{\"\n{}\"}}", stderr_record);
                             match std::str::from_utf8(&output.stderr) {
-                                Ok(v) => {
-                                    info!("Found: {{\"\n{}\"}}", v);
-                                    return Err("Stderr mismatch".to_string());
-                                }
-                                Err(e) => {
-                                    error!("Failed parsing output.stderr. Err: {e}");
-                                    return Err("Failed parsing output.stderr".to_string());
-                                }
+                                Ok(v) => info!("Found: {{\"\n{}\"}}", v),
+                                Err(e) => error!("Failed parsing output.stderr. Err: {e}"),
                             }
+                            return Err(AnvilError::TestMismatch { test: test_path.clone(), stream: "stderr".to_string() });
                         }
                     }
                     Err(e) => {
                         error!("Failed reading stderr record for {{{}}}. Err: {e}", stderr_record_path.display());
-                        return Err("Failed reading stderr record".to_string());
+                        return Err(AnvilError::io(&stderr_record_path, e));
                     }
                 }
             } else {
@@ -986,292 +1073,88 @@ pub fn run_test(test_path: &PathBuf, record: bool) -> Result<String,String> {
             error!("Test command for {{{}}} failed", test_path.display());
             io::stdout().write_all(&output.stdout).unwrap();
             io::stderr().write_all(&output.stderr).unwrap();
-            Err("Test command failed".to_string())
+            Err(build_step_failed(&test_path.display().to_string(), &output))
         }
     }
 }
 
-pub fn gen_header(target_path: &PathBuf, anvil_kern: AnvilKern, target_tag: &String, bin_name: &String) -> Result<String,String> {
-    let repo = Repository::discover(target_path);
-    let mut head_author_name = "".to_string();
-    let id;
-    let mut commit_message = "".to_string();
-    let gen_time = SystemTime::now();
-    let commit_time;
-    let gen_timestamp = gen_time.duration_since(SystemTime::UNIX_EPOCH);
-    let mut fgen_time = "".to_string();
-    match gen_timestamp {
-        Ok(t) => {
-            fgen_time = format!("{}", t.as_secs());
-        }
-        Err(e) => {
-            error!("Failed getting gen timestamp. Err: {e}");
+pub fn gen_header(target_path: &PathBuf, anvil_kern: AnvilKern, target_tag: &String, git_tag: &str, bin_name: &String, opts: &HeaderOptions) -> Result<String,AnvilError> {
+    let info = header_info(target_path, target_tag, git_tag, bin_name, opts.reproducible)?;
+    let (kind, files) = match opts.template {
+        Some(ref template) => {
+            let contents = fs::read_to_string(template).map_err(|e| AnvilError::io(template, e))?;
+            ("template".to_string(), vec![(template_output_name(template), render_template(&contents, &info)?)])
         }
-    }
-    match repo {
-        Ok(r) => {
-            let lookup_name = format!("refs/tags/{}", target_tag);
-            let reference = r.find_reference(&lookup_name);
-            match reference {
-                Ok(refr) => {
-                    if !refr.is_tag() {
-                        error!("{target_tag} is not a reference");
-                        return Err(format!("Requested tag is not a reference: {target_tag}").to_string());
-                    } else {
-                        let commit = refr.peel_to_commit();
-                        match commit {
-                            Ok(commit) => {
-                               if let Ok(msg) = commit.message() {
-                                   info!("Commit message: {{{}}}", msg);
-                                   commit_message = msg.escape_default().to_string();
-                               }
-                               id = commit.id().to_string();
-                               info!("Commit id: {{{}}}", id);
-                               let author = commit.author();
-                               let name = author.name();
-                               match name {
-                                   Ok(name) => {
-                                       head_author_name = name.to_string();
-                                       info!("Commit author: {{{}}}", head_author_name);
-                                   }
-                                   Err(e) => {
-                                       warn!("Commit author is empty: {}, {}", e, head_author_name);
-                                   }
-                                }
-                                commit_time = commit.time().seconds().to_string();
-                                info!("Commit time: {{{}}}", commit_time);
-                                   }
-                            Err(e) => {
-                                       error!("Failed peel to head commit for {{{}}}. Err: {e}", target_path.display());
-                                       return Err("Failed peel to head commit for repo".to_string());
-                            }
-                        }
-                    }
-                }
-                Err(_) => {
-                    if target_tag.is_empty() {
-                        error!("Invalid empty tag request");
-                        return Err("Invalid empty tag request".to_string());
-                    }
-                    warn!("{}", format!("Failed getting tag {target_tag}, retrying using HEAD"));
-                    let head = r.head();
-                    match head {
-                        Ok(head) => {
-                            let commit = head.peel_to_commit();
-                            match commit {
-                                Ok(commit) => {
-                                    if let Ok(msg) = commit.message() {
-                                        info!("Commit message: {{{}}}", msg);
-                                        commit_message = msg.escape_default().to_string();
-                                    }
-                                    id = commit.id().to_string();
-                                    info!("Commit id: {{{}}}", id);
-                                    let author = commit.author();
-                                    let name = author.name();
-                                    match name {
-                                        Ok(name) => {
-                                            head_author_name = name.to_string();
-                                            info!("Commit author: {{{}}}", head_author_name);
-                                        }
-                                        Err(e) => {
-                                            warn!("Commit author is empty: {}, {}", e, head_author_name);
-                                        }
-                                    }
-                                    commit_time = commit.time().seconds().to_string();
-                                    info!("Commit time: {{{}}}", commit_time);
-                                }
-                                Err(e) => {
-                                    error!("Failed peel to head commit for {{{}}}. Err: {e}", target_path.display());
-                                    return Err("Failed peel to head commit for repo".to_string());
-                                }
-                            }
-                        },
-                        Err(e) => {
-                            error!("Failed getting head for {{{}}}. Err: {e}", target_path.display());
-                            return Err("Failed getting head for repo".to_string());
-                        }
-                    }
+        None => {
+            let lang = match opts.lang.or(HeaderLang::for_kern(anvil_kern)) {
+                Some(l) => l,
+                None => {
+                    error!("No header language for {{{}}} kern. Set [header] lang in stego.lock, or pass --lang", anvil_kern);
+                    return Err(AnvilError::Usage("Missing header language".to_string()));
                 }
-            }
+            };
+            (lang.to_string(), render_header(lang, &info, &opts.fields))
         }
-        Err(e) => {
-            error!("Failed discovering repo for {{{}}}. Err: {e}", target_path.display());
-            return Err("Failed discover of repo".to_string());
+    };
+    for (file_name, contents) in files {
+        let file_path = format!("{}/{}", target_path.display(), file_name);
+        trace!("Generating {} header. Target path: {{{}}} Tag: {{{}}}", kind, file_path, target_tag);
+        // Keep the mtime of unchanged files, so make does not rebuild
+        if fs::read_to_string(&file_path).is_ok_and(|c| c == contents) {
+            debug!("{{{}}} is unchanged, not writing it", file_path);
+            continue;
         }
-    }
-    match anvil_kern {
-        AnvilKern::AmbosoC => {
-            let header_path = format!("{}/anvil__{}.h", target_path.display(), bin_name);
-            trace!("Generating C header. Target path: {{{}}} Tag: {{{}}}", header_path, target_tag);
-            let output = File::create(header_path);
-            let header_string = format!("//Generated by invil v{INVIL_VERSION}\n
-//Repo at https://github.com/jgabaut/invil\n
-#ifndef ANVIL__{bin_name}__\n
-#define ANVIL__{bin_name}__\n
-static const char ANVIL__API_LEVEL__STRING[] = \"{EXPECTED_AMBOSO_API_LEVEL}\"; /**< Represents amboso version used for [anvil__{bin_name}.h] generated header.*/\n
-static const char ANVIL__{bin_name}__VERSION_STRING[] = \"{target_tag}\"; /**< Represents current version for [anvil__{bin_name}.h] generated header.*/\n
-static const char ANVIL__{bin_name}__VERSION_DESC[] = \"{id}\"; /**< Represents current version info for [anvil__{bin_name}.h] generated header.*/\n
-static const char ANVIL__{bin_name}__VERSION_DATE[] = \"{commit_time}\"; /**< Represents date for current version for [anvil__{bin_name}.h] generated header.*/\n
-static const char ANVIL__{bin_name}__VERSION_AUTHOR[] = \"{head_author_name}\"; /**< Represents author for current version for [anvil__{bin_name}.h] generated header.*/\n
-static const char ANVIL__{bin_name}__HEADER_GENTIME[] = \"{fgen_time}\"; /**< Represents gen time for [anvil__{bin_name}.h] generated header.*/\n
-const char *get_ANVIL__API__LEVEL__(void); /**< Returns a version string for amboso API of [anvil__{bin_name}.h] generated header.*/\n
-const char *get_ANVIL__VERSION__(void); /**< Returns a version string for [anvil__{bin_name}.h] generated header.*/\n
-const char *get_ANVIL__VERSION__DESC__(void); /**< Returns a version info string for [anvil__{bin_name}.h] generated header.*/\n
-const char *get_ANVIL__VERSION__DATE__(void); /**< Returns a version date string for [anvil__{bin_name}.h] generated header.*/\n
-const char *get_ANVIL__VERSION__AUTHOR__(void); /**< Returns a version author string for [anvil__{bin_name}.h] generated header.*/\n
-const char *get_ANVIL__HEADER__GENTIME__(void); /**< Returns a string for time of gen for [anvil__{bin_name}.h] generated header.*/\n
-#ifndef INVIL__{bin_name}__HEADER__
-#define INVIL__{bin_name}__HEADER__
-static const char INVIL__VERSION__STRING[] = \"{INVIL_VERSION}\"; /**< Represents invil version used for [anvil__{bin_name}.h] generated header.*/\n
-static const char INVIL__OS__STRING[] = \"{INVIL_OS}\"; /**< Represents build os used for [anvil__{bin_name}.h] generated header.*/\n
-static const char INVIL__COMMIT__DESC__STRING[] = \"{commit_message}\"; /**< Represents message for HEAD commit used for [anvil__{bin_name}.h] generated header.*/\n
-const char *get_INVIL__API__LEVEL__(void); /**< Returns a version string for invil version of [anvil__{bin_name}.h] generated header.*/\n
-const char *get_INVIL__OS__(void); /**< Returns a version string for os used for [anvil__{bin_name}.h] generated header.*/\n
-const char *get_INVIL__COMMIT__DESC__(void); /**< Returns a string for HEAD commit message used for [anvil__{bin_name}.h] generated header.*/\n
-#endif // INVIL__{bin_name}__HEADER__
-#endif\n");
-            match output {
-                Ok(mut f) => {
-                    let res = write!(f, "{}", header_string);
-                    match res {
-                        Ok(_) => {
-                            debug!("Done generating header file");
-                        }
-                        Err(e) => {
-                            error!("Failed printing header file");
-                            return Err(e.to_string());
-                        }
+        let output = File::create(&file_path);
+        match output {
+            Ok(mut f) => {
+                let res = write!(f, "{}", contents);
+                match res {
+                    Ok(_) => {
+                        debug!("Done generating {{{}}}", file_path);
                     }
-                }
-                Err(_) => {
-                    return Err("Failed gen of header file".to_string());
-                }
-            }
-            let c_impl_path = format!("{}/anvil__{}.c", target_path.display(), bin_name);
-            let output = File::create(c_impl_path);
-            let c_impl_string = format!("//Generated by invil v{INVIL_VERSION}\n
-#include \"anvil__{bin_name}.h\"\n
-const char *get_ANVIL__VERSION__(void)
-{{
-    return ANVIL__{bin_name}__VERSION_STRING;
-}}\n
-const char *get_ANVIL__API__LEVEL__(void)
-{{
-    return ANVIL__API_LEVEL__STRING;
-}}\n
-const char *get_ANVIL__VERSION__DESC__(void)
-{{
-    return ANVIL__{bin_name}__VERSION_DESC;
-}}\n
-const char *get_ANVIL__VERSION__DATE__(void)
-{{
-    return ANVIL__{bin_name}__VERSION_DATE;
-}}\n
-const char *get_ANVIL__VERSION__AUTHOR__(void)
-{{
-    return ANVIL__{bin_name}__VERSION_AUTHOR;
-}}\n
-const char *get_ANVIL__HEADER__GENTIME__(void)
-{{
-    return ANVIL__{bin_name}__HEADER_GENTIME;
-}}\n
-#ifdef INVIL__{bin_name}__HEADER__
-const char *get_INVIL__API__LEVEL__(void)
-{{
-    return INVIL__VERSION__STRING;
-}}\n
-const char *get_INVIL__COMMIT__DESC__(void)
-{{
-    return INVIL__COMMIT__DESC__STRING;
-}}\n
-const char *get_INVIL__OS__(void)
-{{
-    return INVIL__OS__STRING;
-}}
-#endif\n");
-            match output {
-                Ok(mut f) => {
-                    let res = write!(f, "{}", c_impl_string);
-                    match res {
-                        Ok(_) => {
-                            debug!("Done generating c impl file");
-                        }
-                        Err(e) => {
-                            error!("Failed printing c impl file");
-                            return Err(e.to_string());
-                        }
+                    Err(e) => {
+                        error!("Failed printing {{{}}}", file_path);
+                        return Err(AnvilError::io(&file_path, e));
                     }
                 }
-                Err(_) => {
-                    return Err("Failed gen of c impl file".to_string());
-                }
             }
-            Ok("Done C generation".to_string())
-        }
-        AnvilKern::AnvilPy => {
-            let header_path = format!("{}/anvil__{}.py", target_path.display(), bin_name);
-            trace!("Generating python header. Target path: {{{}}} Tag: {{{}}}", header_path, target_tag);
-            let output = File::create(header_path);
-            let header_string = format!("# Generated by invil v{INVIL_VERSION}\n
-# Repo at https://github.com/jgabaut/invil\n
-ANVIL__API_LEVEL__STRING = \"{EXPECTED_AMBOSO_API_LEVEL}\"\n
-ANVIL__{bin_name}__VERSION_STRING = \"{target_tag}\"\n
-ANVIL__{bin_name}__VERSION_DESC = \"{id}\"\n
-ANVIL__{bin_name}__VERSION_DATE = \"{commit_time}\"\n
-ANVIL__{bin_name}__VERSION_AUTHOR = \"{head_author_name}\"\n
-ANVIL__{bin_name}__HEADER_GENTIME = \"{fgen_time}\"\n
-def get_ANVIL_API_LEVEL__() -> str:\n    return ANVIL__API_LEVEL_STRING\n
-def get_ANVIL__VERSION__() -> str:\n    return ANVIL__{bin_name}__VERSION_STRING\n
-def get_ANVIL__VERSION__DESC__() -> str:\n    return ANVIL__{bin_name}__VERSION_DESC\n
-def get_ANVIL__VERSION__DATE__() -> str:\n    return ANVIL__{bin_name}__VERSION_DATE\n
-def get_ANVIL__VERSION__AUTHOR__() -> str:\n    return ANVIL__{bin_name}__VERSION_AUTHOR\n
-def get_ANVIL__VERSION__HEADER_GENTIME__() -> str:\n    return ANVIL__{bin_name}__HEADER_GENTIME\n");
-            match output {
-                Ok(mut f) => {
-                    let res = write!(f, "{}", header_string);
-                    match res {
-                        Ok(_) => {
-                            debug!("Done generating header file");
-                            Ok("Done python generation".to_string())
-                        }
-                        Err(e) => {
-                            error!("Failed printing header file");
-                            return Err(e.to_string());
-                        }
-                    }
-                }
-                Err(_) => {
-                    return Err("Failed gen of header file".to_string());
-                }
+            Err(e) => {
+                error!("Failed creating {{{}}}", file_path);
+                return Err(AnvilError::io(&file_path, e));
             }
         }
-        AnvilKern::Custom => {
-            Err("TODO: implement header gen for custom kern".to_string())
-        }
     }
+    Ok(format!("Done {kind} generation"))
 }
 
-fn try_lex_makefile(file_path: impl AsRef<Path>, dbg_print: bool, skip_recap: bool, report_warns: bool) -> Result<String,String> {
+fn try_lex_makefile(file_path: impl AsRef<Path>, dbg_print: bool, skip_recap: bool, report_warns: bool) -> Result<String,AnvilError> {
     warn!("Makefile parsing is experimental.");
     let path = file_path.as_ref();
     let res = lex_makefile(path, dbg_print, skip_recap, report_warns);
-    match res {
-        Ok(warns) => {
-            if warns != 0 {
-                trace!("Failed lex for {{{}}}.\nTot warns: {warns}.", path.display());
-                return Err(format!("Lex failure, {warns} warnings."));
-            }
-            debug!("Lex successful for {{{}}}.", path.display());
-            Ok(format!("Lex success with {warns} warns."))
-        }
+    let warns = match res {
+        Ok(warns) => warns,
         Err(e) => {
             trace!("Failed lex for {{{}}}.\nError was:    {e}", path.display());
-            Err("Lex failure".to_string())
+            return Err(e);
         }
+    };
+    let findings = lint_makefile_file(path)?;
+    for f in findings.iter() {
+        println!("{f}");
+    }
+    if warns != 0 {
+        trace!("Failed lex for {{{}}}.\nTot warns: {warns}.", path.display());
+        return Err(AnvilError::Config(format!("Lex failure, {warns} warnings.")));
+    }
+    let errors = findings.iter().filter(|f| f.severity == LintSeverity::Error).count();
+    if errors != 0 {
+        return Err(AnvilError::Config(format!("Makefile lint failure, {errors} errors.")));
     }
+    debug!("Lex successful for {{{}}}.", path.display());
+    Ok(format!("Lex success with {warns} warns, {} lint findings.", findings.len()))
 }
 
-pub fn handle_linter_flag(stego_path: &PathBuf, lint_mode: &AmbosoLintMode) -> Result<String,String> {
+pub fn handle_linter_flag(stego_path: &PathBuf, lint_mode: &AmbosoLintMode) -> Result<String,AnvilError> {
     debug!("Linter for file: {{{}}}", stego_path.display());
     if stego_path.exists() {
         trace!("Found {}", stego_path.display());
@@ -1285,6 +1168,29 @@ pub fn handle_linter_flag(stego_path: &PathBuf, lint_mode: &AmbosoLintMode) -> R
             AmbosoLintMode::NajloQuiet => {
                 try_lex_makefile(stego_path, false, true, true)
             }
+            AmbosoLintMode::NajloJson => {
+                let mk = parse_makefile(stego_path)?;
+                match serde_json::to_string_pretty(&mk) {
+                    Ok(json) => {
+                        println!("{json}");
+                        Ok("Makefile json output done".to_string())
+                    }
+                    Err(e) => {
+                        error!("Failed json output for {{{}}}. Err: {e}", stego_path.display());
+                        Err(AnvilError::Msg(format!("Makefile json output failed: {e}")))
+                    }
+                }
+            }
+            AmbosoLintMode::NajloGraph(format) => {
+                let mk = parse_makefile(stego_path)?;
+                let dir = match stego_path.parent() {
+                    Some(d) if !d.as_os_str().is_empty() => d,
+                    _ => Path::new("."),
+                };
+                let graph = make_graph(&mk, dir);
+                print!("{}", render_graph(&graph, *format)?);
+                Ok("Makefile graph output done".to_string())
+            }
             AmbosoLintMode::LintOnly => {
                 let res = try_parse_stego(stego_path);
                 match res {
@@ -1294,7 +1200,7 @@ pub fn handle_linter_flag(stego_path: &PathBuf, lint_mode: &AmbosoLintMode) -> R
                 }
                     Err(e) => {
                         trace!("Failed lint for {{{}}}.\nError was:    {e}",stego_path.display());
-                        Err("Lint failure".to_string())
+                        Err(e)
                     }
                 }
             }
@@ -1340,11 +1246,15 @@ pub fn handle_linter_flag(stego_path: &PathBuf, lint_mode: &AmbosoLintMode) -> R
         }
     } else {
         error!("Could not find file: {{{}}}", stego_path.display());
-        Err("Failed linter call".to_string())
+        Err(AnvilError::not_found(stego_path))
     }
 }
 
-pub fn handle_running_make() {
+/// Runs make (or the automake prep chain) in the current directory.
+///
+/// With `builtin`, or when make is missing, the Makefile is run by the built-in executor.
+/// Returns the exit code of the command, leaving it to the caller to decide how to exit.
+pub fn handle_running_make(builtin: bool) -> Result<i32,AnvilError> {
     if cfg!(target_os = "windows") {
         todo!("Support windows make run?");
         /*
@@ -1355,310 +1265,224 @@ pub fn handle_running_make() {
          */
     } else if Path::new("./Makefile").exists() {
         info!("Found Makefile");
-        debug!("Running \'make\'");
-        let output = Command::new("make")
-            .output()
-            .expect("failed to execute process");
+        let output = run_make(&[], builtin, Path::new("."))?;
 
-        match output.status.code() {
+        match output.code {
             Some(make_ec) => {
                 if make_ec == 0 {
                     debug!("make succeded with status: {}", make_ec.to_string());
-                    exit(make_ec);
                 } else {
                     error!("make failed with status: {}", make_ec.to_string());
                     io::stdout().write_all(&output.stdout).unwrap();
                     io::stderr().write_all(&output.stderr).unwrap();
-                    exit(make_ec);
                 }
+                Ok(make_ec)
             }
             None => {
                 error!("make command failed");
                 io::stdout().write_all(&output.stdout).unwrap();
                 io::stderr().write_all(&output.stderr).unwrap();
-                exit(1);
+                Err(AnvilError::BuildStepFailed {
+                    step: "make".to_string(),
+                    code: None,
+                    stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                })
             }
         }
     } else if Path::new("./configure.ac").exists() && Path::new("./Makefile.am").exists() {
         debug!("Running \'aclocal ; autoconf; automake --add-missing ; ./configure; make\'");
+        let autotools_prep_cmd = "aclocal ; autoconf ; automake --add-missing ; ./configure; make";
         let output = Command::new("sh")
             .arg("-c")
-            .arg("aclocal ; autoconf ; automake --add-missing ; ./configure; make")
+            .arg(autotools_prep_cmd)
             .output()
-            .expect("failed to execute process");
+            .map_err(|e| AnvilError::missing_tool("sh", e))?;
 
         match output.status.code() {
             Some(autotools_prep_ec) => {
                 if autotools_prep_ec == 0 {
                     debug!("Automake prep succeded with status: {}", autotools_prep_ec.to_string());
-                    exit(autotools_prep_ec);
                 } else {
                     error!("Automake failed with status: {}", autotools_prep_ec.to_string());
                     io::stdout().write_all(&output.stdout).unwrap();
                     io::stderr().write_all(&output.stderr).unwrap();
-                    exit(autotools_prep_ec);
                 }
+                Ok(autotools_prep_ec)
             }
             None => {
                 error!("Automake prep command failed");
                 io::stdout().write_all(&output.stdout).unwrap();
                 io::stderr().write_all(&output.stderr).unwrap();
-                exit(1);
+                Err(build_step_failed(autotools_prep_cmd, &output))
             }
         }
     } else {
         error!("Can't find Makefile or configure.ac and Makefile.am. Quitting.");
-        exit(1);
+        Err(AnvilError::Config("Missing Makefile or configure.ac and Makefile.am".to_string()))
     }
 }
 
-pub fn lex_makefile(file_path: impl AsRef<Path>, dbg_print: bool, skip_recap: bool, report_warns: bool) -> io::Result<u64> {
-    let path = file_path.as_ref();
+/// Runs make in `dir` with `make_args`.
+///
+/// The built-in executor is used with `builtin`, or when make is not installed.
+fn run_make(make_args: &[String], builtin: bool, dir: &Path) -> Result<BuildOutput,AnvilError> {
+    if !builtin {
+        let mut cmd = Command::new("make");
+        cmd.args(make_args).current_dir(dir);
+        debug!("Running \'{:?}\'", cmd);
+        match cmd.output() {
+            Ok(output) => return Ok(output.into()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                warn!("make was not found, using the built-in make");
+            }
+            Err(e) => return Err(AnvilError::missing_tool("make", e)),
+        }
+    }
+    debug!("Running the built-in make with {:?}", make_args);
+    run_builtin_make(dir, MakeOptions::from_args(make_args))
+}
 
-    // Check if the file exists
-    if !path.exists() {
-        error!("File not found: {}", path.display());
-        std::process::exit(1);
+/// Maps the result of [handle_running_make] to a query result.
+fn make_run_result(builtin: bool) -> Result<String,AnvilError> {
+    match handle_running_make(builtin) {
+        Ok(0) => Ok("Done running make".to_string()),
+        Ok(ec) => Err(AnvilError::BuildStepFailed {
+            step: "make".to_string(),
+            code: Some(ec),
+            stderr: String::new(),
+        }),
+        Err(e) => Err(e),
     }
+}
 
-    let mut last_rulename: String = "".to_string();
-    let mut _ingr_i: u64 = 0;
+/// Prints the Makefile at `file_path` in najlo's format: main expressions, rules, their prerequisites and their recipes.
+///
+/// Returns the number of recipe lines starting with spaces instead of a tab, which make reads as main expressions.
+/// Mtimes are read relative to the Makefile's directory.
+pub fn lex_makefile(file_path: impl AsRef<Path>, dbg_print: bool, skip_recap: bool, report_warns: bool) -> Result<u64,AnvilError> {
+    let path = file_path.as_ref();
+    let contents = fs::read_to_string(path).map_err(|e| {
+        error!("Failed to read file: {}", path.display());
+        AnvilError::io(path, e)
+    })?;
+    let mk = parse_makefile_source(path, &contents)?;
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    let mod_time = |name: &str| mtime_secs(&dir.join(name)).map_or("NO_TIME".to_string(), |t| t.to_string());
+    let raw_lines: Vec<&str> = contents.lines().collect();
+    let rulewarn_regex = Regex::new(RULEWARN_REGEX).expect("Failed to create rulewarn regex");
+    let first_rule_line = mk.rules.first().map(|r| r.span.start_line);
+
+    // Main expressions, by line, with the rules in between
+    let mut items: Vec<(usize, Option<String>)> = Vec::new();
+    items.extend(mk.variables.iter().map(|v| (v.span.start_line, Some(v.to_string().replace('\n', "\\n")))));
+    items.extend(mk.includes.iter().map(|i| (i.span.start_line, Some(format!("{} {}", if i.optional { "-include" } else { "include" }, i.paths.join(" "))))));
+    for c in mk.conditionals.iter() {
+        items.push((c.span.start_line, Some(format!("{} {}", c.kind, c.condition))));
+        for (line, condition) in c.else_lines.iter().zip(c.else_conditions.iter()) {
+            items.push((*line, Some(format!("else {condition}").trim_end().to_string())));
+        }
+        items.push((c.span.end_line, Some("endif".to_string())));
+    }
+    items.extend(mk.expressions.iter().map(|e| (e.span.start_line, Some(e.text.clone()))));
+    items.extend(mk.rules.iter().map(|r| (r.span.start_line, None)));
+    items.sort_by_key(|(line, _)| *line);
+
+    let mut tot_warns: u64 = 0;
     let mut mainexpr_arr: Vec<String> = Vec::new();
     let mut rules_arr: Vec<String> = Vec::new();
     let mut ruleingrs_arr: Vec<String> = Vec::new();
     let mut rulexpr_arr: Vec<String> = Vec::new();
-    let mut tot_warns: u64 = 0;
-    let mut cur_line: u64 = 0;
-    let mut rule_i: usize = 0;
-    let mut rulexpr_i: u64 = 0;
-    let mut mainexpr_i: usize = 0;
-    // Read the file line by line
-    if let Ok(file) = File::open(path) {
-        let tab_regex = Regex::new(&format!("^{}", RULELINE_MARK_CHAR)).expect("Failed to create ruleline regex");
-        let rule_regex = Regex::new(RULE_REGEX).expect("Failed to create rule regex");
-
-        let mut continuation = false;
-        let mut current_line = String::new();
-
-        let rulewarn_regex = Regex::new(RULEWARN_REGEX).expect("Failed to create rulewarn regex");
-        for line in io::BufReader::new(file).lines().map_while(Option::Some).flatten() {
-            if continuation {
-                current_line.pop();
-                current_line.push_str(&line);
-            } else {
-                current_line = line;
-            }
-            continuation = current_line.ends_with("\\");
-
-            if continuation {
-                continue
-            }
-            cur_line += 1;
-            if current_line.is_empty() {
-                trace!("Ignoring empty line.");
+    let mut rules = mk.rules.iter();
+    for (line, item) in items {
+        let Some(expr) = item else {
+            let Some(rule) = rules.next() else {
                 continue;
+            };
+            let rule_i = rules_arr.len();
+            let rulename = rule.targets.join(" ");
+            let ingrs: Vec<&String> = rule.prerequisites.iter().chain(rule.order_only.iter()).collect();
+            let ingrs_len = ingrs.len();
+            let rulepart_decl = format!("{{RULE}} [#{rule_i}] -> {{{rulename}}} <- {{{}}}", mod_time(&rulename));
+            let rulepart_deps = format!("<- {{DEPS}} -> {{{}}} -> [#{ingrs_len}]", ingrs.iter().map(|i| i.as_str()).collect::<Vec<_>>().join(" "));
+            rules_arr.push(format!("{rulepart_decl} {rulepart_deps}"));
+            if dbg_print {
+                println!("{rulepart_decl}\n\t{rulepart_deps} ->");
             }
-            let _comment = cut_line_at_char(&current_line, '#', CutDirection::After);
-            let stripped_line = cut_line_at_char(&current_line, '#', CutDirection::Before);
-            if rule_regex.is_match(stripped_line) {
-                let rulename = cut_line_at_char(stripped_line, ':', CutDirection::Before);
-                let mut rule_ingredients = cut_line_at_char(cut_line_at_char(stripped_line, ':', CutDirection::After), ' ', CutDirection::After); // Cut ': '
-                let mut ingrs_len = 0;
-                rulexpr_i = 0;
-                ruleingrs_arr.push("".to_string());
-                rulexpr_arr.push("".to_string());
-                let set_len: bool = rule_ingredients.is_empty();
-                if set_len {
-                    rule_ingredients = "NO_DEPS";
-                    ingrs_len = 0;
-                }
-                let ingrs_arr: Vec<_> = rule_ingredients.split_whitespace().collect();
-                if !set_len {
-                    ingrs_len = ingrs_arr.len();
-                }
-                //println!("Line matches rule regex: {}", stripped_line);
-                last_rulename = rulename.to_string();
-                let mod_time: String;
-                let rule_path = Path::new(rulename);
-                if rule_path.exists() {
-                    let metadata = fs::metadata(rulename)?;
-
-                    if let Ok(time) = metadata.modified() {
-                        let mod_timestamp = time.duration_since(SystemTime::UNIX_EPOCH);
-                        match mod_timestamp {
-                            Ok(t) => {
-                                mod_time=format!("{}", t.as_secs());
-                            }
-                            Err(e) => {
-                                panic!("Failed getting modification time for {}. Err: {e}", rule_path.display());
-                            }
-                        }
-                    } else {
-                        // This branch is meant for unsupported platforms.
-                        mod_time="NO_TIME".to_string();
-                    }
-                } else {
-                    mod_time="NO_TIME".to_string();
-                }
-                let rulepart_decl = format!("{{RULE}} [#{rule_i}] -> {{{rulename}}} <- {{{mod_time}}}");
-                let rulepart_deps = if !set_len {
-                    format!("<- {{DEPS}} -> {{{rule_ingredients}}} -> [#{ingrs_len}]")
-                } else {
-                    format!("<- {{DEPS}} -> {{}} -> [#{ingrs_len}]")
-                };
-                //let rule_str = format!("{{RULE}} [#{rule_i}] -> {{{rulename}}} <- {{{mod_time}}} <- {{DEPS}} -> {{{rule_ingredients}}} -> [#{ingrs_len}]");
-                let rule_str = format!("{rulepart_decl} {rulepart_deps}");
-                rules_arr.push(rule_str.clone());
+            let mut ruleingrs = String::new();
+            for (ingr_i, ingr) in ingrs.iter().enumerate() {
+                let ingr_mod_time = mod_time(ingr);
                 if dbg_print {
-                    println!("{rulepart_decl}\n\t{rulepart_deps} ->");
-                }
-                let mut ingr_mod_time: String;
-                if !set_len {
-                    for (ingr_i, ingr) in ingrs_arr.iter().enumerate() {
-                        let ingr_path = Path::new(ingr);
-                        if ingr_path.exists() {
-                            //TODO
-                            //In amboso 2.0.3, the embedded najlo version (0.0.3) wrongly
-                            //passes the rule name to the modification time call. A strict port
-                            //would do the same?
-                            let ingr_metadata = fs::metadata(ingr_path)?;
-
-                            if let Ok(time) = ingr_metadata.modified() {
-                                let mod_timestamp = time.duration_since(SystemTime::UNIX_EPOCH);
-                                match mod_timestamp {
-                                    Ok(t) => {
-                                        ingr_mod_time=format!("{}", t.as_secs());
-                                    }
-                                    Err(e) => {
-                                        panic!("Failed getting modification time for {}. Err: {e}", ingr_path.display());
-                                    }
-                                }
-                            } else {
-                                // This branch is meant for unsupported platforms.
-                                ingr_mod_time="NO_TIME".to_string();
-                            }
-                        } else {
-                            ingr_mod_time="NO_TIME".to_string();
-                        }
-
-                        let ingr_str = format!("{{{ingr}}} {{[{ingr_i}], [{ingr_mod_time}]}}, ");
-                        if dbg_print {
-                            println!("\t\t{{INGR}} - {{{ingr}}} [{ingr_i}], [{ingr_mod_time}]");
-                        }
-                        ruleingrs_arr[rule_i] = format!("{}{ingr_str}", ruleingrs_arr[rule_i]);
-                    }
-                    ruleingrs_arr[rule_i] = format!("{{RULE: {rulename} #{rule_i}}} <-- [{}]", ruleingrs_arr[rule_i]);
-                } else {
-                    if dbg_print {
-                        println!("\t\t{{{rule_ingredients}}}");
-                    }
-                    ruleingrs_arr[rule_i] = format!("{{RULE: {rulename} #{rule_i}}} <-- [{{NO_DEPS}}]");
-                }
-                if dbg_print {
-                    println!("\t}};");
-                }
-                rule_i += 1;
-            } else if !last_rulename.is_empty() && tab_regex.is_match(stripped_line) {
-                //println!("Line starts with a tab: {}", stripped_line);
-                let stripped_rulexpr_line = cut_line_at_char(stripped_line, '\t', CutDirection::After);
-                if stripped_rulexpr_line.is_empty() {
-                    trace!("Ignoring empty stripped rulexpr line.");
-                    continue;
+                    println!("\t\t{{INGR}} - {{{ingr}}} [{ingr_i}], [{ingr_mod_time}]");
                 }
+                ruleingrs.push_str(&format!("{{{ingr}}} {{[{ingr_i}], [{ingr_mod_time}]}}, "));
+            }
+            if ingrs.is_empty() {
                 if dbg_print {
-                    println!("\t{{RULE_EXPR}} -> {{{stripped_rulexpr_line}}}, [#{rulexpr_i}],");
-                }
-                if rulexpr_arr.is_empty() {
-                    error!("Can't have this. Line: [#{cur_line}], Stripped rulexpr line: {{{stripped_rulexpr_line}}}");
-                    panic!("OUCH");
+                    println!("\t\t{{NO_DEPS}}");
                 }
-                let rulexpr_str = format!("{{RULE_EXPR #{rulexpr_i}}} {{{stripped_rulexpr_line}}}, ");
-                rulexpr_arr[rule_i-1] = format!("{}{}", rulexpr_arr[rule_i-1], rulexpr_str);
-                rulexpr_i += 1;
+                ruleingrs_arr.push(format!("{{RULE: {rulename} #{rule_i}}} <-- [{{NO_DEPS}}]"));
             } else {
-                //println!("Line does not start with a tab: {}", stripped_line);
-                if stripped_line.is_empty() {
-                    trace!("Ignoring empty stripped line.");
-                    continue;
-                } else {
-                    rulexpr_i = 0;
-                }
-                if last_rulename.is_empty() {
-                    if tab_regex.is_match(stripped_line) {
-                        // This branch is not 1-1 in najlo, but it's needed
-                        //
-                        // TODO
-                        // Correctly concatenate the expressions, in some way
-                        // Current implementation may be a bit clunky but is close
-                        //
-                        debug!("Found mainexpr starting with a tab. {{{stripped_line}}}");
-                        todo!("Implement handling tabbed main_exprs");
-                        /*
-                        let mainexpr_str = format!("{{EXPR_MAIN}} -> {{{stripped_line}}}, [#{mainexpr_i}]");
-                        if dbg_print {
-                            println!("{},", mainexpr_str);
-                        }
-                        mainexpr_arr[mainexpr_i-1] = format!("{}{}", mainexpr_arr[mainexpr_i-1], mainexpr_str);
-                        continue;
-                        */
-                    }
-                    //println!("Line is an expression before any rule was found");
-                    let mainexpr_str = format!("{{EXPR_MAIN}} -> {{{stripped_line}}}, [#{mainexpr_i}]");
-                    if dbg_print {
-                        println!("{},", mainexpr_str);
-                    }
-                    mainexpr_arr.push(mainexpr_str);
-                    mainexpr_i += 1;
-                } else {
-                    //println!("Line is an expression after at least one rule was found");
-                    let mainexpr_str = format!("{{EXPR_MAIN}} -> {{{stripped_line}}}, [#{mainexpr_i}]");
-                    if dbg_print {
-                        println!("{},", mainexpr_str);
-                    }
-                    if report_warns && rulewarn_regex.is_match(stripped_line) {
-                        warn!("A recipe line must start with a tab.");
-                        warn!("{stripped_line}");
-                        warn!("^^^ Any recipe line starting with a space will be interpreted as a main expression.");
-                        tot_warns += 1;
-                    }
-                    mainexpr_arr.push(mainexpr_str);
-                    mainexpr_i += 1;
+                ruleingrs_arr.push(format!("{{RULE: {rulename} #{rule_i}}} <-- [{ruleingrs}]"));
+            }
+            if dbg_print {
+                println!("\t}};");
+            }
+            let mut rulexprs = String::new();
+            for (rulexpr_i, recipe) in rule.recipe.iter().enumerate() {
+                let text = recipe.text.replace("\\\n", " ");
+                if dbg_print {
+                    println!("\t{{RULE_EXPR}} -> {{{text}}}, [#{rulexpr_i}],");
                 }
+                rulexprs.push_str(&format!("{{RULE_EXPR #{rulexpr_i}}} {{{text}}}, "));
             }
+            rulexpr_arr.push(rulexprs);
+            continue;
+        };
+        let mainexpr_str = format!("{{EXPR_MAIN}} -> {{{expr}}}, [#{}]", mainexpr_arr.len());
+        if dbg_print {
+            println!("{mainexpr_str},");
         }
+        let raw = raw_lines.get(line - 1).copied().unwrap_or("");
+        if report_warns && first_rule_line.is_some_and(|l| line > l) && rulewarn_regex.is_match(raw) {
+            warn!("A recipe line must start with a tab.");
+            warn!("{}", raw.trim());
+            warn!("^^^ Any recipe line starting with a space will be interpreted as a main expression.");
+            tot_warns += 1;
+        }
+        mainexpr_arr.push(mainexpr_str);
+    }
 
-        if skip_recap { return Ok(tot_warns); }
+    if skip_recap { return Ok(tot_warns); }
 
-        println!("{{MAIN}} -> {{");
-        for mexpr in mainexpr_arr {
-            println!("\t[{}],", mexpr);
-        }
-        println!("}}");
-        println!("{{RULES}} -> {{");
-        for rule in &rules_arr {
-            println!("\t[{}],", rule);
-        }
-        println!("}}");
-        println!("{{DEPS}} -> {{");
-        for ruleingr in ruleingrs_arr {
-            println!("\t[{}],", ruleingr);
-        }
-        println!("}}");
-        println!("{{RULE_EXPRS}} -> {{");
-        for (i, rulexpr) in rulexpr_arr.iter().enumerate() {
-            println!("\t[[{}] --> [{rulexpr}]],", rules_arr[i]);
-        }
-        println!("}}");
-    } else {
-        eprintln!("Failed to open file: {}", path.display());
-        std::process::exit(1);
+    println!("{{MAIN}} -> {{");
+    for mexpr in mainexpr_arr {
+        println!("\t[{}],", mexpr);
+    }
+    println!("}}");
+    println!("{{RULES}} -> {{");
+    for rule in &rules_arr {
+        println!("\t[{}],", rule);
+    }
+    println!("}}");
+    println!("{{DEPS}} -> {{");
+    for ruleingr in ruleingrs_arr {
+        println!("\t[{}],", ruleingr);
     }
+    println!("}}");
+    println!("{{RULE_EXPRS}} -> {{");
+    for (i, rulexpr) in rulexpr_arr.iter().enumerate() {
+        println!("\t[[{}] --> [{rulexpr}]],", rules_arr[i]);
+    }
+    println!("}}");
 
     Ok(tot_warns)
 }
 
-fn build_step(args: &Args, env: &AmbosoEnv, cflg_str: String, query: &str, bin_path: PathBuf, target_path: PathBuf, bin: String, head_was_detached: bool, do_postbuild: bool) -> Result<String,String> {
-    let output;
+/// Runs the build command for the kern in `build_dir`, then the postbuild step in git mode.
+fn build_step(args: &Args, env: &AmbosoEnv, build_dir: &Path, cflg_str: String, query: &str, bin_path: PathBuf, target_path: PathBuf, bin: String, head_was_detached: bool, do_postbuild: bool) -> Result<String,AnvilError> {
     let build_step_command;
     match env.anvil_kern {
         AnvilKern::AmbosoC => {
@@ -1689,48 +1513,40 @@ fn build_step(args: &Args, env: &AmbosoEnv, cflg_str: String, query: &str, bin_p
                     }
                     None => {
                         error!("Missing anvilcustom_env");
-                        return Err("Missing anvilcustom_env".to_string());
+                        return Err(AnvilError::Config("Missing anvilcustom_env".to_string()));
                     }
                 }
             }
             #[cfg(not(feature = "anvilCustom"))] {
                 // Handle AnvilCustom case when the feature is not enabled
                 error!("AnvilCustom kern feature is not enabled");
-                return Err("AnvilCustom kern feauture is not enabled".to_string());
+                return Err(AnvilError::Config("AnvilCustom kern feauture is not enabled".to_string()));
             }
         }
     }
 
-    match env.anvil_kern {
+    let output: BuildOutput = match env.anvil_kern {
         AnvilKern::AmbosoC => {
-            if args.no_rebuild {
-                let mut cmd = Command::new(build_step_command);
-                for arg in &args.extra_args {
-                    cmd.arg(arg);
-                }
-                cmd.arg(cflg_str);
-                debug!("Running \'{:?}\'", cmd);
-                output = cmd.output()
-                            .expect("failed to execute process");
-            } else {
-                let mut cmd = Command::new("make");
-                for arg in &args.extra_args {
-                    cmd.arg(arg);
-                }
-                cmd.arg("rebuild")
-                   .arg(cflg_str);
-                debug!("Running \'{:?}\'", cmd);
-                output = cmd.output()
-                            .expect("failed to execute process");
+            let mut make_args = Vec::new();
+            if let Some(jobs) = args.jobs {
+                make_args.push(format!("-j{jobs}"));
+            }
+            make_args.extend(args.extra_args.iter().cloned());
+            if !args.no_rebuild {
+                make_args.push("rebuild".to_string());
             }
+            make_args.push(cflg_str);
+            run_make(&make_args, args.builtin_make, build_dir)?
         }
         AnvilKern::AnvilPy => {
             debug!("Running \'{build_step_command}\'");
-            output = Command::new(build_step_command)
+            Command::new(build_step_command)
+                .current_dir(build_dir)
                 .arg("-m")  // Using -o bin_path would allow skipping the mv command
                 .arg("build")
                 .output()
-                .expect("failed to execute process");
+                .map_err(|e| AnvilError::missing_tool(build_step_command, e))?
+                .into()
         }
         AnvilKern::Custom => {
             // "custom_builder" "target_d" "builds_dir" "bin_name" "q_tag" "stego_dir"
@@ -1746,12 +1562,14 @@ fn build_step(args: &Args, env: &AmbosoEnv, cflg_str: String, query: &str, bin_p
                 cmd.arg(arg);
             }
 
+            cmd.current_dir(build_dir);
             debug!("Running \'{:?}\'", cmd);
-            output = cmd.output()
-                .expect("failed to execute process");
+            cmd.output()
+                .map_err(|e| AnvilError::missing_tool(build_step_command, e))?
+                .into()
         }
-    }
-    match output.status.code() {
+    };
+    match output.code {
         Some(make_ec) => {
             if make_ec == 0 {
                debug!("{{{}}} succeded with status: {}", build_step_command, make_ec.to_string());
@@ -1773,19 +1591,27 @@ fn build_step(args: &Args, env: &AmbosoEnv, cflg_str: String, query: &str, bin_p
                 warn!("{{{}}} failed with status: {}", build_step_command, make_ec.to_string());
                 io::stdout().write_all(&output.stdout).unwrap();
                 io::stderr().write_all(&output.stderr).unwrap();
-                Err(format!("{{{build_step_command}}} failed"))
+                Err(AnvilError::BuildStepFailed {
+                    step: build_step_command.to_string(),
+                    code: Some(make_ec),
+                    stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                })
             }
         }
         None => {
             error!("{{{}}} command failed", build_step_command);
             io::stdout().write_all(&output.stdout).unwrap();
             io::stderr().write_all(&output.stderr).unwrap();
-            Err(format!("{{{build_step_command}}} command failed"))
+            Err(AnvilError::BuildStepFailed {
+                step: build_step_command.to_string(),
+                code: None,
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            })
         }
     }
 }
 
-fn git_switch_and_submodule_init_re(query: &str, head_was_detached: bool) -> Result<String,String> {
+fn git_switch_and_submodule_init_re(query: &str, head_was_detached: bool, work_dir: &Path) -> Result<String,AnvilError> {
     trace!("Running \'git switch -\'");
     if head_was_detached {
         debug!("Checkout started from a detached HEAD, will add --detach to the switchback");
@@ -1793,18 +1619,20 @@ fn git_switch_and_submodule_init_re(query: &str, head_was_detached: bool) -> Res
     let output = match head_was_detached {
         false => {
             Command::new("git")
+            .current_dir(work_dir)
             .arg("switch")
             .arg("-")
             .output()
-            .expect("failed to execute process")
+            .map_err(|e| AnvilError::missing_tool("git", e))?
         }
         true => {
             Command::new("git")
+            .current_dir(work_dir)
             .arg("switch")
             .arg("-")
             .arg("--detach")
             .output()
-            .expect("failed to execute process")
+            .map_err(|e| AnvilError::missing_tool("git", e))?
         }
     };
     match output.status.code() {
@@ -1813,12 +1641,13 @@ fn git_switch_and_submodule_init_re(query: &str, head_was_detached: bool) -> Res
                debug!("git switch succeded with status: {}", gswitch_ec.to_string());
                 trace!("Running \'git submodule update --init --recursive\'");
                 let output = Command::new("git")
+                    .current_dir(work_dir)
                     .arg("submodule")
                     .arg("update")
                     .arg("--init")
                     .arg("--recursive")
                     .output()
-                    .expect("failed to execute process");
+                    .map_err(|e| AnvilError::missing_tool("git", e))?;
                 match output.status.code() {
                     Some(gsinit_end_ec) => {
                         if gsinit_end_ec == 0 {
@@ -1829,33 +1658,33 @@ fn git_switch_and_submodule_init_re(query: &str, head_was_detached: bool) -> Res
                             warn!("git submodule init failed with status: {}", gsinit_end_ec.to_string());
                             io::stdout().write_all(&output.stdout).unwrap();
                             io::stderr().write_all(&output.stderr).unwrap();
-                            Err("git submodule init failed".to_string())
+                            Err(git_step_failed("git submodule update --init --recursive", &output))
                         }
                     }
                     None => {
                         error!("git submodule init command failed");
                         io::stdout().write_all(&output.stdout).unwrap();
                         io::stderr().write_all(&output.stderr).unwrap();
-                        Err("git submodule init command failed".to_string())
+                        Err(git_step_failed("git submodule update --init --recursive", &output))
                     }
                 }
             } else {
                 warn!("git switch failed with status: {}", gswitch_ec.to_string());
                 io::stdout().write_all(&output.stdout).unwrap();
                 io::stderr().write_all(&output.stderr).unwrap();
-                Err("git switch failed".to_string())
+                Err(git_step_failed("git switch -", &output))
             }
         }
         None => {
             error!("git switch command failed");
             io::stdout().write_all(&output.stdout).unwrap();
             io::stderr().write_all(&output.stderr).unwrap();
-            Err("git switch command failed".to_string())
+            Err(git_step_failed("git switch -", &output))
         }
     }
 }
 
-fn postbuild_step(env: &AmbosoEnv, query: &str, bin_path: PathBuf, target_path: PathBuf, bin: String, head_was_detached: bool) -> Result<String,String> {
+fn postbuild_step(env: &AmbosoEnv, query: &str, bin_path: PathBuf, target_path: PathBuf, bin: String, head_was_detached: bool) -> Result<String,AnvilError> {
 
     let output;
     let builds_path = match env.builds_dir.clone() {
@@ -1871,10 +1700,11 @@ fn postbuild_step(env: &AmbosoEnv, query: &str, bin_path: PathBuf, target_path:
         AnvilKern::AmbosoC => {
             trace!("Running \'mv {} {}\'", build_path.display(), bin_path.display());
             output = Command::new("mv")
+                .current_dir(env.work_dir())
                 .arg(build_path)
                 .arg(bin_path)
                 .output()
-                .expect("failed to execute process");
+                .map_err(|e| AnvilError::missing_tool("mv", e))?;
         }
         AnvilKern::AnvilPy => {
             #[cfg(feature = "anvilPy")] {
@@ -1885,10 +1715,11 @@ fn postbuild_step(env: &AmbosoEnv, query: &str, bin_path: PathBuf, target_path:
                 info!("curr_proj_name {} srcdist_name {}", curr_proj_name, srcdist_name);
                 trace!("Running \'mv {} {}\'", srcdist_path.display(), target_path.display());
                 let output_srcdist = Command::new("mv")
+                    .current_dir(env.work_dir())
                     .arg(srcdist_path)
                     .arg(target_path.clone())
                     .output()
-                    .expect("failed to execute process");
+                    .map_err(|e| AnvilError::missing_tool("mv", e))?;
                 match output_srcdist.status.code() {
                     Some(mv_ec) => {
                         if mv_ec == 0 {
@@ -1906,10 +1737,11 @@ fn postbuild_step(env: &AmbosoEnv, query: &str, bin_path: PathBuf, target_path:
 
                                     trace!("Running \'mv {} {}\'", curr_unpack_path.display(), target_unpack_path.display());
                                     let output_unpackmv = Command::new("mv")
+                                        .current_dir(env.work_dir())
                                         .arg(curr_unpack_path.clone())
                                         .arg(target_unpack_path.clone())
                                         .output()
-                                        .expect("failed to execute process");
+                                        .map_err(|e| AnvilError::missing_tool("mv", e))?;
                                     match output_unpackmv.status.code() {
                                         Some(mv_ec) => {
                                             if mv_ec == 0 {
@@ -1918,14 +1750,14 @@ fn postbuild_step(env: &AmbosoEnv, query: &str, bin_path: PathBuf, target_path:
                                                 warn!("mv unpack failed with status: {}", mv_ec.to_string());
                                                 io::stdout().write_all(&output_unpackmv.stdout).unwrap();
                                                 io::stderr().write_all(&output_unpackmv.stderr).unwrap();
-                                                return Err("mv unpack failed".to_string());
+                                                return Err(build_step_failed("mv", &output_unpackmv));
                                             }
                                         }
                                         None => {
                                             error!("mv unpack command failed");
-                                            io::stdout().write_all(&output_srcdist.stdout).unwrap();
-                                            io::stderr().write_all(&output_srcdist.stderr).unwrap();
-                                            return Err("mv command failed".to_string());
+                                            io::stdout().write_all(&output_unpackmv.stdout).unwrap();
+                                            io::stderr().write_all(&output_unpackmv.stderr).unwrap();
+                                            return Err(build_step_failed("mv", &output_unpackmv));
                                         }
                                     }
                                     let mut unpack_initpy_path = target_unpack_path.clone();
@@ -1934,7 +1766,7 @@ fn postbuild_step(env: &AmbosoEnv, query: &str, bin_path: PathBuf, target_path:
                                         Ok(_) => {}
                                         Err(e) => {
                                             error!("Failed post_unpack() for {{{}}}. Err: {e}", target_unpack_path.display());
-                                            return Err("Failed post_unpack()".to_string());
+                                            return Err(e.context("Failed post_unpack()"));
                                         }
                                     }
                                 }
@@ -1946,29 +1778,30 @@ fn postbuild_step(env: &AmbosoEnv, query: &str, bin_path: PathBuf, target_path:
                             warn!("mv srcdist failed with status: {}", mv_ec.to_string());
                             io::stdout().write_all(&output_srcdist.stdout).unwrap();
                             io::stderr().write_all(&output_srcdist.stderr).unwrap();
-                            return Err("mv failed".to_string());
+                            return Err(build_step_failed("mv", &output_srcdist));
                         }
                     }
                     None => {
                         error!("mv srcdist command failed");
                         io::stdout().write_all(&output_srcdist.stdout).unwrap();
                         io::stderr().write_all(&output_srcdist.stderr).unwrap();
-                        return Err("mv command failed".to_string());
+                        return Err(build_step_failed("mv", &output_srcdist));
                     }
                 }
                 let curr_whldist_name = format!("./dist/{}-{}-py3-none-any.whl", curr_proj_name, query);
                 let curr_whldist_path = PathBuf::from(curr_whldist_name);
                 trace!("Running \'mv {} {}\'", curr_whldist_path.display(), target_path.display());
                 output = Command::new("mv")
+                    .current_dir(env.work_dir())
                     .arg(curr_whldist_path)
                     .arg(target_path)
                     .output()
-                    .expect("failed to execute process");
+                    .map_err(|e| AnvilError::missing_tool("mv", e))?;
             }
             #[cfg(not(feature = "anvilPy"))] {
                 // Handle AnvilPy case when the feature is not enabled
                 error!("AnvilPy kern feature is not enabled. Can't build {} to {}", bin, target_path.display());
-                return Err("AnvilPy kern feauture is not enabled".to_string());
+                return Err(AnvilError::Config("AnvilPy kern feauture is not enabled".to_string()));
             }
         }
         AnvilKern::Custom => {
@@ -1979,7 +1812,7 @@ fn postbuild_step(env: &AmbosoEnv, query: &str, bin_path: PathBuf, target_path:
                     debug!("Ignoring the move step.");
                     match env.run_mode.as_ref().unwrap() {
                         AmbosoMode::GitMode => {
-                            let gswinit_res = git_switch_and_submodule_init_re(query, head_was_detached);
+                            let gswinit_res = git_switch_and_submodule_init_re(query, head_was_detached, env.work_dir());
                             match gswinit_res {
                                 Ok(_) => {
                                     trace!("Done git cleaning");
@@ -1993,22 +1826,23 @@ fn postbuild_step(env: &AmbosoEnv, query: &str, bin_path: PathBuf, target_path:
                         }
                         _ => {
                             error!("Unexpected mode in postbuild_step(): {:?}", env.run_mode.as_ref());
-                            return Err("Unexpected mode in postbuild step".to_string());
+                            return Err(AnvilError::Config("Unexpected mode in postbuild step".to_string()));
                         }
                     }
                 }
                 trace!("TODO: postbuild checks for custom kern");
                 trace!("Running \'mv {} {}\'", build_path.display(), bin_path.display());
                 output = Command::new("mv")
+                    .current_dir(env.work_dir())
                     .arg(build_path)
                     .arg(bin_path)
                     .output()
-                    .expect("failed to execute process");
+                    .map_err(|e| AnvilError::missing_tool("mv", e))?;
             }
             #[cfg(not(feature = "anvilCustom"))] {
                 // Handle AnvilCustom case when the feature is not enabled
                 error!("AnvilCustom kern feature is not enabled. Can't build {} to {}", bin, target_path.display());
-                return Err("AnvilCustom kern feauture is not enabled".to_string());
+                return Err(AnvilError::Config("AnvilCustom kern feauture is not enabled".to_string()));
             }
         }
     }
@@ -2021,7 +1855,7 @@ fn postbuild_step(env: &AmbosoEnv, query: &str, bin_path: PathBuf, target_path:
                         debug!("mv succeded with status: {}", mv_ec.to_string());
                         match env.run_mode.as_ref().unwrap() {
                             AmbosoMode::GitMode => {
-                                let gswinit_res = git_switch_and_submodule_init_re(query, head_was_detached);
+                                let gswinit_res = git_switch_and_submodule_init_re(query, head_was_detached, env.work_dir());
                                 match gswinit_res {
                                     Ok(m) => {
                                         trace!("Done git cleaning");
@@ -2035,21 +1869,21 @@ fn postbuild_step(env: &AmbosoEnv, query: &str, bin_path: PathBuf, target_path:
                             }
                             _ => {
                                 error!("Unexpected mode in postbuild_step(): {:?}", env.run_mode.as_ref());
-                                Err("Unexpected mode in postbuild step".to_string())
+                                Err(AnvilError::Config("Unexpected mode in postbuild step".to_string()))
                             }
                         }
                     } else {
                         warn!("mv failed with status: {}", mv_ec.to_string());
                         io::stdout().write_all(&output.stdout).unwrap();
                         io::stderr().write_all(&output.stderr).unwrap();
-                        Err("mv failed".to_string())
+                        Err(build_step_failed("mv", &output))
                     }
                 }
                 None => {
                     error!("mv command failed");
                     io::stdout().write_all(&output.stdout).unwrap();
                     io::stderr().write_all(&output.stderr).unwrap();
-                    Err("mv command failed".to_string())
+                    Err(build_step_failed("mv", &output))
                 }
             }
         }
@@ -0,0 +1,334 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::path::{Path, PathBuf};
+use clap::Parser;
+use crate::core::{Args, AmbosoEnv, AmbosoMode, INVIL_NAME, check_passed_args};
+use crate::ops::{do_build, do_run, do_delete, run_test, gen_header};
+use crate::error::AnvilError;
+
+/// A project managed by a `stego.lock`, usable without going through the CLI.
+///
+/// Paths are resolved against the project root, which is also the working directory
+/// of the commands run by operations. The process working directory is never changed.
+#[derive(Debug)]
+pub struct Project {
+    root: PathBuf,
+    args: Args,
+    env: AmbosoEnv,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BuildOutcome {
+    /// The binary was built at the contained path
+    Built(PathBuf),
+    /// The binary was already present at the contained path, and no build was done
+    Ready(PathBuf),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TestKind {
+    /// Test from the success tests dir
+    Bone,
+    /// Test from the error tests dir
+    Kulpo,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TestCase {
+    pub name: String,
+    pub path: PathBuf,
+    pub kind: TestKind,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TestOutcome {
+    Passed,
+    /// The test ran but did not match its recorded output
+    Failed(String),
+}
+
+impl Project {
+
+    /// Opens the project for the passed `stego.lock`, reading it as the CLI would.
+    ///
+    /// The directory containing `stego.lock` is used as project root.
+    /// Only git and base mode are accepted: tests are available in both.
+    /// In git mode, the repo must be clean.
+    pub fn open(stego_path: impl AsRef<Path>, mode: AmbosoMode) -> Result<Project,AnvilError> {
+        let stego_path = match stego_path.as_ref().canonicalize() {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Failed resolving {{{}}}. Err: {e}", stego_path.as_ref().display());
+                return Err(AnvilError::Config(format!("Can't find {}.", stego_path.as_ref().display())));
+            }
+        };
+        let root = match stego_path.parent() {
+            Some(r) => r.to_path_buf(),
+            None => {
+                return Err(AnvilError::Usage(format!("Unexpected stego.lock path: {{{}}}", stego_path.display())));
+            }
+        };
+        let mut args = Args::parse_from([INVIL_NAME]);
+        match mode {
+            AmbosoMode::GitMode => {
+                args.git = true;
+            }
+            AmbosoMode::BaseMode => {
+                args.base = true;
+            }
+            AmbosoMode::TestMode | AmbosoMode::TestMacro => {
+                error!("Can't open a project in {:?}", mode);
+                return Err(AnvilError::Usage("Projects can only be opened in git or base mode".to_string()));
+            }
+        }
+        args.amboso_dir = Some(root.join("bin"));
+        args.stego_dir = Some(root.clone());
+        args.work_dir = Some(root.clone());
+        let env = check_passed_args(&mut args)?;
+        Ok(Project {
+            root,
+            args,
+            env,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn env(&self) -> &AmbosoEnv {
+        &self.env
+    }
+
+    /// Returns the supported tags for the project mode, in ascending order.
+    pub fn tags(&self) -> Vec<String> {
+        let table = match self.env.run_mode {
+            Some(AmbosoMode::BaseMode) => &self.env.basemode_versions_table,
+            _ => &self.env.gitmode_versions_table,
+        };
+        table.keys().map(|k| k.0.clone()).collect()
+    }
+
+    /// Returns the path of the binary for `tag`, whether it was built or not.
+    pub fn bin_path(&self, tag: &str) -> Result<PathBuf,AnvilError> {
+        match (&self.env.amboso_dir, &self.env.bin) {
+            (Some(dir), Some(bin)) => {
+                let mut path = dir.clone();
+                path.push(format!("v{tag}"));
+                path.push(bin);
+                Ok(path)
+            }
+            _ => Err(AnvilError::Config("Missing amboso_dir or bin name".to_string())),
+        }
+    }
+
+    /// Builds `tag`, unless it's already built and `force` is false.
+    pub fn build(&self, tag: &str, force: bool) -> Result<BuildOutcome,AnvilError> {
+        let bin_path = self.bin_path(tag)?;
+        let was_ready = bin_path.is_file() && !force;
+        let mut args = self.op_args(tag);
+        args.build = true;
+        args.force = force;
+        do_build(&self.env, &args)?;
+        if was_ready {
+            Ok(BuildOutcome::Ready(bin_path))
+        } else {
+            Ok(BuildOutcome::Built(bin_path))
+        }
+    }
+
+    /// Runs the binary for `tag` with `run_args`, inheriting stdio.
+    ///
+    /// Returns the exit code of the binary.
+    pub fn run(&self, tag: &str, run_args: &[String]) -> Result<i32,AnvilError> {
+        let mut args = self.op_args(tag);
+        args.run = true;
+        args.extra_args = run_args.to_vec();
+        do_run(&self.env, &args)
+    }
+
+    /// Deletes the binary for `tag`.
+    pub fn delete(&self, tag: &str) -> Result<(),AnvilError> {
+        let mut args = self.op_args(tag);
+        args.delete = true;
+        do_delete(&self.env, &args).map(|_| ())
+    }
+
+    /// Returns all executable tests, success tests first, sorted by name.
+    pub fn tests(&self) -> Vec<TestCase> {
+        let bone = self.env.bonetests_table.iter().map(|(name, path)| {
+            TestCase { name: name.clone(), path: path.clone(), kind: TestKind::Bone }
+        });
+        let kulpo = self.env.kulpotests_table.iter().map(|(name, path)| {
+            TestCase { name: name.clone(), path: path.clone(), kind: TestKind::Kulpo }
+        });
+        bone.chain(kulpo).collect()
+    }
+
+    /// Runs the test called `name`, comparing its output with the recorded one.
+    pub fn run_test(&self, name: &str) -> Result<TestOutcome,AnvilError> {
+        let test = self.find_test(name)?;
+        match run_test(&test.path, &self.root, false, self.env.test_timeout) {
+            Ok(_) => Ok(TestOutcome::Passed),
+            Err(e @ AnvilError::TestMismatch { .. }) => Ok(TestOutcome::Failed(e.to_string())),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Runs the test called `name`, recording its output.
+    pub fn record_test(&self, name: &str) -> Result<(),AnvilError> {
+        let test = self.find_test(name)?;
+        run_test(&test.path, &self.root, true, self.env.test_timeout).map(|_| ())
+    }
+
+    /// Sets whether generated headers use the commit time as gen time, like `--reproducible`.
+    pub fn set_reproducible_headers(&mut self, reproducible: bool) {
+        self.env.header_reproducible = reproducible;
+    }
+
+    /// Generates the header and implementation files for `tag` in `target_dir`.
+    pub fn gen_header(&self, target_dir: &Path, tag: &str) -> Result<(),AnvilError> {
+        let bin = match self.env.bin {
+            Some(ref b) => b.clone(),
+            None => {
+                return Err(AnvilError::Config("Missing bin name for header gen".to_string()));
+            }
+        };
+        gen_header(&self.root.join(target_dir), self.env.anvil_kern, &tag.to_string(), &self.env.git_tag_name(tag), &bin, &self.env.header_options()).map(|_| ())
+    }
+
+    fn find_test(&self, name: &str) -> Result<TestCase,AnvilError> {
+        match self.tests().into_iter().find(|t| t.name == name) {
+            Some(t) => Ok(t),
+            None => {
+                error!("{{{}}} was not a valid test.", name);
+                Err(AnvilError::Usage(format!("Invalid test: {{{name}}}")))
+            }
+        }
+    }
+
+    fn op_args(&self, tag: &str) -> Args {
+        let mut args = self.args.clone();
+        args.tag = Some(tag.to_string());
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn test_open_base_project() {
+        let stego_path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("stego.lock");
+        let project = Project::open(stego_path, AmbosoMode::BaseMode).expect("Failed opening project");
+        assert_eq!(project.tags(), vec!["0.0.5".to_string(), "0.0.7".to_string()]);
+        let tests = project.tests();
+        assert!(tests.iter().any(|t| t.name == "vershw.k" && t.kind == TestKind::Bone));
+        assert!(project.run_test("missing.k").is_err());
+        assert!(project.bin_path("0.0.5").unwrap().ends_with("bin/v0.0.5/hello_world"));
+    }
+
+    #[test]
+    fn test_project_ops() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = TempDir::new("project");
+        let tag_dir = root.join("bin").join("v0.1.0");
+        fs::create_dir_all(&tag_dir).unwrap();
+        fs::create_dir_all(root.join("kazoj").join("bone")).unwrap();
+        fs::create_dir_all(root.join("kazoj").join("kulpo")).unwrap();
+        fs::write(root.join("stego.lock"), "\
+[build]
+source = \"main.c\"
+bin = \"hello\"
+makevers = \"0.1.0\"
+automakevers = \"9.0.0\"
+tests = \"kazoj\"
+
+[tests]
+testsdir = \"bone\"
+errortestsdir = \"kulpo\"
+
+[versions]
+\"B0.1.0\" = \"Make build\"
+").unwrap();
+        fs::write(tag_dir.join("main.c"), "int main(void) { return 0; }\n").unwrap();
+        fs::write(tag_dir.join("Makefile"), "hello: main.c\n\tcc $(CFLAGS) -o hello main.c\nrebuild: hello\n").unwrap();
+        // The test only passes when run from the project root
+        let test_path = root.join("kazoj").join("bone").join("root.k");
+        fs::write(&test_path, "#!/bin/sh\ntest -f stego.lock && echo in root\n").unwrap();
+        fs::set_permissions(&test_path, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(test_path.with_extension("k.stdout"), "").unwrap();
+        fs::write(test_path.with_extension("k.stderr"), "").unwrap();
+
+        let cwd = std::env::current_dir().unwrap();
+        let mut project = Project::open(root.join("stego.lock"), AmbosoMode::BaseMode).expect("Failed opening project");
+        project.env.cflags_arg = "CFLAGS=-O0".to_string();
+        let bin_path = tag_dir.join("hello");
+        assert_eq!(project.build("0.1.0", false).unwrap(), BuildOutcome::Built(bin_path.clone()));
+        assert!(bin_path.is_file());
+        assert_eq!(project.build("0.1.0", false).unwrap(), BuildOutcome::Ready(bin_path.clone()));
+
+        assert_eq!(project.tests(), vec![TestCase { name: "root.k".to_string(), path: test_path.clone(), kind: TestKind::Bone }]);
+        assert!(matches!(project.run_test("root.k").unwrap(), TestOutcome::Failed(_)));
+        project.record_test("root.k").unwrap();
+        assert_eq!(fs::read_to_string(test_path.with_extension("k.stdout")).unwrap(), "in root\n");
+        assert_eq!(project.run_test("root.k").unwrap(), TestOutcome::Passed);
+
+        project.delete("0.1.0").unwrap();
+        assert!(!bin_path.exists());
+        assert_eq!(std::env::current_dir().unwrap(), cwd);
+    }
+
+    #[test]
+    fn test_project_build_failure() {
+        use std::fs;
+
+        let root = TempDir::new("project-fail");
+        let tag_dir = root.join("bin").join("v0.1.0");
+        fs::create_dir_all(&tag_dir).unwrap();
+        // Tags before makevers are built with a single compiler call
+        fs::write(root.join("stego.lock"), "\
+[build]
+source = \"main.c\"
+bin = \"hello\"
+makevers = \"0.2.0\"
+automakevers = \"9.0.0\"
+tests = \"kazoj\"
+
+[tests]
+testsdir = \"bone\"
+errortestsdir = \"kulpo\"
+
+[versions]
+\"B0.1.0\" = \"Single file build\"
+").unwrap();
+        fs::write(tag_dir.join("main.c"), "int main(void) { return missing; }\n").unwrap();
+
+        let mut project = Project::open(root.join("stego.lock"), AmbosoMode::BaseMode).expect("Failed opening project");
+        project.env.cflags_arg = "-O0".to_string();
+        let err = project.build("0.1.0", false).unwrap_err();
+        match err.root() {
+            AnvilError::BuildStepFailed { code, stderr, .. } => {
+                assert!(matches!(code, Some(x) if *x != 0));
+                assert!(stderr.contains("missing"));
+            }
+            e => panic!("Expected BuildStepFailed, got {e:?}"),
+        }
+        assert!(!tag_dir.join("hello").exists());
+    }
+}
@@ -0,0 +1,253 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use crate::core::SemVerKey;
+use crate::error::AnvilError;
+use crate::semver::SemVer;
+
+pub const TAG_QUERY_ALL: &str = "all";
+pub const TAG_QUERY_LATEST: &str = "latest";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparator {
+    pub op: CmpOp,
+    pub version: SemVer,
+}
+
+impl Comparator {
+    fn new(op: CmpOp, major: u64, minor: u64, patch: u64) -> Self {
+        Comparator { op, version: SemVer { major, minor, patch, pre: Vec::new(), build: Vec::new() } }
+    }
+
+    /// Returns true if this comparator names a pre-release of the same `major.minor.patch` as `v`.
+    fn allows_pre(&self, v: &SemVer) -> bool {
+        !self.version.pre.is_empty()
+            && (self.version.major, self.version.minor, self.version.patch) == (v.major, v.minor, v.patch)
+    }
+
+    pub fn matches(&self, v: &SemVer) -> bool {
+        let ord = v.precedence(&self.version);
+        match self.op {
+            CmpOp::Eq => ord == Ordering::Equal,
+            CmpOp::Gt => ord == Ordering::Greater,
+            CmpOp::Ge => ord != Ordering::Less,
+            CmpOp::Lt => ord == Ordering::Less,
+            CmpOp::Le => ord != Ordering::Greater,
+        }
+    }
+}
+
+/// A tag query, as passed in place of a tag.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagQuery {
+    /// A tag, used as is
+    Exact(String),
+    /// The tag this many places before the latest one
+    Latest(usize),
+    /// Every tag
+    All,
+    /// Every tag matching all the comparators
+    Range(Vec<Comparator>),
+}
+
+/// Parses a possibly partial version, like `1`, `1.2` or `1.2.x`, into its parts.
+fn parse_partial(s: &str) -> Option<Vec<u64>> {
+    let mut parts = Vec::new();
+    for (i, p) in s.split('.').enumerate() {
+        if i > 2 {
+            return None;
+        }
+        if matches!(p, "x" | "X" | "*") {
+            break;
+        }
+        if p.is_empty() || !p.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        parts.push(p.parse().ok()?);
+    }
+    Some(parts)
+}
+
+/// Comparators for `^v`: changes that don't modify the left-most non-zero part.
+fn caret(parts: &[u64]) -> Vec<Comparator> {
+    let (ma, mi, pa) = (parts[0], parts.get(1).copied().unwrap_or(0), parts.get(2).copied().unwrap_or(0));
+    let upper = match parts.len() {
+        _ if ma > 0 => Comparator::new(CmpOp::Lt, ma + 1, 0, 0),
+        1 => Comparator::new(CmpOp::Lt, 1, 0, 0),
+        _ if mi > 0 || parts.len() == 2 => Comparator::new(CmpOp::Lt, 0, mi + 1, 0),
+        _ => Comparator::new(CmpOp::Lt, 0, 0, pa + 1),
+    };
+    vec![Comparator::new(CmpOp::Ge, ma, mi, pa), upper]
+}
+
+/// Comparators for `~v`, or a wildcard like `1.2.x`: changes to the parts after the last given one.
+fn tilde(parts: &[u64], wildcard: bool) -> Vec<Comparator> {
+    let (ma, mi, pa) = (parts[0], parts.get(1).copied().unwrap_or(0), parts.get(2).copied().unwrap_or(0));
+    let upper = match parts.len() {
+        1 => Comparator::new(CmpOp::Lt, ma + 1, 0, 0),
+        3 if wildcard => return vec![Comparator::new(CmpOp::Eq, ma, mi, pa)],
+        _ => Comparator::new(CmpOp::Lt, ma, mi + 1, 0),
+    };
+    vec![Comparator::new(CmpOp::Ge, ma, mi, pa), upper]
+}
+
+fn parse_comparator(s: &str) -> Option<Vec<Comparator>> {
+    let (op, rest) = if let Some(r) = s.strip_prefix(">=") {
+        (Some(CmpOp::Ge), r)
+    } else if let Some(r) = s.strip_prefix("<=") {
+        (Some(CmpOp::Le), r)
+    } else if let Some(r) = s.strip_prefix('>') {
+        (Some(CmpOp::Gt), r)
+    } else if let Some(r) = s.strip_prefix('<') {
+        (Some(CmpOp::Lt), r)
+    } else if let Some(r) = s.strip_prefix('=') {
+        (Some(CmpOp::Eq), r)
+    } else if let Some(r) = s.strip_prefix('^') {
+        return parse_partial(r).filter(|p| !p.is_empty()).map(|p| caret(&p));
+    } else if let Some(r) = s.strip_prefix('~') {
+        return parse_partial(r).filter(|p| !p.is_empty()).map(|p| tilde(&p, false));
+    } else {
+        (None, s)
+    };
+    if let (Some(op), Some(version)) = (op, SemVer::parse(rest)) {
+        return Some(vec![Comparator { op, version }]);
+    }
+    let parts = parse_partial(rest)?;
+    match op {
+        // Missing parts are zero
+        Some(op) => Some(vec![Comparator::new(op, parts.first().copied().unwrap_or(0), parts.get(1).copied().unwrap_or(0), parts.get(2).copied().unwrap_or(0))]),
+        None if parts.is_empty() => Some(Vec::new()),
+        None => Some(tilde(&parts, true)),
+    }
+}
+
+/// Parses `query`: `all`, `latest`, `latest-N`, `^1.2`, `~1.2`, `0.2.x`, `>=0.2.0 <0.3.0`, or an exact tag.
+pub fn parse_tag_query(query: &str) -> Result<TagQuery, AnvilError> {
+    let q = query.trim();
+    if q == TAG_QUERY_ALL {
+        return Ok(TagQuery::All);
+    }
+    if q == TAG_QUERY_LATEST {
+        return Ok(TagQuery::Latest(0));
+    }
+    if let Some(n) = q.strip_prefix("latest-") {
+        return n.parse().map(TagQuery::Latest).map_err(|_| AnvilError::Usage(format!("Invalid tag query: {{{query}}}")));
+    }
+    if SemVer::parse(q).is_some() {
+        return Ok(TagQuery::Exact(q.to_string()));
+    }
+    let mut comparators = Vec::new();
+    for part in q.split([' ', ',']).filter(|p| !p.is_empty()) {
+        match parse_comparator(part) {
+            Some(c) => comparators.extend(c),
+            None => return Ok(TagQuery::Exact(query.to_string())),
+        }
+    }
+    Ok(TagQuery::Range(comparators))
+}
+
+impl TagQuery {
+    /// Returns the tags in `tags` matched by this query, in ascending order.
+    pub fn resolve<'a>(&self, tags: impl IntoIterator<Item = &'a SemVerKey>) -> Vec<String> {
+        let mut tags: Vec<&SemVerKey> = tags.into_iter().collect();
+        tags.sort();
+        match self {
+            TagQuery::Exact(t) => vec![t.clone()],
+            TagQuery::All => tags.iter().map(|t| t.0.clone()).collect(),
+            TagQuery::Latest(n) => tags.iter().rev().nth(*n).map(|t| vec![t.0.clone()]).unwrap_or_default(),
+            // Pre-releases only match if a comparator names one for the same version
+            TagQuery::Range(comparators) => tags.iter()
+                .filter(|t| SemVer::parse(&t.0).is_some_and(|v| {
+                    (v.pre.is_empty() || comparators.iter().any(|c| c.allows_pre(&v)))
+                        && comparators.iter().all(|c| c.matches(&v))
+                }))
+                .map(|t| t.0.clone())
+                .collect(),
+        }
+    }
+}
+
+/// Resolves `query` against a versions table.
+///
+/// Exact tags are returned as they are, so validation stays with the ops. Other queries fail if nothing matches.
+pub fn resolve_tag_query(query: &str, table: &BTreeMap<SemVerKey, String>) -> Result<Vec<String>, AnvilError> {
+    let parsed = parse_tag_query(query)?;
+    let tags = parsed.resolve(table.keys());
+    if tags.is_empty() {
+        error!("No tag matches {{{query}}}");
+        return Err(AnvilError::UnknownTag(query.to_string()));
+    }
+    if !matches!(parsed, TagQuery::Exact(_)) {
+        debug!("Query {{{query}}} resolved to {{{}}}", tags.join(", "));
+    }
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> BTreeMap<SemVerKey, String> {
+        ["0.1.0", "0.2.0", "0.2.1", "0.2.10", "0.3.0", "1.0.0", "1.2.0", "1.3.5", "2.0.0"].iter()
+            .map(|t| (SemVerKey(t.to_string()), String::new()))
+            .collect()
+    }
+
+    fn resolve(q: &str) -> Vec<String> {
+        resolve_tag_query(q, &table()).unwrap_or_default()
+    }
+
+    #[test]
+    fn test_resolve_tag_query() {
+        assert_eq!(resolve("latest"), ["2.0.0"]);
+        assert_eq!(resolve("latest-2"), ["1.2.0"]);
+        assert_eq!(resolve("latest-9"), Vec::<String>::new());
+        assert_eq!(resolve("all").len(), 9);
+        assert_eq!(resolve("^1.2"), ["1.2.0", "1.3.5"]);
+        assert_eq!(resolve("^0.2"), ["0.2.0", "0.2.1", "0.2.10"]);
+        assert_eq!(resolve("~1.2"), ["1.2.0"]);
+        assert_eq!(resolve("0.2.x"), ["0.2.0", "0.2.1", "0.2.10"]);
+        assert_eq!(resolve(">=0.2.0 <0.3.0"), ["0.2.0", "0.2.1", "0.2.10"]);
+        assert_eq!(resolve(">0.3.0, <=1.3"), ["1.0.0", "1.2.0"]);
+        assert_eq!(resolve("0.2.1"), ["0.2.1"]);
+        // Exact tags are left for the ops to validate
+        assert_eq!(resolve("9.9.9"), ["9.9.9"]);
+        assert!(resolve_tag_query("^3", &table()).is_err());
+    }
+
+    #[test]
+    fn test_range_pre_releases() {
+        let table: BTreeMap<SemVerKey, String> = ["0.2.0", "0.2.1", "0.3.0-rc.1", "0.3.0-rc.2", "0.3.0", "0.4.0-rc.1"].iter()
+            .map(|t| (SemVerKey(t.to_string()), String::new()))
+            .collect();
+        let resolve = |q: &str| resolve_tag_query(q, &table).unwrap_or_default();
+
+        assert_eq!(resolve(">=0.2.0 <0.3.0"), ["0.2.0", "0.2.1"]);
+        assert_eq!(resolve("^0.2"), ["0.2.0", "0.2.1"]);
+        assert_eq!(resolve(">=0.2.0"), ["0.2.0", "0.2.1", "0.3.0"]);
+        assert_eq!(resolve(">=0.3.0-rc.2"), ["0.3.0-rc.2", "0.3.0"]);
+        assert_eq!(resolve(">=0.3.0-rc.1 <0.3.0"), ["0.3.0-rc.1", "0.3.0-rc.2"]);
+        assert_eq!(resolve("latest"), ["0.4.0-rc.1"]);
+    }
+}
@@ -0,0 +1,412 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use clap::Parser;
+use git2::Repository;
+use regex::Regex;
+use toml_edit::{DocumentMut, Item, value};
+use crate::core::{Args, AmbosoMode, SemVerKey, DEFAULT_DISCOVER_PATTERN, INVIL_NAME, ANVIL_EXIT_BUILD, ANVIL_EXIT_CONFIG, ANVIL_EXIT_FAILURE, is_git_repo_clean};
+use crate::error::{AnvilError, error_chain};
+use crate::fmt::parse_stego_document;
+use crate::project::Project;
+use crate::semver::SemVer;
+use crate::stego::{deserialize_stego, StegoLock};
+use crate::tag::{add_version_entry, create_git_tag, discover_git_tags_with_pre, tag_name_for_version};
+
+/// What a release did, or would do with `dry_run`.
+#[derive(Debug, PartialEq)]
+pub struct ReleasePlan {
+    pub previous: Option<String>,
+    pub version: String,
+    /// Files changed besides stego.lock, relative to the stego dir
+    pub version_files: Vec<PathBuf>,
+}
+
+/// Returns the version after `latest` for a `patch`, `minor` or `major` release.
+///
+/// A pre-release `latest` is finalised when the bump would land on its version core, so `1.3.0-rc.1` goes to `1.3.0` on minor.
+pub fn next_version(latest: &str, level: &str, pre: Option<&str>) -> Result<String, AnvilError> {
+    let Some(SemVer { major, minor, patch, pre: latest_pre, .. }) = SemVer::parse(latest) else {
+        return Err(AnvilError::Config(format!("Invalid version: {{{latest}}}")));
+    };
+    let was_pre = !latest_pre.is_empty();
+    let (major, minor, patch) = match level {
+        "major" if was_pre && minor == 0 && patch == 0 => (major, 0, 0),
+        "major" => (major + 1, 0, 0),
+        "minor" if was_pre && patch == 0 => (major, minor, 0),
+        "minor" => (major, minor + 1, 0),
+        "patch" if was_pre => (major, minor, patch),
+        "patch" => (major, minor, patch + 1),
+        _ => return Err(AnvilError::Usage(format!("Invalid release level: {{{level}}}"))),
+    };
+    Ok(match pre {
+        Some(p) => format!("{major}.{minor}.{patch}-{p}"),
+        None => format!("{major}.{minor}.{patch}"),
+    })
+}
+
+/// Returns the `[versions] discover` pattern in `stego`, or the default one.
+fn discover_pattern(stego: &StegoLock) -> &str {
+    stego.versions.as_ref().and_then(|v| v.discover.as_deref()).unwrap_or(DEFAULT_DISCOVER_PATTERN)
+}
+
+/// Returns the latest git mode version in `stego`, or among the tags in `repo` matching its discover pattern.
+///
+/// Pre-releases are only tagged, so they are found in `repo`.
+pub fn latest_git_version(stego: &StegoLock, repo: Option<&Repository>) -> Option<String> {
+    let mut tags = Vec::new();
+    if let Some(repo) = repo {
+        match discover_git_tags_with_pre(repo, discover_pattern(stego)) {
+            Ok(discovered) => tags.extend(discovered.into_iter().map(|t| t.version)),
+            Err(e) => warn!("Failed reading git tags. Err: {}", error_chain(&e)),
+        }
+    }
+    stego.versions.iter()
+        .flat_map(|v| v.tags.keys())
+        .filter(|k| !k.starts_with('B'))
+        .map(|k| k.to_string())
+        .chain(tags)
+        .map(SemVerKey)
+        .max()
+        .map(|k| k.0)
+}
+
+/// Sets `[project] version` in a `pyproject.toml`.
+pub fn bump_pyproject_version(contents: &str, version: &str) -> Result<String, AnvilError> {
+    let mut doc = contents.parse::<DocumentMut>().map_err(|e| AnvilError::Config(format!("pyproject.toml: {e}")))?;
+    let Some(project) = doc.get_mut("project").and_then(Item::as_table_mut) else {
+        return Err(AnvilError::Config("pyproject.toml has no [project] table".to_string()));
+    };
+    match project.get_mut("version").and_then(Item::as_value_mut) {
+        Some(v) => {
+            let decor = v.decor().clone();
+            *v = version.into();
+            *v.decor_mut() = decor;
+        }
+        None => {
+            project.insert("version", value(version));
+        }
+    }
+    Ok(doc.to_string())
+}
+
+/// Sets the version argument of `AC_INIT` in a `configure.ac`.
+pub fn bump_configure_ac_version(contents: &str, version: &str) -> Result<String, AnvilError> {
+    let ac_init = Regex::new(r"(AC_INIT\(\s*\[[^\]]*\]\s*,\s*\[)[^\]]*(\])").expect("Failed to create regex");
+    if !ac_init.is_match(contents) {
+        return Err(AnvilError::Config("configure.ac has no AC_INIT with a version".to_string()));
+    }
+    Ok(ac_init.replace(contents, |caps: &regex::Captures| format!("{}{version}{}", &caps[1], &caps[2])).to_string())
+}
+
+type VersionBump = fn(&str, &str) -> Result<String, AnvilError>;
+
+/// A file a release writes, with the contents to restore if the release fails.
+struct FileChange {
+    path: PathBuf,
+    old: String,
+    new: String,
+}
+
+/// Returns the change to the version file for `kern`, if there is one and it changes.
+fn bump_version_file(stego_dir: &Path, kern: &str, version: &str) -> Result<Option<FileChange>, AnvilError> {
+    let (name, bump): (&str, VersionBump) = match kern {
+        "anvilPy" => ("pyproject.toml", bump_pyproject_version),
+        "amboso-C" => ("configure.ac", bump_configure_ac_version),
+        _ => {
+            debug!("No version file to update for kern {{{kern}}}");
+            return Ok(None);
+        }
+    };
+    let path = stego_dir.join(name);
+    if !path.exists() {
+        debug!("No {{{}}} to update", path.display());
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(&path).map_err(|e| AnvilError::io(&path, e))?;
+    let bumped = bump(&contents, version)?;
+    if bumped == contents {
+        return Ok(None);
+    }
+    Ok(Some(FileChange { path, old: contents, new: bumped }))
+}
+
+/// Writes `changes`, then commits `files` and tags HEAD as `version`.
+fn apply_release(repo: &Repository, stego_dir: &Path, changes: &[FileChange], files: &[PathBuf], version: &str, tag_name: &str, description: &str) -> Result<(), AnvilError> {
+    for change in changes {
+        fs::write(&change.path, &change.new).map_err(|e| AnvilError::io(&change.path, e))?;
+    }
+    if !files.is_empty() {
+        let oid = commit_files(repo, stego_dir, files, &format!("Release {version}"))?;
+        info!("Committed {{{oid}}}");
+    }
+    create_git_tag(repo, tag_name, description)
+}
+
+/// Moves HEAD and the index back to `head` and restores the contents of `changes`.
+fn rollback_release(repo: &Repository, head: &git2::Commit, changes: &[FileChange]) {
+    if let Err(e) = repo.reset(head.as_object(), git2::ResetType::Mixed, None) {
+        error!("Failed resetting to {{{}}}. Err: {e}", head.id());
+    }
+    for change in changes {
+        if let Err(e) = fs::write(&change.path, &change.old) {
+            error!("Failed restoring {{{}}}. Err: {e}", change.path.display());
+        }
+    }
+}
+
+/// Commits `files`, relative to `stego_dir`, on HEAD.
+fn commit_files(repo: &Repository, stego_dir: &Path, files: &[PathBuf], message: &str) -> Result<git2::Oid, AnvilError> {
+    let Some(workdir) = repo.workdir() else {
+        return Err(AnvilError::Git(git2::Error::from_str("Can't commit in a bare repo")));
+    };
+    let workdir = workdir.canonicalize().map_err(|e| AnvilError::io(workdir, e))?;
+    let stego_dir = stego_dir.canonicalize().map_err(|e| AnvilError::io(stego_dir, e))?;
+    let mut index = repo.index()?;
+    for file in files {
+        let path = stego_dir.join(file);
+        let rel = path.strip_prefix(&workdir).map_err(|_| AnvilError::Usage(format!("{{{}}} is outside the repo", path.display())))?;
+        index.add_path(rel)?;
+    }
+    index.write()?;
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let sig = repo.signature()?;
+    let parent = repo.head()?.peel_to_commit()?;
+    Ok(repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &[&parent])?)
+}
+
+/// Options for `release`, besides the stego.lock path.
+#[derive(Debug, Default)]
+pub struct ReleaseOptions {
+    pub level: String,
+    pub pre: Option<String>,
+    pub description: Option<String>,
+    pub header_dir: Option<PathBuf>,
+    /// Generate the header with the commit time as gen time
+    pub reproducible: bool,
+    pub build: bool,
+    pub dry_run: bool,
+}
+
+/// Cuts a release: adds the next version to stego.lock, bumps the kern's version file, commits and tags.
+///
+/// Then regenerates the header in `header_dir` and builds the new tag, when asked.
+pub fn release(stego_path: &Path, default_kern: &str, opts: &ReleaseOptions) -> Result<ReleasePlan, AnvilError> {
+    let stego_dir = stego_path.parent().map(Path::to_path_buf).unwrap_or_default();
+    let contents = fs::read_to_string(stego_path).map_err(|e| AnvilError::io(stego_path, e))?;
+    let stego = deserialize_stego(stego_path, &contents)?;
+    let previous = latest_git_version(&stego, Repository::discover(&stego_dir).ok().as_ref());
+    let version = next_version(previous.as_deref().unwrap_or("0.0.0"), &opts.level, opts.pre.as_deref())?;
+    let mut plan = ReleasePlan { previous, version: version.clone(), version_files: Vec::new() };
+    if opts.dry_run {
+        return Ok(plan);
+    }
+
+    // Everything is checked before the first write, and undone if a later step fails
+    let repo = Repository::discover(&stego_dir)?;
+    // Strict, so a missing repo is an error
+    let mut args = Args::parse_from([INVIL_NAME]);
+    args.strict = true;
+    if !is_git_repo_clean(&stego_dir, &args)? {
+        return Err(AnvilError::Config("Repo has uncommitted changes, commit or stash them before a release".to_string()));
+    }
+    let tag_name = tag_name_for_version(&version, discover_pattern(&stego));
+    if repo.find_reference(&format!("refs/tags/{tag_name}")).is_ok() {
+        return Err(AnvilError::Usage(format!("Git tag {{{tag_name}}} already exists")));
+    }
+    let head = repo.head()?.peel_to_commit()?;
+    repo.signature()?;
+
+    let description = opts.description.clone().unwrap_or(format!("Release {version}"));
+    // stego.lock versions are strict semver, without pre-release
+    let listed = opts.pre.is_none();
+    let mut changes = Vec::new();
+    let mut files = Vec::new();
+    if listed {
+        let mut doc = parse_stego_document(stego_path, &contents)?;
+        add_version_entry(&mut doc, &version, &description)?;
+        changes.push(FileChange { path: stego_path.to_path_buf(), old: contents.clone(), new: doc.to_string() });
+        files.push(PathBuf::from(stego_path.file_name().unwrap_or_default()));
+    } else {
+        warn!("Pre-release {{{version}}} can't be a stego.lock version, only tagging it");
+    }
+    let kern = stego.anvil.as_ref().and_then(|a| a.kern.clone()).unwrap_or(default_kern.to_string());
+    if let Some(change) = bump_version_file(&stego_dir, &kern, &version)? {
+        let name = PathBuf::from(change.path.file_name().unwrap_or_default());
+        plan.version_files.push(name.clone());
+        files.push(name);
+        changes.push(change);
+    }
+    if let Err(e) = apply_release(&repo, &stego_dir, &changes, &files, &version, &tag_name, &description) {
+        warn!("Release of {{{version}}} failed, rolling back");
+        rollback_release(&repo, &head, &changes);
+        return Err(e);
+    }
+
+    if opts.header_dir.is_some() || opts.build {
+        let mut project = Project::open(stego_path, AmbosoMode::GitMode)?;
+        project.set_reproducible_headers(opts.reproducible);
+        if let Some(ref dir) = opts.header_dir {
+            project.gen_header(dir, &version)?;
+        }
+        if opts.build {
+            if listed {
+                project.build(&version, false)?;
+            } else {
+                warn!("Not building pre-release {{{version}}}, it is not in stego.lock");
+            }
+        }
+    }
+    Ok(plan)
+}
+
+pub fn handle_release_subcommand(args: &Args, opts: &ReleaseOptions) -> ExitCode {
+    let stego_path = args.stego_dir.clone().unwrap_or(PathBuf::from(".")).join("stego.lock");
+    let kern = args.anvil_kern.clone().unwrap_or("amboso-C".to_string());
+    match release(&stego_path, &kern, opts) {
+        Ok(plan) => {
+            let previous = plan.previous.unwrap_or("none".to_string());
+            if opts.dry_run {
+                println!("{previous} -> {}", plan.version);
+            } else {
+                info!("Released {{{}}} (previous: {{{previous}}})", plan.version);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            error!("Release failed. Err: {}", error_chain(&e));
+            match e.root() {
+                AnvilError::StegoParse { .. } => ExitCode::from(ANVIL_EXIT_CONFIG),
+                AnvilError::BuildStepFailed { .. } => ExitCode::from(ANVIL_EXIT_BUILD),
+                _ => ExitCode::from(ANVIL_EXIT_FAILURE),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn test_next_version() {
+        assert_eq!(next_version("0.2.1", "patch", None).unwrap(), "0.2.2");
+        assert_eq!(next_version("0.2.1", "minor", None).unwrap(), "0.3.0");
+        assert_eq!(next_version("0.2.1", "major", Some("rc.1")).unwrap(), "1.0.0-rc.1");
+        assert_eq!(next_version("1.3.0-rc.1", "minor", None).unwrap(), "1.3.0");
+        assert_eq!(next_version("1.3.0-rc.1", "major", None).unwrap(), "2.0.0");
+        assert!(next_version("0.2", "patch", None).is_err());
+        assert!(next_version("0.2.1", "huge", None).is_err());
+    }
+
+    #[test]
+    fn test_bump_version_files() {
+        let pyproject = "[project]\nname = \"demo\"\nversion = \"0.1.0\" # bumped by invil\n";
+        assert_eq!(bump_pyproject_version(pyproject, "0.2.0").unwrap(), "[project]\nname = \"demo\"\nversion = \"0.2.0\" # bumped by invil\n");
+        let configure_ac = "AC_INIT([demo], [0.1.0], [email@example.com])\nAM_INIT_AUTOMAKE([foreign -Wall])\n";
+        assert_eq!(bump_configure_ac_version(configure_ac, "0.2.0").unwrap(), "AC_INIT([demo], [0.2.0], [email@example.com])\nAM_INIT_AUTOMAKE([foreign -Wall])\n");
+        assert!(bump_configure_ac_version("AC_OUTPUT\n", "0.2.0").is_err());
+    }
+
+    /// Returns a new repo in `dir`, with `files` committed.
+    fn init_repo(dir: &Path, files: &[(&str, &str)]) -> Repository {
+        let repo = Repository::init(dir).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "invil").unwrap();
+        config.set_str("user.email", "invil@example.com").unwrap();
+        let mut index = repo.index().unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+            index.add_path(Path::new(name)).unwrap();
+        }
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = repo.signature().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "Init", &tree, &[]).unwrap();
+        drop(tree);
+        repo
+    }
+
+    #[test]
+    fn test_release_sequence() {
+        let dir = TempDir::new("release");
+        let repo = init_repo(&dir, &[("stego.lock", "[build]\nsource = \"main.c\"\nbin = \"hello\"\n\n[versions]\n\"1.2.0\" = \"First\"\n")]);
+        let stego_path = dir.join("stego.lock");
+        let sig = repo.signature().unwrap();
+
+        let cut = |pre: Option<&str>| {
+            let opts = ReleaseOptions { level: "minor".to_string(), pre: pre.map(str::to_string), ..Default::default() };
+            let plan = release(&stego_path, "amboso-C", &opts).unwrap();
+            (plan.previous.unwrap(), plan.version)
+        };
+        assert_eq!(cut(Some("rc.1")), ("1.2.0".to_string(), "1.3.0-rc.1".to_string()));
+        assert_eq!(cut(Some("rc.2")), ("1.3.0-rc.1".to_string(), "1.3.0-rc.2".to_string()));
+        assert_eq!(cut(None), ("1.3.0-rc.2".to_string(), "1.3.0".to_string()));
+        assert!(fs::read_to_string(&stego_path).unwrap().contains("\"1.3.0\""));
+        assert!(repo.find_reference("refs/tags/1.3.0-rc.2").is_ok());
+
+        // A release failing its checks leaves no changes behind
+        fs::write(dir.join("configure.ac"), "AC_OUTPUT\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("configure.ac")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        let head = repo.commit(Some("HEAD"), &sig, &sig, "Add configure.ac", &tree, &[&parent]).unwrap();
+        let contents = fs::read_to_string(&stego_path).unwrap();
+        let opts = ReleaseOptions { level: "minor".to_string(), ..Default::default() };
+        assert!(matches!(release(&stego_path, "amboso-C", &opts), Err(AnvilError::Config(_))));
+        assert_eq!(fs::read_to_string(&stego_path).unwrap(), contents);
+        assert_eq!(repo.head().unwrap().peel_to_commit().unwrap().id(), head);
+        assert!(repo.find_reference("refs/tags/1.4.0").is_err());
+    }
+
+    #[test]
+    fn test_release_build_failure() {
+        let dir = TempDir::new("release-build");
+        // Tags before makevers are built with a single compiler call
+        let repo = init_repo(&dir, &[
+            ("stego.lock", "[build]\nsource = \"main.c\"\nbin = \"hello\"\nmakevers = \"9.0.0\"\nautomakevers = \"9.0.0\"\n\n[versions]\n\"0.1.0\" = \"First\"\n"),
+            ("main.c", "int main(void) { return missing; }\n"),
+            (".gitignore", "bin\n"),
+        ]);
+
+        let mut args = Args::parse_from([INVIL_NAME]);
+        args.stego_dir = Some(dir.to_path_buf());
+        let opts = ReleaseOptions { level: "patch".to_string(), build: true, ..Default::default() };
+        assert_eq!(handle_release_subcommand(&args, &opts), ExitCode::from(ANVIL_EXIT_BUILD));
+        assert!(repo.find_reference("refs/tags/0.1.1").is_ok());
+    }
+
+    #[test]
+    fn test_release_discovered_tags() {
+        let dir = TempDir::new("release-discover");
+        let repo = init_repo(&dir, &[("stego.lock", "[versions]\ndiscover = \"v*\"\n")]);
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.tag_lightweight("v1.2.0", head.as_object(), false).unwrap();
+        let stego_path = dir.join("stego.lock");
+
+        let opts = ReleaseOptions { level: "minor".to_string(), pre: Some("rc.1".to_string()), ..Default::default() };
+        let plan = release(&stego_path, "amboso-C", &opts).unwrap();
+        assert_eq!((plan.previous.as_deref(), plan.version.as_str()), (Some("1.2.0"), "1.3.0-rc.1"));
+        assert!(repo.find_reference("refs/tags/v1.3.0-rc.1").is_ok());
+        let opts = ReleaseOptions { level: "minor".to_string(), ..Default::default() };
+        let plan = release(&stego_path, "amboso-C", &opts).unwrap();
+        assert_eq!((plan.previous.as_deref(), plan.version.as_str()), (Some("1.3.0-rc.1"), "1.3.0"));
+        assert!(repo.find_reference("refs/tags/v1.3.0").is_ok());
+        assert!(repo.find_reference("refs/tags/1.3.0").is_err());
+    }
+}
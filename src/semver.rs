@@ -0,0 +1,231 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A pre-release identifier, compared numerically when it is all digits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreReleaseId {
+    Numeric(u64),
+    Alpha(String),
+}
+
+impl Ord for PreReleaseId {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (PreReleaseId::Numeric(a), PreReleaseId::Numeric(b)) => a.cmp(b),
+            (PreReleaseId::Numeric(_), PreReleaseId::Alpha(_)) => Ordering::Less,
+            (PreReleaseId::Alpha(_), PreReleaseId::Numeric(_)) => Ordering::Greater,
+            (PreReleaseId::Alpha(a), PreReleaseId::Alpha(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for PreReleaseId {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for PreReleaseId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreReleaseId::Numeric(n) => write!(f, "{n}"),
+            PreReleaseId::Alpha(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+/// A version as defined by SemVer 2.0.0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SemVer {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Vec<PreReleaseId>,
+    /// Build metadata, ignored for precedence
+    pub build: Vec<String>,
+}
+
+fn is_ident(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn parse_numeric(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.chars().all(|c| c.is_ascii_digit()) || (s.len() > 1 && s.starts_with('0')) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+impl SemVer {
+    /// Parses `input`, returning `None` if it is not a valid SemVer 2.0.0 version.
+    pub fn parse(input: &str) -> Option<SemVer> {
+        let (rest, build) = match input.split_once('+') {
+            Some((r, b)) => (r, Some(b)),
+            None => (input, None),
+        };
+        let (core, pre) = match rest.split_once('-') {
+            Some((c, p)) => (c, Some(p)),
+            None => (rest, None),
+        };
+        let mut nums = core.split('.');
+        let major = parse_numeric(nums.next()?)?;
+        let minor = parse_numeric(nums.next()?)?;
+        let patch = parse_numeric(nums.next()?)?;
+        if nums.next().is_some() {
+            return None;
+        }
+        let pre = match pre {
+            Some(p) => p.split('.').map(|id| {
+                if !is_ident(id) {
+                    None
+                } else if id.chars().all(|c| c.is_ascii_digit()) {
+                    parse_numeric(id).map(PreReleaseId::Numeric)
+                } else {
+                    Some(PreReleaseId::Alpha(id.to_string()))
+                }
+            }).collect::<Option<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+        let build = match build {
+            Some(b) => b.split('.').map(|id| is_ident(id).then(|| id.to_string())).collect::<Option<Vec<_>>>()?,
+            None => Vec::new(),
+        };
+        Some(SemVer { major, minor, patch, pre, build })
+    }
+
+    /// Compares by SemVer precedence: build metadata is ignored, and a pre-release is lower than its release.
+    pub fn precedence(&self, other: &SemVer) -> Ordering {
+        (self.major, self.minor, self.patch).cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => self.pre.cmp(&other.pre),
+            })
+    }
+
+    /// Returns true if this has no pre-release or build metadata.
+    pub fn is_release(&self) -> bool {
+        self.pre.is_empty() && self.build.is_empty()
+    }
+}
+
+impl fmt::Display for SemVer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if !self.pre.is_empty() {
+            let pre: Vec<String> = self.pre.iter().map(|id| id.to_string()).collect();
+            write!(f, "-{}", pre.join("."))?;
+        }
+        if !self.build.is_empty() {
+            write!(f, "+{}", self.build.join("."))?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares strings that are not valid versions: leading numbers by value, then the strings themselves.
+pub fn lenient_compare(v1: &str, v2: &str) -> Ordering {
+    let numbers = |v: &str| -> Vec<u64> {
+        v.split(['-', '+']).next().unwrap_or_default()
+            .split('.')
+            .map_while(|s| s.parse::<u64>().ok())
+            .collect()
+    };
+    numbers(v1).cmp(&numbers(v2)).then_with(|| v1.cmp(v2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_spec_precedence() {
+        // The ordering example from the SemVer 2.0.0 spec, item 11
+        let ordered = ["1.0.0-alpha", "1.0.0-alpha.1", "1.0.0-alpha.beta", "1.0.0-beta", "1.0.0-beta.2", "1.0.0-beta.11", "1.0.0-rc.1", "1.0.0"];
+        for w in ordered.windows(2) {
+            let (a, b) = (SemVer::parse(w[0]).unwrap(), SemVer::parse(w[1]).unwrap());
+            assert_eq!(a.precedence(&b), Ordering::Less, "{} < {}", w[0], w[1]);
+        }
+        assert_eq!(SemVer::parse("1.0.0-rc.10").unwrap().precedence(&SemVer::parse("1.0.0-rc.9").unwrap()), Ordering::Greater);
+        assert_eq!(SemVer::parse("1.0.0+a").unwrap().precedence(&SemVer::parse("1.0.0+b").unwrap()), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid() {
+        for v in ["1.2", "1.2.3.4", "01.2.3", "1.2.3-", "1.2.3-01", "1.2.3+", "1.2.3-a..b", "a.b.c", "1.2.3-Ä"] {
+            assert!(SemVer::parse(v).is_none(), "{v} should be invalid");
+        }
+        assert!(SemVer::parse("1.2.3-0a.01a+build.007").is_some());
+    }
+
+    fn version() -> impl Strategy<Value = String> {
+        let num = prop_oneof![Just("0".to_string()), "[1-9][0-9]{0,3}"];
+        let ident = prop_oneof![num.clone(), "[0-9]{0,2}[a-zA-Z-][0-9a-zA-Z-]{0,3}"];
+        let pre = prop::collection::vec(ident, 0..3);
+        let build = prop::collection::vec("[0-9a-zA-Z-]{1,4}", 0..2);
+        (num.clone(), num.clone(), num, pre, build).prop_map(|(ma, mi, pa, pre, build)| {
+            let mut v = format!("{ma}.{mi}.{pa}");
+            if !pre.is_empty() {
+                v.push_str(&format!("-{}", pre.join(".")));
+            }
+            if !build.is_empty() {
+                v.push_str(&format!("+{}", build.join(".")));
+            }
+            v
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parse_display_roundtrip(v in version()) {
+            let parsed = SemVer::parse(&v).expect("Generated version should parse");
+            prop_assert_eq!(parsed.to_string(), v);
+        }
+
+        #[test]
+        fn prop_precedence_is_antisymmetric(a in version(), b in version()) {
+            let (va, vb) = (SemVer::parse(&a).unwrap(), SemVer::parse(&b).unwrap());
+            prop_assert_eq!(va.precedence(&vb), vb.precedence(&va).reverse());
+        }
+
+        #[test]
+        fn prop_precedence_is_transitive(a in version(), b in version(), c in version()) {
+            let (va, vb, vc) = (SemVer::parse(&a).unwrap(), SemVer::parse(&b).unwrap(), SemVer::parse(&c).unwrap());
+            if va.precedence(&vb) != Ordering::Greater && vb.precedence(&vc) != Ordering::Greater {
+                prop_assert_ne!(va.precedence(&vc), Ordering::Greater);
+            }
+        }
+
+        #[test]
+        fn prop_build_metadata_is_ignored(a in version(), build in "[0-9a-zA-Z-]{1,4}") {
+            let va = SemVer::parse(&a).unwrap();
+            let mut vb = va.clone();
+            vb.build = vec![build];
+            prop_assert_eq!(va.precedence(&vb), Ordering::Equal);
+        }
+
+        #[test]
+        fn prop_prerelease_is_lower(a in version(), pre in "[a-z]{1,4}") {
+            let mut release = SemVer::parse(&a).unwrap();
+            release.pre.clear();
+            let mut pre_release = release.clone();
+            pre_release.pre = vec![PreReleaseId::Alpha(pre)];
+            prop_assert_eq!(pre_release.precedence(&release), Ordering::Less);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::collections::BTreeMap;
+use std::path::Path;
+use serde::Deserialize;
+use toml::de::{DeTable, DeValue};
+use crate::core::{
+    ANVIL_VERSION_KEYNAME, ANVIL_KERN_KEYNAME,
+    ANVIL_SOURCE_KEYNAME, ANVIL_BIN_KEYNAME, ANVIL_MAKE_VERS_KEYNAME, ANVIL_AUTOMAKE_VERS_KEYNAME,
+    ANVIL_BUILDS_DIR_KEYNAME, ANVIL_TESTSDIR_KEYNAME,
+    ANVIL_BONEDIR_KEYNAME, ANVIL_KULPODIR_KEYNAME,
+};
+use crate::error::{AnvilError, render_diagnostic};
+
+pub const STEGO_ANVIL_SECTION: &str = "anvil";
+pub const STEGO_BUILD_SECTION: &str = "build";
+pub const STEGO_TESTS_SECTION: &str = "tests";
+pub const STEGO_HEADER_SECTION: &str = "header";
+pub const STEGO_LINT_SECTION: &str = "lint";
+pub const STEGO_VERSIONS_SECTION: &str = "versions";
+pub const STEGO_CUSTOMBUILDER_KEYNAME: &str = "custombuilder";
+pub const STEGO_DISCOVER_KEYNAME: &str = "discover";
+pub const STEGO_HEADER_LANG_KEYNAME: &str = "lang";
+pub const STEGO_HEADER_TEMPLATE_KEYNAME: &str = "template";
+pub const STEGO_HEADER_FIELDS_KEYNAME: &str = "fields";
+
+/// Typed contents of a `stego.lock` file
+#[derive(Deserialize, Debug, Default)]
+pub struct StegoLock {
+    pub anvil: Option<StegoAnvil>,
+    pub build: Option<StegoBuild>,
+    pub tests: Option<StegoTests>,
+    pub header: Option<StegoHeader>,
+    pub lint: Option<StegoLint>,
+    pub versions: Option<StegoVersions>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct StegoAnvil {
+    pub version: Option<String>,
+    pub kern: Option<String>,
+    pub custombuilder: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct StegoBuild {
+    pub source: Option<String>,
+    pub bin: Option<String>,
+    pub makevers: Option<String>,
+    pub automakevers: Option<String>,
+    pub dir: Option<String>,
+    pub tests: Option<String>,
+}
+
+/// The `[versions]` section: listed tags, and an optional pattern for git tags to discover
+#[derive(Deserialize, Debug, Default)]
+pub struct StegoVersions {
+    pub discover: Option<String>,
+    #[serde(flatten)]
+    pub tags: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct StegoTests {
+    pub testsdir: Option<String>,
+    pub errortestsdir: Option<String>,
+}
+
+/// The `[header]` section: options for generated headers
+#[derive(Deserialize, Debug, Default)]
+pub struct StegoHeader {
+    pub lang: Option<String>,
+    pub template: Option<String>,
+    pub fields: Option<Vec<String>>,
+}
+
+/// The `[lint]` section: a level for each lint rule, `off`, `info`, `warning` or `error`
+#[derive(Deserialize, Debug, Default)]
+pub struct StegoLint {
+    #[serde(flatten)]
+    pub levels: BTreeMap<String, String>,
+}
+
+/// Returns the known keys for `section`, or None when any key is accepted.
+fn known_keys(section: &str) -> Option<&'static [&'static str]> {
+    match section {
+        STEGO_ANVIL_SECTION => Some(&[ANVIL_VERSION_KEYNAME, ANVIL_KERN_KEYNAME, STEGO_CUSTOMBUILDER_KEYNAME]),
+        STEGO_BUILD_SECTION => Some(&[ANVIL_SOURCE_KEYNAME, ANVIL_BIN_KEYNAME, ANVIL_MAKE_VERS_KEYNAME, ANVIL_AUTOMAKE_VERS_KEYNAME, ANVIL_BUILDS_DIR_KEYNAME, ANVIL_TESTSDIR_KEYNAME]),
+        STEGO_TESTS_SECTION => Some(&[ANVIL_BONEDIR_KEYNAME, ANVIL_KULPODIR_KEYNAME]),
+        STEGO_HEADER_SECTION => Some(&[STEGO_HEADER_LANG_KEYNAME, STEGO_HEADER_TEMPLATE_KEYNAME, STEGO_HEADER_FIELDS_KEYNAME]),
+        _ => None,
+    }
+}
+
+const KNOWN_SECTIONS: &[&str] = &[STEGO_ANVIL_SECTION, STEGO_BUILD_SECTION, STEGO_TESTS_SECTION, STEGO_HEADER_SECTION, STEGO_LINT_SECTION, STEGO_VERSIONS_SECTION];
+
+/// Deserializes `contents` as a `stego.lock`, without checking for unknown keys.
+pub fn deserialize_stego(stego_path: &Path, contents: &str) -> Result<StegoLock, AnvilError> {
+    toml::from_str(contents).map_err(|e| AnvilError::stego_parse(stego_path, contents, e))
+}
+
+/// Parses `contents` as a `stego.lock`, logging a warning for each unknown key.
+pub fn parse_stego_str(stego_path: &Path, contents: &str) -> Result<StegoLock, AnvilError> {
+    let stego = deserialize_stego(stego_path, contents)?;
+    for w in unknown_key_warnings(stego_path, contents) {
+        warn!("{w}");
+    }
+    Ok(stego)
+}
+
+/// Returns a rendered diagnostic for each unknown section or key in `contents`.
+///
+/// Returns no diagnostics when `contents` is not valid TOML.
+pub fn unknown_key_warnings(stego_path: &Path, contents: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let table = match DeTable::parse(contents) {
+        Ok(t) => t,
+        Err(_) => return res,
+    };
+    for (section, value) in table.get_ref().iter() {
+        let section_name: &str = section.get_ref();
+        if !KNOWN_SECTIONS.contains(&section_name) {
+            res.push(unknown_diagnostic(stego_path, contents, section.span().start, "section", section_name, KNOWN_SECTIONS));
+            continue;
+        }
+        let Some(keys) = known_keys(section_name) else {
+            continue;
+        };
+        if let DeValue::Table(t) = value.get_ref() {
+            for key in t.keys() {
+                let key_name: &str = key.get_ref();
+                if !keys.contains(&key_name) {
+                    res.push(unknown_diagnostic(stego_path, contents, key.span().start, &format!("key in [{section_name}]"), key_name, keys));
+                }
+            }
+        }
+    }
+    res
+}
+
+/// Returns the byte offset of `key` in `[section]`, if present.
+pub fn key_offset(contents: &str, section: &str, key: &str) -> Option<usize> {
+    let table = DeTable::parse(contents).ok()?;
+    let (_, value) = table.get_ref().iter().find(|(k, _)| *k.get_ref() == section)?;
+    match value.get_ref() {
+        DeValue::Table(t) => t.keys().find(|k| *k.get_ref() == key).map(|k| k.span().start),
+        _ => None,
+    }
+}
+
+fn unknown_diagnostic(stego_path: &Path, contents: &str, pos: usize, what: &str, name: &str, candidates: &[&str]) -> String {
+    let mut message = format!("unknown {what}: {{{name}}}");
+    if let Some(s) = suggest(name, candidates) {
+        message.push_str(&format!(", did you mean {{{s}}}?"));
+    }
+    render_diagnostic(stego_path, contents, pos, &message)
+}
+
+/// Returns the closest candidate to `name`, if it's close enough to be a likely typo.
+pub fn suggest<'a>(name: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let max_dist = (name.chars().count() / 3).max(1);
+    candidates.iter()
+        .map(|c| (edit_distance(name, c), *c))
+        .filter(|(d, _)| *d <= max_dist)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, c)| c)
+}
+
+/// Levenshtein distance between `a` and `b`, over chars.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_keys() {
+        let contents = "[build]\nsource = \"main.c\"\nbinn = \"main\"\n\n[versoins]\n\"0.1.0\" = \"a\"\n";
+        let warns = unknown_key_warnings(Path::new("stego.lock"), contents);
+        assert_eq!(warns.len(), 2);
+        assert!(warns[0].starts_with("stego.lock:3:1: unknown key in [build]: {binn}, did you mean {bin}?"));
+        assert!(warns[1].starts_with("stego.lock:5:2: unknown section: {versoins}, did you mean {versions}?"));
+    }
+
+    #[test]
+    fn test_type_error() {
+        let contents = "[build]\nsource = \"main.c\"\nbin = 3\n";
+        match parse_stego_str(Path::new("stego.lock"), contents) {
+            Err(AnvilError::StegoParse { line, col, .. }) => {
+                assert_eq!(line, 3);
+                assert_eq!(col, 7);
+            }
+            other => panic!("Expected StegoParse, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_suggest() {
+        assert_eq!(suggest("makever", &["makevers", "automakevers"]), Some("makevers"));
+        assert_eq!(suggest("zzz", &["bin", "source"]), None);
+    }
+}
@@ -0,0 +1,480 @@
+//  SPDX-License-Identifier: GPL-3.0-only
+/*  Build tool with support for git tags, wrapping make.
+ *  Copyright (C) 2023-2026  jgabaut
+ *  This program is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU General Public License as published by
+ *  the Free Software Foundation, version 3 of the License.
+ *  This program is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU General Public License for more details.
+ *  You should have received a copy of the GNU General Public License
+ *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use git2::{ErrorCode, Repository};
+use toml_edit::{DocumentMut, Item, Key, Table, value};
+use crate::core::{Args, TagCommands, SemVerKey, ANVIL_EXIT_CONFIG, ANVIL_EXIT_FAILURE, ANVIL_EXIT_USAGE, is_semver, semver_compare};
+use crate::error::{AnvilError, error_chain};
+use crate::fmt::{parse_stego_document, sort_versions, versions_sorted};
+use crate::semver::SemVer;
+use crate::stego::{deserialize_stego, STEGO_VERSIONS_SECTION};
+
+/// Returns the `[versions]` key for `version`.
+pub fn version_key(version: &str, base: bool) -> String {
+    if base {
+        format!("B{version}")
+    } else {
+        version.to_string()
+    }
+}
+
+fn versions_table_mut(doc: &mut DocumentMut) -> &mut Table {
+    if !doc.contains_key(STEGO_VERSIONS_SECTION) {
+        doc.insert(STEGO_VERSIONS_SECTION, Item::Table(Table::new()));
+    }
+    doc[STEGO_VERSIONS_SECTION].as_table_mut().expect("[versions] should be a table")
+}
+
+/// Adds `key` to `[versions]`, keeping the table sorted if it already was.
+pub fn add_version_entry(doc: &mut DocumentMut, key: &str, description: &str) -> Result<(), AnvilError> {
+    let was_sorted = versions_sorted(doc);
+    let versions = versions_table_mut(doc);
+    if versions.contains_key(key) {
+        return Err(AnvilError::Usage(format!("{{{key}}} is already in [versions]")));
+    }
+    versions.insert(key, value(description));
+    if was_sorted {
+        sort_versions(doc);
+    }
+    Ok(())
+}
+
+/// Removes `key` from `[versions]`, returning its description.
+pub fn remove_version_entry(doc: &mut DocumentMut, key: &str) -> Result<String, AnvilError> {
+    let removed = doc.get_mut(STEGO_VERSIONS_SECTION)
+        .and_then(Item::as_table_mut)
+        .and_then(|v| v.remove(key));
+    match removed {
+        Some(item) => Ok(item.as_str().unwrap_or_default().to_string()),
+        None => Err(AnvilError::UnknownTag(key.to_string())),
+    }
+}
+
+/// Renames `old` to `new` in `[versions]`, keeping its description and comments.
+pub fn rename_version_entry(doc: &mut DocumentMut, old: &str, new: &str) -> Result<(), AnvilError> {
+    let was_sorted = versions_sorted(doc);
+    let versions = versions_table_mut(doc);
+    if versions.contains_key(new) {
+        return Err(AnvilError::Usage(format!("{{{new}}} is already in [versions]")));
+    }
+    let Some((old_key, item)) = versions.remove_entry(old) else {
+        return Err(AnvilError::UnknownTag(old.to_string()));
+    };
+    let mut new_key = Key::new(new);
+    *new_key.leaf_decor_mut() = old_key.leaf_decor().clone();
+    versions.insert_formatted(&new_key, item);
+    if was_sorted {
+        sort_versions(doc);
+    }
+    Ok(())
+}
+
+/// Creates `tag` on HEAD, annotated with `message` when a signature is configured.
+///
+/// An existing tag is only used if it already points to HEAD.
+pub fn create_git_tag(repo: &Repository, tag: &str, message: &str) -> Result<(), AnvilError> {
+    let head = repo.head()?.peel_to_commit()?;
+    if let Ok(existing) = repo.find_reference(&format!("refs/tags/{tag}")) {
+        if existing.peel_to_commit()?.id() == head.id() {
+            info!("Git tag {{{tag}}} already exists on HEAD, using it");
+            return Ok(());
+        }
+        error!("Git tag {{{tag}}} already exists and does not point to HEAD");
+        return Err(AnvilError::Usage(format!("Git tag {{{tag}}} already exists")));
+    }
+    match repo.signature() {
+        Ok(sig) => {
+            repo.tag(tag, head.as_object(), &sig, message, false)?;
+        }
+        Err(e) => {
+            warn!("No git signature, creating lightweight tag {{{tag}}}. Err: {e}");
+            repo.tag_lightweight(tag, head.as_object(), false)?;
+        }
+    }
+    info!("Created git tag {{{tag}}} at {{{}}}", head.id());
+    Ok(())
+}
+
+/// Deletes `tag`, warning if it does not exist.
+pub fn delete_git_tag(repo: &Repository, tag: &str) -> Result<(), AnvilError> {
+    match repo.tag_delete(tag) {
+        Ok(()) => {
+            info!("Deleted git tag {{{tag}}}");
+            Ok(())
+        }
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            warn!("Git tag {{{tag}}} does not exist");
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Points `new` at the target of `old` and deletes `old`, keeping annotations.
+pub fn rename_git_tag(repo: &Repository, old: &str, new: &str) -> Result<(), AnvilError> {
+    let reference = match repo.find_reference(&format!("refs/tags/{old}")) {
+        Ok(r) => r,
+        Err(e) if e.code() == ErrorCode::NotFound => {
+            warn!("Git tag {{{old}}} does not exist, not creating {{{new}}}");
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    match reference.peel_to_tag() {
+        Ok(annotated) => {
+            let target = annotated.target()?;
+            let sig = annotated.tagger().map(|s| s.to_owned()).map_or_else(|| repo.signature(), Ok)?;
+            repo.tag(new, &target, &sig, annotated.message()?.unwrap_or_default(), false)?;
+        }
+        Err(_) => {
+            let commit = reference.peel_to_commit()?;
+            repo.tag_lightweight(new, commit.as_object(), false)?;
+        }
+    }
+    repo.tag_delete(old)?;
+    info!("Renamed git tag {{{old}}} to {{{new}}}");
+    Ok(())
+}
+
+/// A git tag found by [`discover_git_tags`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredTag {
+    /// The version parsed from the tag name
+    pub version: String,
+    /// The git tag name
+    pub name: String,
+    /// First line of the annotated tag message, empty for lightweight tags
+    pub description: String,
+}
+
+/// Returns the literal part of glob `pattern`, before its first wildcard.
+fn pattern_prefix(pattern: &str) -> &str {
+    &pattern[..pattern.find(['*', '?', '[']).unwrap_or(pattern.len())]
+}
+
+fn tag_version(name: &str, pattern: &str, with_pre: bool) -> Option<String> {
+    [Some(name), name.strip_prefix(pattern_prefix(pattern)), name.strip_prefix('v')].into_iter()
+        .flatten()
+        .find(|v| SemVer::parse(v).is_some_and(|s| with_pre || s.is_release()))
+        .map(str::to_string)
+}
+
+/// Returns the version for git tag `name` matching `pattern`.
+///
+/// Tries the name as is, then without the pattern's literal prefix, then without a leading `v`.
+pub fn version_from_tag_name(name: &str, pattern: &str) -> Option<String> {
+    tag_version(name, pattern, false)
+}
+
+/// Returns the git tag name for a new `version` matching `pattern`, with its literal prefix.
+pub fn tag_name_for_version(version: &str, pattern: &str) -> String {
+    format!("{}{version}", pattern_prefix(pattern))
+}
+
+/// Returns the tags in `repo` matching glob `pattern` that parse as a release version, sorted by version.
+pub fn discover_git_tags(repo: &Repository, pattern: &str) -> Result<Vec<DiscoveredTag>, AnvilError> {
+    discover_tags(repo, pattern, false)
+}
+
+/// Like [`discover_git_tags`], also returning pre-release tags.
+pub fn discover_git_tags_with_pre(repo: &Repository, pattern: &str) -> Result<Vec<DiscoveredTag>, AnvilError> {
+    discover_tags(repo, pattern, true)
+}
+
+fn discover_tags(repo: &Repository, pattern: &str, with_pre: bool) -> Result<Vec<DiscoveredTag>, AnvilError> {
+    let mut res = Vec::new();
+    let names = repo.tag_names(Some(pattern))?;
+    for name in names.iter() {
+        let Some(name) = name? else {
+            continue;
+        };
+        let Some(version) = tag_version(name, pattern, with_pre) else {
+            debug!("Skipping git tag {{{name}}}, not a release version");
+            continue;
+        };
+        let description = match repo.find_reference(&format!("refs/tags/{name}"))?.peel_to_tag() {
+            Ok(annotated) => annotated.message()?.unwrap_or_default().lines().next().unwrap_or_default().trim().to_string(),
+            Err(_) => String::new(),
+        };
+        res.push(DiscoveredTag { version, name: name.to_string(), description });
+    }
+    res.sort_by_key(|t| SemVerKey(t.version.clone()));
+    Ok(res)
+}
+
+/// Creates `amboso_dir/v<tag>` with a copy of the current source, and Makefile when `with_makefile`.
+fn snapshot_base_dir(stego_dir: &Path, amboso_dir: &Path, tag: &str, source: Option<&str>, with_makefile: bool) -> Result<(), AnvilError> {
+    let tag_dir = amboso_dir.join(format!("v{tag}"));
+    if tag_dir.exists() {
+        warn!("{{{}}} already exists, leaving it as it is", tag_dir.display());
+        return Ok(());
+    }
+    fs::create_dir_all(&tag_dir).map_err(|e| AnvilError::io(&tag_dir, e))?;
+    let mut files: Vec<&str> = source.into_iter().collect();
+    if with_makefile {
+        files.push("Makefile");
+    }
+    for file in files {
+        let from = stego_dir.join(file);
+        if !from.exists() {
+            warn!("Missing {{{}}}, not copying it to {{{}}}", from.display(), tag_dir.display());
+            continue;
+        }
+        let to = tag_dir.join(file);
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent).map_err(|e| AnvilError::io(parent, e))?;
+        }
+        fs::copy(&from, &to).map_err(|e| AnvilError::io(&from, e))?;
+    }
+    info!("Created {{{}}}", tag_dir.display());
+    Ok(())
+}
+
+struct TagSession {
+    stego_path: PathBuf,
+    stego_dir: PathBuf,
+    contents: String,
+    doc: DocumentMut,
+}
+
+impl TagSession {
+    fn open(stego_path: &Path) -> Result<Self, AnvilError> {
+        let contents = fs::read_to_string(stego_path).map_err(|e| AnvilError::io(stego_path, e))?;
+        let doc = parse_stego_document(stego_path, &contents)?;
+        let stego_dir = stego_path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(TagSession { stego_path: stego_path.to_path_buf(), stego_dir, contents, doc })
+    }
+
+    fn repo(&self) -> Result<Repository, AnvilError> {
+        Ok(Repository::discover(&self.stego_dir)?)
+    }
+
+    /// Returns the git tag name for `version`, like [`AmbosoEnv::git_tag_name`](crate::core::AmbosoEnv::git_tag_name).
+    ///
+    /// With a discover pattern in stego.lock, this is the name of the matching discovered tag.
+    fn git_tag_name(&self, repo: &Repository, version: &str) -> Result<String, AnvilError> {
+        let stego = deserialize_stego(&self.stego_path, &self.contents)?;
+        let Some(pattern) = stego.versions.and_then(|v| v.discover) else {
+            return Ok(version.to_string());
+        };
+        let name = discover_git_tags(repo, &pattern)?.into_iter()
+            .find(|t| t.version == version)
+            .map_or_else(|| version.to_string(), |t| t.name);
+        Ok(name)
+    }
+
+    fn save(&self) -> Result<(), AnvilError> {
+        fs::write(&self.stego_path, self.doc.to_string()).map_err(|e| AnvilError::io(&self.stego_path, e))
+    }
+}
+
+fn check_semver(version: &str) -> Result<(), AnvilError> {
+    if is_semver(version) {
+        Ok(())
+    } else {
+        Err(AnvilError::Usage(format!("{{{version}}} is not a valid semver")))
+    }
+}
+
+/// Adds `version` to `[versions]`, then creates its git tag or, with `base`, its base mode dir.
+pub fn tag_add(stego_path: &Path, amboso_dir: &Path, version: &str, description: &str, base: bool) -> Result<(), AnvilError> {
+    check_semver(version)?;
+    let mut session = TagSession::open(stego_path)?;
+    let stego = deserialize_stego(stego_path, &session.contents)?;
+    let latest = stego.versions.iter()
+        .flat_map(|v| v.tags.keys())
+        .filter(|k| k.starts_with('B') == base)
+        .map(|k| SemVerKey(k.trim_start_matches('B').to_string()))
+        .max();
+    if let Some(latest) = latest {
+        if semver_compare(version, &latest.0) != Ordering::Greater {
+            warn!("{{{version}}} is not newer than the latest version {{{latest}}}");
+        }
+    }
+    add_version_entry(&mut session.doc, &version_key(version, base), description)?;
+    // New tags get the discover prefix, so they are found like the others
+    let tag_name = match stego.versions.as_ref().and_then(|v| v.discover.as_deref()) {
+        Some(pattern) => tag_name_for_version(version, pattern),
+        None => version.to_string(),
+    };
+    let build = stego.build.unwrap_or_default();
+    if base {
+        let with_makefile = build.makevers.as_ref().is_some_and(|m| semver_compare(version, m) != Ordering::Less);
+        snapshot_base_dir(&session.stego_dir, amboso_dir, version, build.source.as_deref(), with_makefile)?;
+    } else {
+        create_git_tag(&session.repo()?, &tag_name, description)?;
+    }
+    session.save()
+}
+
+/// Removes `version` from `[versions]` and deletes its git tag. Base mode dirs are kept.
+pub fn tag_rm(stego_path: &Path, amboso_dir: &Path, version: &str, base: bool) -> Result<(), AnvilError> {
+    let mut session = TagSession::open(stego_path)?;
+    remove_version_entry(&mut session.doc, &version_key(version, base))?;
+    if base {
+        let tag_dir = amboso_dir.join(format!("v{version}"));
+        if tag_dir.exists() {
+            info!("Keeping {{{}}}, remove it by hand if it is not needed", tag_dir.display());
+        }
+    } else {
+        let repo = session.repo()?;
+        delete_git_tag(&repo, &session.git_tag_name(&repo, version)?)?;
+    }
+    session.save()
+}
+
+/// Renames `old` to `new` in `[versions]`, moving its git tag or base mode dir.
+pub fn tag_rename(stego_path: &Path, amboso_dir: &Path, old: &str, new: &str, base: bool) -> Result<(), AnvilError> {
+    check_semver(new)?;
+    let mut session = TagSession::open(stego_path)?;
+    rename_version_entry(&mut session.doc, &version_key(old, base), &version_key(new, base))?;
+    if base {
+        let old_dir = amboso_dir.join(format!("v{old}"));
+        let new_dir = amboso_dir.join(format!("v{new}"));
+        if new_dir.exists() {
+            return Err(AnvilError::Usage(format!("{{{}}} already exists", new_dir.display())));
+        }
+        if old_dir.exists() {
+            fs::rename(&old_dir, &new_dir).map_err(|e| AnvilError::io(&old_dir, e))?;
+            info!("Moved {{{}}} to {{{}}}", old_dir.display(), new_dir.display());
+        }
+    } else {
+        let repo = session.repo()?;
+        let old_name = session.git_tag_name(&repo, old)?;
+        // Keep the prefix of discovered tags, so v1.2.0 is renamed to v1.3.0
+        let new_name = old_name.replacen(old, new, 1);
+        rename_git_tag(&repo, &old_name, &new_name)?;
+    }
+    session.save()
+}
+
+pub fn handle_tag_subcommand(args: &Args, op: &TagCommands) -> ExitCode {
+    let stego_path = args.stego_dir.clone().unwrap_or(PathBuf::from(".")).join("stego.lock");
+    let amboso_dir = args.amboso_dir.clone().unwrap_or(PathBuf::from("./bin"));
+    let res = match op {
+        TagCommands::Add { version, description, base } => tag_add(&stego_path, &amboso_dir, version, description, *base),
+        TagCommands::Rm { version, base } => tag_rm(&stego_path, &amboso_dir, version, *base),
+        TagCommands::Rename { old, new, base } => tag_rename(&stego_path, &amboso_dir, old, new, *base),
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("Tag command failed. Err: {}", error_chain(&e));
+            match e.root() {
+                AnvilError::StegoParse { .. } | AnvilError::UnknownTag(_) => ExitCode::from(ANVIL_EXIT_CONFIG),
+                AnvilError::Usage(_) => ExitCode::from(ANVIL_EXIT_USAGE),
+                _ => ExitCode::from(ANVIL_EXIT_FAILURE),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn test_version_entries() {
+        let contents = "[versions]\n\"B0.1.0\" = \"base\"\n# first tag\n\"0.1.0\" = \"first\"\n\"0.3.0\" = \"third\"\n";
+        let mut doc = parse_stego_document(Path::new("stego.lock"), contents).expect("Failed parsing");
+        add_version_entry(&mut doc, "0.2.0", "second").expect("Failed adding");
+        assert!(add_version_entry(&mut doc, "0.2.0", "again").is_err());
+        rename_version_entry(&mut doc, "0.1.0", "0.0.9").expect("Failed renaming");
+        assert_eq!(remove_version_entry(&mut doc, "0.3.0").expect("Failed removing"), "third");
+        assert!(matches!(remove_version_entry(&mut doc, "0.3.0"), Err(AnvilError::UnknownTag(_))));
+        assert_eq!(doc.to_string(), "[versions]\n\"B0.1.0\" = \"base\"\n# first tag\n\"0.0.9\" = \"first\"\n\"0.2.0\" = \"second\"\n");
+    }
+
+    #[test]
+    fn test_version_from_tag_name() {
+        assert_eq!(version_from_tag_name("v1.2.3", "v*").as_deref(), Some("1.2.3"));
+        assert_eq!(version_from_tag_name("1.2.3", "*").as_deref(), Some("1.2.3"));
+        assert_eq!(version_from_tag_name("v1.2.3", "*").as_deref(), Some("1.2.3"));
+        assert_eq!(version_from_tag_name("release-0.2.0", "release-[0-9]*").as_deref(), Some("0.2.0"));
+        assert_eq!(version_from_tag_name("v1.2.3-rc.1", "v*"), None);
+        assert_eq!(version_from_tag_name("vnext", "v*"), None);
+    }
+
+    /// Returns a new repo in a temp dir, with one commit.
+    fn temp_repo(name: &str) -> (TempDir, Repository) {
+        let dir = TempDir::new(name);
+        let repo = Repository::init(&dir).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "invil").unwrap();
+        config.set_str("user.email", "invil@example.com").unwrap();
+        let sig = repo.signature().unwrap();
+        let tree = repo.find_tree(repo.index().unwrap().write_tree().unwrap()).unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, "Init", &tree, &[]).unwrap();
+        drop(tree);
+        (dir, repo)
+    }
+
+    fn commit_empty(repo: &Repository, message: &str) {
+        let sig = repo.signature().unwrap();
+        let parent = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.commit(Some("HEAD"), &sig, &sig, message, &parent.tree().unwrap(), &[&parent]).unwrap();
+    }
+
+    #[test]
+    fn test_create_git_tag() {
+        let (_dir, repo) = temp_repo("create-tag");
+        create_git_tag(&repo, "0.1.0", "First").unwrap();
+        create_git_tag(&repo, "0.1.0", "First").expect("A tag on HEAD should be reused");
+        commit_empty(&repo, "Second");
+        assert!(matches!(create_git_tag(&repo, "0.1.0", "First"), Err(AnvilError::Usage(_))));
+    }
+
+    #[test]
+    fn test_discovered_tag_names() {
+        let (dir, repo) = temp_repo("discovered-tags");
+        let stego_path = dir.join("stego.lock");
+        fs::write(&stego_path, "[versions]\ndiscover = \"v*\"\n\"1.2.0\" = \"First\"\n").unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.tag_lightweight("v1.2.0", head.as_object(), false).unwrap();
+        let amboso_dir = dir.join("bin");
+
+        tag_rename(&stego_path, &amboso_dir, "1.2.0", "1.3.0", false).unwrap();
+        assert!(repo.find_reference("refs/tags/v1.2.0").is_err());
+        assert!(repo.find_reference("refs/tags/v1.3.0").is_ok());
+        tag_rm(&stego_path, &amboso_dir, "1.3.0", false).unwrap();
+        assert!(repo.find_reference("refs/tags/v1.3.0").is_err());
+        assert_eq!(fs::read_to_string(&stego_path).unwrap(), "[versions]\ndiscover = \"v*\"\n");
+        tag_add(&stego_path, &amboso_dir, "1.4.0", "Next", false).unwrap();
+        assert!(repo.find_reference("refs/tags/v1.4.0").is_ok());
+        assert!(repo.find_reference("refs/tags/1.4.0").is_err());
+    }
+
+    #[test]
+    fn test_tag_exit_codes() {
+        use clap::Parser;
+
+        let dir = TempDir::new("tag-exit-codes");
+        fs::write(dir.join("stego.lock"), "[versions]\n\"B0.1.0\" = \"First\"\n").unwrap();
+        fs::create_dir_all(dir.join("bin").join("v0.1.0")).unwrap();
+        fs::create_dir_all(dir.join("bin").join("v0.2.0")).unwrap();
+        let mut args = Args::parse_from([crate::core::INVIL_NAME]);
+        args.stego_dir = Some(dir.to_path_buf());
+        args.amboso_dir = Some(dir.join("bin"));
+
+        let rename = TagCommands::Rename { old: "0.1.0".to_string(), new: "0.2.0".to_string(), base: true };
+        assert_eq!(handle_tag_subcommand(&args, &rename), ExitCode::from(ANVIL_EXIT_USAGE));
+        let rm = TagCommands::Rm { version: "0.3.0".to_string(), base: true };
+        assert_eq!(handle_tag_subcommand(&args, &rm), ExitCode::from(ANVIL_EXIT_CONFIG));
+        assert_eq!(fs::read_to_string(dir.join("stego.lock")).unwrap(), "[versions]\n\"B0.1.0\" = \"First\"\n");
+    }
+}
@@ -12,11 +12,12 @@
  *  along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 use crate::core::{Args, Commands};
+use crate::error::AnvilError;
+use crate::stego::parse_stego_str;
 use std::{env};
 use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
 use std::fs;
-use toml::Table;
 
 pub fn prog_name() -> Option<String> {
     env::current_exe().ok()
@@ -141,6 +142,39 @@ pub fn print_subcommand_args(args: &Args) {
         Some(Commands::Version) => {
             debug!("Printing version");
         }
+        Some(Commands::Lint { deny, fix }) => {
+            if deny.is_some() {
+                debug!("Linting stego.lock, denying warnings");
+            } else {
+                debug!("Linting stego.lock");
+            }
+            if *fix {
+                debug!("Applying safe lint fixes");
+            }
+        }
+        Some(Commands::Fmt { check }) => {
+            if *check {
+                debug!("Checking stego.lock formatting");
+            } else {
+                debug!("Formatting stego.lock");
+            }
+        }
+        Some(Commands::Migrate { dry_run }) => {
+            if *dry_run {
+                debug!("Printing stego.lock migration diff");
+            } else {
+                debug!("Migrating stego.lock");
+            }
+        }
+        Some(Commands::Release { level, pre, .. }) => {
+            debug!("Releasing next {level} version, pre-release: {{{}}}", pre.clone().unwrap_or_default());
+        }
+        Some(Commands::Tag { op }) => {
+            debug!("Running tag command: {{{:?}}}", op);
+        }
+        Some(Commands::Config { op }) => {
+            debug!("Running config command: {{{:?}}}", op);
+        }
         None => {}
     }
 }
@@ -250,18 +284,83 @@ pub fn print_warranty_info() {
   ALL NECESSARY SERVICING, REPAIR OR CORRECTION.\n");
 }
 
-pub fn try_parse_stego(stego_path: &PathBuf) -> Result<String,String> {
-    let stego = fs::read_to_string(stego_path).expect("Could not read {stego_path} contents");
+pub fn try_parse_stego(stego_path: &PathBuf) -> Result<String,AnvilError> {
+    let stego = fs::read_to_string(stego_path).map_err(|e| AnvilError::io(stego_path, e))?;
     trace!("Stego contents: {{{}}}", stego);
-    let toml_value = stego.parse::<Table>();
-    match toml_value {
+    match parse_stego_str(stego_path, &stego) {
         Ok(_) => {
             debug!("try_parse_stego(): Lint success for {{{}}}", stego_path.display());
             Ok("Lint success".to_string())
         }
         Err(e) => {
             error!("Lint failed for {{{}}}. Err: {e}", stego_path.display());
-            Err("Lint failed".to_string())
+            Err(e)
+        }
+    }
+}
+
+/// Returns a line by line diff of `old` and `new`, with `-`, `+` and ` ` prefixes.
+pub fn line_diff(old: &str, new: &str) -> String {
+    let a: Vec<&str> = old.lines().collect();
+    let b: Vec<&str> = new.lines().collect();
+    // lcs[i][j] is the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
         }
     }
+    let mut res = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            res.push_str(&format!(" {}\n", a[i]));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            res.push_str(&format!("-{}\n", a[i]));
+            i += 1;
+        } else {
+            res.push_str(&format!("+{}\n", b[j]));
+            j += 1;
+        }
+    }
+    res
+}
+
+/// A fresh dir for a test, named `invil-<name>-<pid>` under the temp dir and removed on drop.
+#[cfg(test)]
+pub(crate) struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub(crate) fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!("invil-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("Failed creating temp dir");
+        TempDir(path)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
 }